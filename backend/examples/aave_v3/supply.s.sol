@@ -0,0 +1,32 @@
+// Intent: Supply 1000 USDC to Aave V3
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+import {Script} from "forge-std/Script.sol";
+
+interface IERC20 {
+    function approve(address spender, uint256 amount) external returns (bool);
+}
+
+interface IPool {
+    function supply(address asset, uint256 amount, address onBehalfOf, uint16 referralCode) external;
+}
+
+contract SupplyScript is Script {
+    address constant USDC = 0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48;
+    IPool constant POOL = IPool(0x87870Bca3F3fD6335C3F4ce8392D69350B4fA4E2);
+
+    // The sender the script was generated for
+    address constant SENDER = 0x0000000000000000000000000000000000000001;
+
+    function run() external {
+        // USDC has 6 decimals
+        uint256 amount = 1_000 * 1e6;
+
+        vm.startBroadcast(SENDER);
+        // Approve exactly the amount supplied, never an unlimited allowance
+        IERC20(USDC).approve(address(POOL), amount);
+        POOL.supply(USDC, amount, SENDER, 0);
+        vm.stopBroadcast();
+    }
+}
@@ -0,0 +1,77 @@
+// Intent: Swap 1 ETH for USDC on Uniswap V3 with 0.5% slippage
+// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.20;
+
+import {Script} from "forge-std/Script.sol";
+
+interface IQuoterV2 {
+    struct QuoteExactInputSingleParams {
+        address tokenIn;
+        address tokenOut;
+        uint256 amountIn;
+        uint24 fee;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    function quoteExactInputSingle(QuoteExactInputSingleParams memory params)
+        external
+        returns (uint256 amountOut, uint160 sqrtPriceX96After, uint32 initializedTicksCrossed, uint256 gasEstimate);
+}
+
+interface ISwapRouter02 {
+    struct ExactInputSingleParams {
+        address tokenIn;
+        address tokenOut;
+        uint24 fee;
+        address recipient;
+        uint256 amountIn;
+        uint256 amountOutMinimum;
+        uint160 sqrtPriceLimitX96;
+    }
+
+    function exactInputSingle(ExactInputSingleParams calldata params) external payable returns (uint256 amountOut);
+}
+
+contract SwapScript is Script {
+    address constant WETH = 0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2;
+    address constant USDC = 0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48;
+    IQuoterV2 constant QUOTER = IQuoterV2(0x61fFE014bA17989E743c5F6cB21bF9697530B21e);
+    ISwapRouter02 constant ROUTER = ISwapRouter02(0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45);
+
+    // The sender the script was generated for
+    address constant SENDER = 0x0000000000000000000000000000000000000001;
+
+    uint24 constant FEE = 500;
+    uint256 constant SLIPPAGE_BPS = 50;
+
+    function run() external {
+        uint256 amountIn = 1 ether;
+
+        // Quote before broadcasting so the quote isn't sent as a transaction
+        (uint256 quoted,,,) = QUOTER.quoteExactInputSingle(
+            IQuoterV2.QuoteExactInputSingleParams({
+                tokenIn: WETH,
+                tokenOut: USDC,
+                amountIn: amountIn,
+                fee: FEE,
+                sqrtPriceLimitX96: 0
+            })
+        );
+        uint256 minimumOut = quoted * (10_000 - SLIPPAGE_BPS) / 10_000;
+
+        vm.startBroadcast(SENDER);
+        // SwapRouter02 wraps the ETH sent with the call when tokenIn is WETH
+        ROUTER.exactInputSingle{value: amountIn}(
+            ISwapRouter02.ExactInputSingleParams({
+                tokenIn: WETH,
+                tokenOut: USDC,
+                fee: FEE,
+                recipient: SENDER,
+                amountIn: amountIn,
+                amountOutMinimum: minimumOut,
+                sqrtPriceLimitX96: 0
+            })
+        );
+        vm.stopBroadcast();
+    }
+}
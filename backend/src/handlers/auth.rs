@@ -0,0 +1,86 @@
+use super::rate_limit::query_param;
+use crate::models::AppState;
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Wallet address proven by a Sign-In with Ethereum session, added to request extensions
+#[derive(Debug, Clone, Copy)]
+pub struct AuthenticatedAddress(pub Address);
+
+#[derive(Serialize)]
+pub struct NonceResponse {
+    pub nonce: String,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    pub message: String,
+    pub signature: String,
+}
+
+#[derive(Serialize)]
+pub struct VerifyResponse {
+    pub token: String,
+    pub address: Address,
+    pub expires_in_secs: u64,
+}
+
+pub async fn auth_nonce(State(state): State<Arc<AppState>>) -> Json<NonceResponse> {
+    Json(NonceResponse {
+        nonce: state.auth.issue_nonce(),
+    })
+}
+
+pub async fn auth_verify(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<Json<VerifyResponse>, (StatusCode, String)> {
+    let (token, session) = state
+        .auth
+        .sign_in(&request.message, &request.signature)
+        .map_err(|e| (StatusCode::UNAUTHORIZED, e.to_string()))?;
+
+    Ok(Json(VerifyResponse {
+        token,
+        address: session.address,
+        expires_in_secs: state.config.auth.session_ttl_secs,
+    }))
+}
+
+/// Require a SIWE session token (`Authorization: Bearer` or `session_token` query
+/// parameter) when SIWE is enabled, exposing the verified address to handlers
+pub async fn require_session(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !state.config.auth.siwe_enabled {
+        return next.run(request).await;
+    }
+
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.to_string())
+        .or_else(|| query_param(request.uri(), "session_token"));
+
+    match token.and_then(|token| state.auth.session(&token)) {
+        Some(session) => {
+            request
+                .extensions_mut()
+                .insert(AuthenticatedAddress(session.address));
+            next.run(request).await
+        }
+        None => (StatusCode::UNAUTHORIZED, "Sign in with Ethereum required").into_response(),
+    }
+}
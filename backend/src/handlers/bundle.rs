@@ -0,0 +1,105 @@
+use super::forge::job_origin;
+use super::replay::{check_session_owner, find_session};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, BundleRequest, FlashbotsBundle, TransactionDetails};
+use crate::utils::{flashbots_bundle, validate_bundle_request, EVENT_LOG_FILE};
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::signers::LocalWallet;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path as FsPath;
+use std::sync::Arc;
+
+/// The parts of a recorded `result` event a bundle is built from
+#[derive(Deserialize)]
+struct RecordedResult {
+    transactions: Vec<TransactionDetails>,
+    #[serde(default)]
+    impersonated: bool,
+    #[serde(default)]
+    funded: bool,
+    #[serde(default)]
+    time_travel: bool,
+}
+
+/// Package a session's final transactions, approvals included, as a Flashbots
+/// `eth_sendBundle` payload, for intents whose transactions must land together
+/// in one block or not at all
+pub async fn export_bundle(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Query(request): Query<BundleRequest>,
+) -> Result<Json<FlashbotsBundle>, Response> {
+    validate_bundle_request(&request, &state.config.validation).map_err(IntoResponse::into_response)?;
+
+    let session_dir = find_session(&state, &session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+
+    let result = latest_result(&session_dir)
+        .ok_or_else(|| (StatusCode::UNPROCESSABLE_ENTITY, "Session has no simulated transactions to bundle").into_response())?;
+    if result.impersonated {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "The session ran as an impersonated sender; its transactions can't be signed as is").into_response());
+    }
+    if result.funded {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "The session ran with synthetic balances; its transactions would fail on-chain").into_response());
+    }
+    if result.time_travel {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, "The session's phases are separated in time and can't land in one block").into_response());
+    }
+
+    let config = &state.config.bundle;
+    let signer = if request.signed {
+        let key = state
+            .session_env
+            .get(&session_dir)
+            .into_iter()
+            .find(|(name, _)| *name == config.signer_env)
+            .map(|(_, key)| key)
+            .ok_or_else(|| {
+                let message = format!("Signed bundles need the sender's key in the session's {} variable", config.signer_env);
+                (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+            })?;
+        // The parse error could echo the key, so it isn't passed on
+        let wallet = key.parse::<LocalWallet>().map_err(|_| {
+            let message = format!("The session's {} variable isn't a valid private key", config.signer_env);
+            (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+        })?;
+        Some(wallet)
+    } else {
+        None
+    };
+
+    // Without an RPC the bundle is read through one of the session's chain's endpoints
+    let session_chain = job_origin(&session_dir).and_then(|origin| origin.chain_id);
+    let rpc_url = match (request.rpc_url, session_chain.or(request.chain_id)) {
+        (Some(rpc_url), _) => rpc_url,
+        (None, Some(chain_id)) => state.rpc_pool.select(chain_id).await.ok_or_else(|| {
+            (StatusCode::BAD_GATEWAY, format!("No RPC endpoint of chain {} is answering", chain_id)).into_response()
+        })?,
+        (None, None) => {
+            return Err((StatusCode::BAD_REQUEST, "The session's chain is unknown; pass rpc_url or chain_id").into_response());
+        }
+    };
+    flashbots_bundle(&rpc_url, &result.transactions, signer, request.block, config)
+        .await
+        .map(Json)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response())
+}
+
+/// The last `result` event the session's jobs recorded
+fn latest_result(session_dir: &FsPath) -> Option<RecordedResult> {
+    let log = fs::read_to_string(session_dir.join(EVENT_LOG_FILE)).ok()?;
+    log.lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|event| event["type"] == "result")
+        .and_then(|event| serde_json::from_value(event).ok())
+}
@@ -0,0 +1,7 @@
+use crate::utils::{ChainPreset, CHAIN_PRESETS};
+use axum::Json;
+
+/// Chains a request can name by `chain_id` alone, for a client-side chain picker
+pub async fn list_chains() -> Json<&'static [ChainPreset]> {
+    Json(CHAIN_PRESETS)
+}
@@ -0,0 +1,95 @@
+use super::forge::ORIGIN_FILE;
+use super::replay::{check_session_owner, find_session};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, FeedbackRecord, FeedbackRequest, JobOrigin};
+use crate::utils::TemplateKey;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Marks a session whose outcome has been reported
+const FEEDBACK_FILE: &str = "feedback.json";
+const MAX_TX_HASHES: usize = 20;
+const MAX_COMMENT_CHARS: usize = 2000;
+
+fn is_tx_hash(hash: &str) -> bool {
+    hash.len() == 66 && hash.starts_with("0x") && hash[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Record whether a session's transactions executed on-chain. Once per session;
+/// the outcome counts towards the success rate of the session's template and the
+/// per-protocol tallies that flag guidelines producing failing code.
+pub async fn submit_feedback(
+    State(state): State<Arc<AppState>>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Json(request): Json<FeedbackRequest>,
+) -> Result<StatusCode, Response> {
+    if request.tx_hashes.len() > MAX_TX_HASHES || !request.tx_hashes.iter().all(|hash| is_tx_hash(hash)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("tx_hashes must be at most {} 32-byte hex hashes", MAX_TX_HASHES),
+        )
+            .into_response());
+    }
+    if request.comment.as_ref().is_some_and(|comment| comment.chars().count() > MAX_COMMENT_CHARS) {
+        return Err((StatusCode::BAD_REQUEST, format!("comment is limited to {} characters", MAX_COMMENT_CHARS)).into_response());
+    }
+
+    let session_dir = find_session(&state, &request.session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+
+    let origin = fs::read_to_string(session_dir.join(ORIGIN_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str::<JobOrigin>(&content).ok())
+        .ok_or_else(|| {
+            (StatusCode::UNPROCESSABLE_ENTITY, "Session has no generated script to give feedback on").into_response()
+        })?;
+    let record = FeedbackRecord {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        session_id: request.session_id,
+        protocols: origin.protocols,
+        from_template: origin.from_template,
+        variant: origin.variant,
+        success: request.success,
+        tx_hashes: request.tx_hashes,
+        comment: request.comment,
+    };
+    // Creating the marker is what claims the session's one feedback, so two
+    // submissions racing each other can't both count
+    let mut marker = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(session_dir.join(FEEDBACK_FILE))
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                (StatusCode::CONFLICT, "Feedback was already given for this session").into_response()
+            }
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        })?;
+    marker
+        .write_all(serde_json::to_string(&record).unwrap_or_default().as_bytes())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+
+    if let Some(chain_id) = origin.chain_id.filter(|_| state.config.templates.enabled) {
+        let key = TemplateKey {
+            intent: &origin.intent,
+            sender: &origin.from_address,
+            requested_by: origin.requested_by,
+            chain_id,
+            slippage_bps: origin.slippage_bps,
+        };
+        state.templates.record(&key, None, record.success);
+    }
+    state.feedback.record(&record);
+
+    Ok(StatusCode::NO_CONTENT)
+}
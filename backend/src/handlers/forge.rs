@@ -1,548 +1,2686 @@
-use crate::models::{ForgeOutput, ForgeRequest, ForgeStep, AppState, FixRequest, SessionData, TransactionDetails};
-use crate::utils::{run_command_with_output, install_dependencies};
+use crate::models::{ActionPlan, AnalysisEngine, TokenDelta, ForgeRequest, ForgeStep, Stage, ErrorCode, AppState, FixRequest, JobOrigin, RiskReport, SessionData, TraceCall, TransactionDetails};
+use crate::utils::{
+    annotate_usd, assign_phases, builtin_analysis, chain_id, check_affordability, check_policy, check_script, check_slippage, find_addresses, fork_args, find_ens_names, is_ens_name, resolve_ens_names, clear_broadcasts, execution_trace, parse_diagnostics, parse_script_output, parse_test_results, read_broadcast, render_trace, run_json_command, script_libraries, transaction_details,
+    add_remappings, install_dependencies, missing_approvals, missing_imports, package_for_import, portfolio_summary, portfolio_value_usd, sandboxed_command, score_risk, pin_solc_version, preflight_rpc, select_solc_version, session_profile, write_session_profile, unmet_intent, CacheKey, CachedResult, CircuitOpen, TemplateKey, save_script_version, save_version_transactions, script_imports, slither_analysis, unified_diff, validate_fix_request, validate_forge_request, ValidationError, parse_event_id, with_prompt_log, AuditEvent, CommandOutcome, ForkSnapshot, PreflightFailure, ListedToken, NetworkAccess, NextEvent, SessionEvents, Severity, METRICS,
+};
+use crate::handlers::{AuthenticatedAddress, RequestId};
 use axum::{
-    extract::{Query, State},
-    response::sse::{Event, Sse},
+    extract::{Extension, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
-use eyre::Result;
+use ethers::types::{Address, U256};
+use ethers::utils::to_checksum;
+use solang_parser::pt::SourceUnit;
+use eyre::{eyre, Result};
 use futures::stream::{self, Stream};
-use std::{convert::Infallible, fs, sync::Arc};
-use tokio::process::Command;
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    fs,
+    future::Future,
+    process::Output,
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{mpsc::Sender, Mutex, MutexGuard, OwnedSemaphorePermit, Semaphore};
+use tracing::{info, info_span, warn, Instrument, Span};
 use uuid::Uuid;
-use tempfile::TempDir;
-use std::path::PathBuf;
-use crate::processors::LLMGenerator;
+use std::path::{Path, PathBuf};
+use crate::processors::{examples_section, ClassificationError, LLMGenerator, LLMImpl};
+use async_openai::types::{ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent};
 use fs_extra::dir::copy;
 
 
 pub async fn fix_forge_process(
     State(state): State<Arc<AppState>>,
-    Query(request): Query<FixRequest>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    let state = state.clone();
+    headers: HeaderMap,
+    Extension(request_id): Extension<RequestId>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Query(mut request): Query<FixRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    validate_fix_request(&mut request, &state.config.validation).map_err(IntoResponse::into_response)?;
 
-    tokio::spawn(async move {
-        let mut generator = state.template_generator.lock().await;
-        
-        // Get temp_dir from state
-        let temp_dirs = state.temp_dirs.lock().await;
-        let temp_dir = match temp_dirs.get(&request.temp_dir) {
-            Some(dir) => dir,
-            None => {
-                tx.send(ForgeStep {
-                    title: "Error".to_string(),
-                    output: "Session directory not found".to_string(),
-                }).await.ok();
+    let auth = auth.map(|Extension(AuthenticatedAddress(address))| address);
+    if let Some((events, from)) = resume_events(&state, &headers, auth).await? {
+        return Ok(create_forge_stream(&state, events, from));
+    }
+    let env = request_env(&headers, &state.config.sandbox.request_env).map_err(IntoResponse::into_response)?;
+
+    // Fix events continue the session's numbering so they're resumable too
+    let events = match state.session_events.lock().await.get(&request.temp_dir) {
+        Some(events) => events.clone(),
+        None => SessionEvents::new(None, None),
+    };
+    let from = events.next_index();
+    let (tx, rx) = tokio::sync::mpsc::channel(state.config.server.event_buffer.max(1));
+    tokio::spawn(events.clone().record(rx, request_id.0));
+
+    let session_dir = PathBuf::from(&request.temp_dir);
+    let job = fix_job(state.clone(), request, auth, env, tx.clone());
+    spawn_job(&state, tx, logging_prompts(state.config.llm.log_prompts, session_dir, job));
+
+    Ok(create_forge_stream(&state, events, from))
+}
+
+/// The recorded events a reconnecting client missed, if it sent `Last-Event-ID`
+async fn resume_events(
+    state: &AppState,
+    headers: &HeaderMap,
+    auth: Option<Address>,
+) -> Result<Option<(Arc<SessionEvents>, usize)>, Response> {
+    let Some(last_event_id) = headers.get("last-event-id").and_then(|value| value.to_str().ok()) else {
+        return Ok(None);
+    };
+    let Some((session, index)) = parse_event_id(last_event_id) else {
+        return Err((StatusCode::BAD_REQUEST, "Malformed Last-Event-ID").into_response());
+    };
+
+    let events = state.session_events.lock().await.get(session).cloned();
+    let Some(events) = events else {
+        return Err((StatusCode::NOT_FOUND, "No recorded events for this session").into_response());
+    };
+    if auth.is_some() && events.owner() != auth {
+        return Err((StatusCode::FORBIDDEN, "Session belongs to a different address").into_response());
+    }
+
+    info!(session, index, "Resuming event stream");
+    Ok(Some((events, index + 1)))
+}
+
+async fn fix_job(
+    state: Arc<AppState>,
+    request: FixRequest,
+    auth: Option<Address>,
+    env: Vec<(String, String)>,
+    tx: Sender<ForgeStep>,
+) {
+    if !llm_available(&state, Stage::Fixing, &tx).await {
+        return;
+    }
+    let origin = job_origin(Path::new(&request.temp_dir));
+    let impersonate = origin.as_ref().is_some_and(|origin| origin.impersonate);
+    let funding = origin.as_ref().and_then(|origin| origin.funding.clone());
+    let time_travel = origin.as_ref().is_some_and(|origin| origin.time_travel);
+    let origin_chain = origin.as_ref().and_then(|origin| origin.chain_id);
+    let variant = origin.and_then(|origin| origin.variant);
+    let mut generator = generator_for(&state, variant.as_deref()).lock().await;
+    
+    // Get the session's path from state; the map stays locked only for the lookup
+    let project_path = state.temp_dirs.lock().await.get(&request.temp_dir).map(|dir| dir.path().to_path_buf());
+    let project_path = match project_path {
+        Some(path) => path,
+        None => {
+            tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::SessionNotFound, "Session directory not found")).await.ok();
+            return;
+        }
+    };
+
+    tx.send(ForgeStep::progress(Stage::Fixing)).await.ok();
+
+    // List all files in temp directory
+    tx.send(ForgeStep::status(Stage::Fixing, format!("Listing files in temp dir: {:?}", 
+        std::fs::read_dir(&project_path)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .collect::<Vec<_>>()
+    ))).await.ok();
+
+    let session_file = project_path.join("session.json");
+
+    // Check if session file exists and read it
+    let mut session_data = match fs::read_to_string(&session_file) {
+        Ok(content) => match serde_json::from_str::<SessionData>(&content) {
+            Ok(data) => data,
+            Err(e) => {
+                tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::SessionInvalid, format!("Failed to parse session data: {}", e))).await.ok();
                 return;
             }
-        };
+        },
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::SessionNotFound, format!("Failed to read session file: {}", e))).await.ok();
+            return;
+        }
+    };
 
-        // List all files in temp directory
-        tx.send(ForgeStep {
-            title: "Fixing".to_string(),
-            output: format!("Listing files in temp dir: {:?}", 
-                std::fs::read_dir(temp_dir.path())
-                    .unwrap()
-                    .filter_map(|e| e.ok())
-                    .map(|e| e.path())
-                    .collect::<Vec<_>>()
-            ),
-        }).await.ok();
-
-        let session_file = temp_dir.path().join("session.json");
-
-        // Check if session file exists and read it
-        let mut session_data = match fs::read_to_string(&session_file) {
-            Ok(content) => match serde_json::from_str::<SessionData>(&content) {
-                Ok(data) => data,
-                Err(e) => {
-                    tx.send(ForgeStep {
-                        title: "Error".to_string(),
-                        output: format!("Failed to parse session data: {}", e),
-                    }).await.ok();
-                    return;
-                }
-            },
-            Err(e) => {
-                tx.send(ForgeStep {
-                    title: "Error".to_string(),
-                    output: format!("Failed to read session file: {}", e),
-                }).await.ok();
+    // With SIWE enabled only the wallet that created the session may fix it
+    if let Some(address) = auth {
+        if session_data.owner() != Some(address) {
+            tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::Forbidden, "Session belongs to a different address")).await.ok();
+            return;
+        }
+    }
+
+    // Variables sent with the fix replace the ones the session was started with
+    if !env.is_empty() {
+        state.session_env.set(&project_path, env);
+    }
+    // The fix is simulated on the chain the session was generated for, on one of
+    // its endpoints unless the request names an RPC
+    let rpc_url = match (request.rpc_url, origin_chain) {
+        (Some(rpc_url), _) => rpc_url,
+        (None, Some(chain_id)) => match state.rpc_pool.select(chain_id).await {
+            Some(rpc_url) => rpc_url,
+            None => {
+                let message = format!("No RPC endpoint of chain {} is answering; pass rpc_url", chain_id);
+                tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::RpcUnreachable, message)).await.ok();
                 return;
             }
+        },
+        (None, None) => "http://localhost:8545".to_string(),
+    };
+    if !preflight(&state, &rpc_url, origin_chain, Stage::Fixing, &tx).await {
+        return;
+    }
+    let script_path = project_path.join("script").join("Script.s.sol");
+
+    // Create script directory if it doesn't exist
+    if let Err(e) = std::fs::create_dir_all(script_path.parent().unwrap()) {
+        tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::Internal, format!("Failed to create script directory: {}", e)))
+        .await
+        .ok();
+        return;
+    }
+
+    // A missing package is an environment problem, not a code problem: install
+    // it and retry before spending an LLM round trip
+    let missing = missing_imports(&request.error);
+    if !missing.is_empty() && has_missing_packages(&state, &project_path, &missing) {
+        if !install_packages(&state, &project_path, &missing, &tx).await {
+            return;
+        }
+
+        tx.send(ForgeStep::status(Stage::Fixing, "Installed missing dependencies, retrying simulation\n"))
+        .await
+        .ok();
+
+        // Simulation explains the transactions with the LLM
+        drop(generator);
+        let simulation = Simulation {
+            sender: session_data.from_address.as_deref(),
+            impersonate,
+            funding: funding.as_deref(),
+            time_travel,
         };
+        let succeeded = simulate_script(&state, &project_path, &rpc_url, simulation, &tx).await;
+        METRICS
+            .fix_iterations
+            .with_label_values(&[if succeeded { "success" } else { "failed" }])
+            .inc();
+        return;
+    }
 
-        let project_path = temp_dir.path().to_path_buf();
-        let script_path = project_path.join("script").join("Script.s.sol");
+    if !apply_fix(&state, &mut generator, &project_path, &mut session_data, &request.error, &tx).await {
+        return;
+    }
+    drop(generator);
+    if !compile_first(&state, &project_path, &tx).await {
+        return;
+    }
 
-        // Create script directory if it doesn't exist
-        if let Err(e) = std::fs::create_dir_all(script_path.parent().unwrap()) {
-            tx.send(ForgeStep {
-                title: "Error".to_string(),
-                output: format!("Failed to create script directory: {}", e),
-            })
+    let simulation = Simulation {
+        sender: session_data.from_address.as_deref(),
+        impersonate,
+        funding: funding.as_deref(),
+        time_travel,
+    };
+    let succeeded = simulate_script(&state, &project_path, &rpc_url, simulation, &tx).await;
+
+    METRICS
+        .fix_iterations
+        .with_label_values(&[if succeeded { "success" } else { "failed" }])
+        .inc();
+}
+
+/// Have the LLM fix the session's script for `error`, then write it, report the
+/// diff, save the conversation and run the pre-compile checks. Returns false once
+/// an error has been reported.
+async fn apply_fix(
+    state: &AppState,
+    generator: &mut LLMImpl,
+    project_path: &Path,
+    session_data: &mut SessionData,
+    error: &str,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let script_path = project_path.join("script").join("Script.s.sol");
+    let session_file = project_path.join("session.json");
+    if let Some(origin) = job_origin(project_path) {
+        state.stats.fix_iteration(&origin);
+    }
+
+    // The fix is reported as a diff against this once it's complete, rather
+    // than streaming the whole regenerated file
+    let previous_code = fs::read_to_string(&script_path).unwrap_or_default();
+
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let result = match tokio::time::timeout(
+        llm_timeout,
+        generator.fix_forge_code(
+            project_path.to_path_buf(),
+            error,
+            &mut session_data.messages,
+            without_token_deltas(tx),
+        ),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => {
+            send_timeout(tx, Stage::Fixing, ErrorCode::LlmTimeout, "LLM generation", llm_timeout).await;
+            return false;
+        }
+    };
+
+    let fixed_code = match result {
+        Ok(fixed_code) => fixed_code,
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Fixing, llm_error_code(&e), e.to_string()))
             .await
             .ok();
-            return;
+            return false;
+        }
+    };
+    let Some(code) = solidity_block(&fixed_code) else {
+        tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::InvalidScript, "No Solidity code block found"))
+        .await
+        .ok();
+        return false;
+    };
+
+    if let Err(e) = fs::write(&script_path, code.trim()) {
+        tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::Internal, e.to_string()))
+        .await
+        .ok();
+        return false;
+    }
+    let version = save_script_version(project_path, code.trim())
+        .map_err(|e| warn!("Failed to store script version: {}", e))
+        .ok();
+    audit(state, project_path, AuditEvent::Script { version, script: code.trim() });
+
+    let diff = unified_diff("script/Script.s.sol", &previous_code, code.trim(), 3);
+    tx.send(ForgeStep::ScriptDiff { diff }).await.ok();
+
+    // update the messages to the session file
+    if let Err(e) = fs::write(&session_file, serde_json::to_string(&session_data).unwrap()) {
+        tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::Internal, e.to_string()))
+        .await
+        .ok();
+        return false;
+    }
+
+    // Catch syntax and structure errors without a forge compile cycle
+    let unit = match check_script(code) {
+        Ok(unit) => unit,
+        Err(diagnostic) => {
+            tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::InvalidScript, diagnostic))
+            .await
+            .ok();
+            return false;
         }
+    };
 
-        match generator
-            .fix_forge_code(
-                temp_dir.path().to_path_buf(),
-                &request.error,
-                &mut session_data.messages,
-                tx.clone(),
-            )
+    // Addresses from the original prompt (guidelines) and the user are known recipients
+    let prompts = session_data
+        .messages
+        .iter()
+        .filter_map(|message| match &message.content {
+            ChatCompletionRequestUserMessageContent::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if !enforce_policy(state, project_path, code, &prompts, session_data.from_address.as_deref(), tx).await {
+        return false;
+    }
+
+    install_imported_packages(state, project_path, &unit, tx).await
+}
+
+/// Compile the script with `forge build` before simulating it, feeding compiler
+/// errors straight back to the LLM up to `build.max_compile_fixes` times, since
+/// most failures are compile errors and a build is much quicker than `forge script`.
+/// Returns false once an error has been reported.
+async fn compile_first(state: &AppState, project_path: &Path, tx: &Sender<ForgeStep>) -> bool {
+    let config = &state.config.build;
+    if !config.compile_first {
+        return true;
+    }
+
+    for attempt in 0..=config.max_compile_fixes {
+        tx.send(ForgeStep::status(Stage::Writing, "Compiling script...\n")).await.ok();
+        let errors = match build_script(state, project_path, tx).await {
+            Ok(None) => return true,
+            Ok(Some(errors)) => errors,
+            Err(()) => return false,
+        };
+        if attempt == config.max_compile_fixes {
+            tx.send(ForgeStep::error(
+                Stage::Writing,
+                ErrorCode::CompileFailed,
+                format!("Forge build failed:\n{}", errors),
+            ))
             .await
-        {
-            Ok(fixed_code) => {
-                if let Some(code) = fixed_code
-                    .split("```")
-                    .nth(1)
-                    .and_then(|s| s.strip_prefix("solidity\n").or(Some(s)))
-                {
-
-                    fs::write(&script_path, code.trim()).unwrap();
-
-                    // update the messages to the session file
-                    if let Err(e) = fs::write(&session_file, serde_json::to_string(&session_data).unwrap()) {
-                        tx.send(ForgeStep {
-                            title: "Error".to_string(),
-                            output: e.to_string(),
-                        })
-                        .await
-                        .ok();
-                        return;
-                    }
-
-                    let rpc_url = request
-                        .rpc_url
-                        .unwrap_or_else(|| "http://localhost:8545".to_string());
-                    match Command::new("forge")
-                        .args(&[
-                            "script",
-                            "script/Script.s.sol",
-                            "--fork-url",
-                            &rpc_url,
-                            "-vvvv",
-                        ])
-                        .current_dir(&project_path)
-                        .output()
-                        .await
-                    {
-                        Ok(output) => {
-                            // Log both stdout and stderr for debugging
-                            let stdout = String::from_utf8_lossy(&output.stdout);
-                            let stderr = String::from_utf8_lossy(&output.stderr);
-                            
-                            tx.send(ForgeStep {
-                                title: "Simulating Transactions".to_string(),
-                                output: format!("STDOUT:\n{}\n\nSTDERR:\n{}", stdout, stderr),
-                            })
-                            .await
-                            .ok();
-
-                            // Parse successful output
-                            if output.status.success() {
-                                let json_path = project_path
-                                    .join("broadcast")
-                                    .join("Script.s.sol")
-                                    .join("1")
-                                    .join("dry-run")
-                                    .join("run-latest.json");
-
-                                if json_path.exists() {
-                                    if let Ok(json_content) = fs::read_to_string(json_path) {
-                                        if let Ok(forge_output) =
-                                            serde_json::from_str::<ForgeOutput>(&json_content)
-                                        {
-                                            let transactions: Vec<TransactionDetails> =
-                                                forge_output
-                                                    .transactions
-                                                    .into_iter()
-                                                    .map(|tx| TransactionDetails {
-                                                        to: tx.contractAddress,
-                                                        function: tx.function,
-                                                        arguments: tx.arguments,
-                                                        value: tx.transaction.value,
-                                                        input_data: tx.transaction.input,
-                                                    })
-                                                    .collect();
-
-                                            tx.send(ForgeStep {
-                                                title: "Simulating Transactions".to_string(),
-                                                output: serde_json::to_string(&transactions)
-                                                    .unwrap(),
-                                            })
-                                            .await
-                                            .ok();
-                                        } else {
-                                            tx.send(ForgeStep {
-                                                title: "Error".to_string(),
-                                                output: "Failed to parse Forge output".to_string(),
-                                            })
-                                            .await
-                                            .ok();
-                                        }
-                                    } else {
-                                        tx.send(ForgeStep {
-                                            title: "Error".to_string(),
-                                            output: "Failed to read Forge output".to_string(),
-                                        })
-                                        .await
-                                        .ok();
-                                    }
-                                }
-                            } else {
-                                tx.send(ForgeStep {
-                                    title: "Error".to_string(),
-                                    output: format!("Forge script failed:\nSTDOUT:\n{}\n\nSTDERR:\n{}", stdout, stderr),
-                                })
-                                .await
-                                .ok();
-                            }
-                        }
-                        Err(e) => {
-                            tx.send(ForgeStep {
-                                title: "Error".to_string(),
-                                output: e.to_string(),
-                            })
-                            .await
-                            .ok();
-                        }
-                    };
-                }
-            }
-            Err(e) => {
-                tx.send(ForgeStep {
-                    title: "Error".to_string(),
-                    output: e.to_string(),
-                })
-                .await
-                .ok();
-            }
+            .ok();
+            return false;
         }
 
-        // Clean up
-        // if let Err(e) = std::fs::remove_dir_all(&project_path) {
-        //     eprintln!("Failed to clean up fix directory: {}", e);
-        // }
-    });
+        tx.send(ForgeStep::status(
+            Stage::Fixing,
+            format!("Compilation failed, fixing (attempt {}/{})\n", attempt + 1, config.max_compile_fixes),
+        ))
+        .await
+        .ok();
+        let session_file = project_path.join("session.json");
+        let Some(mut session_data) = fs::read_to_string(&session_file)
+            .ok()
+            .and_then(|content| serde_json::from_str::<SessionData>(&content).ok())
+        else {
+            tx.send(ForgeStep::error(Stage::Fixing, ErrorCode::SessionInvalid, "Failed to read session data"))
+            .await
+            .ok();
+            return false;
+        };
+
+        let variant = job_origin(project_path).and_then(|origin| origin.variant);
+        let mut generator = generator_for(state, variant.as_deref()).lock().await;
+        let error = format!("Forge build failed:\n{}", errors);
+        if !apply_fix(state, &mut generator, project_path, &mut session_data, &error, tx).await {
+            return false;
+        }
+    }
+
+    false
+}
+
+/// `forge build` the session's script, streaming its diagnostics. `Ok(Some(errors))`
+/// holds the compiler errors when it doesn't compile; `Err` means a failure was
+/// already reported.
+async fn build_script(state: &AppState, project_path: &Path, tx: &Sender<ForgeStep>) -> Result<Option<String>, ()> {
+    if let Err(conflict) = pin_compiler(project_path, Stage::Writing, tx).await {
+        return Ok(Some(conflict));
+    }
+
+    let build_timeout = Duration::from_secs(state.config.timeouts.forge_build_secs);
+    let timer = METRICS.forge_duration.with_label_values(&["build"]).start_timer();
+    // solc may need downloading, so the build isn't cut off from the network
+    let mut command = forge_command(state, project_path, NetworkAccess::Full);
+    command.args(["build", "script/Script.s.sol", "--json"]);
+    let step = |line| ForgeStep::CompileOutput { stage: Stage::Writing, line };
+    let result = run_json_command(&mut command, tx, step, build_timeout)
+        .instrument(info_span!("forge.build"))
+        .await;
+    timer.observe_duration();
+
+    let output = match result {
+        Ok(CommandOutcome::Completed(output)) => output,
+        Ok(CommandOutcome::TimedOut) => {
+            send_timeout(tx, Stage::Writing, ErrorCode::ForgeTimeout, "forge build", build_timeout).await;
+            return Err(());
+        }
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Writing, ErrorCode::Internal, e.to_string()))
+            .await
+            .ok();
+            return Err(());
+        }
+    };
+
+    let diagnostics = parse_diagnostics(&String::from_utf8_lossy(&output.stdout), project_path).unwrap_or_default();
+    for diagnostic in &diagnostics {
+        tx.send(ForgeStep::Diagnostic { stage: Stage::Writing, diagnostic: diagnostic.clone() })
+        .await
+        .ok();
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == "error")
+        .map(|diagnostic| diagnostic.formatted.trim())
+        .collect::<Vec<_>>();
+
+    if !errors.is_empty() {
+        Ok(Some(errors.join("\n\n")))
+    } else if !output.status.success() {
+        // Failed before solc ran, e.g. an unresolvable import; forge said why on stderr
+        Ok(Some(failure_output(&output)))
+    } else {
+        Ok(None)
+    }
+}
 
-    create_forge_stream(rx)
+/// Pin the solc version that the pragmas of every source forge compiles and
+/// everything they import accept, so forge doesn't pick one per file. `Err` holds the
+/// conflicting pragmas when no version accepts them all.
+async fn pin_compiler(project_path: &Path, stage: Stage, tx: &Sender<ForgeStep>) -> Result<(), String> {
+    let Some(version) = select_solc_version(project_path).await? else {
+        return Ok(());
+    };
+    match pin_solc_version(project_path, &version) {
+        Ok(true) => {
+            tx.send(ForgeStep::status(stage, format!("Compiling with solc {}\n", version))).await.ok();
+        }
+        Ok(false) => {}
+        Err(e) => warn!("Failed to pin solc {}: {}", version, e),
+    }
+    Ok(())
+}
+
+/// What a failed forge command printed about the failure: its stderr, or its
+/// stdout when that's empty
+fn failure_output(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.trim().is_empty() {
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    } else {
+        stderr.trim().to_string()
+    }
 }
 
 pub async fn stream_forge_process(
     State(state): State<Arc<AppState>>,
-    Query(request): Query<ForgeRequest>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let session_id = request.session_id.unwrap_or_else(|| Uuid::new_v4().to_string());
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
+    headers: HeaderMap,
+    Extension(request_id): Extension<RequestId>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Query(mut request): Query<ForgeRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    validate_forge_request(&mut request, &state.config.validation).map_err(IntoResponse::into_response)?;
+    select_rpc(&state, &mut request).await?;
+
+    let auth = auth.map(|Extension(AuthenticatedAddress(address))| address);
+    check_sender(&mut request, auth).await?;
+    request.requested_by = auth;
+    request.request_id = request_id.0.clone();
+    let env = request_env(&headers, &state.config.sandbox.request_env).map_err(IntoResponse::into_response)?;
+    // What a job does can depend on its environment, so one that sets any
+    // neither joins an identical job nor reuses a cached result. Neither does
+    // an impersonated, funded or time-travel preview, whose result isn't one
+    // the user could sign as is.
+    if !env.is_empty() || request.impersonate || request.simulate_with_funding || request.time_travel {
+        request.no_cache = true;
+    }
+
+    // A browser reconnecting after a dropped connection resumes the original job
+    if let Some((events, from)) = resume_events(&state, &headers, auth).await? {
+        return Ok(create_forge_stream(&state, events, from));
+    }
+
+    if let Some((used, quota)) = state.storage.exceeded() {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!(
+                "Session storage is full ({} of {} MB used); try again once older sessions expire or are deleted",
+                used, quota
+            ),
+        )
+            .into_response());
+    }
+
+    // An identical request that's already running shares its job and stream. This
+    // job's events are registered before its directory is checked out, so two
+    // identical requests arriving together can't both start one.
+    let owner = auth.or_else(|| request.from_address.parse().ok());
+    let events = SessionEvents::new(None, owner);
+    let job_key = (!request.no_cache).then(|| running_job_key(&request));
+    if let Some(key) = &job_key {
+        let mut running_jobs = state.running_jobs.lock().await;
+        if let Some(running) = running_jobs.get(key).filter(|running| !running.is_finished()) {
+            info!("Joining an identical running job");
+            METRICS.jobs_joined.inc();
+            return Ok(create_forge_stream(&state, running.clone(), 0));
+        }
+        running_jobs.insert(key.clone(), events.clone());
+    }
+
+    let session_id = request.session_id.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+    let (tx, rx) = tokio::sync::mpsc::channel(state.config.server.event_buffer.max(1));
 
-    // Create and store temp dir
-    let temp_dir = match TempDir::with_prefix(&format!("forge_{}_", session_id)) {
+    // Check out a pre-copied project dir and store it
+    let temp_dir = match state.project_pool.checkout().await {
         Ok(dir) => {
             // Store TempDir in state using its path as key
             let path = dir.path().to_string_lossy().to_string();
+            info!(session_id, path, "Session directory checked out");
             let mut temp_dirs = state.temp_dirs.lock().await;
             temp_dirs.insert(path.clone(), dir);
+            events.set_session(path.clone());
 
             // Send path to client
-            tx.send(ForgeStep {
-                title: "Session".to_string(),
-                output: path.clone(),
-            }).await.ok();
+            tx.send(ForgeStep::SessionCreated { session: path.clone() }).await.ok();
 
             PathBuf::from(path)
         }
         Err(e) => {
-            tx.send(ForgeStep {
-                title: "Error".to_string(),
-                output: format!("Failed to create temp directory: {}", e),
-            }).await.ok();
-            return create_forge_stream(rx);
+            tx.send(ForgeStep::error(Stage::Initializing, ErrorCode::Internal, format!("Failed to create temp directory: {}", e))).await.ok();
+            drop(tx);
+            tokio::spawn(events.clone().record(rx, request_id.0));
+            return Ok(create_forge_stream(&state, events, 0));
         }
     };
 
-    let _permit = state.process_limiter.acquire().await.unwrap();
-    let state = state.clone(); // Clone the Arc here
+    if !env.is_empty() {
+        state.session_env.set(&temp_dir, env);
+    }
+    let session = temp_dir.to_string_lossy().to_string();
+    state.session_events.lock().await.insert(session, events.clone());
+    tokio::spawn(events.clone().record(rx, request_id.0));
 
-    tokio::spawn(async move {
-        // Create session-specific temp dir
+    let job_state = state.clone();
+    let job_events = events.clone();
+    spawn_job(&state, tx.clone(), async move {
+        let job = forge_job(job_state.clone(), request, temp_dir.clone(), tx);
+        logging_prompts(job_state.config.llm.log_prompts, temp_dir.clone(), job).await;
+        if let Some(key) = job_key {
+            let mut running_jobs = job_state.running_jobs.lock().await;
+            if running_jobs.get(&key).is_some_and(|events| Arc::ptr_eq(events, &job_events)) {
+                running_jobs.remove(&key);
+            }
+        }
+        // Without a session file the session can't be fixed, so hand the dir back
+        if !temp_dir.join("session.json").exists() {
+            release_session(&job_state, &temp_dir).await;
+        }
+    });
+
+    Ok(create_forge_stream(&state, events, 0))
+}
+
+/// What makes two forge requests the same job
+fn running_job_key(request: &ForgeRequest) -> String {
+    serde_json::json!([
+        request.intent.trim(),
+        request.from_address.to_ascii_lowercase(),
+        request.requested_by,
+        request.rpc_url,
+        request.chain_id,
+        request.slippage_bps,
+        request.parallel,
+        request.compiler_settings(),
+    ])
+    .to_string()
+}
+
+/// ENS names and token symbols an intent mentions, resolved on the fork
+pub(crate) struct Mentions<'a> {
+    pub names: Vec<(String, Address)>,
+    /// The fork's chain, `None` when it couldn't be read
+    pub chain_id: Option<u64>,
+    pub tokens: Vec<&'a ListedToken>,
+}
+
+/// Resolve the names an intent mentions. Fails only when an ENS name doesn't resolve.
+pub(crate) async fn resolve_mentions<'a>(state: &'a AppState, rpc_url: &str, intent: &str) -> Result<Mentions<'a>> {
+    let names = resolve_ens_names(rpc_url, &find_ens_names(intent)).await?;
+
+    // Token lookups are per chain; a fork that can't report its chain fails
+    // loudly at simulation, so they're skipped here
+    let chain_id = chain_id(rpc_url)
+        .await
+        .inspect_err(|e| warn!("Failed to read the fork's chain id: {}", e))
+        .ok();
+    let tokens = chain_id
+        .map(|chain_id| state.token_registry.resolve(intent, chain_id))
+        .unwrap_or_default();
+
+    Ok(Mentions { names, chain_id, tokens })
+}
+
+/// Give a request that names only its chain the fastest of the chain's RPC
+/// endpoints that's up
+pub(crate) async fn select_rpc(state: &AppState, request: &mut ForgeRequest) -> Result<(), Response> {
+    let Some(chain_id) = request.chain_id.filter(|_| request.rpc_url.is_none()) else {
+        return Ok(());
+    };
+    let chains = state.rpc_pool.chains();
+    if !chains.contains(&chain_id) {
+        let chains = chains.iter().map(u64::to_string).collect::<Vec<_>>().join(", ");
+        let message = format!("No RPC is known for chain {}; pass rpc_url or use one of {}", chain_id, chains);
+        return Err(ValidationError::new("chain_id", "UNKNOWN_CHAIN", message).into_response());
+    }
+    match state.rpc_pool.select(chain_id).await {
+        Some(rpc_url) => {
+            request.rpc_url = Some(rpc_url);
+            Ok(())
+        }
+        None => Err((StatusCode::BAD_GATEWAY, format!("None of chain {}'s RPC endpoints is answering", chain_id)).into_response()),
+    }
+}
+
+/// Resolve an ENS sender on the fork the request simulates against, then make
+/// sure the sender is the wallet that signed in
+pub(crate) async fn check_sender(request: &mut ForgeRequest, auth: Option<Address>) -> Result<(), Response> {
+    if is_ens_name(&request.from_address) {
+        let rpc_url = request
+            .rpc_url
+            .clone()
+            .unwrap_or_else(|| "http://localhost:8545".to_string());
+        let name = request.from_address.to_ascii_lowercase();
+        match resolve_ens_names(&rpc_url, &[name]).await {
+            Ok(resolved) => request.from_address = to_checksum(&resolved[0].1, None),
+            Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string()).into_response()),
+        }
+    }
+
+    // Transactions are only generated for the wallet that signed in, unless
+    // they're a preview run as someone else
+    if let Some(address) = auth.filter(|_| !request.impersonate) {
+        if request.from_address.parse::<Address>().ok() != Some(address) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("from_address does not match the signed-in address {:?}", address),
+            )
+                .into_response());
+        }
+    }
+
+    Ok(())
+}
 
-        // Use temp_dir.path() for all file operations
-        let project_path = temp_dir.clone();
+/// Header carrying `NAME=value` for a variable the session's forge runs get
+const FORGE_ENV_HEADER: &str = "x-forge-env";
 
-        tx.send(ForgeStep {
-            title: "Initializing Forge".to_string(),
-            output: temp_dir.as_path().to_string_lossy().to_string(),
+/// The variables a request sets for its session's forge runs, limited to the
+/// names `sandbox.request_env` lists. Values are never echoed back.
+fn request_env(headers: &HeaderMap, allowed: &[String]) -> Result<Vec<(String, String)>, (StatusCode, String)> {
+    headers
+        .get_all(FORGE_ENV_HEADER)
+        .iter()
+        .map(|header| {
+            let (name, value) = header
+                .to_str()
+                .ok()
+                .and_then(|header| header.split_once('='))
+                .ok_or_else(|| (StatusCode::BAD_REQUEST, format!("{} must be NAME=value", FORGE_ENV_HEADER)))?;
+            let name = name.trim();
+            if !allowed.iter().any(|allowed| allowed == name) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Environment variable {} can't be set by requests", name),
+                ));
+            }
+            Ok((name.to_string(), value.to_string()))
         })
+        .collect()
+}
+
+/// Where a session keeps its invariant test between runs, outside forge's source dirs
+const INVARIANTS_DIR: &str = "invariants";
+const INVARIANT_TEST_FILE: &str = "Invariants.t.sol";
+/// The intent's action plan, kept with the session to verify simulations against
+const PLAN_FILE: &str = "plan.json";
+/// How the session's job was set up, for attributing feedback
+pub(crate) const ORIGIN_FILE: &str = "origin.json";
+
+/// How long a released session's events stay available to reconnecting clients
+const RESUME_GRACE: Duration = Duration::from_secs(300);
+
+/// Forget a session and return its directory to the project pool
+async fn release_session(state: &Arc<AppState>, path: &Path) {
+    state.result_cache.forget(path);
+    state.session_env.remove(path);
+    let session = path.to_string_lossy().to_string();
+    let dir = state.temp_dirs.lock().await.remove(&session);
+    if let Some(dir) = dir {
+        state.project_pool.recycle(dir).await;
+    }
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(RESUME_GRACE).await;
+        state.session_events.lock().await.remove(&session);
+    });
+}
+
+async fn forge_job(state: Arc<AppState>, mut request: ForgeRequest, temp_dir: PathBuf, tx: Sender<ForgeStep>) {
+    // Use temp_dir.path() for all file operations
+    let project_path = temp_dir.clone();
+
+    tx.send(ForgeStep::progress(Stage::Initializing)).await.ok();
+    tx.send(ForgeStep::status(Stage::Initializing, temp_dir.as_path().to_string_lossy().to_string()))
+    .await
+    .ok();
+    if !llm_available(&state, Stage::Initializing, &tx).await {
+        return;
+    }
+
+    if let Err(e) = write_session_profile(&project_path, &request.compiler_settings()) {
+        tx.send(ForgeStep::error(Stage::Initializing, ErrorCode::Internal, format!("Failed to apply compiler settings: {}", e)))
         .await
         .ok();
+        return;
+    }
 
-        // Instead of forge init, copy the base project contents
-        let options = fs_extra::dir::CopyOptions::new()
-            .content_only(true);  // This makes it copy only the contents
+    let rpc_url = request
+        .rpc_url
+        .clone()
+        .unwrap_or_else(|| "http://localhost:8545".to_string());
+    if !preflight(&state, &rpc_url, request.chain_id, Stage::Initializing, &tx).await {
+        return;
+    }
 
-        if let Err(e) = fs_extra::dir::copy(&state.base_forge_dir, &temp_dir, &options) {
-            tx.send(ForgeStep {
-                title: "Error".to_string(),
-                output: e.to_string(),
-            })
+    // Give the model the addresses behind ENS names and token symbols so it never has to guess them
+    let mentions = match resolve_mentions(&state, &rpc_url, &request.intent).await {
+        Ok(mentions) => mentions,
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Initializing, ErrorCode::EnsResolutionFailed, e.to_string()))
             .await
             .ok();
             return;
         }
+    };
+    let chain_id = mentions.chain_id;
+    // The intent is rewritten below, so the simulation keeps its own copy of the sender
+    let sender = request.from_address.clone();
+    let funding_tokens = mentions.tokens.iter().map(|token| token.address).collect::<Vec<_>>();
+    let simulation = Simulation {
+        sender: Some(&sender),
+        impersonate: request.impersonate,
+        funding: request.simulate_with_funding.then_some(&funding_tokens[..]),
+        time_travel: request.time_travel,
+    };
+    let variant = state
+        .experiment
+        .as_ref()
+        .map(|experiment| experiment.assign(&project_path.to_string_lossy()));
+    let models = match variant {
+        Some(variant) => [variant.models[0].as_str(), variant.models[1].as_str()],
+        None => [state.config.llm.code_model.as_str(), state.config.llm.chat_model.as_str()],
+    };
 
-        let mut messages = vec![];
+    // An identical recent request's result is reused instead of generating it again.
+    // Parallel jobs span several sessions and aren't cached, and impersonated,
+    // funded or time-travel previews aren't results the user could sign as is.
+    if let Some(chain_id) = chain_id.filter(|_| state.config.cache.enabled && !request.parallel && !simulation.is_synthetic()) {
+        let guidelines_version = state.protocol_processor.version();
+        let compiler = request.compiler_settings();
+        let key = CacheKey {
+            intent: &request.intent,
+            from_address: &request.from_address,
+            chain_id,
+            slippage_bps: request.slippage_bps,
+            guidelines_version: &guidelines_version,
+            models,
+            variant: variant.map(|variant| variant.name.as_str()),
+            compiler: &compiler,
+        };
+        if let Some((age, cached)) = state.result_cache.get(&key).filter(|_| !request.no_cache) {
+            if send_cached_result(&project_path, age, cached, &tx).await {
+                return;
+            }
+        }
+        state.result_cache.expect(&project_path, &key);
+    }
 
-        let mut generator = state.template_generator.lock().await;
+    // A template that keeps working for intents shaped like this one scripts it
+    // without the LLM. Its script goes through the same checks as a generated one.
+    let submitted_intent = request.intent.clone();
+    let submitted_slippage = request.slippage_bps;
+    let template_key = chain_id.map(|chain_id| TemplateKey {
+        intent: &submitted_intent,
+        sender: &sender,
+        requested_by: request.requested_by,
+        chain_id,
+        slippage_bps: submitted_slippage,
+    });
+    let template = template_key
+        .as_ref()
+        .filter(|_| state.config.templates.enabled && !request.parallel)
+        .and_then(|key| state.templates.instantiate(key));
+    if template.is_some() {
+        tx.send(ForgeStep::status(Stage::Generating, "Scripting from a template that worked for intents like this one\n"))
+        .await
+        .ok();
+    }
+    // Template runs don't count towards an experiment's variants
+    let variant = variant.filter(|_| template.is_none());
 
-        let guidelines = state.protocol_processor.get_guideline(&*generator, &request.intent).await.unwrap();
+    if !mentions.names.is_empty() {
+        let lines = mentions
+            .names
+            .iter()
+            .map(|(name, address)| format!("- {} = {}", name, to_checksum(address, None)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        tx.send(ForgeStep::status(Stage::Initializing, format!("Resolved ENS names:\n{}\n", lines)))
+        .await
+        .ok();
+        request.intent = format!("{}\n\nResolved ENS names:\n{}", request.intent, lines);
+    }
+    if !mentions.tokens.is_empty() {
+        let lines = mentions
+            .tokens
+            .iter()
+            .map(|token| format!("- {} = {} ({} decimals)", token.symbol, to_checksum(&token.address, None), token.decimals))
+            .collect::<Vec<_>>()
+            .join("\n");
+        tx.send(ForgeStep::status(Stage::Initializing, format!("Resolved tokens:\n{}\n", lines)))
+        .await
+        .ok();
+        request.intent = format!("{}\n\nToken addresses on chain {}:\n{}", request.intent, chain_id.unwrap_or_default(), lines);
+    }
+    let resolved = mentions
+        .names
+        .into_iter()
+        .chain(mentions.tokens.iter().map(|token| (token.symbol.clone(), token.address)))
+        .collect::<Vec<_>>();
 
+    // Ground amounts like "half my USDC" in what the sender actually holds. Kept out
+    // of protocol classification and decomposition, which only need the intent.
+    let portfolio = match chain_id.filter(|_| template.is_none()) {
+        Some(chain_id) => {
+            let config = &state.config;
+            let registry = &state.token_registry;
+            portfolio_summary(&request.from_address, chain_id, &config.portfolio, &config.prices, registry, &state.token_cache)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to fetch the sender's portfolio: {}", e);
+                    None
+                })
+        }
+        None => None,
+    };
 
-        // read remappings.txt
-        let remappings = fs::read_to_string(temp_dir.as_path().join("remappings.txt")).unwrap();
+    let mut generator = generator_for(&state, variant.map(|variant| variant.name.as_str())).lock().await;
 
-        // Generate code
-        match generator
-            .generate_forge_code(
-                &request.from_address,
-                &request.intent,
-                &guidelines,
-                &remappings,
-                &mut messages,  
-                tx.clone(), // Pass the sender to allow progress updates
-            )
-            .await
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let protocols = if let Some((_, protocols)) = &template {
+        // A template's protocols whose guidelines have since been removed are left out
+        let available = state.protocol_processor.available_protocols();
+        protocols.iter().filter(|protocol| available.contains(protocol)).cloned().collect()
+    } else {
+        match tokio::time::timeout(
+            llm_timeout,
+            state.protocol_processor.detect_protocols(&*generator, &request.intent),
+        )
+        .await
         {
-            Ok(forge_code) => {
-                                // Send update before parsing install commands
-                tx.send(ForgeStep {
-                    title: "Generating Code".to_string(),
-                    output: "Saving session...".to_string() + "\n",
-                })
+            Ok(Ok(protocols)) => protocols,
+            // Generation can still go ahead without guidelines
+            Ok(Err(e)) if matches!(e.downcast_ref(), Some(ClassificationError::NoMatchingGuidelines(_))) => {
+                tx.send(ForgeStep::Warning { message: format!("{}; generating without protocol guidelines", e) })
                 .await
                 .ok();
-
-                // update the messages to the session file
-                let session_file = temp_dir.join("session.json");
-                let session_data = SessionData {
-                    messages: messages,
-                };
-                if let Err(e) = fs::write(&session_file, serde_json::to_string(&session_data).unwrap()) {
-                    tx.send(ForgeStep {
-                        title: "Error".to_string(),
-                        output: e.to_string(),
-                    })
-                    .await
-                    .ok();
-                    return;
-                }
-
-                // Extract and write Solidity code
-                let code = match forge_code
-                    .split("```")
-                    .nth(1)
-                    .and_then(|s| s.strip_prefix("solidity\n").or(Some(s)))
-                    .ok_or_else(|| eyre::eyre!("No Solidity code block found"))
-                {
-                    Ok(code) => code.to_string(),
-                    Err(e) => {
-                        tx.send(ForgeStep {
-                            title: "Error".to_string(),
-                            output: e.to_string(),
-                        })
-                        .await
-                        .ok();
-                        return;
-                    }
-                };
-
-                tx.send(ForgeStep {
-                    title: "Writing Code".to_string(),
-                    output: "Writing code...".to_string() + "\n",
-                })
+                Vec::new()
+            }
+            Ok(Err(e)) => {
+                tx.send(ForgeStep::error(Stage::Initializing, llm_error_code(&e), format!("Failed to select protocol guidelines: {}", e)))
                 .await
                 .ok();
+                return;
+            }
+            Err(_) => {
+                send_timeout(&tx, Stage::Initializing, ErrorCode::LlmTimeout, "Protocol classification", llm_timeout).await;
+                return;
+            }
+        }
+    };
 
-                // List files in temp directory
-                let files = match fs::read_dir(temp_dir.as_path()) {
-                    Ok(entries) => {
-                        let paths: Vec<_> = entries
-                            .filter_map(|e| e.ok())
-                            .map(|e| e.path())
-                            .collect();
-                        format!("Files in directory:\n{:#?}", paths)
-                    },
-                    Err(e) => format!("Error reading directory: {}", e)
-                };
+    // Pin the intent down to a structured plan for generation to follow
+    tx.send(ForgeStep::progress(Stage::Planning)).await.ok();
+    let plan = match tokio::time::timeout(llm_timeout, generator.parse_intent(&request.intent)).await {
+        Ok(Ok(plan)) => {
+            tx.send(ForgeStep::Plan { plan: plan.clone() }).await.ok();
+            Some(plan)
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to parse an action plan, generating from the intent alone: {}", e);
+            None
+        }
+        Err(_) => {
+            send_timeout(&tx, Stage::Planning, ErrorCode::LlmTimeout, "Intent parsing", llm_timeout).await;
+            return;
+        }
+    };
 
-                tx.send(ForgeStep {
-                    title: "Directory Contents".to_string(), 
-                    output: files,
-                })
-                .await
-                .ok();
+    // An explicit tolerance wins over one the plan read from the intent
+    request.slippage_bps = request.slippage_bps.or_else(|| {
+        plan.as_ref()
+            .and_then(|plan| plan.actions.iter().filter_map(|action| action.slippage_bps).max())
+    });
+    if let Some(plan) = &plan {
+        let mut plan = plan.clone();
+        for action in &mut plan.actions {
+            action.slippage_bps = action.slippage_bps.or(request.slippage_bps);
+        }
+        if let Err(e) = fs::write(project_path.join(PLAN_FILE), serde_json::to_string(&plan).unwrap_or_default()) {
+            warn!("Failed to store action plan: {}", e);
+        }
+    }
+    register_job(
+        &state,
+        &project_path,
+        &JobOrigin {
+            intent: submitted_intent.clone(),
+            from_address: request.from_address.clone(),
+            chain_id,
+            slippage_bps: submitted_slippage,
+            protocols: protocols.clone(),
+            from_template: template.is_some(),
+            variant: variant.map(|variant| variant.name.clone()),
+            impersonate: request.impersonate,
+            funding: simulation.funding.map(<[Address]>::to_vec),
+            time_travel: request.time_travel,
+            requested_by: request.requested_by,
+        },
+    );
 
-                // Write and compile code
-                let script_path = temp_dir.as_path().join("script").join("Script.s.sol");
-                if let Err(e) = fs::write(&script_path, &code.trim()) {
-                    tx.send(ForgeStep {
-                        title: "Error".to_string(),
-                        output: e.to_string(),
-                    })
-                    .await
-                    .ok();
-                    return;
-                }
+    let planned = plan.as_ref().map(|plan| plan.actions.as_slice()).unwrap_or_default();
+    let actions = planned.iter().map(|action| action.action).collect::<Vec<_>>();
+    let mut guidelines = state.protocol_processor.guidelines_for(&protocols, &request.intent, planned, &state.config.guidelines);
+    let examples = state.examples.select(&protocols, &actions, state.config.few_shot.max_examples);
+    if !examples.is_empty() && template.is_none() {
+        let names = examples.iter().map(|example| example.name.as_str()).collect::<Vec<_>>();
+        info!(examples = ?names, "Including example scripts");
+        guidelines.push_str(&examples_section(&examples));
+    }
 
-                tx.send(ForgeStep {
-                    title: "Simulating Transactions".to_string(),
-                    output: "Compiling script...".to_string() + "\n",
-                })
-                .await
-                .ok();
+    let policy = &state.config.policy;
+    let deadlines = (policy.enabled && policy.forbid_bad_deadlines).then(|| {
+        format!(
+            "Any function taking a deadline must be given block.timestamp + {} (seconds); never a fixed timestamp or type(uint256).max.",
+            policy.deadline_buffer_secs
+        )
+    });
+    let prompt = variant.and_then(|variant| variant.prompt.clone());
+    let time_travel = request.time_travel.then(time_travel_instructions);
+    let context = [portfolio, request.slippage_bps.map(slippage_instructions), deadlines, time_travel, prompt]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n\n");
 
-                let rpc_url = request
-                    .rpc_url
-                    .unwrap_or_else(|| "http://localhost:8545".to_string());
-
-                // Initial simulation
-                match Command::new("forge")
-                    .args(&[
-                        "script",
-                        "script/Script.s.sol",
-                        "--fork-url",
-                        &rpc_url,
-                        "-vvvv",
-                    ])
-                    .current_dir(&project_path)
-                    .output()
-                    .await
-                {
-                    Ok(output) => {
-                        // Log both stdout and stderr for debugging
-                        let stdout = String::from_utf8_lossy(&output.stdout);
-                        let stderr = String::from_utf8_lossy(&output.stderr);
-                        
-                        tx.send(ForgeStep {
-                            title: "Simulating Transactions".to_string(),
-                            output: format!("STDOUT:\n{}\n\nSTDERR:\n{}", stdout, stderr),
-                        })
-                        .await
-                        .ok();
-
-                        // Parse successful output
-                        if output.status.success() {
-                            let json_path = project_path
-                                .join("broadcast")
-                                .join("Script.s.sol")
-                                .join("1")
-                                .join("dry-run")
-                                .join("run-latest.json");
-
-                            if json_path.exists() {
-                                if let Ok(json_content) = fs::read_to_string(json_path) {
-                                    if let Ok(forge_output) =
-                                        serde_json::from_str::<ForgeOutput>(&json_content)
-                                    {
-                                        let transactions: Vec<TransactionDetails> = forge_output
-                                            .transactions
-                                            .into_iter()
-                                            .map(|tx| TransactionDetails {
-                                                to: tx.contractAddress,
-                                                function: tx.function,
-                                                arguments: tx.arguments,
-                                                value: tx.transaction.value,
-                                                input_data: tx.transaction.input,
-                                            })
-                                            .collect();
-
-                                        tx.send(ForgeStep {
-                                            title: "Simulating Transactions".to_string(),
-                                            output: serde_json::to_string(&transactions).unwrap(),
-                                        })
-                                        .await
-                                        .ok();
-                                    } else {
-                                        tx.send(ForgeStep {
-                                            title: "Error".to_string(),
-                                            output: "Failed to parse Forge output".to_string(),
-                                        })
-                                        .await
-                                        .ok();
-                                        return;
-                                    }
-                                } else {
-                                    tx.send(ForgeStep {
-                                        title: "Error".to_string(),
-                                        output: "Failed to read Forge output".to_string(),
-                                    })
-                                    .await
-                                    .ok();
-                                    return;
-                                }
-                            }
-                        } else {
-                            tx.send(ForgeStep {
-                                title: "Error".to_string(),
-                                output: format!("Forge script failed:\nSTDOUT:\n{}\n\nSTDERR:\n{}", stdout, stderr),
-                            })
-                            .await
-                            .ok();
-                        }
-                    }
-                    Err(e) => {
-                        tx.send(ForgeStep {
-                            title: "Error".to_string(),
-                            output: e.to_string(),
-                        })
-                        .await
-                        .ok();
-                        return;
-                    }
-                };
+    // Parts of a parallel request are generated from their own text; the plan covers the whole intent
+    if request.parallel {
+        let parts = match tokio::time::timeout(llm_timeout, generator.decompose_intent(&request.intent)).await {
+            Ok(Ok(parts)) => parts,
+            Ok(Err(e)) => {
+                warn!("Failed to decompose intent, generating a single script: {}", e);
+                vec![request.intent.clone()]
             }
-            Err(e) => {
-                tx.send(ForgeStep {
-                    title: "Error".to_string(),
-                    output: e.to_string(),
-                })
-                .await
-                .ok();
+            Err(_) => {
+                send_timeout(&tx, Stage::Generating, ErrorCode::LlmTimeout, "Intent decomposition", llm_timeout).await;
+                return;
             }
+        };
+
+        if parts.len() > 1 {
+            tx.send(ForgeStep::status(
+                Stage::Generating,
+                format!("Split intent into {} independent actions:\n- {}\n", parts.len(), parts.join("\n- ")),
+            ))
+            .await
+            .ok();
+            parallel_forge_job(&state, generator, &request, parts, &context, &guidelines, project_path, &rpc_url, simulation, &tx).await;
+            return;
         }
+    }
 
-        // Clean up at the end
-        // if let Err(e) = std::fs::remove_dir_all(&project_path) {
-        //     eprintln!("Failed to clean up session {}: {}", session_id, e);
-        // }
+    let scripted = if let Some((script, _)) = &template {
+        drop(generator);
+        save_template_session(&request, &project_path, &tx).await
+            && write_script(&state, script, &[&guidelines, &request.intent], &request.from_address, &project_path, &tx).await
+    } else {
+        let mut intent = with_context(&request.intent, &context);
+        if let Some(plan) = &plan {
+            let plan = serde_json::to_string_pretty(plan).unwrap_or_default();
+            intent = format!("{}\n\nAction plan (follow it exactly):\n{}", intent, plan);
+        }
+        let generated =
+            generate_script(&state, &mut generator, &request, &intent, &guidelines, &project_path, &tx).await;
+        drop(generator);
+        generated
+    };
+    // A generated script that fails isn't recorded; a template that fails counts against it
+    if !scripted && template.is_none() {
+        return;
+    }
 
-        // Permit is automatically released when _permit is dropped
-    });
+    let succeeded = scripted
+        && verify_resolved_addresses(&project_path, &resolved, &tx).await
+        && verify_slippage(&project_path, request.slippage_bps, &tx).await
+        && compile_first(&state, &project_path, &tx).await
+        && simulate_script(&state, &project_path, &rpc_url, simulation, &tx).await;
+    if let Some(key) = template_key.filter(|_| state.config.templates.enabled && (succeeded || template.is_some())) {
+        let script = read_script(&project_path);
+        let generated = template.is_none().then_some((script.as_str(), protocols.as_slice()));
+        state.templates.record(&key, generated, succeeded);
+    }
+}
 
-    create_forge_stream(rx)
+/// Save the session of a job scripted from a template. Fixes work from the
+/// script on disk; the intent is all the conversation needs.
+async fn save_template_session(request: &ForgeRequest, project_path: &Path, tx: &Sender<ForgeStep>) -> bool {
+    let session_data = SessionData {
+        messages: ChatCompletionRequestUserMessageArgs::default()
+            .content(request.intent.clone())
+            .build()
+            .into_iter()
+            .collect(),
+        from_address: Some(request.from_address.clone()),
+        owner: request.requested_by,
+    };
+    if let Err(e) = fs::write(project_path.join("session.json"), serde_json::to_string(&session_data).unwrap()) {
+        tx.send(ForgeStep::error(Stage::Generating, ErrorCode::Internal, e.to_string()))
+        .await
+        .ok();
+        return false;
+    }
+    true
 }
 
+/// Store where a job came from and count it towards its protocols' stats
+fn register_job(state: &AppState, project_path: &Path, origin: &JobOrigin) {
+    if let Err(e) = fs::write(project_path.join(ORIGIN_FILE), serde_json::to_string(origin).unwrap_or_default()) {
+        warn!("Failed to store job origin: {}", e);
+    }
+    state.stats.job_started(origin);
+}
 
-fn create_forge_stream(
-    mut rx: tokio::sync::mpsc::Receiver<ForgeStep>
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    Sse::new(stream::unfold(rx, |mut rx| async move {
-        match rx.recv().await {
-            Some(step) => {
-                let event = Event::default().data(serde_json::to_string(&step).unwrap());
-                Some((Ok(event), rx))
+pub(crate) fn job_origin(project_path: &Path) -> Option<JobOrigin> {
+    fs::read_to_string(project_path.join(ORIGIN_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
+
+/// Add a record to the audit trail, attributed to whoever requested the session's job
+fn audit(state: &AppState, project_path: &Path, event: AuditEvent) {
+    let Some(audit) = &state.audit else {
+        return;
+    };
+    let origin = job_origin(project_path);
+    let requested_by = origin.as_ref().map(|origin| match origin.requested_by {
+        Some(address) => to_checksum(&address, None),
+        None => origin.from_address.clone(),
+    });
+    audit.append(
+        &project_path.to_string_lossy(),
+        requested_by.as_deref(),
+        origin.as_ref().map(|origin| origin.intent.as_str()),
+        event,
+    );
+}
+
+/// The generator for an experiment variant, which may have its own models
+fn generator_for<'a>(state: &'a AppState, variant: Option<&str>) -> &'a Mutex<LLMImpl> {
+    state
+        .experiment
+        .as_ref()
+        .zip(variant)
+        .and_then(|(experiment, variant)| experiment.variant(variant)?.generator.as_ref())
+        .unwrap_or(&state.template_generator)
+}
+
+/// Extra material for the generation prompt that decomposition and protocol
+/// classification don't need, such as the sender's portfolio
+fn with_context(intent: &str, context: &str) -> String {
+    if context.is_empty() {
+        return intent.to_string();
+    }
+    format!("{}\n\n{}", intent, context)
+}
+
+fn slippage_instructions(bps: u32) -> String {
+    format!(
+        "Maximum slippage: {} bps ({:.2}%). Derive every swap's minimum output (amountOutMinimum or equivalent) \
+        from an on-chain quote reduced by this tolerance, i.e. quote * (10000 - {}) / 10000. Never use 0.",
+        bps,
+        bps as f64 / 100.0,
+        bps
+    )
+}
+
+fn time_travel_instructions() -> String {
+    "The intent spans time. Run its phases in order and, between them, advance the fork with \
+    `vm.warp(block.timestamp + <seconds>)` and `vm.roll(block.number + <blocks>)` (about 12 seconds per block), \
+    outside `vm.startBroadcast()`/`vm.stopBroadcast()`. Read any amounts a later phase depends on, \
+    such as accrued rewards, after the warp rather than before."
+        .to_string()
+}
+
+/// Generate a script for `intent` into a session directory, save the session
+/// (as `request`'s sender and requester) and run the pre-compile checks.
+/// Returns false once an error has been reported.
+async fn generate_script(
+    state: &AppState,
+    generator: &mut LLMImpl,
+    request: &ForgeRequest,
+    intent: &str,
+    guidelines: &str,
+    project_path: &Path,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let mut messages = vec![];
+
+    // read remappings.txt
+    let remappings = fs::read_to_string(project_path.join("remappings.txt")).unwrap_or_default();
+
+    // Generate code
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let result = match tokio::time::timeout(
+        llm_timeout,
+        generator.generate_forge_code(
+            &request.from_address,
+            intent,
+            guidelines,
+            &remappings,
+            &mut messages,
+            tx.clone(), // Pass the sender to allow progress updates
+        ),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => {
+            send_timeout(tx, Stage::Generating, ErrorCode::LlmTimeout, "LLM generation", llm_timeout).await;
+            return false;
+        }
+    };
+
+    let forge_code = match result {
+        Ok(forge_code) => forge_code,
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Generating, llm_error_code(&e), e.to_string()))
+            .await
+            .ok();
+            return false;
+        }
+    };
+
+    // Send update before parsing install commands
+    tx.send(ForgeStep::status(Stage::Generating, "Saving session...\n"))
+    .await
+    .ok();
+
+    // update the messages to the session file
+    let session_file = project_path.join("session.json");
+    let session_data = SessionData {
+        messages,
+        from_address: Some(request.from_address.clone()),
+        owner: request.requested_by,
+    };
+    if let Err(e) = fs::write(&session_file, serde_json::to_string(&session_data).unwrap()) {
+        tx.send(ForgeStep::error(Stage::Generating, ErrorCode::Internal, e.to_string()))
+        .await
+        .ok();
+        return false;
+    }
+
+    // Extract and write Solidity code
+    let code = match solidity_block(&forge_code).ok_or_else(|| eyre::eyre!("No Solidity code block found")) {
+        Ok(code) => code.to_string(),
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Generating, ErrorCode::InvalidScript, e.to_string()))
+            .await
+            .ok();
+            return false;
+        }
+    };
+
+    if !write_script(state, &code, &[guidelines, intent], &request.from_address, project_path, tx).await {
+        return false;
+    }
+
+    write_invariant_test(state, generator, &request.from_address, intent, code.trim(), project_path, tx).await;
+    true
+}
+
+/// Write a session's script and run the pre-compile checks on it: syntax,
+/// policy (with `known_texts` as where addresses may legitimately come from)
+/// and package installs. Returns false once an error has been reported.
+async fn write_script(
+    state: &AppState,
+    code: &str,
+    known_texts: &[&str],
+    from_address: &str,
+    project_path: &Path,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    tx.send(ForgeStep::CodeChunk { code: code.trim().to_string() })
+    .await
+    .ok();
+    tx.send(ForgeStep::progress(Stage::Writing)).await.ok();
+
+    // List files in temp directory
+    let files = match fs::read_dir(project_path) {
+        Ok(entries) => {
+            let paths: Vec<_> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .collect();
+            format!("Files in directory:\n{:#?}", paths)
+        },
+        Err(e) => format!("Error reading directory: {}", e)
+    };
+
+    tx.send(ForgeStep::status(Stage::Writing, files))
+    .await
+    .ok();
+
+    // Write and compile code
+    let script_path = project_path.join("script").join("Script.s.sol");
+    if let Err(e) = fs::write(&script_path, code.trim()) {
+        tx.send(ForgeStep::error(Stage::Writing, ErrorCode::Internal, e.to_string()))
+        .await
+        .ok();
+        return false;
+    }
+    let version = save_script_version(project_path, code.trim())
+        .map_err(|e| warn!("Failed to store script version: {}", e))
+        .ok();
+    audit(state, project_path, AuditEvent::Script { version, script: code.trim() });
+
+    // Catch syntax and structure errors without a forge compile cycle
+    let unit = match check_script(code) {
+        Ok(unit) => unit,
+        Err(diagnostic) => {
+            tx.send(ForgeStep::error(Stage::Writing, ErrorCode::InvalidScript, diagnostic))
+            .await
+            .ok();
+            return false;
+        }
+    };
+
+    if !enforce_policy(state, project_path, code, known_texts, Some(from_address), tx).await {
+        return false;
+    }
+
+    if !install_imported_packages(state, project_path, &unit, tx).await {
+        return false;
+    }
+
+    true
+}
+
+/// The first fenced code block in an LLM response, without its language tag
+fn solidity_block(response: &str) -> Option<&str> {
+    response
+        .split("```")
+        .nth(1)
+        .map(|block| block.strip_prefix("solidity\n").unwrap_or(block))
+}
+
+/// Have the LLM write the session's invariant test for the script just generated.
+/// The job goes on without one if that fails.
+async fn write_invariant_test(
+    state: &AppState,
+    generator: &LLMImpl,
+    from_address: &str,
+    intent: &str,
+    script: &str,
+    project_path: &Path,
+    tx: &Sender<ForgeStep>,
+) {
+    if !state.config.invariants.enabled {
+        return;
+    }
+    tx.send(ForgeStep::status(Stage::Writing, "Writing invariant checks...\n")).await.ok();
+
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let response = match tokio::time::timeout(llm_timeout, generator.generate_invariant_test(from_address, intent, script)).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            warn!("Failed to generate invariant test: {}", e);
+            return;
+        }
+        Err(_) => {
+            warn!("Generating invariant test timed out after {}s", llm_timeout.as_secs());
+            return;
+        }
+    };
+    let Some(test) = solidity_block(&response) else {
+        warn!("No Solidity code block in the invariant test response");
+        return;
+    };
+
+    let invariants_dir = project_path.join(INVARIANTS_DIR);
+    if let Err(e) = fs::create_dir_all(&invariants_dir).and_then(|_| fs::write(invariants_dir.join(INVARIANT_TEST_FILE), test.trim())) {
+        warn!("Failed to write invariant test: {}", e);
+    }
+}
+
+/// Check that the script uses every address an ENS name or token symbol in the
+/// intent resolved to, reporting the ones it left out as an error for the fix loop
+async fn verify_resolved_addresses(project_path: &Path, resolved: &[(String, Address)], tx: &Sender<ForgeStep>) -> bool {
+    let code = fs::read_to_string(project_path.join("script").join("Script.s.sol")).unwrap_or_default();
+    let used: HashSet<Address> = find_addresses(&code).into_iter().map(|(_, address)| address).collect();
+    let missing = resolved
+        .iter()
+        .filter(|(_, address)| !used.contains(address))
+        .map(|(name, address)| format!("- {} ({})", name, to_checksum(address, None)))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return true;
+    }
+
+    tx.send(ForgeStep::error(
+        Stage::Writing,
+        ErrorCode::InvalidScript,
+        format!("The script must use the resolved addresses of these names:\n{}", missing.join("\n")),
+    ))
+    .await
+    .ok();
+
+    false
+}
+
+/// Check that the script's swaps bound their output, within the requested
+/// tolerance when there is one, reporting problems as an error for the fix loop
+async fn verify_slippage(project_path: &Path, slippage_bps: Option<u32>, tx: &Sender<ForgeStep>) -> bool {
+    let code = fs::read_to_string(project_path.join("script").join("Script.s.sol")).unwrap_or_default();
+    let problems = check_slippage(&code, slippage_bps);
+    if problems.is_empty() {
+        return true;
+    }
+
+    tx.send(ForgeStep::error(
+        Stage::Writing,
+        ErrorCode::InvalidScript,
+        format!("Swaps must protect against slippage:\n{}", problems.join("\n")),
+    ))
+    .await
+    .ok();
+
+    false
+}
+
+/// Generate one script per independent action, each in its own session, then
+/// simulate them concurrently and report the merged transactions in part order.
+/// The first part uses the job's session; the rest check out their own, which
+/// are kept until they expire like the job's.
+#[allow(clippy::too_many_arguments)]
+async fn parallel_forge_job(
+    state: &Arc<AppState>,
+    mut generator: MutexGuard<'_, LLMImpl>,
+    request: &ForgeRequest,
+    parts: Vec<String>,
+    context: &str,
+    guidelines: &str,
+    project_path: PathBuf,
+    rpc_url: &str,
+    simulation: Simulation<'_>,
+    tx: &Sender<ForgeStep>,
+) {
+    let mut part_paths = vec![project_path.clone()];
+    let mut part_sessions = PartSessions { state, paths: Vec::new() };
+    // Recorders of the announced part sessions, which end with the job
+    let mut part_events = Vec::new();
+    for index in 1..parts.len() {
+        match state.project_pool.checkout().await {
+            Ok(dir) => {
+                let path = dir.path().to_path_buf();
+                part_sessions.paths.push(path.clone());
+                state
+                    .temp_dirs
+                    .lock()
+                    .await
+                    .insert(path.to_string_lossy().to_string(), dir);
+                if let Err(e) = write_session_profile(&path, &request.compiler_settings()) {
+                    tx.send(ForgeStep::error(Stage::Initializing, ErrorCode::Internal, format!("Failed to apply compiler settings: {}", e)))
+                    .await
+                    .ok();
+                    return;
+                }
+                state.session_env.share(&project_path, &path);
+                let created = ForgeStep::SessionCreated { session: path.to_string_lossy().to_string() };
+                let events_tx = record_part_session(state, request, &path).await;
+                events_tx.send(created.clone()).await.ok();
+                part_events.push(events_tx);
+                part_sessions.paths.pop();
+                tx.send(ForgeStep::Part { part: index, event: Box::new(created) }).await.ok();
+                part_paths.push(path);
             }
-            None => {
+            Err(e) => {
+                tx.send(ForgeStep::error(Stage::Initializing, ErrorCode::Internal, format!("Failed to create temp directory: {}", e)))
+                .await
+                .ok();
+                return;
+            }
+        }
+    }
+
+    // Generation shares the single LLM client, so it runs part by part
+    for (index, (intent, path)) in parts.iter().zip(&part_paths).enumerate() {
+        let part_tx = part_sender(tx, index);
+        let intent = with_context(intent, context);
+        if !generate_script(state, &mut generator, request, &intent, guidelines, path, &part_tx).await
+            || !verify_slippage(path, request.slippage_bps, &part_tx).await
+        {
+            return;
+        }
+    }
+    drop(generator);
+
+    for (index, path) in part_paths.iter().enumerate() {
+        if !compile_first(state, path, &part_sender(tx, index)).await {
+            return;
+        }
+    }
+
+    // One simulation runs on this job's permit; others run alongside only on spare permits
+    let extra_permits = (1..parts.len())
+        .map_while(|_| state.process_limiter.clone().try_acquire_owned().ok())
+        .collect::<Vec<_>>();
+    let concurrency = 1 + extra_permits.len();
+
+    tx.send(ForgeStep::status(Stage::Simulating, format!("Compiling {} scripts, {} at a time...\n", parts.len(), concurrency)))
+    .await
+    .ok();
+
+    let session = part_paths[0].to_string_lossy().to_string();
+    let script = part_paths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| format!("// Part {}: {}\n{}", index + 1, parts[index], read_script(path)))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let slots = Semaphore::new(concurrency);
+    let simulations = part_paths.into_iter().enumerate().map(|(index, path)| {
+        let slots = &slots;
+        async move {
+            let _slot = slots.acquire().await;
+            let part_tx = part_sender(tx, index);
+            (index, run_simulation(state, &path, rpc_url, simulation, &part_tx).await)
+        }
+    });
+    let results = futures::future::join_all(simulations).await;
+    drop(extra_permits);
+
+    let mut merged = Vec::new();
+    for (index, result) in results {
+        let Some(transactions) = result else {
+            tx.send(ForgeStep::error(
+                Stage::Simulating,
+                ErrorCode::SimulationFailed,
+                format!("Simulation failed for part {}: {}", index + 1, parts[index]),
+            ))
+            .await
+            .ok();
+            return;
+        };
+        merged.extend(transactions.into_iter().enumerate().map(|(order, mut transaction)| {
+            transaction.part = Some(index);
+            transaction.order = Some(order);
+            transaction
+        }));
+    }
+    merged.sort_by_key(|transaction| (transaction.part, transaction.order));
+    prepend_missing_approvals(rpc_url, &mut merged, tx).await;
+    warn_if_unaffordable(rpc_url, &merged, tx).await;
+    let token_deltas = price_transactions(state, rpc_url, &mut merged).await;
+
+    tx.send(ForgeStep::Transactions { transactions: merged.clone() })
+    .await
+    .ok();
+    if !verify_intent(state, &project_path, &merged, &token_deltas, tx).await {
+        return;
+    }
+    let risk = assess_risk(state, rpc_url, &merged, &token_deltas).await;
+    if !within_risk_limit(state, risk.as_ref(), tx).await {
+        return;
+    }
+    explain_transactions(state, &merged, &token_deltas, tx).await;
+    audit(state, &project_path, AuditEvent::Simulation { succeeded: true, transactions: &merged });
+    if let Some(origin) = job_origin(&project_path) {
+        state.stats.job_succeeded(&session, &origin);
+    }
+    tx.send(ForgeStep::Result { session, script, transactions: merged, token_deltas,
+        risk,
+        cached_age_secs: None,
+        impersonated: simulation.impersonate,
+        funded: simulation.funding.is_some(),
+        time_travel: simulation.time_travel,
+    })
+    .await
+    .ok();
+}
+
+/// Record events for a part session of a parallel job. Once the returned sender
+/// is dropped with the job, the session expires like any other.
+async fn record_part_session(state: &AppState, request: &ForgeRequest, path: &Path) -> Sender<ForgeStep> {
+    let (events_tx, events_rx) = tokio::sync::mpsc::channel(state.config.server.event_buffer.max(1));
+    let session = path.to_string_lossy().to_string();
+    let owner = request.requested_by.or_else(|| request.from_address.parse().ok());
+    let events = SessionEvents::new(Some(session.clone()), owner);
+    state.session_events.lock().await.insert(session, events.clone());
+    tokio::spawn(events.record(events_rx, request.request_id.clone()));
+    events_tx
+}
+
+/// The sessions a parallel job checked out for its parts after the first and
+/// hasn't announced yet, handed back to the pool if the job ends before it does.
+/// Announced ones stay checked out until they expire.
+struct PartSessions<'a> {
+    state: &'a Arc<AppState>,
+    paths: Vec<PathBuf>,
+}
+
+impl Drop for PartSessions<'_> {
+    fn drop(&mut self) {
+        for path in self.paths.drain(..) {
+            let state = self.state.clone();
+            tokio::spawn(async move { release_session(&state, &path).await });
+        }
+    }
+}
+
+/// A sender that forwards everything except streamed LLM tokens
+fn without_token_deltas(tx: &Sender<ForgeStep>) -> Sender<ForgeStep> {
+    let (filtered_tx, mut filtered_rx) = tokio::sync::mpsc::channel::<ForgeStep>(100);
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        while let Some(step) = filtered_rx.recv().await {
+            if matches!(step, ForgeStep::TokenDelta { .. }) {
+                continue;
+            }
+            if tx.send(step).await.is_err() {
+                break;
+            }
+        }
+    });
+    filtered_tx
+}
+
+/// A sender that wraps each step in a `Part` event with its part index
+fn part_sender(tx: &Sender<ForgeStep>, index: usize) -> Sender<ForgeStep> {
+    let (part_tx, mut part_rx) = tokio::sync::mpsc::channel::<ForgeStep>(100);
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        while let Some(step) = part_rx.recv().await {
+            let step = ForgeStep::Part {
+                part: index,
+                event: Box::new(step),
+            };
+            if tx.send(step).await.is_err() {
+                break;
+            }
+        }
+    });
+    part_tx
+}
+
+/// Check the script against the configured policy, reporting violations as an
+/// error for the fix loop. Addresses found in `known_texts` and the sender are
+/// treated as legitimate recipients. Time-travel cheatcodes are allowed when the
+/// session's job asked for a time-travel simulation.
+async fn enforce_policy(
+    state: &AppState,
+    project_path: &Path,
+    code: &str,
+    known_texts: &[&str],
+    from_address: Option<&str>,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let known_addresses: HashSet<Address> = known_texts
+        .iter()
+        .flat_map(|text| find_addresses(text))
+        .map(|(_, address)| address)
+        .chain(from_address.and_then(|from| from.parse().ok()))
+        .collect();
+
+    let time_travel = job_origin(project_path).is_some_and(|origin| origin.time_travel);
+    let violations = check_policy(code, &known_addresses, &state.config.policy, time_travel);
+    if violations.is_empty() {
+        return true;
+    }
+
+    tx.send(ForgeStep::error(
+        Stage::Writing,
+        ErrorCode::PolicyViolation,
+        format!(
+            "Policy violation, the script must be rewritten without these patterns:\n{}",
+            violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n")
+        ),
+    ))
+    .await
+    .ok();
+
+    false
+}
+
+/// Run the session's script against a fork, streaming forge output, the security
+/// review and the simulated transactions. Returns whether the simulation succeeded.
+async fn simulate_script(
+    state: &AppState,
+    project_path: &Path,
+    rpc_url: &str,
+    simulation: Simulation<'_>,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let Some(mut transactions) = run_simulation(state, project_path, rpc_url, simulation, tx).await else {
+        audit(state, project_path, AuditEvent::Simulation { succeeded: false, transactions: &[] });
+        return false;
+    };
+    tx.send(ForgeStep::progress_within(Stage::Parsing, 1, 1)).await.ok();
+
+    let mut token_deltas = Vec::new();
+    let mut risk = None;
+    if !transactions.is_empty() {
+        prepend_missing_approvals(rpc_url, &mut transactions, tx).await;
+        warn_if_unaffordable(rpc_url, &transactions, tx).await;
+        token_deltas = price_transactions(state, rpc_url, &mut transactions).await;
+        tx.send(ForgeStep::Transactions { transactions: transactions.clone() })
+        .await
+        .ok();
+        if !verify_intent(state, project_path, &transactions, &token_deltas, tx).await {
+            audit(state, project_path, AuditEvent::Simulation { succeeded: false, transactions: &transactions });
+            return false;
+        }
+        risk = assess_risk(state, rpc_url, &transactions, &token_deltas).await;
+        if !within_risk_limit(state, risk.as_ref(), tx).await {
+            audit(state, project_path, AuditEvent::Simulation { succeeded: false, transactions: &transactions });
+            return false;
+        }
+        explain_transactions(state, &transactions, &token_deltas, tx).await;
+    }
+
+    audit(state, project_path, AuditEvent::Simulation { succeeded: true, transactions: &transactions });
+    send_result(state, project_path, transactions, token_deltas, risk, simulation, tx).await;
+    true
+}
+
+/// Send a session's final event, keeping it for identical requests
+async fn send_result(
+    state: &AppState,
+    project_path: &Path,
+    transactions: Vec<TransactionDetails>,
+    token_deltas: Vec<TokenDelta>,
+    risk: Option<RiskReport>,
+    simulation: Simulation<'_>,
+    tx: &Sender<ForgeStep>,
+) {
+    let session = project_path.to_string_lossy().to_string();
+    if let Some(origin) = job_origin(project_path) {
+        state.stats.job_succeeded(&session, &origin);
+    }
+    let script = read_script(project_path);
+    state.result_cache.complete(
+        project_path,
+        CachedResult {
+            script: script.clone(),
+            session_file: fs::read_to_string(project_path.join("session.json")).unwrap_or_default(),
+            transactions: transactions.clone(),
+            token_deltas: token_deltas.clone(),
+            risk: risk.clone(),
+        },
+    );
+
+    tx.send(ForgeStep::Result {
+        session,
+        script,
+        transactions,
+        token_deltas,
+        risk,
+        cached_age_secs: None,
+        impersonated: simulation.impersonate,
+        funded: simulation.funding.is_some(),
+        time_travel: simulation.time_travel,
+    })
+    .await
+    .ok();
+}
+
+/// Answer with an identical earlier request's result. Its script and conversation
+/// are copied into this session so it can still be fixed. Returns false if they
+/// couldn't be, and the job should generate as usual.
+async fn send_cached_result(project_path: &Path, age: Duration, cached: CachedResult, tx: &Sender<ForgeStep>) -> bool {
+    let written = fs::write(project_path.join("script").join("Script.s.sol"), &cached.script)
+        .and_then(|_| fs::write(project_path.join("session.json"), &cached.session_file));
+    if let Err(e) = written {
+        warn!("Failed to restore a cached result, generating instead: {}", e);
+        return false;
+    }
+    if let Err(e) = save_script_version(project_path, &cached.script) {
+        warn!("Failed to store script version: {}", e);
+    }
+
+    tx.send(ForgeStep::Warning {
+        message: format!(
+            "Reusing the result of an identical request from {}s ago; balances and prices may have moved since. Send no_cache=true to generate it again.",
+            age.as_secs()
+        ),
+    })
+    .await
+    .ok();
+    tx.send(ForgeStep::Result {
+        session: project_path.to_string_lossy().to_string(),
+        script: cached.script,
+        transactions: cached.transactions,
+        token_deltas: cached.token_deltas,
+        risk: cached.risk,
+        cached_age_secs: Some(age.as_secs()),
+        impersonated: false,
+        funded: false,
+        time_travel: false,
+    })
+    .await
+    .ok();
+    true
+}
+
+/// Put exact-amount approvals in front of the bundle for allowances the script
+/// relied on but never granted (e.g. an approve made under `vm.prank`)
+async fn prepend_missing_approvals(rpc_url: &str, transactions: &mut Vec<TransactionDetails>, tx: &Sender<ForgeStep>) {
+    match missing_approvals(rpc_url, transactions).await {
+        Ok(approvals) if !approvals.is_empty() => {
+            let added = approvals
+                .iter()
+                .map(|approval| format!("- {} on {}", approval.arguments.join(", "), approval.to))
+                .collect::<Vec<_>>()
+                .join("\n");
+            tx.send(ForgeStep::status(
+                Stage::Parsing,
+                format!("Added approvals the transactions need but the script didn't send:\n{}\n", added),
+            ))
+            .await
+            .ok();
+            transactions.splice(0..0, approvals);
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Skipping approval check: {}", e),
+    }
+}
+
+/// USD estimates for transaction values and the sender's token deltas, when enabled
+async fn price_transactions(state: &AppState, rpc_url: &str, transactions: &mut [TransactionDetails]) -> Vec<TokenDelta> {
+    if !state.config.prices.enabled {
+        return Vec::new();
+    }
+    annotate_usd(rpc_url, &state.anvil_forks, &state.config.prices, transactions)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Skipping USD annotation: {}", e);
+            Vec::new()
+        })
+}
+
+/// Check the simulated balance changes accomplish the session's action plan.
+/// Mismatches are a warning, or an error for the fix loop (returning false) when
+/// `verification.fail_on_mismatch` is set.
+async fn verify_intent(
+    state: &AppState,
+    project_path: &Path,
+    transactions: &[TransactionDetails],
+    token_deltas: &[TokenDelta],
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let config = &state.config.verification;
+    if !config.enabled || !state.config.prices.enabled {
+        return true;
+    }
+    let Some(plan) = fs::read_to_string(project_path.join(PLAN_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str::<ActionPlan>(&content).ok())
+    else {
+        return true;
+    };
+
+    let problems = unmet_intent(&plan, transactions, token_deltas);
+    if problems.is_empty() {
+        return true;
+    }
+    let message = format!(
+        "The simulated balance changes don't accomplish the intent:\n{}",
+        problems.join("\n")
+    );
+    if !config.fail_on_mismatch {
+        tx.send(ForgeStep::Warning { message }).await.ok();
+        return true;
+    }
+
+    tx.send(ForgeStep::error(Stage::Parsing, ErrorCode::IntentNotSatisfied, message))
+    .await
+    .ok();
+    false
+}
+
+/// Score the bundle's risk against the sender's holdings, or `None` when scoring
+/// is disabled or fails
+async fn assess_risk(
+    state: &AppState,
+    rpc_url: &str,
+    transactions: &[TransactionDetails],
+    token_deltas: &[TokenDelta],
+) -> Option<RiskReport> {
+    let config = &state.config;
+    if !config.risk.enabled {
+        return None;
+    }
+
+    let chain_id = match chain_id(rpc_url).await {
+        Ok(chain_id) => chain_id,
+        Err(e) => {
+            warn!("Skipping risk scoring: {}", e);
+            return None;
+        }
+    };
+    let from = &transactions.first()?.from;
+    let registry = &state.token_registry;
+    let portfolio_usd = portfolio_value_usd(from, chain_id, &config.portfolio, &config.prices, registry, &state.token_cache)
+        .await
+        .unwrap_or_else(|e| {
+            warn!("No portfolio value for risk scoring: {}", e);
+            None
+        });
+    let allowed: HashSet<Address> = config
+        .policy
+        .allowed_addresses
+        .iter()
+        .filter_map(|address| address.parse().ok())
+        .collect();
+    let known = |address: Address| allowed.contains(&address) || registry.contains(chain_id, address);
+
+    match score_risk(rpc_url, transactions, token_deltas, portfolio_usd, &known, &config.risk, &config.prices).await {
+        Ok(risk) => Some(risk),
+        Err(e) => {
+            warn!("Skipping risk scoring: {}", e);
+            None
+        }
+    }
+}
+
+/// Policy gate on the risk score: reports an error and returns false when the
+/// bundle scores above `policy.max_risk_score`, or couldn't be scored
+async fn within_risk_limit(state: &AppState, risk: Option<&RiskReport>, tx: &Sender<ForgeStep>) -> bool {
+    let Some(max) = state.config.policy.max_risk_score.filter(|_| state.config.policy.enabled) else {
+        return true;
+    };
+    let Some(risk) = risk else {
+        tx.send(ForgeStep::error(
+            Stage::Parsing,
+            ErrorCode::PolicyViolation,
+            format!("The bundle couldn't be scored for risk, and policy allows only scores up to {}", max),
+        ))
+        .await
+        .ok();
+        return false;
+    };
+    if risk.score <= max {
+        return true;
+    }
+
+    tx.send(ForgeStep::error(
+        Stage::Parsing,
+        ErrorCode::PolicyViolation,
+        format!(
+            "Risk score {} is above the allowed {}:\n{}",
+            risk.score,
+            max,
+            risk.reasons.iter().map(|reason| reason.to_string()).collect::<Vec<_>>().join("\n")
+        ),
+    ))
+    .await
+    .ok();
+    false
+}
+
+/// Describe the transactions in plain English for users reviewing them before
+/// signing. A failed explanation is logged rather than failing the job.
+async fn explain_transactions(
+    state: &AppState,
+    transactions: &[TransactionDetails],
+    token_deltas: &[TokenDelta],
+    tx: &Sender<ForgeStep>,
+) {
+    if !state.config.explanations.enabled {
+        return;
+    }
+
+    let transactions = serde_json::to_string_pretty(transactions).unwrap_or_default();
+    let token_deltas = serde_json::to_string_pretty(token_deltas).unwrap_or_default();
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let generator = state.template_generator.lock().await;
+    match tokio::time::timeout(llm_timeout, generator.explain_transactions(&transactions, &token_deltas)).await {
+        Ok(Ok(explanation)) => {
+            tx.send(ForgeStep::Explanation { explanation }).await.ok();
+        }
+        Ok(Err(e)) => warn!("Failed to explain transactions: {}", e),
+        Err(_) => warn!("Explaining transactions timed out after {}s", llm_timeout.as_secs()),
+    }
+}
+
+/// Warn when the sender can't cover the transactions' value and gas on the fork
+async fn warn_if_unaffordable(rpc_url: &str, transactions: &[TransactionDetails], tx: &Sender<ForgeStep>) {
+    match check_affordability(rpc_url, transactions).await {
+        Ok(Some(message)) => {
+            tx.send(ForgeStep::Warning { message }).await.ok();
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Skipping affordability check: {}", e),
+    }
+}
+
+fn read_script(project_path: &Path) -> String {
+    fs::read_to_string(project_path.join("script").join("Script.s.sol")).unwrap_or_default()
+}
+
+/// How a session's script is simulated
+#[derive(Clone, Copy)]
+struct Simulation<'a> {
+    /// Address the script runs as rather than forge's default sender
+    sender: Option<&'a str>,
+    /// Send the transactions to an anvil fork as `sender`, impersonated, instead
+    /// of only simulating them
+    impersonate: bool,
+    /// Give `sender` ETH and these tokens on an anvil fork first
+    funding: Option<&'a [Address]>,
+    /// Let the script warp time between phases, reporting each phase's
+    /// transactions separately
+    time_travel: bool,
+}
+
+impl Simulation<'_> {
+    /// Whether the simulation runs in conditions the user couldn't recreate on-chain
+    fn is_synthetic(&self) -> bool {
+        self.prepares_fork() || self.time_travel
+    }
+
+    /// Whether the anvil fork has to be set up before the script runs
+    fn prepares_fork(&self) -> bool {
+        self.impersonate || self.funding.is_some()
+    }
+}
+
+/// Set an anvil fork up for a synthetic simulation: impersonate the sender and
+/// give it ETH and the intent's tokens, as the simulation asks. A token whose
+/// balance can't be set is warned about rather than failing the job.
+async fn prepare_fork(
+    state: &AppState,
+    snapshot: &mut ForkSnapshot,
+    sender: Option<Address>,
+    simulation: Simulation<'_>,
+    tx: &Sender<ForgeStep>,
+) -> Result<()> {
+    let sender = sender.ok_or_else(|| eyre!("Impersonation and funding need a sender address"))?;
+    if simulation.impersonate {
+        snapshot.impersonate(sender).await?;
+    }
+
+    let Some(tokens) = simulation.funding else {
+        return Ok(());
+    };
+    let config = &state.config.anvil;
+    snapshot.set_balance(sender, U256::from(config.funding_eth) * U256::exp10(18)).await?;
+    let mut funded = vec![format!("{} ETH", config.funding_eth)];
+    for &token in tokens {
+        match snapshot.deal(token, sender, config.funding_token_units).await {
+            Ok(_) => funded.push(format!("{} of {}", config.funding_token_units, to_checksum(&token, None))),
+            Err(e) => {
+                tx.send(ForgeStep::Warning { message: format!("Couldn't fund the sender with {}: {}", to_checksum(&token, None), e) })
+                .await
+                .ok();
+            }
+        }
+    }
+    tx.send(ForgeStep::Warning {
+        message: format!(
+            "Simulating with synthetic balances the sender doesn't hold: {}. The result previews the intent and won't go through on-chain as is.",
+            funded.join(", ")
+        ),
+    })
+    .await
+    .ok();
+    Ok(())
+}
+
+/// Run `forge script` against the fork and review it, returning the simulated
+/// transactions. Failures are reported to the client and return `None`.
+async fn run_simulation(
+    state: &AppState,
+    project_path: &Path,
+    rpc_url: &str,
+    simulation: Simulation<'_>,
+    tx: &Sender<ForgeStep>,
+) -> Option<Vec<TransactionDetails>> {
+    tx.send(ForgeStep::progress(Stage::Simulating)).await.ok();
+    if let Err(conflict) = pin_compiler(project_path, Stage::Simulating, tx).await {
+        tx.send(ForgeStep::error(Stage::Simulating, ErrorCode::CompileFailed, conflict)).await.ok();
+        return None;
+    }
+    if let Err(e) = clear_broadcasts(project_path) {
+        warn!("Failed to clear earlier broadcast files: {}", e);
+    }
+
+    let script_timeout = Duration::from_secs(state.config.timeouts.forge_script_secs);
+    let timer = METRICS.forge_duration.with_label_values(&["script"]).start_timer();
+    let mut command = forge_command(state, project_path, NetworkAccess::for_rpc(rpc_url));
+    command.args(["script", "script/Script.s.sol", "--json", "-vvvv"]);
+    command.args(fork_args(rpc_url));
+    // msg.sender, balances and allowances are the user's even when the script
+    // calls `vm.startBroadcast()` without an address
+    let sender = simulation.sender.and_then(|sender| sender.parse::<Address>().ok());
+    if let Some(sender) = sender {
+        command.args(["--sender", &format!("{:?}", sender)]);
+    }
+    // Impersonating, the transactions are really sent to the anvil node, with
+    // `--unlocked` sending them unsigned; the snapshot undoes them afterwards
+    let impersonated = sender.filter(|_| simulation.impersonate);
+    if impersonated.is_some() {
+        command.args(["--broadcast", "--unlocked"]);
+    }
+    // Warps only move the clock of forge's local fork, whose state its own
+    // on-chain re-simulation wouldn't share, so that step is skipped
+    if simulation.time_travel {
+        command.arg("--skip-simulation");
+    }
+    let prepares_fork = simulation.prepares_fork();
+    let mut snapshot = if state.config.anvil.snapshots || prepares_fork {
+        state
+            .anvil_forks
+            .snapshot(rpc_url)
+            .await
+            .inspect_err(|e| warn!("Failed to snapshot the fork: {}", e))
+            .ok()
+            .flatten()
+    } else {
+        None
+    };
+    if prepares_fork {
+        let prepared = match snapshot.as_mut() {
+            Some(snapshot) => prepare_fork(state, snapshot, sender, simulation, tx)
+                .await
+                .map_err(|e| (ErrorCode::SimulationFailed, e.to_string())),
+            None => Err((
+                ErrorCode::ForkUnsupported,
+                "Impersonation and funding need the fork RPC to be an anvil node".to_string(),
+            )),
+        };
+        if let Err((code, message)) = prepared {
+            if let Some(snapshot) = snapshot {
+                snapshot.revert().await.ok();
+            }
+            tx.send(ForgeStep::error(Stage::Simulating, code, message)).await.ok();
+            return None;
+        }
+    }
+    let step = |line| ForgeStep::CompileOutput { stage: Stage::Simulating, line };
+    let result = run_json_command(&mut command, tx, step, script_timeout)
+        .instrument(info_span!("forge.script"))
+        .await;
+    timer.observe_duration();
+    if let Some(snapshot) = snapshot {
+        if let Err(e) = snapshot.revert().await {
+            warn!("Failed to revert the fork to its snapshot: {}", e);
+        }
+    }
+
+    let output = match result {
+        Ok(CommandOutcome::Completed(output)) => output,
+        Ok(CommandOutcome::TimedOut) => {
+            send_timeout(tx, Stage::Simulating, ErrorCode::ForgeTimeout, "forge script", script_timeout).await;
+            return None;
+        }
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Simulating, ErrorCode::Internal, e.to_string()))
+            .await
+            .ok();
+            return None;
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let script_output = parse_script_output(&stdout);
+    let trace = script_output.as_ref().map(execution_trace).unwrap_or_default();
+    if let Some(script_output) = &script_output {
+        for message in &script_output.logs {
+            tx.send(ForgeStep::ConsoleLog { stage: Stage::Simulating, message: message.clone() })
+            .await
+            .ok();
+        }
+        tx.send(ForgeStep::GasReport {
+            stage: Stage::Simulating,
+            name: "script".to_string(),
+            gas_used: script_output.gas_used,
+        })
+        .await
+        .ok();
+        if !trace.is_empty() {
+            tx.send(ForgeStep::Trace { stage: Stage::Simulating, calls: trace.clone() }).await.ok();
+        }
+    }
+
+    if !output.status.success() || script_output.as_ref().is_some_and(|output| !output.success) {
+        // The error step carries the failure, logs and trace in full for the fix loop
+        let mut message = format!("Forge script failed:\n{}\n", failure_output(&output));
+        if let Some(script_output) = script_output.as_ref().filter(|output| !output.logs.is_empty()) {
+            message.push_str(&format!("\nConsole logs:\n{}\n", script_output.logs.join("\n")));
+        }
+        if !trace.is_empty() {
+            message.push_str(&format!("\nTrace:\n{}", render_trace(&trace)));
+        }
+        let code = classify_script_failure(&stdout, &stderr);
+        if code == ErrorCode::RpcUnreachable {
+            state.rpc_pool.report_failure(rpc_url);
+        }
+        tx.send(ForgeStep::error(Stage::Simulating, code, message))
+            .await
+            .ok();
+        return None;
+    }
+
+    // forge deploys the libraries ahead of the script and links it against them
+    let libraries = script_libraries(project_path);
+    if !libraries.is_empty() {
+        tx.send(ForgeStep::status(
+            Stage::Simulating,
+            format!("Deployed and linked external libraries: {}\n", libraries.join(", ")),
+        ))
+        .await
+        .ok();
+    }
+
+    if !run_invariant_tests(state, project_path, rpc_url, tx).await {
+        return None;
+    }
+
+    tx.send(ForgeStep::progress(Stage::SecurityReview)).await.ok();
+    if !security_review(state, project_path, tx).await {
+        return None;
+    }
+
+    tx.send(ForgeStep::progress(Stage::Parsing)).await.ok();
+
+    let fork_chain = chain_id(rpc_url)
+        .await
+        .inspect_err(|e| warn!("Failed to read the fork's chain id: {}", e))
+        .ok();
+    let broadcast = match read_broadcast(project_path, fork_chain, impersonated.is_some()) {
+        Ok(broadcast) => broadcast,
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Parsing, ErrorCode::Internal, e.to_string()))
+            .await
+            .ok();
+            return None;
+        }
+    };
+    let Some(broadcast) = broadcast else {
+        return Some(Vec::new());
+    };
+    let mut transactions = transaction_details(broadcast);
+    if simulation.time_travel {
+        report_phases(&mut transactions, &trace, tx).await;
+    }
+    if let Err(e) = save_version_transactions(project_path, &transactions) {
+        warn!("Failed to store simulated transactions: {}", e);
+    }
+    Some(transactions)
+}
+
+/// Tell the client which transactions of a time-travel simulation go out when,
+/// setting each one's phase
+async fn report_phases(transactions: &mut [TransactionDetails], trace: &[TraceCall], tx: &Sender<ForgeStep>) {
+    let Some(phases) = assign_phases(transactions, trace) else {
+        tx.send(ForgeStep::Warning {
+            message: "Couldn't tell which transactions come after each time warp; they're shown as a single phase".to_string(),
+        })
+        .await
+        .ok();
+        return;
+    };
+    let summary = phases
+        .iter()
+        .enumerate()
+        .map(|(phase, start)| {
+            let count = transactions.iter().filter(|transaction| transaction.phase == Some(phase)).count();
+            format!("Phase {}, {}: {} transaction{}", phase + 1, start, count, if count == 1 { "" } else { "s" })
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    tx.send(ForgeStep::status(Stage::Parsing, format!("{}\n", summary))).await.ok();
+    if phases.len() > 1 {
+        tx.send(ForgeStep::Warning {
+            message: "The script warped time between phases. Send each phase's transactions only once that much time has actually passed; later phases depend on it.".to_string(),
+        })
+        .await
+        .ok();
+    }
+}
+
+/// Run the session's invariant test against the fork. It's copied into `test/`
+/// only for this run, so a test that doesn't compile never breaks `forge script`;
+/// such a test is dropped with a warning. Failing checks are reported as an
+/// error for the fix loop and return false.
+async fn run_invariant_tests(state: &AppState, project_path: &Path, rpc_url: &str, tx: &Sender<ForgeStep>) -> bool {
+    let source = project_path.join(INVARIANTS_DIR).join(INVARIANT_TEST_FILE);
+    if !state.config.invariants.enabled || !source.exists() {
+        return true;
+    }
+    let test_path = project_path.join("test").join(INVARIANT_TEST_FILE);
+    if let Err(e) = fs::create_dir_all(project_path.join("test")).and_then(|_| fs::copy(&source, &test_path)) {
+        warn!("Skipping invariant checks: {}", e);
+        return true;
+    }
+    tx.send(ForgeStep::status(Stage::Simulating, "Checking invariants...\n")).await.ok();
+
+    let test_timeout = Duration::from_secs(state.config.timeouts.forge_script_secs);
+    let timer = METRICS.forge_duration.with_label_values(&["test"]).start_timer();
+    let mut command = forge_command(state, project_path, NetworkAccess::for_rpc(rpc_url));
+    let match_path = format!("test/{}", INVARIANT_TEST_FILE);
+    command.args(["test", "--match-path", match_path.as_str(), "--json", "-vvv"]);
+    command.args(fork_args(rpc_url));
+    let step = |line| ForgeStep::CompileOutput { stage: Stage::Simulating, line };
+    let result = run_json_command(&mut command, tx, step, test_timeout)
+        .instrument(info_span!("forge.test"))
+        .await;
+    timer.observe_duration();
+    fs::remove_file(&test_path).ok();
+
+    let output = match result {
+        Ok(CommandOutcome::Completed(output)) => output,
+        Ok(CommandOutcome::TimedOut) => {
+            send_timeout(tx, Stage::Simulating, ErrorCode::ForgeTimeout, "forge test", test_timeout).await;
+            return false;
+        }
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::Simulating, ErrorCode::Internal, e.to_string()))
+            .await
+            .ok();
+            return false;
+        }
+    };
+
+    let results = parse_test_results(&String::from_utf8_lossy(&output.stdout));
+    let mut failures = Vec::new();
+    for (name, result) in results.iter().flat_map(|suites| suites.values()).flat_map(|suite| &suite.test_results) {
+        for message in &result.decoded_logs {
+            tx.send(ForgeStep::ConsoleLog { stage: Stage::Simulating, message: message.clone() })
+            .await
+            .ok();
+        }
+        if let Some(unit) = &result.kind.unit {
+            tx.send(ForgeStep::GasReport { stage: Stage::Simulating, name: name.clone(), gas_used: unit.gas })
+            .await
+            .ok();
+        }
+        if result.status == "Failure" {
+            let mut failure = format!("- {}: {}", name, result.reason.as_deref().unwrap_or("failed"));
+            for message in &result.decoded_logs {
+                failure.push_str(&format!("\n    {}", message));
+            }
+            failures.push(failure);
+        }
+    }
+    if output.status.success() {
+        return true;
+    }
+
+    // Without results the tests never ran, which for a generated test means it didn't compile
+    if results.is_none() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if classify_script_failure(&stdout, &stderr) == ErrorCode::CompileFailed {
+            fs::remove_file(&source).ok();
+            tx.send(ForgeStep::Warning {
+                message: "The generated invariant checks didn't compile and were skipped".to_string(),
+            })
+            .await
+            .ok();
+            return true;
+        }
+        failures.push(failure_output(&output));
+    }
+
+    tx.send(ForgeStep::error(
+        Stage::Simulating,
+        ErrorCode::InvariantFailed,
+        format!(
+            "The script ran but doesn't have the effect the intent asks for; these balance checks failed:\n{}",
+            failures.join("\n")
+        ),
+    ))
+    .await
+    .ok();
+    false
+}
+
+/// Review the compiled script for dangerous patterns, streaming each finding.
+/// Returns false if the configuration blocks results with high-severity findings.
+async fn security_review(state: &AppState, project_path: &Path, tx: &Sender<ForgeStep>) -> bool {
+    let config = &state.config.analysis;
+    let findings = match config.engine {
+        AnalysisEngine::Off => return true,
+        AnalysisEngine::Builtin => {
+            let source = fs::read_to_string(project_path.join("script").join("Script.s.sol")).unwrap_or_default();
+            builtin_analysis(&source)
+        }
+        AnalysisEngine::Slither => match slither_analysis(project_path, &state.config.sandbox).await {
+            Ok(findings) => findings,
+            Err(e) => {
+                tx.send(ForgeStep::status(Stage::SecurityReview, format!("{}, falling back to built-in checks\n", e)))
+                .await
+                .ok();
+                let source = fs::read_to_string(project_path.join("script").join("Script.s.sol")).unwrap_or_default();
+                builtin_analysis(&source)
+            }
+        },
+    };
+
+    if findings.is_empty() {
+        tx.send(ForgeStep::status(Stage::SecurityReview, "No issues found\n"))
+        .await
+        .ok();
+        return true;
+    }
+
+    for finding in &findings {
+        tx.send(ForgeStep::status(Stage::SecurityReview, serde_json::to_string(finding).unwrap()))
+        .await
+        .ok();
+    }
+
+    let high: Vec<_> = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::High)
+        .collect();
+    if config.block_on_high && !high.is_empty() {
+        let reasons = high
+            .iter()
+            .map(|finding| match finding.line {
+                Some(line) => format!("- line {}: {} ({})", line, finding.description, finding.check),
+                None => format!("- {} ({})", finding.description, finding.check),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        tx.send(ForgeStep::error(
+            Stage::SecurityReview,
+            ErrorCode::SecurityReviewBlocked,
+            format!("Security review found high-severity issues:\n{}", reasons),
+        ))
+        .await
+        .ok();
+        return false;
+    }
+
+    true
+}
+
+/// Install the registry packages the script imports that the session doesn't
+/// have yet. Returns false if an install failed.
+async fn install_imported_packages(
+    state: &AppState,
+    project_path: &Path,
+    unit: &SourceUnit,
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    install_packages(state, project_path, &script_imports(unit), tx).await
+}
+
+fn has_missing_packages(state: &AppState, project_path: &Path, imports: &[String]) -> bool {
+    let packages = &state.config.base_project.dependencies;
+    imports
+        .iter()
+        .filter_map(|import| package_for_import(packages, import))
+        .any(|package| !package.is_installed(project_path))
+}
+
+/// Install the registry packages providing `imports` and add their remappings
+async fn install_packages(
+    state: &AppState,
+    project_path: &Path,
+    imports: &[String],
+    tx: &Sender<ForgeStep>,
+) -> bool {
+    let packages = &state.config.base_project.dependencies;
+    let mut missing = imports
+        .iter()
+        .filter_map(|import| package_for_import(packages, import))
+        .filter(|package| !package.is_installed(project_path))
+        .collect::<Vec<_>>();
+    missing.sort_by_key(|package| &package.name);
+    missing.dedup_by_key(|package| &package.name);
+    if missing.is_empty() {
+        return true;
+    }
+
+    for (index, package) in missing.iter().enumerate() {
+        tx.send(ForgeStep::progress_within(Stage::InstallingDependencies, index as u64, missing.len() as u64))
+        .await
+        .ok();
+        tx.send(ForgeStep::status(
+            Stage::InstallingDependencies,
+            format!("Installing {} ({})\n", package.name, package.install_target()),
+        ))
+        .await
+        .ok();
+
+        let result = install_dependencies(project_path, package, &state.config.sandbox, tx.clone())
+            .instrument(info_span!("forge.install", package = %package.name))
+            .await;
+        if let Err(e) = result {
+            tx.send(ForgeStep::error(Stage::InstallingDependencies, ErrorCode::DependencyInstallFailed, format!("Failed to install {}: {}", package.name, e)))
+            .await
+            .ok();
+            return false;
+        }
+    }
+
+    let remappings = match add_remappings(project_path, &missing) {
+        Ok(()) => regenerate_remappings(state, project_path).await,
+        Err(e) => Err(e.into()),
+    };
+    match remappings {
+        Ok(remappings) => {
+            tx.send(ForgeStep::status(Stage::InstallingDependencies, format!("Updated remappings.txt:\n{}", remappings)))
+            .await
+            .ok();
+            true
+        }
+        Err(e) => {
+            tx.send(ForgeStep::error(Stage::InstallingDependencies, ErrorCode::Internal, format!("Failed to update remappings: {}", e)))
+            .await
+            .ok();
+            false
+        }
+    }
+}
+
+/// Rewrite the session's remappings.txt from `forge remappings`, which merges
+/// the file's entries with those detected in newly installed libraries. Later
+/// fix prompts read the file, so they see the new import paths.
+async fn regenerate_remappings(state: &AppState, project_path: &Path) -> Result<String> {
+    let output = forge_command(state, project_path, NetworkAccess::None)
+        .arg("remappings")
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(eyre!("forge remappings failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+
+    let remappings = String::from_utf8_lossy(&output.stdout).to_string();
+    fs::write(project_path.join("remappings.txt"), &remappings)?;
+    Ok(remappings)
+}
+
+/// A sandboxed `forge` invocation, building with the session's own copy of the
+/// shared build cache
+fn forge_command(state: &AppState, project_path: &Path, network: NetworkAccess) -> tokio::process::Command {
+    let mut command = sandboxed_command("forge", project_path, network, &state.config.sandbox);
+    if let Some(profile) = session_profile(project_path) {
+        command.env("FOUNDRY_PROFILE", profile);
+    }
+    command.envs(state.session_env.get(project_path));
+    command
+}
+
+/// Tell apart why `forge script` failed from its output
+fn classify_script_failure(stdout: &str, stderr: &str) -> ErrorCode {
+    let output = format!("{}\n{}", stdout, stderr);
+    if output.contains("Compiler run failed") || output.contains("Compilation failed") {
+        ErrorCode::CompileFailed
+    } else if output.contains("Could not instantiate forked environment")
+        || output.contains("error sending request")
+        || output.contains("Connection refused")
+    {
+        ErrorCode::RpcUnreachable
+    } else if output.contains("revert") || output.contains("Revert") {
+        ErrorCode::SimulationReverted
+    } else {
+        ErrorCode::SimulationFailed
+    }
+}
+
+/// Probe the job's RPC before spending any LLM tokens on it, reporting an RPC
+/// that's down, slow or on the wrong chain as the job's error
+async fn preflight(state: &AppState, rpc_url: &str, expected_chain: Option<u64>, stage: Stage, tx: &Sender<ForgeStep>) -> bool {
+    let config = &state.config.rpc;
+    if !config.preflight {
+        return true;
+    }
+    let timeout = Duration::from_secs(config.preflight_timeout_secs);
+    let failure = match preflight_rpc(rpc_url, expected_chain, config.require_archive, timeout).await {
+        Ok(health) => {
+            let message = format!("RPC is on chain {} at block {}\n", health.chain_id, health.block_number);
+            tx.send(ForgeStep::status(stage, message)).await.ok();
+            return true;
+        }
+        Err(failure) => failure,
+    };
+    let code = match failure {
+        PreflightFailure::Unreachable(_) => {
+            state.rpc_pool.report_failure(rpc_url);
+            ErrorCode::RpcUnreachable
+        }
+        PreflightFailure::WrongChain { .. } => ErrorCode::RpcWrongChain,
+        PreflightFailure::NotArchive => ErrorCode::RpcNotArchive,
+    };
+    tx.send(ForgeStep::error(stage, code, failure.to_string())).await.ok();
+    false
+}
+
+/// Refuse a job up front while the LLM gateway's circuit is open and no fallback
+/// can take its calls, rather than letting it fail slowly
+async fn llm_available(state: &AppState, stage: Stage, tx: &Sender<ForgeStep>) -> bool {
+    let retry_after = state
+        .llm_breaker
+        .as_ref()
+        .filter(|_| state.config.llm.fallback.is_none())
+        .and_then(|breaker| breaker.retry_after());
+    let Some(retry_after) = retry_after else {
+        return true;
+    };
+    tx.send(ForgeStep::error(stage, ErrorCode::LlmUnavailable, CircuitOpen { retry_after }.to_string()))
+    .await
+    .ok();
+    false
+}
+
+/// Error code for a failed LLM call
+fn llm_error_code(e: &eyre::Report) -> ErrorCode {
+    if e.downcast_ref::<CircuitOpen>().is_some() {
+        ErrorCode::LlmUnavailable
+    } else {
+        ErrorCode::LlmFailed
+    }
+}
+
+/// Report that a step ran past its time limit and was stopped
+async fn send_timeout(tx: &Sender<ForgeStep>, stage: Stage, code: ErrorCode, step: &str, limit: Duration) {
+    tx.send(ForgeStep::error(stage, code, format!("{} did not finish within {}s and was stopped", step, limit.as_secs())))
+    .await
+    .ok();
+}
+
+/// Run a job with its LLM requests logged to its session directory, if enabled
+async fn logging_prompts(enabled: bool, session_dir: PathBuf, job: impl Future<Output = ()>) {
+    if enabled {
+        with_prompt_log(session_dir, job).await
+    } else {
+        job.await
+    }
+}
+
+/// Run a job on the server's task tracker, aborting it (and killing any child
+/// processes it owns) when the server starts shutting down.
+fn spawn_job<F>(state: &AppState, tx: Sender<ForgeStep>, job: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let shutdown = state.shutdown.clone();
+    let slots = state.process_limiter.clone();
+    let queued = state.queued_jobs.clone();
+    let span = Span::current();
+    state.tasks.spawn(async move {
+        let run = async {
+            let Some(_permit) = acquire_job_slot(slots, &queued, &tx).await else {
+                return;
+            };
+            job.await
+        };
+        tokio::select! {
+            _ = run => {}
+            _ = shutdown.cancelled() => {
+                let step = ForgeStep::Error {
+                    code: ErrorCode::ShuttingDown,
+                    stage: None,
+                    retriable: true,
+                    message: "Server is shutting down".to_string(),
+                };
+                tx.send(step).await.ok();
+            }
+        }
+    }.instrument(span));
+}
+
+/// Wait for a concurrency permit, telling the client its queue position if
+/// none is free
+async fn acquire_job_slot(
+    slots: Arc<Semaphore>,
+    queued: &AtomicUsize,
+    tx: &Sender<ForgeStep>,
+) -> Option<OwnedSemaphorePermit> {
+    if let Ok(permit) = slots.clone().try_acquire_owned() {
+        return Some(permit);
+    }
+
+    let position = queued.fetch_add(1, Ordering::Relaxed) + 1;
+    tx.send(ForgeStep::status(Stage::Queued, format!("Queued (position {})", position)))
+        .await
+        .ok();
+
+    let timer = METRICS.job_queue_wait.start_timer();
+    let permit = slots.acquire_owned().await.ok();
+    timer.observe_duration();
+    queued.fetch_sub(1, Ordering::Relaxed);
+    permit
+}
+
+/// Stream a session's events to the client, starting at event `from`
+fn create_forge_stream(
+    state: &AppState,
+    events: Arc<SessionEvents>,
+    from: usize,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Comment lines during long LLM/compile pauses stop proxies dropping idle connections
+    let keep_alive = KeepAlive::new()
+        .interval(Duration::from_secs(state.config.server.sse_keepalive_secs.max(1)))
+        .text("ping");
+
+    Sse::new(stream::unfold(Some((events, from)), |cursor| async move {
+        // `None` once the close event has gone out, which ends the stream
+        let (events, index) = cursor?;
+        match events.next(index).await {
+            NextEvent::Step { id, data } => {
+                let mut event = Event::default().data(data.as_ref());
+                if let Some(id) = id {
+                    event = event.id(id);
+                }
+                Some((Ok(event), Some((events, index + 1))))
+            }
+            // A comment keeps the stream going past an event that can't be replayed
+            NextEvent::Lost => Some((Ok(Event::default().comment("event unavailable")), Some((events, index + 1)))),
+            NextEvent::End => {
                 // Send a final "close" event before ending the stream
                 let event = Event::default()
                     .event("close")
                     .data("stream complete");
-                Some((Ok(event), rx))
+                Some((Ok(event), None))
             }
         }
     }))
+    .keep_alive(keep_alive)
 }
\ No newline at end of file
@@ -0,0 +1,118 @@
+use super::forge::{check_sender, resolve_mentions, select_rpc};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, ForgeRequest, IntentPreview, RequiredApproval, ResolvedName};
+use crate::processors::{ClassificationError, LLMGenerator};
+use crate::utils::{preflight_rpc, validate_forge_request, CircuitOpen, PreflightFailure};
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use std::{sync::Arc, time::Duration};
+
+/// Work out what an intent would do — its action plan, protocols, resolved names
+/// and the approvals it needs — without generating or simulating a script, so the
+/// user can confirm the interpretation first
+pub async fn preview_intent(
+    State(state): State<Arc<AppState>>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Json(mut request): Json<ForgeRequest>,
+) -> Result<Json<IntentPreview>, Response> {
+    validate_forge_request(&mut request, &state.config.validation).map_err(IntoResponse::into_response)?;
+    select_rpc(&state, &mut request).await?;
+    let auth = auth.map(|Extension(AuthenticatedAddress(address))| address);
+    check_sender(&mut request, auth).await?;
+
+    let rpc_url = request
+        .rpc_url
+        .clone()
+        .unwrap_or_else(|| "http://localhost:8545".to_string());
+    let rpc = &state.config.rpc;
+    if rpc.preflight {
+        let timeout = Duration::from_secs(rpc.preflight_timeout_secs);
+        preflight_rpc(&rpc_url, request.chain_id, rpc.require_archive, timeout)
+            .await
+            .map_err(|failure| {
+                let status = match failure {
+                    PreflightFailure::Unreachable(_) => StatusCode::BAD_GATEWAY,
+                    _ => StatusCode::BAD_REQUEST,
+                };
+                (status, failure.to_string()).into_response()
+            })?;
+    }
+    let mentions = resolve_mentions(&state, &rpc_url, &request.intent)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+
+    let llm_timeout = Duration::from_secs(state.config.timeouts.llm_secs);
+    let generator = state.template_generator.lock().await;
+    let (plan, protocols) = tokio::time::timeout(llm_timeout, async {
+        let plan = generator.parse_intent(&request.intent).await?;
+        let protocols = match state.protocol_processor.detect_protocols(&*generator, &request.intent).await {
+            Err(e) if matches!(e.downcast_ref(), Some(ClassificationError::NoMatchingGuidelines(_))) => Vec::new(),
+            protocols => protocols?,
+        };
+        Ok::<_, eyre::Report>((plan, protocols))
+    })
+    .await
+    .map_err(|_| (StatusCode::GATEWAY_TIMEOUT, "Intent parsing timed out").into_response())?
+    .map_err(|e| {
+        let status = match e.downcast_ref::<CircuitOpen>() {
+            Some(_) => StatusCode::SERVICE_UNAVAILABLE,
+            None => StatusCode::BAD_GATEWAY,
+        };
+        (status, format!("Failed to parse intent: {}", e)).into_response()
+    })?;
+    drop(generator);
+
+    let token_address = |token: &str| {
+        mentions
+            .tokens
+            .iter()
+            .find(|listed| listed.symbol.eq_ignore_ascii_case(token))
+            .map(|listed| listed.address)
+            .or_else(|| token.parse::<Address>().ok())
+    };
+    let approvals = plan
+        .actions
+        .iter()
+        .filter(|action| action.action.pulls_tokens())
+        .filter_map(|action| {
+            // Native ETH is sent as value and needs no allowance
+            let token = action.tokens.first().filter(|token| !token.eq_ignore_ascii_case("ETH"))?;
+            Some(RequiredApproval {
+                token: token.clone(),
+                address: token_address(token).map(|address| to_checksum(&address, None)),
+                amount: action.amounts.first().cloned(),
+                protocol: action.protocol.clone(),
+            })
+        })
+        .collect();
+
+    Ok(Json(IntentPreview {
+        protocols,
+        chain_id: mentions.chain_id,
+        from_address: request.from_address,
+        ens_names: mentions
+            .names
+            .iter()
+            .map(|(name, address)| ResolvedName {
+                name: name.clone(),
+                address: to_checksum(address, None),
+            })
+            .collect(),
+        tokens: mentions
+            .tokens
+            .iter()
+            .map(|token| ResolvedName {
+                name: token.symbol.clone(),
+                address: to_checksum(&token.address, None),
+            })
+            .collect(),
+        approvals,
+        plan,
+    }))
+}
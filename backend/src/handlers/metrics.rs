@@ -0,0 +1,45 @@
+use crate::models::AppState;
+use crate::utils::METRICS;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::{atomic::Ordering, Arc};
+
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Gauges derived from state are sampled at scrape time
+    METRICS
+        .active_sessions
+        .set(state.temp_dirs.lock().await.len() as i64);
+    METRICS
+        .job_slots_available
+        .set(state.process_limiter.available_permits() as i64);
+    METRICS
+        .job_queue_depth
+        .set(state.queued_jobs.load(Ordering::Relaxed) as i64);
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(),
+    )
+}
+
+/// Count requests per matched route so path parameters don't explode cardinality
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let endpoint = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    METRICS
+        .http_requests
+        .with_label_values(&[&endpoint, response.status().as_str()])
+        .inc();
+
+    response
+}
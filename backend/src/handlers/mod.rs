@@ -1,3 +1,33 @@
+mod auth;
+mod bundle;
+mod chains;
+mod feedback;
 mod forge;
+mod intent;
+mod metrics;
+mod models;
+mod protocols;
+mod rate_limit;
+mod replay;
+mod sessions;
+mod request_id;
+mod stats;
+mod submit;
+mod versions;
 
-pub use forge::{fix_forge_process, stream_forge_process}; 
\ No newline at end of file
+pub use bundle::export_bundle;
+pub use chains::list_chains;
+pub use feedback::submit_feedback;
+pub use forge::{fix_forge_process, stream_forge_process};
+pub use intent::preview_intent;
+pub use metrics::{metrics_handler, track_requests};
+pub use models::list_models;
+pub use protocols::{get_guideline, list_protocols};
+pub use rate_limit::rate_limit;
+pub use replay::replay_session;
+pub use sessions::{ack_session, delete_session, enforce_retention, enforce_storage_quota};
+pub use stats::{collect_stats, stats_handler};
+pub use submit::submit_private_transaction;
+pub use versions::diff_versions;
+pub use request_id::{assign_request_id, RequestId};
+pub use auth::{auth_nonce, auth_verify, require_session, AuthenticatedAddress};
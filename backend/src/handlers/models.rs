@@ -0,0 +1,12 @@
+use crate::models::{AppState, ModelList};
+use axum::{extract::State, Json};
+use std::sync::Arc;
+
+/// Models the LLM gateway serves, for a client-side model picker
+pub async fn list_models(State(state): State<Arc<AppState>>) -> Json<ModelList> {
+    Json(ModelList {
+        models: state.models.clone(),
+        code_model: state.config.llm.code_model.clone(),
+        chat_model: state.config.llm.chat_model.clone(),
+    })
+}
@@ -0,0 +1,42 @@
+use crate::models::{AppState, GuidelineRequest, ProtocolInfo};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+/// Protocols the generator has guidelines for, for a client-side protocol picker
+pub async fn list_protocols(State(state): State<Arc<AppState>>) -> Json<Vec<ProtocolInfo>> {
+    Json(state.protocol_processor.protocols())
+}
+
+/// A protocol's guideline markdown, exactly as the generator is given it, or
+/// with `full=true` the full version it was compressed from. Only the loaded
+/// version is kept, so asking for another one is a 404.
+pub async fn get_guideline(
+    State(state): State<Arc<AppState>>,
+    Path(protocol): Path<String>,
+    Query(request): Query<GuidelineRequest>,
+) -> Response {
+    let Some((content, version)) = state.protocol_processor.guideline(&protocol, request.full) else {
+        return (StatusCode::NOT_FOUND, format!("No guidelines for protocol {}", protocol)).into_response();
+    };
+    if request.version.as_ref().is_some_and(|requested| *requested != version) {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("The {} guideline is at version {}", protocol, version),
+        )
+            .into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/markdown; charset=utf-8".to_string()),
+            (header::ETAG, format!("\"{}\"", version)),
+        ],
+        content.to_string(),
+    )
+        .into_response()
+}
@@ -0,0 +1,76 @@
+use crate::models::AppState;
+use crate::utils::RateLimitError;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use std::sync::Arc;
+
+/// Header carrying the caller's API key. Browsers can't set headers on an
+/// `EventSource`, so an `api_key` query parameter is accepted as well.
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Seconds a client should wait before retrying when all its job slots are busy
+const JOB_RETRY_AFTER_SECS: u64 = 5;
+
+pub fn api_key(headers: &HeaderMap, uri: &Uri) -> Option<String> {
+    headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+        .or_else(|| query_param(uri, "api_key"))
+}
+
+/// Raw value of a query parameter, for middleware that runs before extractors
+pub(super) fn query_param(uri: &Uri, name: &str) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+pub async fn rate_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = api_key(request.headers(), request.uri());
+
+    let guard = match state.rate_limiter.acquire(key.as_deref()) {
+        Ok(guard) => guard,
+        Err(RateLimitError::UnknownKey) => {
+            return (StatusCode::UNAUTHORIZED, "Missing or unknown API key").into_response();
+        }
+        Err(RateLimitError::TooManyRequests(retry_after)) => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.as_secs().max(1).to_string())],
+                "Rate limit exceeded",
+            )
+                .into_response();
+        }
+        Err(RateLimitError::TooManyJobs) => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, JOB_RETRY_AFTER_SECS.to_string())],
+                "Too many concurrent jobs for this API key",
+            )
+                .into_response();
+        }
+    };
+
+    // Jobs stream their progress in the response body, so the job slot is held
+    // until the body is dropped rather than until the handler returns
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _guard = &guard;
+        chunk
+    });
+
+    Response::from_parts(parts, Body::from_stream(body))
+}
@@ -0,0 +1,110 @@
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, ReplayRequest, SessionData};
+use crate::utils::EVENT_LOG_FILE;
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures::stream::{self, Stream};
+use serde_json::Value;
+use std::{
+    convert::Infallible,
+    fs,
+    path::{Path as FsPath, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Longest pause between replayed events, so idle stretches don't stall a replay
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(5);
+
+/// Re-stream a session's recorded events with their original pacing, scaled by
+/// `speed` (2.0 plays twice as fast, 0 sends everything at once)
+pub async fn replay_session(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Query(request): Query<ReplayRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let speed = request.speed.unwrap_or(1.0);
+    if !speed.is_finite() || speed < 0.0 {
+        return Err((StatusCode::BAD_REQUEST, "speed must be a non-negative number").into_response());
+    }
+
+    let session_dir = find_session(&state, &session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+
+    let log = fs::read_to_string(session_dir.join(EVENT_LOG_FILE))
+        .map_err(|_| (StatusCode::NOT_FOUND, "No event log recorded for this session").into_response())?;
+    let events = log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .collect::<Vec<_>>();
+
+    let keep_alive = KeepAlive::new()
+        .interval(Duration::from_secs(state.config.server.sse_keepalive_secs.max(1)))
+        .text("ping");
+
+    Ok(Sse::new(stream::unfold(
+        (events.into_iter(), None::<u64>, false),
+        move |(mut events, previous, done)| async move {
+            if done {
+                return None;
+            }
+            let Some(mut event) = events.next() else {
+                let event = Event::default().event("close").data("replay complete");
+                return Some((Ok(event), (events, previous, true)));
+            };
+
+            let timestamp = event
+                .as_object_mut()
+                .and_then(|object| object.remove("timestamp_ms"))
+                .and_then(|timestamp| timestamp.as_u64());
+            if let (Some(previous), Some(timestamp)) = (previous, timestamp) {
+                if speed > 0.0 {
+                    let gap = Duration::from_millis(timestamp.saturating_sub(previous)).div_f64(speed);
+                    tokio::time::sleep(gap.min(MAX_REPLAY_GAP)).await;
+                }
+            }
+
+            let event = Event::default().data(event.to_string());
+            Some((Ok(event), (events, timestamp.or(previous), false)))
+        },
+    ))
+    .keep_alive(keep_alive))
+}
+
+/// With SIWE enabled, only the wallet that created a session may read it
+pub(crate) fn check_session_owner(
+    session_dir: &FsPath,
+    auth: Option<Extension<AuthenticatedAddress>>,
+) -> Result<(), (StatusCode, &'static str)> {
+    if let Some(Extension(AuthenticatedAddress(address))) = auth {
+        let owner = fs::read_to_string(session_dir.join("session.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str::<SessionData>(&content).ok())
+            .and_then(|session| session.owner());
+        if owner != Some(address) {
+            return Err((StatusCode::FORBIDDEN, "Session belongs to a different address"));
+        }
+    }
+    Ok(())
+}
+
+/// Session ids are the final component of the session directory path
+pub(crate) async fn find_session(state: &AppState, session_id: &str) -> Option<PathBuf> {
+    state
+        .temp_dirs
+        .lock()
+        .await
+        .values()
+        .map(|dir| dir.path().to_path_buf())
+        .find(|path| path.file_name().is_some_and(|name| name == session_id))
+}
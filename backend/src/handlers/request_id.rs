@@ -0,0 +1,39 @@
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use uuid::Uuid;
+
+pub static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies one HTTP request and the job it starts across logs, spans and events
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+/// Reuse a well-formed `x-request-id` from the client or generate one, expose it
+/// to handlers and the request span, and echo it in the response
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let header = HeaderValue::from_str(&id).expect("request ids are ASCII");
+    request.headers_mut().insert(REQUEST_ID_HEADER.clone(), header.clone());
+    request.extensions_mut().insert(RequestId(id));
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER.clone(), header);
+    response
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
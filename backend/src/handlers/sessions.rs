@@ -0,0 +1,197 @@
+use super::replay::{check_session_owner, find_session};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, SessionDeleted};
+use crate::utils::{session_size, METRICS};
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::io;
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Delete a session and everything kept about it: its directory (script,
+/// conversation, versions, event and prompt logs), its recorded events and the
+/// results it cached. The audit trail and on-chain feedback are append-only and
+/// keep their records.
+pub async fn delete_session(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+) -> Result<Json<SessionDeleted>, Response> {
+    let session_dir = idle_session(&state, &session_id, auth).await?;
+    let cached_results = purge_session(&state, &session_dir).await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove session directory: {}", e)).into_response()
+    })?;
+    info!(session_id, "Session deleted");
+
+    Ok(Json(SessionDeleted { session_id, cached_results }))
+}
+
+/// The client has what it needs from a session. Unless the retention policy says
+/// otherwise, the session is removed now rather than when it expires.
+pub async fn ack_session(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+) -> Result<StatusCode, Response> {
+    let session_dir = idle_session(&state, &session_id, auth).await?;
+    if !state.config.retention.purge_on_ack {
+        return Ok(StatusCode::ACCEPTED);
+    }
+    purge_session(&state, &session_dir).await.map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to remove session directory: {}", e)).into_response()
+    })?;
+    info!(session_id, "Session acknowledged and removed");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Directory of a session the caller owns and no job is running for
+async fn idle_session(
+    state: &AppState,
+    session_id: &str,
+    auth: Option<Extension<AuthenticatedAddress>>,
+) -> Result<PathBuf, Response> {
+    let session_dir = find_session(state, session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+    let session = session_dir.to_string_lossy().to_string();
+    let running = state
+        .session_events
+        .lock()
+        .await
+        .get(&session)
+        .is_some_and(|events| !events.is_finished());
+    if running {
+        return Err((StatusCode::CONFLICT, "A job is still running for this session").into_response());
+    }
+    Ok(session_dir)
+}
+
+/// Remove a session's directory, recorded events and cached results, returning
+/// how many results it had cached. The directory isn't recycled into the project pool.
+pub(crate) async fn purge_session(state: &AppState, session_dir: &FsPath) -> io::Result<usize> {
+    let session = session_dir.to_string_lossy().to_string();
+    let events = state.session_events.lock().await.remove(&session);
+    if let Some(events) = events {
+        state.running_jobs.lock().await.retain(|_, running| !Arc::ptr_eq(running, &events));
+    }
+
+    let dir = state.temp_dirs.lock().await.remove(&session);
+    if let Some(dir) = dir {
+        tokio::task::spawn_blocking(move || dir.close()).await.map_err(io::Error::other)??;
+    }
+
+    state.session_env.remove(session_dir);
+    let cached_results = state.result_cache.purge(session_dir);
+    state.stats.forget_session(&session);
+    Ok(cached_results)
+}
+
+/// Measure session storage every interval until shutdown. While it's over the
+/// quota, the sessions that finished longest ago are evicted if configured.
+pub async fn enforce_storage_quota(state: Arc<AppState>) {
+    let config = &state.config.storage;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown.cancelled() => break,
+        }
+
+        let dirs = state
+            .temp_dirs
+            .lock()
+            .await
+            .values()
+            .map(|dir| dir.path().to_path_buf())
+            .collect::<Vec<_>>();
+        let sizes = tokio::task::spawn_blocking(move || {
+            dirs.into_iter()
+                .map(|dir| {
+                    let size = session_size(&dir);
+                    (dir, size)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        let mut used = sizes.iter().map(|(_, size)| size).sum::<u64>();
+
+        if config.evict_completed && state.storage.exceeds(used) {
+            let session_events = state.session_events.lock().await;
+            let mut finished = sizes
+                .into_iter()
+                .filter_map(|(dir, size)| {
+                    let finished_at = session_events.get(dir.to_string_lossy().as_ref())?.finished_at()?;
+                    Some((finished_at, dir, size))
+                })
+                .collect::<Vec<_>>();
+            drop(session_events);
+            finished.sort_by_key(|(finished_at, _, _)| *finished_at);
+
+            for (_, dir, size) in finished {
+                if !state.storage.exceeds(used) {
+                    break;
+                }
+                match purge_session(&state, &dir).await {
+                    Ok(_) => {
+                        used = used.saturating_sub(size);
+                        METRICS.sessions_evicted.with_label_values(&["quota"]).inc();
+                        info!(session = %dir.display(), "Evicted session over the storage quota");
+                    }
+                    Err(e) => warn!(session = %dir.display(), "Failed to evict session: {}", e),
+                }
+            }
+        }
+
+        state.storage.set_used(used);
+        if let Some((used, quota)) = state.storage.exceeded() {
+            warn!("Session storage is at {} MB, over its {} MB quota", used, quota);
+        }
+    }
+}
+
+/// Remove sessions whose job ended longer ago than the retention policy keeps
+/// them for, checking every interval until shutdown
+pub async fn enforce_retention(state: Arc<AppState>) {
+    let config = &state.config.retention;
+    let keep_failed = Duration::from_secs(config.failed_hours * 3600);
+    let keep_completed = Duration::from_secs(config.completed_minutes * 60);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.check_interval_secs.max(1)));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.shutdown.cancelled() => break,
+        }
+
+        // Only sessions still holding a directory; released ones are recycled
+        let sessions = state.temp_dirs.lock().await.keys().cloned().collect::<Vec<_>>();
+        let session_events = state.session_events.lock().await;
+        let expired = sessions
+            .into_iter()
+            .filter_map(|session| {
+                let (finished_at, succeeded) = session_events.get(&session)?.outcome()?;
+                let (keep, reason) = if succeeded { (keep_completed, "completed") } else { (keep_failed, "failed") };
+                (finished_at.elapsed() >= keep).then_some((session, reason))
+            })
+            .collect::<Vec<_>>();
+        drop(session_events);
+
+        for (session, reason) in expired {
+            match purge_session(&state, FsPath::new(&session)).await {
+                Ok(_) => {
+                    METRICS.sessions_evicted.with_label_values(&[reason]).inc();
+                    info!(session, reason, "Removed expired session");
+                }
+                Err(e) => warn!(session, "Failed to remove expired session: {}", e),
+            }
+        }
+    }
+}
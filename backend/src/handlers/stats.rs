@@ -0,0 +1,14 @@
+use crate::models::{AppState, Stats};
+use axum::{extract::State, Json};
+use std::sync::Arc;
+
+/// Per-protocol and per-variant usage, success rate and fix iterations, LLM latency
+/// and on-chain feedback since startup
+pub async fn stats_handler(State(state): State<Arc<AppState>>) -> Json<Stats> {
+    Json(collect_stats(&state))
+}
+
+pub fn collect_stats(state: &AppState) -> Stats {
+    let experiment = state.experiment.as_ref().map(|experiment| experiment.name.as_str());
+    state.stats.snapshot(&state.feedback, experiment)
+}
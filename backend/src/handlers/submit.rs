@@ -0,0 +1,74 @@
+use super::replay::{check_session_owner, find_session};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, SessionData, SubmitRequest, SubmitResponse};
+use crate::utils::rpc_provider;
+use axum::{
+    extract::{Extension, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, Bytes};
+use ethers::utils::{hex, rlp::Rlp};
+use std::fs;
+use std::sync::Arc;
+use tracing::info;
+
+/// Send a transaction the session's sender signed through its chain's private
+/// RPC, keeping it out of the public mempool where swaps get sandwiched. Only
+/// the sender's own transactions are relayed.
+pub async fn submit_private_transaction(
+    State(state): State<Arc<AppState>>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Json(request): Json<SubmitRequest>,
+) -> Result<Json<SubmitResponse>, Response> {
+    let config = &state.config.private_rpc;
+    if !config.enabled {
+        return Err((StatusCode::NOT_FOUND, "Private submission is disabled on this server").into_response());
+    }
+
+    let raw = hex::decode(request.raw_transaction.trim_start_matches("0x"))
+        .map_err(|_| (StatusCode::BAD_REQUEST, "raw_transaction must be hex").into_response())?;
+    let (transaction, signature) = TypedTransaction::decode_signed(&Rlp::new(&raw))
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Not a signed transaction: {}", e)).into_response())?;
+    let signer = signature
+        .recover(transaction.sighash())
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid signature: {}", e)).into_response())?;
+    let chain_id = transaction
+        .chain_id()
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "The transaction must be signed for a chain id (EIP-155)").into_response())?
+        .as_u64();
+
+    let session_dir = find_session(&state, &request.session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+    let sender = fs::read_to_string(session_dir.join("session.json"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<SessionData>(&content).ok())
+        .and_then(|session| session.from_address)
+        .and_then(|from| from.parse::<Address>().ok());
+    if sender != Some(signer) {
+        return Err((StatusCode::FORBIDDEN, "The transaction isn't signed by the session's sender").into_response());
+    }
+
+    let endpoint = config.endpoints.get(&chain_id.to_string()).ok_or_else(|| {
+        (StatusCode::UNPROCESSABLE_ENTITY, format!("No private RPC is configured for chain {}", chain_id)).into_response()
+    })?;
+    // Private RPC URLs may carry an API key; only the host is reported
+    let relay = reqwest::Url::parse(endpoint)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_default();
+    let provider = rpc_provider(endpoint.as_str())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    let pending = provider
+        .send_raw_transaction(Bytes::from(raw))
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, format!("{} rejected the transaction: {}", relay, e)).into_response())?;
+
+    let hash = format!("{:?}", pending.tx_hash());
+    info!(session = %request.session_id, chain_id, relay = %relay, hash = %hash, "Relayed transaction privately");
+    Ok(Json(SubmitResponse { hash, chain_id, relay }))
+}
@@ -0,0 +1,42 @@
+use super::replay::{check_session_owner, find_session};
+use crate::handlers::AuthenticatedAddress;
+use crate::models::{AppState, DiffRequest, VersionDiff};
+use crate::utils::{compare_transactions, parse_version, read_version, unified_diff};
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+/// Compare two stored script versions of a session: the source diff and how
+/// the simulated transactions changed, e.g. to review what an auto-fix did
+pub async fn diff_versions(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    auth: Option<Extension<AuthenticatedAddress>>,
+    Query(request): Query<DiffRequest>,
+) -> Result<Json<VersionDiff>, Response> {
+    let (Some(from), Some(to)) = (parse_version(&request.from), parse_version(&request.to)) else {
+        return Err((StatusCode::BAD_REQUEST, "from and to must be versions like v1 or 1").into_response());
+    };
+
+    let session_dir = find_session(&state, &session_id)
+        .await
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Session not found").into_response())?;
+    check_session_owner(&session_dir, auth).map_err(IntoResponse::into_response)?;
+
+    let read = |version| read_version(&session_dir, version).map_err(|e| (StatusCode::NOT_FOUND, e.to_string()));
+    let (old_script, old_transactions) = read(from).map_err(IntoResponse::into_response)?;
+    let (new_script, new_transactions) = read(to).map_err(IntoResponse::into_response)?;
+
+    Ok(Json(VersionDiff {
+        from,
+        to,
+        diff: unified_diff("script/Script.s.sol", &old_script, &new_script, 3),
+        transactions: old_transactions
+            .zip(new_transactions)
+            .map(|(old, new)| compare_transactions(&old, &new)),
+    }))
+}
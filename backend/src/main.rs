@@ -4,16 +4,22 @@ mod handlers;
 mod utils;
 
 use crate::processors::{
-    HeuristLLM, LLMGenerator, LLMImpl, ProtocolGuidelinesProcessor,
+    ExampleStore, HeuristLLM, LLMGenerator, LLMImpl, ProtocolGuidelinesProcessor, VerifiedContract,
+    etherscan::{abi_signatures, extract_contract_source, get_etherscan_contract},
 };
 use axum::{
-    routing::get,
+    middleware,
+    routing::{delete, get, post},
     Router,
     extract::State,
 };
 use eyre::Result;
-use handlers::{stream_forge_process, fix_forge_process};
+use handlers::{
+    ack_session, assign_request_id, auth_nonce, auth_verify, delete_session, diff_versions, enforce_retention, export_bundle, enforce_storage_quota, fix_forge_process, get_guideline, list_chains, list_models, list_protocols, metrics_handler, rate_limit, require_session, submit_private_transaction,
+    collect_stats, preview_intent, replay_session, stats_handler, stream_forge_process, submit_feedback, track_requests,
+};
 use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::sync::Semaphore;
@@ -21,87 +27,310 @@ use tower_http::{
     cors::{CorsLayer, Any},
     trace::{self, TraceLayer},
 };
-use tracing::{info, Level};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use crate::models::{Cli, Commands, AppState};
-use std::path::PathBuf;
+use tracing::{info, warn, Level};
+use crate::models::{AppState, AuditAction, BaseProjectAction, BaseProjectConfig, Cli, Commands, Config, FoundryConfig, LlmConfig, RiskConfig, SandboxMode};
+use std::path::{Path, PathBuf};
 use clap::Parser;
 use eyre::eyre;
 use std::fs;
+use std::future::IntoFuture;
+use std::time::Duration;
+use ethers::types::Address;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 use std::process::Command;
-use crate::utils::run_command_with_output;
+use crate::utils::{
+    configure_rpc_client, export_audit, export_stats, AnvilForks, init_tracing, make_request_span, package_remappings, register_secret, write_foundry_toml, AuthStore, CircuitBreaker, Experiment, ProjectPool, RateLimiter, AuditLog, FeedbackStore, StorageQuota, JobStats, ResultCache, RpcPool, SessionEnv, TemplateStore, TokenCache, TokenRegistry, METRICS,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG")
-                .unwrap_or_else(|_| "backend=debug,tower_http=debug".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     let cli = Cli::parse();
+    let mut config = Config::load(&cli.config)?;
+    for secret in config.secrets() {
+        register_secret(secret);
+    }
+    configure_rpc_client(&config.rpc);
+
+    // Initialize tracing
+    let tracer_provider = init_tracing(&config.tracing)?;
 
     match cli.command {
-        Some(Commands::Server) => {
-            run_server().await?;
+        Some(Commands::Server { host, port }) => {
+            if !host.is_empty() {
+                config.server.hosts = host;
+            }
+            if let Some(port) = port {
+                config.server.port = port;
+            }
+            run_server(config).await?;
+        },
+        Some(Commands::GenerateGuidelines { protocol, links, address, chain, output_dir }) => match address {
+            Some(address) => generate_contract_guidelines(&config.risk, protocol, address, chain, output_dir).await?,
+            None => generate_protocol_guidelines(protocol, links.unwrap_or_default(), output_dir).await?,
+        },
+        Some(Commands::CompressGuidelines { protocol, dir }) => {
+            compress_protocol_guidelines(protocol, dir).await?;
         },
-        Some(Commands::GenerateGuidelines { protocol, links, output_dir  }) => {
-            generate_protocol_guidelines(protocol, links, output_dir).await?;
+        Some(Commands::BaseProject { action: BaseProjectAction::Update }) => {
+            update_base_project(config).await?;
+        },
+        Some(Commands::Audit { action: AuditAction::Export { output, session, since } }) => {
+            let path = Path::new(&config.audit.path);
+            match output {
+                Some(output) => {
+                    let exported = export_audit(path, session.as_deref(), since, &mut fs::File::create(&output)?)?;
+                    info!("Audit chain verified, exported {} records to {:?}", exported, output);
+                }
+                // Records go to stdout alone, so they can be piped
+                None => {
+                    export_audit(path, session.as_deref(), since, &mut std::io::stdout().lock())?;
+                }
+            }
         },
         None => {
             // Default to running the server if no command is provided
-            run_server().await?;
+            run_server(config).await?;
         }
     }
 
+    if let Some(provider) = tracer_provider {
+        provider.shutdown().ok();
+    }
+
     Ok(())
 }
 
-async fn run_server() -> Result<()> {
+async fn run_server(mut config: Config) -> Result<()> {
     info!("Starting server...");
+    if config.auth.siwe_enabled && config.auth.domain.is_none() {
+        return Err(eyre!("auth.siwe_enabled requires auth.domain, the domain sign-in messages must be issued for"));
+    }
+    if config.policy.enabled && config.policy.max_risk_score.is_some() && !config.risk.enabled {
+        return Err(eyre!("policy.max_risk_score rejects bundles that aren't scored, so it requires risk.enabled"));
+    }
+    if matches!(config.sandbox.mode, SandboxMode::Bwrap | SandboxMode::Nsjail) && !config.sandbox.allow_unrestricted_ports {
+        return Err(eyre!(
+            "sandbox.mode \"{}\" can't restrict simulations to the fork RPC's port; use \"landlock\", or set sandbox.allow_unrestricted_ports",
+            format!("{:?}", config.sandbox.mode).to_lowercase()
+        ));
+    }
 
-    let base_forge_dir = initialize_base_project().await?;
+    let base_forge_dir = initialize_base_project(&config.base_project, &config.foundry).await?;
+
+    resolve_shared_cache(&mut config)?;
+    if config.build.prebuild {
+        prebuild_base_project(&base_forge_dir, config.build.shared_cache_path.as_deref());
+    }
     
     // Initialize protocol guidelines
     let protocol_processor = ProtocolGuidelinesProcessor::new("./guidelines")?;
     info!("Loaded protocol guidelines: {:?}", protocol_processor.available_protocols());
 
-    let template_generator = LLMImpl::Heurist(HeuristLLM::new("cesar#huret-1")?);
+    let examples = if config.few_shot.enabled {
+        ExampleStore::load(&config.few_shot.dir)?
+    } else {
+        ExampleStore::default()
+    };
+    info!("Loaded {} example scripts", examples.len());
+
+    let token_registry = TokenRegistry::load(&config.token_lists).await;
+
+    let llm_breaker = config
+        .llm
+        .circuit_breaker
+        .enabled
+        .then(|| Arc::new(CircuitBreaker::new(config.llm.circuit_breaker.clone())));
+    let generator = |code_model: &str, chat_model: &str| -> Result<LLMImpl> {
+        Ok(LLMImpl::Heurist(
+            HeuristLLM::new("cesar#huret-1")?
+                .with_models(code_model, chat_model)
+                .with_breaker(llm_breaker.clone(), config.llm.fallback.as_ref())
+                .with_stream_resumes(config.llm.stream_resumes)
+                .with_max_continuations(config.llm.max_continuations),
+        ))
+    };
+    let template_generator = generator(&config.llm.code_model, &config.llm.chat_model)?;
+    let models = discover_models(&template_generator, &config.llm).await?;
+    let experiment = config
+        .experiment
+        .as_ref()
+        .map(|experiment| Experiment::new(experiment, &config.llm, generator))
+        .transpose()?;
+    if let Some(experiment) = &experiment {
+        info!("Running experiment {}", experiment.name);
+    }
+    let max_jobs = config.server.max_concurrent_jobs;
+    METRICS.job_slots_total.set(max_jobs as i64);
     let state = Arc::new(AppState {
         template_generator: Mutex::new(template_generator),
-        process_limiter: Arc::new(Semaphore::new(100)),
+        process_limiter: Arc::new(Semaphore::new(max_jobs)),
+        queued_jobs: Arc::new(AtomicUsize::new(0)),
         temp_dirs: Mutex::new(HashMap::new()),
+        session_events: Mutex::new(HashMap::new()),
+        running_jobs: Mutex::new(HashMap::new()),
         protocol_processor: Arc::new(protocol_processor),
-        base_forge_dir,
+        examples,
+        templates: TemplateStore::load(&config.templates),
+        feedback: FeedbackStore::load(&config.feedback),
+        stats: JobStats::default(),
+        experiment,
+        audit: config.audit.enabled.then(|| AuditLog::open(&config.audit)).transpose()?,
+        storage: StorageQuota::new(&config.storage),
+        session_env: SessionEnv::default(),
+        anvil_forks: AnvilForks::default(),
+        rpc_pool: RpcPool::new(&config.rpc),
+        project_pool: ProjectPool::new(base_forge_dir, config.build.shared_cache_path.clone(), config.server.warm_pool_size),
+        shutdown: CancellationToken::new(),
+        tasks: TaskTracker::new(),
+        rate_limiter: RateLimiter::new(config.rate_limit.clone()),
+        auth: AuthStore::new(&config.auth),
+        token_registry,
+        token_cache: TokenCache::new(Duration::from_secs(config.portfolio.cache_ttl_secs)),
+        result_cache: ResultCache::new(Duration::from_secs(config.cache.ttl_secs), config.cache.max_entries),
+        llm_breaker,
+        models,
+        config: config.clone(),
     });
 
-    let app = Router::new()
+    let forge_routes = Router::new()
         .route("/forge/stream", get(stream_forge_process))
         .route("/forge/fix", get(fix_forge_process))
+        .route("/forge/replay/:session_id", get(replay_session))
+        .route("/forge/diff/:session_id", get(diff_versions))
+        .route("/forge/bundle/:session_id", get(export_bundle))
+        .route("/forge/feedback", post(submit_feedback))
+        .route("/forge/submit", post(submit_private_transaction))
+        .route("/sessions/:session_id", delete(delete_session))
+        .route("/sessions/:session_id/ack", post(ack_session))
+        .route("/intent/preview", post(preview_intent))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_session))
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit));
+
+    let app = Router::new()
+        .merge(forge_routes)
+        .route("/auth/nonce", get(auth_nonce))
+        .route("/auth/verify", post(auth_verify))
+        .route("/models", get(list_models))
+        .route("/chains", get(list_chains))
+        .route("/protocols", get(list_protocols))
+        .route("/guidelines/:protocol", get(get_guideline))
+        .route("/metrics", get(metrics_handler))
+        .route("/stats", get(stats_handler))
+        .route_layer(middleware::from_fn(track_requests))
         .layer(
             TraceLayer::new_for_http()
-                .make_span_with(trace::DefaultMakeSpan::new()
-                    .level(Level::INFO))
+                .make_span_with(make_request_span)
                 .on_response(trace::DefaultOnResponse::new()
                     .level(Level::INFO)),
         )
+        // Outside the trace layer so the request span can record the id
+        .layer(middleware::from_fn(assign_request_id))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        .with_state(state.clone());
 
     info!("Routes registered: {:?}", app);
 
-    let addr = "0.0.0.0:3000";
-    info!("Listening on http://{}", addr);
-    
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let mut servers = Vec::new();
+    for addr in config.server.bind_addresses() {
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .map_err(|e| eyre!("Failed to bind {}: {}", addr, e))?;
+        info!("Listening on http://{}", addr);
+        servers.push(
+            axum::serve(listener, app.clone())
+                .with_graceful_shutdown(state.shutdown.clone().cancelled_owned())
+                .into_future(),
+        );
+    }
+
+    tokio::spawn(state.project_pool.clone().run_refill(state.shutdown.clone()));
+    tokio::spawn(enforce_retention(state.clone()));
+    if config.storage.quota_mb.is_some() {
+        tokio::spawn(enforce_storage_quota(state.clone()));
+    }
+    let stats_state = state.clone();
+    tokio::spawn(export_stats(config.stats.clone(), move || collect_stats(&stats_state), state.shutdown.clone()));
+
+    let shutdown = state.shutdown.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutdown signal received, draining in-flight jobs...");
+        shutdown.cancel();
+    });
+
+    let grace = Duration::from_secs(config.server.shutdown_grace_secs);
+    tokio::select! {
+        result = futures::future::try_join_all(servers) => { result?; }
+        _ = async {
+            state.shutdown.cancelled().await;
+            tokio::time::sleep(grace).await;
+        } => {
+            warn!("Timed out waiting for open connections to close");
+        }
+    }
+
+    // Jobs abort on cancellation and kill their child processes; wait for them to finish
+    state.tasks.close();
+    if tokio::time::timeout(grace, state.tasks.wait()).await.is_err() {
+        warn!("Timed out waiting for {} in-flight jobs", state.tasks.len());
+    }
+
+    // Dropping the session temp dirs removes them from disk
+    state.temp_dirs.lock().await.clear();
+    info!("Shutdown complete");
 
     Ok(())
 }
 
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+/// Ask the gateway which models it serves, checking the configured ones are among
+/// them. A gateway that can't list its models leaves the list empty.
+async fn discover_models(llm: &LLMImpl, config: &LlmConfig) -> Result<Vec<String>> {
+    let models = match llm.list_models().await {
+        Ok(models) => models,
+        Err(e) => {
+            warn!("Failed to list the LLM gateway's models: {}", e);
+            return Ok(Vec::new());
+        }
+    };
+    info!("LLM gateway serves {} models", models.len());
+
+    for model in [&config.code_model, &config.chat_model] {
+        if models.contains(model) {
+            continue;
+        }
+        if config.validate_models {
+            return Err(eyre!("Configured model {} isn't served by the LLM gateway", model));
+        }
+        warn!("Configured model {} isn't served by the LLM gateway", model);
+    }
+    Ok(models)
+}
+
 async fn generate_protocol_guidelines(
     protocol: String, 
     links: String, 
@@ -130,10 +359,157 @@ async fn generate_protocol_guidelines(
     Ok(())
 }
 
-async fn initialize_base_project() -> Result<PathBuf> {
+async fn generate_contract_guidelines(
+    config: &RiskConfig,
+    protocol: String,
+    address: String,
+    chain_id: u64,
+    output_dir: PathBuf,
+) -> Result<()> {
+    let api_key = config
+        .etherscan_api_key
+        .as_deref()
+        .ok_or_else(|| eyre!("Set risk.etherscan_api_key to generate guidelines from a contract"))?;
+    let address = format!("{:?}", address.parse::<Address>()?);
+    info!("Generating guidelines for protocol {} from {} on chain {}", protocol, address, chain_id);
+
+    let contract = get_etherscan_contract(&config.etherscan_url, chain_id, &address, api_key).await?;
+    if !contract.is_verified() {
+        return Err(eyre!("{} isn't verified on chain {}", address, chain_id));
+    }
+    // Through a proxy, the logic and its ABI are the implementation's
+    let (logic, implementation) = match contract.implementation() {
+        Some(implementation) => {
+            info!("{} is a proxy for {}", address, implementation);
+            let logic = get_etherscan_contract(&config.etherscan_url, chain_id, implementation, api_key).await?;
+            if !logic.is_verified() {
+                return Err(eyre!("{}'s implementation {} isn't verified on chain {}", address, implementation, chain_id));
+            }
+            (logic, Some(implementation.to_string()))
+        }
+        None => (contract, None),
+    };
+
+    let verified = VerifiedContract {
+        address,
+        chain_id,
+        source: extract_contract_source(&logic)?,
+        signatures: abi_signatures(&logic.abi)?,
+        name: logic.contract_name,
+        implementation,
+    };
+
+    let protocol_processor = ProtocolGuidelinesProcessor::new(&output_dir)?;
+    let llm = HeuristLLM::new("cesar#huret-1")?;
+    protocol_processor.generate_contract_guidelines(&llm, protocol, &verified).await?;
+    info!("Guidelines generated successfully");
+
+    Ok(())
+}
+
+async fn compress_protocol_guidelines(protocol: Option<String>, dir: PathBuf) -> Result<()> {
+    let protocol_processor = ProtocolGuidelinesProcessor::new(&dir)?;
+    let llm = HeuristLLM::new("cesar#huret-1")?;
+
+    let protocols = match protocol {
+        Some(protocol) => vec![protocol],
+        None => {
+            let mut protocols = protocol_processor.available_protocols();
+            protocols.sort();
+            protocols
+        }
+    };
+    for protocol in protocols {
+        let content = protocol_processor.compress_guideline(&llm, &protocol).await?;
+        info!("Compressed guidelines for {} to {} characters", protocol, content.len());
+    }
+
+    Ok(())
+}
+
+/// forge resolves a relative cache path against each project root, so pin it down
+fn resolve_shared_cache(config: &mut Config) -> Result<()> {
+    if let Some(cache_path) = &config.build.shared_cache_path {
+        fs::create_dir_all(cache_path)?;
+        let cache_path = fs::canonicalize(cache_path)?;
+        config.build.shared_cache_path = Some(cache_path);
+    }
+    Ok(())
+}
+
+/// Rebuild the base project from scratch at the pinned versions
+async fn update_base_project(mut config: Config) -> Result<()> {
+    let base_dir = &config.base_project.dir;
+    if base_dir.exists() {
+        info!("Removing existing base project at {:?}", base_dir);
+        fs::remove_dir_all(base_dir)?;
+    }
+
+    let base_dir = initialize_base_project(&config.base_project, &config.foundry).await?;
+    resolve_shared_cache(&mut config)?;
+    prebuild_base_project(&base_dir, config.build.shared_cache_path.as_deref());
+
+    info!("Base project updated at {:?}", base_dir);
+    Ok(())
+}
+
+/// Check out a tag or commit of an installed library
+fn pin_library(base_dir: &Path, name: &str, rev: &str) -> Result<()> {
+    let lib_dir = base_dir.join("lib").join(name);
+    for args in [vec!["fetch", "--tags", "origin"], vec!["checkout", rev]] {
+        let output = Command::new("git").args(&args).current_dir(&lib_dir).output()?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "git {} failed for {}: {}",
+                args.join(" "),
+                name,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+    }
+    info!("Pinned {} to {}", name, rev);
+    Ok(())
+}
+
+/// Compile the installed libraries once, so pooled session dirs carry their
+/// artifacts and a session's `forge script` only compiles the generated script
+fn prebuild_base_project(base_dir: &Path, cache_path: Option<&Path>) {
+    info!("Pre-building base forge project libraries...");
+
+    // Each library's own sources, not its tests or scripts
+    let sources = fs::read_dir(base_dir.join("lib"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name() != "forge-std")
+        .filter_map(|entry| {
+            ["contracts", "src"]
+                .iter()
+                .map(|dir| entry.path().join(dir))
+                .find(|path| path.is_dir())
+        })
+        .collect::<Vec<_>>();
+
+    let mut command = Command::new("forge");
+    command.arg("build").args(&sources).current_dir(base_dir);
+    if let Some(cache_path) = cache_path {
+        command.env("FOUNDRY_CACHE_PATH", cache_path);
+    }
+
+    match command.output() {
+        Ok(output) if output.status.success() => info!("Base project libraries built"),
+        Ok(output) => warn!(
+            "Pre-build failed, sessions will compile libraries themselves: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(e) => warn!("Failed to run forge build: {}", e),
+    }
+}
+
+async fn initialize_base_project(config: &BaseProjectConfig, foundry: &FoundryConfig) -> Result<PathBuf> {
     info!("Initializing base forge project...");
     
-    let base_dir = PathBuf::from("./base_forge_project");
+    let base_dir = config.dir.clone();
     if !base_dir.exists() {
         fs::create_dir_all(&base_dir)?;
         
@@ -143,20 +519,22 @@ async fn initialize_base_project() -> Result<PathBuf> {
             .current_dir(&base_dir)
             .output()?;
 
-        // Install dependencies
-        let dependencies = [
-            "openzeppelin/openzeppelin-contracts",
-            "Uniswap/v3-core",
-            "Uniswap/v3-periphery",
-            "aave/aave-v3-core",
-            "aave/aave-v3-periphery"
-        ];
-
-        for dep in dependencies.iter() {
-            Command::new("forge")
-                .args(&["install", dep, "--no-commit"])
+        if let Some(rev) = &config.forge_std {
+            pin_library(&base_dir, "forge-std", rev)?;
+        }
+
+        for dependency in config.dependencies.iter().filter(|dependency| dependency.preinstall) {
+            let output = Command::new("forge")
+                .args(&["install", &dependency.install_target(), "--no-commit"])
                 .current_dir(&base_dir)
                 .output()?;
+            if !output.status.success() {
+                return Err(eyre!(
+                    "Failed to install {}: {}",
+                    dependency.name,
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
         }
 
         // Generate remappings
@@ -165,12 +543,15 @@ async fn initialize_base_project() -> Result<PathBuf> {
             .current_dir(&base_dir)
             .output()?;
 
-        // Write the output to remappings.txt
-        fs::write(
-            base_dir.join("remappings.txt"),
-            output.stdout
-        )?;
+        // Other packages are installed per session when a script imports them,
+        // but their remappings are listed up front so prompts can reference them
+        let mut remappings = String::from_utf8_lossy(&output.stdout).to_string();
+        remappings.push_str(&package_remappings(&config.dependencies));
+        fs::write(base_dir.join("remappings.txt"), remappings)?;
     }
 
+    // Regenerated on every start so config changes reach existing base projects
+    write_foundry_toml(&base_dir, foundry)?;
+
     Ok(base_dir)
 }
\ No newline at end of file
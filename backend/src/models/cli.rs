@@ -4,6 +4,10 @@ use clap::{Parser, Subcommand};
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
+    /// Path to the TOML config file
+    #[arg(long, global = true, default_value = "./config.toml")]
+    pub config: PathBuf,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -11,7 +15,15 @@ pub struct Cli {
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Start the web server
-    Server,
+    Server {
+        /// Address to bind to, can be repeated to listen on several addresses
+        #[arg(long)]
+        host: Vec<String>,
+
+        /// Port to listen on
+        #[arg(long)]
+        port: Option<u16>,
+    },
     
     /// Generate protocol guidelines
     GenerateGuidelines {
@@ -20,8 +32,16 @@ pub enum Commands {
         protocol: String,
         
         /// Documentation links, comma-separated
+        #[arg(short, long, required_unless_present = "address", conflicts_with = "address")]
+        links: Option<String>,
+
+        /// A verified contract to generate from instead, for protocols without docs
         #[arg(short, long)]
-        links: String,
+        address: Option<String>,
+
+        /// Chain id the contract is deployed on
+        #[arg(long, default_value_t = 1, requires = "address")]
+        chain: u64,
         
         /// Output directory for markdown files
         #[arg(short, long, default_value = "./guidelines")]
@@ -29,6 +49,54 @@ pub enum Commands {
         
 
     },
+
+    /// Write prompt-optimized versions of protocol guidelines, which generation
+    /// is given instead of the full ones
+    CompressGuidelines {
+        /// Only this protocol; all of them if not given
+        #[arg(short, long)]
+        protocol: Option<String>,
+
+        /// Directory holding the guidelines
+        #[arg(short, long, default_value = "./guidelines")]
+        dir: PathBuf,
+    },
+
+    /// Manage the base forge project copied into every session
+    BaseProject {
+        #[command(subcommand)]
+        action: BaseProjectAction,
+    },
+
+    /// Work with the audit trail of generated scripts and simulations
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BaseProjectAction {
+    /// Re-create the base project with the dependency versions pinned in config
+    Update,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuditAction {
+    /// Verify the hash chain and export the records as JSONL
+    Export {
+        /// File to write to, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Only this session's records
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Only records from this Unix timestamp on
+        #[arg(long)]
+        since: Option<u64>,
+    },
 }
 
 #[derive(Parser, Debug)]
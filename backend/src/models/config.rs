@@ -0,0 +1,1056 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tracing: TracingConfig,
+    pub rate_limit: RateLimitConfig,
+    pub auth: AuthConfig,
+    pub validation: ValidationConfig,
+    pub analysis: AnalysisConfig,
+    pub policy: PolicyConfig,
+    pub sandbox: SandboxConfig,
+    pub timeouts: TimeoutConfig,
+    pub build: BuildConfig,
+    pub base_project: BaseProjectConfig,
+    pub foundry: FoundryConfig,
+    pub prices: PricesConfig,
+    pub portfolio: PortfolioConfig,
+    pub token_lists: TokenListsConfig,
+    pub explanations: ExplanationsConfig,
+    pub risk: RiskConfig,
+    pub invariants: InvariantsConfig,
+    pub verification: VerificationConfig,
+    pub llm: LlmConfig,
+    pub cache: ResultCacheConfig,
+    pub guidelines: GuidelinesConfig,
+    pub few_shot: FewShotConfig,
+    pub templates: TemplatesConfig,
+    pub feedback: FeedbackConfig,
+    pub stats: StatsConfig,
+    /// Traffic split between prompt or model variants; unset runs no experiment
+    pub experiment: Option<ExperimentConfig>,
+    pub audit: AuditConfig,
+    pub storage: StorageConfig,
+    pub retention: RetentionConfig,
+    pub anvil: AnvilConfig,
+    pub bundle: BundleConfig,
+    pub private_rpc: PrivateRpcConfig,
+    pub rpc: RpcConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Addresses to bind the HTTP server to, one listener per entry
+    pub hosts: Vec<String>,
+    pub port: u16,
+    /// How long to wait for in-flight jobs and connections on shutdown
+    pub shutdown_grace_secs: u64,
+    /// Forge/fix jobs that may run at once across all clients; the rest wait in a queue
+    pub max_concurrent_jobs: usize,
+    /// Pre-copied project directories kept ready for new sessions
+    pub warm_pool_size: usize,
+    /// Seconds between keep-alive comments on idle SSE streams
+    pub sse_keepalive_secs: u64,
+    /// Events a job may queue before it waits for them to be recorded; progress
+    /// updates are dropped rather than waited on
+    pub event_buffer: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            hosts: vec!["0.0.0.0".to_string()],
+            port: 3000,
+            shutdown_grace_secs: 10,
+            max_concurrent_jobs: 100,
+            warm_pool_size: 4,
+            sse_keepalive_secs: 15,
+            event_buffer: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// OTLP/HTTP collector endpoint, e.g. `http://localhost:4318/v1/traces`. Export is disabled when unset.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "ff-backend".to_string(),
+        }
+    }
+}
+
+/// Limits applied to the forge routes per API key. Unset limits are unlimited.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Reject requests without a key listed in `keys`
+    pub require_api_key: bool,
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent_jobs: Option<u32>,
+    /// Per-key overrides of the defaults above
+    pub keys: HashMap<String, KeyLimits>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct KeyLimits {
+    pub requests_per_minute: Option<u32>,
+    pub max_concurrent_jobs: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Require a Sign-In with Ethereum session on the forge routes
+    pub siwe_enabled: bool,
+    /// Domain (host, and port if not the default) SIWE messages must be issued
+    /// for and their URI must be on; required when SIWE is enabled
+    pub domain: Option<String>,
+    /// Chain ids SIWE messages may be signed for; any when empty
+    pub chain_ids: Vec<u64>,
+    pub session_ttl_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            siwe_enabled: false,
+            domain: None,
+            chain_ids: Vec::new(),
+            session_ttl_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub max_intent_chars: usize,
+    /// Limit on the forge error text clients send to `/forge/fix`
+    pub max_error_chars: usize,
+    pub allowed_rpc_schemes: Vec<String>,
+    /// Let requests preview intents as addresses they don't control by
+    /// impersonating them on an anvil fork
+    pub allow_impersonation: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_intent_chars: 2000,
+            max_error_chars: 20000,
+            allowed_rpc_schemes: vec!["http".to_string(), "https".to_string()],
+            allow_impersonation: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisEngine {
+    Off,
+    /// Pattern heuristics, no external tools required
+    #[default]
+    Builtin,
+    /// Run `slither`, falling back to the built-in checks if it fails
+    Slither,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    pub engine: AnalysisEngine,
+    /// Withhold the simulated transactions when a high-severity issue is found
+    pub block_on_high: bool,
+}
+
+/// Rules generated code must follow before it is compiled. Violations are
+/// returned as errors so the fix loop can rewrite the script.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    pub enabled: bool,
+    pub forbid_selfdestruct: bool,
+    pub forbid_delegatecall: bool,
+    pub forbid_tx_origin: bool,
+    /// Reject `vm.warp` and `vm.roll` outside time-travel simulations,
+    /// where moving the fork's clock would misrepresent what the transactions do
+    pub forbid_time_travel: bool,
+    /// Reject transfers to hardcoded addresses that aren't the user's or in the guidelines
+    pub forbid_unknown_recipients: bool,
+    /// Extra addresses transfers may go to
+    pub allowed_addresses: Vec<String>,
+    /// Reject protocol deadlines that are hardcoded timestamps or further out than `max_deadline_secs`
+    pub forbid_bad_deadlines: bool,
+    /// Buffer after `block.timestamp` generated scripts are told to use for deadlines
+    pub deadline_buffer_secs: u64,
+    pub max_deadline_secs: u64,
+    /// Reject simulated bundles whose risk score is above this, or that couldn't
+    /// be scored; unset allows any score
+    pub max_risk_score: Option<u32>,
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            forbid_selfdestruct: true,
+            forbid_delegatecall: true,
+            forbid_tx_origin: true,
+            forbid_time_travel: true,
+            forbid_unknown_recipients: true,
+            allowed_addresses: Vec::new(),
+            forbid_bad_deadlines: true,
+            deadline_buffer_secs: 1200,
+            max_deadline_secs: 3600,
+            max_risk_score: None,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// A custom prohibited substring
+#[derive(Debug, Clone, Deserialize)]
+pub struct PolicyRule {
+    pub name: String,
+    pub pattern: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMode {
+    #[default]
+    None,
+    /// bubblewrap: read-only root, writable session dir, no network unless needed
+    Bwrap,
+    Nsjail,
+    /// Linux landlock: filesystem writes limited to the session dir, TCP connects
+    /// limited to the fork RPC port
+    Landlock,
+}
+
+/// Isolation for the `forge`/`npm`/`slither` processes that run generated code
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SandboxConfig {
+    pub mode: SandboxMode,
+    /// Only pass allowlisted environment variables to child processes
+    pub restrict_env: bool,
+    /// Variable names, or prefixes ending in `*`
+    pub env_allowlist: Vec<String>,
+    /// Variables a request may set for its session's forge runs with `x-forge-env:
+    /// NAME=value` headers, such as `PRIVATE_KEY`. Exact names only.
+    pub request_env: Vec<String>,
+    /// Extra paths child processes may write to besides the session dir and forge caches
+    pub writable_paths: Vec<String>,
+    /// Address-space limit per child process
+    pub memory_limit_mb: Option<u64>,
+    /// CPU-time limit per child process
+    pub cpu_time_limit_secs: Option<u64>,
+    /// Accept that bwrap and nsjail can't limit simulations to the fork RPC's
+    /// port and share the whole host network with them; those modes are
+    /// refused at startup without it
+    pub allow_unrestricted_ports: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            mode: SandboxMode::None,
+            restrict_env: true,
+            env_allowlist: ["PATH", "HOME", "USER", "LANG", "LC_*", "TMPDIR", "FOUNDRY_*", "SVM_*"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            request_env: Vec::new(),
+            writable_paths: Vec::new(),
+            memory_limit_mb: None,
+            cpu_time_limit_secs: None,
+            allow_unrestricted_ports: false,
+        }
+    }
+}
+
+/// Wall-clock limits per pipeline step; steps that overrun are killed and reported
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Whole LLM call, including streaming the response
+    pub llm_secs: u64,
+    pub forge_build_secs: u64,
+    pub forge_script_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            llm_secs: 180,
+            forge_build_secs: 180,
+            forge_script_secs: 300,
+        }
+    }
+}
+
+/// Where USD estimates for transaction values and token deltas come from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PricesConfig {
+    pub enabled: bool,
+    /// Chainlink aggregators pricing each chain's native token in USD, by chain
+    /// id, read on the fork. Values go unpriced on chains without one.
+    pub native_usd_feeds: HashMap<String, String>,
+    /// CoinGecko API for ERC-20 prices; token deltas go unpriced when unset
+    pub coingecko_url: Option<String>,
+    pub coingecko_api_key: Option<String>,
+    /// CoinGecko asset platform of each chain's tokens, by chain id; tokens on
+    /// other chains go unpriced
+    pub coingecko_platforms: HashMap<String, String>,
+}
+
+impl Default for PricesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            native_usd_feeds: HashMap::from([
+                ("1".to_string(), "0x5f4eC3Df9cbd43714FE2740f5E3616155c5b8419".to_string()),
+                ("10".to_string(), "0x13e3Ee699D1909E989722E753853AE30b17e08c5".to_string()),
+                ("8453".to_string(), "0x71041dddad3595F9CEd3DcCFBe3D1F4b0a16Bb70".to_string()),
+                ("42161".to_string(), "0x639Fe6ab55C921f74e7fac1ee960C0B6293ba612".to_string()),
+            ]),
+            coingecko_url: None,
+            coingecko_api_key: None,
+            coingecko_platforms: HashMap::from([
+                ("1".to_string(), "ethereum".to_string()),
+                ("10".to_string(), "optimistic-ethereum".to_string()),
+                ("56".to_string(), "binance-smart-chain".to_string()),
+                ("137".to_string(), "polygon-pos".to_string()),
+                ("8453".to_string(), "base".to_string()),
+                ("42161".to_string(), "arbitrum-one".to_string()),
+                ("43114".to_string(), "avalanche".to_string()),
+            ]),
+        }
+    }
+}
+
+/// The sender's token balances, summarized into the generation prompt so
+/// intents like "swap half my USDC" resolve to concrete amounts
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PortfolioConfig {
+    pub enabled: bool,
+    /// Alchemy key for balance and metadata lookups; no portfolio context without one
+    pub alchemy_api_key: Option<String>,
+    /// Most holdings listed in the prompt
+    pub max_holdings: usize,
+    /// Known scam token addresses, never listed
+    pub blocked_tokens: Vec<String>,
+    /// Treat tokens CoinGecko has no price for as illiquid spam; only applies
+    /// when `prices.coingecko_url` is set. Tokens on the token lists are always kept.
+    pub require_price: bool,
+    /// How long fetched balances and token metadata are reused
+    pub cache_ttl_secs: u64,
+    /// List the sender's NFTs and their token IDs too
+    pub include_nfts: bool,
+}
+
+impl Default for PortfolioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            alchemy_api_key: None,
+            max_holdings: 25,
+            blocked_tokens: Vec::new(),
+            require_price: true,
+            cache_ttl_secs: 60,
+            include_nfts: true,
+        }
+    }
+}
+
+/// Token lists that map symbols in intents to canonical addresses, on top of
+/// the bundled defaults
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TokenListsConfig {
+    /// Lists in the Uniswap token list format, fetched at startup
+    pub urls: Vec<String>,
+}
+
+impl Default for TokenListsConfig {
+    fn default() -> Self {
+        Self {
+            urls: vec!["https://tokens.uniswap.org".to_string()],
+        }
+    }
+}
+
+/// Risk scoring of the simulated transactions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RiskConfig {
+    pub enabled: bool,
+    /// Etherscan API (v2, multichain) for checking that call targets have verified source
+    pub etherscan_url: String,
+    /// Without a key, only token-listed and allowed addresses count as verified
+    pub etherscan_api_key: Option<String>,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            etherscan_url: "https://api.etherscan.io/v2/api".to_string(),
+            etherscan_api_key: None,
+        }
+    }
+}
+
+/// Reuse of results for identical requests (same intent, sender, chain,
+/// tolerance, guidelines and models)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ResultCacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+}
+
+impl Default for ResultCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 300,
+            max_entries: 500,
+        }
+    }
+}
+
+/// Curated example scripts included in the generation prompt, picked by the
+/// classified protocols and the planned actions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FewShotConfig {
+    pub enabled: bool,
+    /// Holds `<protocol>/<action>[-variant].s.sol` files
+    pub dir: String,
+    pub max_examples: usize,
+}
+
+impl Default for FewShotConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: "./examples".to_string(),
+            max_examples: 2,
+        }
+    }
+}
+
+/// How much protocol guidance goes into a generation prompt
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GuidelinesConfig {
+    /// Estimated tokens all of an intent's protocol guidelines may take together
+    pub max_tokens: usize,
+    /// Share no protocol gets less than, however many the intent touches
+    pub min_tokens_per_protocol: usize,
+}
+
+impl Default for GuidelinesConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: 12_000,
+            min_tokens_per_protocol: 2_000,
+        }
+    }
+}
+
+/// Scripts that simulated successfully, kept as templates for intents of the same
+/// shape (same wording, other amounts and addresses) on the same chain. A template
+/// that has run often enough without failing is used instead of the LLM.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TemplatesConfig {
+    pub enabled: bool,
+    /// JSON file the templates are kept in
+    pub path: String,
+    /// Runs a template needs before it's used
+    pub min_frequency: u64,
+    pub min_success_rate: f64,
+}
+
+impl Default for TemplatesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "./templates.json".to_string(),
+            min_frequency: 3,
+            min_success_rate: 0.9,
+        }
+    }
+}
+
+/// On-chain outcomes reported through `POST /forge/feedback`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FeedbackConfig {
+    /// JSONL file the reports are appended to
+    pub path: String,
+    /// Reports a protocol needs before its failure rate can flag it
+    pub min_reports: u64,
+    /// Failure rate above which a protocol's guidelines are flagged
+    pub max_failure_rate: f64,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self {
+            path: "./feedback.jsonl".to_string(),
+            min_reports: 5,
+            max_failure_rate: 0.3,
+        }
+    }
+}
+
+/// Periodic export of `/stats` snapshots
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatsConfig {
+    /// JSONL file a snapshot is appended to every interval; unset disables the export
+    pub export_path: Option<String>,
+    pub export_interval_secs: u64,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self {
+            export_path: None,
+            export_interval_secs: 3600,
+        }
+    }
+}
+
+/// Disk space session directories may take up
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Unset for no limit
+    pub quota_mb: Option<u64>,
+    /// Over the quota, remove the sessions that finished longest ago before
+    /// refusing new ones
+    pub evict_completed: bool,
+    /// How often usage is measured
+    pub check_interval_secs: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            quota_mb: None,
+            evict_completed: true,
+            check_interval_secs: 30,
+        }
+    }
+}
+
+/// How long sessions are kept once their job has ended
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Sessions whose last job failed, kept around for debugging
+    pub failed_hours: u64,
+    /// Sessions whose last job produced a result, long enough to fix or resume
+    pub completed_minutes: u64,
+    /// Remove a session as soon as its client acknowledges the result
+    pub purge_on_ack: bool,
+    /// How often expired sessions are looked for
+    pub check_interval_secs: u64,
+}
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            failed_hours: 24,
+            completed_minutes: 30,
+            purge_on_ack: true,
+            check_interval_secs: 60,
+        }
+    }
+}
+
+/// Simulations against an anvil node, such as the default `http://localhost:8545` fork
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnvilConfig {
+    /// Snapshot the node before each simulation and revert to it afterwards,
+    /// so fix-loop runs all start from the same state
+    pub snapshots: bool,
+    /// ETH a `simulate_with_funding` sender is given
+    pub funding_eth: u64,
+    /// Whole tokens it's given of each token the intent mentions
+    pub funding_token_units: u64,
+}
+
+impl Default for AnvilConfig {
+    fn default() -> Self {
+        Self {
+            snapshots: true,
+            funding_eth: 100,
+            funding_token_units: 1_000_000,
+        }
+    }
+}
+
+/// How the RPCs requests simulate against are used
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RpcConfig {
+    /// Check a job's RPC answers on the right chain before any LLM call
+    pub preflight: bool,
+    pub preflight_timeout_secs: u64,
+    /// Also require the RPC to serve historical state
+    pub require_archive: bool,
+    /// RPCs by chain id for requests that give `chain_id` without `rpc_url`,
+    /// tried before the chain's preset public RPC
+    pub endpoints: HashMap<String, Vec<String>>,
+    /// How long an endpoint's measured latency is trusted before it's probed again
+    pub probe_interval_secs: u64,
+    /// How long an endpoint that failed is left out of rotation
+    pub failure_cooldown_secs: u64,
+    /// Times a request the provider throttled is retried before failing
+    pub max_retries: u32,
+    /// First retry's backoff, doubled for each later retry up to `max_backoff_ms`
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Requests per second sent to any one provider host; 0 is unlimited.
+    /// Local nodes aren't limited.
+    pub requests_per_second: u32,
+    /// Budgets for particular provider hosts, overriding `requests_per_second`
+    pub provider_budgets: HashMap<String, u32>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            preflight: true,
+            preflight_timeout_secs: 10,
+            require_archive: false,
+            endpoints: HashMap::new(),
+            probe_interval_secs: 30,
+            failure_cooldown_secs: 60,
+            max_retries: 5,
+            initial_backoff_ms: 250,
+            max_backoff_ms: 8_000,
+            requests_per_second: 20,
+            provider_budgets: HashMap::new(),
+        }
+    }
+}
+
+/// Flashbots `eth_sendBundle` payloads built from a session's transactions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BundleConfig {
+    /// Tip per gas each bundled transaction pays the block builder
+    pub priority_fee_gwei: u64,
+    /// Max fee per gas as a multiple of the latest base fee, leaving room for
+    /// it to rise before the target block
+    pub base_fee_multiplier: u64,
+    /// Session environment variable holding the sender's key for signed bundles
+    pub signer_env: String,
+}
+
+impl Default for BundleConfig {
+    fn default() -> Self {
+        Self {
+            priority_fee_gwei: 2,
+            base_fee_multiplier: 2,
+            signer_env: "PRIVATE_KEY".to_string(),
+        }
+    }
+}
+
+/// RPCs that keep signed transactions out of the public mempool until they're
+/// mined, so swaps can't be sandwiched
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrivateRpcConfig {
+    pub enabled: bool,
+    /// Private RPC per chain id, such as Flashbots Protect or MEV Blocker
+    pub endpoints: HashMap<String, String>,
+}
+
+impl Default for PrivateRpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            endpoints: HashMap::from([
+                ("1".to_string(), "https://rpc.flashbots.net/fast".to_string()),
+                ("11155111".to_string(), "https://rpc-sepolia.flashbots.net".to_string()),
+            ]),
+        }
+    }
+}
+
+/// Hash-chained trail of generated scripts and simulations, for compliance
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "./audit.jsonl".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExperimentConfig {
+    pub name: String,
+    pub variants: Vec<VariantConfig>,
+}
+
+/// One arm of an experiment. A variant that sets nothing is the control.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VariantConfig {
+    pub name: String,
+    /// Share of sessions relative to the other variants
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Instead of the `[llm]` models
+    pub code_model: Option<String>,
+    pub chat_model: Option<String>,
+    /// Extra instructions added to the generation prompt
+    pub prompt: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Models used on the LLM gateway
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LlmConfig {
+    /// Writes and fixes scripts and tests
+    pub code_model: String,
+    /// Classification, planning and explanations
+    pub chat_model: String,
+    /// Refuse to start when the gateway's model list doesn't include both models
+    pub validate_models: bool,
+    /// Times an answer whose stream breaks off is resumed from where it stopped
+    pub stream_resumes: u32,
+    /// Times a script cut off at the token limit (or with unbalanced braces) is continued
+    pub max_continuations: u32,
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// OpenAI-compatible provider that takes calls while the gateway's circuit is open
+    pub fallback: Option<LlmFallbackConfig>,
+    /// Keep every request a session's jobs send, as sent, in its `prompts.jsonl`
+    pub log_prompts: bool,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            code_model: "qwen/qwen-2.5-coder-32b-instruct".to_string(),
+            chat_model: "mistralai/mixtral-8x7b-instruct".to_string(),
+            validate_models: true,
+            stream_resumes: 2,
+            max_continuations: 3,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            fallback: None,
+            log_prompts: true,
+        }
+    }
+}
+
+/// When to stop calling a failing LLM gateway
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    /// Consecutive failed or slow calls that open the circuit
+    pub failure_threshold: u32,
+    /// Calls taking longer than this count as failures; for streamed calls, until the stream starts
+    pub slow_call_secs: u64,
+    /// How long the circuit stays open before a trial call is let through
+    pub open_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            failure_threshold: 5,
+            slow_call_secs: 60,
+            open_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LlmFallbackConfig {
+    pub api_base: String,
+    pub api_key: String,
+    /// Stand-ins for `code_model` and `chat_model` on this provider
+    pub code_model: String,
+    pub chat_model: String,
+}
+
+/// Checking the simulated balance changes against the intent's action plan.
+/// Needs `prices.enabled`, which is what computes the sender's token deltas.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VerificationConfig {
+    pub enabled: bool,
+    /// Fail the job (back to the fix loop) instead of flagging the result with a warning
+    pub fail_on_mismatch: bool,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fail_on_mismatch: false,
+        }
+    }
+}
+
+/// LLM-written forge tests asserting the balance changes an intent implies,
+/// run on the fork after the script simulates
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct InvariantsConfig {
+    pub enabled: bool,
+}
+
+impl Default for InvariantsConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// The LLM pass that explains simulated transactions to the user
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExplanationsConfig {
+    pub enabled: bool,
+}
+
+impl Default for ExplanationsConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// Compile the base project's libraries at startup so sessions start with their artifacts
+    pub prebuild: bool,
+    /// `FOUNDRY_CACHE_PATH` of the base project's compile, copied into every session
+    /// so forge only recompiles sources whose content changed (in practice just the
+    /// generated script)
+    pub shared_cache_path: Option<PathBuf>,
+    /// `forge build` generated scripts before simulating them, fixing compile errors right away
+    pub compile_first: bool,
+    /// LLM fixes tried on compile errors before the job fails with them
+    pub max_compile_fixes: usize,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            prebuild: true,
+            shared_cache_path: Some(PathBuf::from("./forge_cache")),
+            compile_first: true,
+            max_compile_fixes: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BaseProjectConfig {
+    /// Template project copied into every session
+    pub dir: PathBuf,
+    /// forge-std tag or commit; whatever `forge init` installs when unset
+    pub forge_std: Option<String>,
+    /// Packages sessions can import, installed when a script first imports them
+    /// unless `preinstall` puts them in the base project
+    pub dependencies: Vec<DependencyConfig>,
+}
+
+/// What goes into the base project's foundry.toml, and so every session's,
+/// in place of what `forge init` wrote
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FoundryConfig {
+    /// `alias = "url"`, usable as `--fork-url` or with `vm.createFork`
+    pub rpc_endpoints: HashMap<String, String>,
+    /// Block explorer API access by chain alias
+    pub etherscan: HashMap<String, EtherscanConfig>,
+    /// Paths cheatcodes like `vm.readFile` may touch; none when empty
+    pub fs_permissions: Vec<FsPermission>,
+    /// Settings per profile, over the generated `default` one. `session` is
+    /// reserved for the settings a request asks for.
+    pub profiles: HashMap<String, toml::Table>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EtherscanConfig {
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<u64>,
+    /// API URL, for explorers foundry doesn't know
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FsPermission {
+    /// `read`, `write` or `read-write`
+    pub access: String,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DependencyConfig {
+    /// Directory under `lib/` that `forge install` creates
+    pub name: String,
+    /// `forge install` target, e.g. `openzeppelin/openzeppelin-contracts`
+    pub repo: String,
+    /// Tag or commit to install; the default branch when unset
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// remappings.txt entry, `prefix=target`
+    pub remapping: String,
+    #[serde(default)]
+    pub preinstall: bool,
+}
+
+impl DependencyConfig {
+    fn new(name: &str, repo: &str, remapping: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            repo: repo.to_string(),
+            tag: None,
+            remapping: remapping.to_string(),
+            preinstall: false,
+        }
+    }
+
+    /// Import prefix the remapping applies to
+    pub fn prefix(&self) -> &str {
+        self.remapping.split_once('=').map_or(self.remapping.as_str(), |(prefix, _)| prefix)
+    }
+
+    /// `repo@tag` when pinned
+    pub fn install_target(&self) -> String {
+        match &self.tag {
+            Some(tag) => format!("{}@{}", self.repo, tag),
+            None => self.repo.clone(),
+        }
+    }
+
+    pub fn is_installed(&self, project_path: &Path) -> bool {
+        project_path
+            .join("lib")
+            .join(&self.name)
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+    }
+}
+
+impl Default for BaseProjectConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("./base_forge_project"),
+            forge_std: None,
+            dependencies: vec![
+                DependencyConfig::new(
+                    "openzeppelin-contracts",
+                    "openzeppelin/openzeppelin-contracts",
+                    "@openzeppelin/contracts/=lib/openzeppelin-contracts/contracts/",
+                ),
+                DependencyConfig::new("v3-core", "Uniswap/v3-core", "@uniswap/v3-core/=lib/v3-core/"),
+                DependencyConfig::new(
+                    "v3-periphery",
+                    "Uniswap/v3-periphery",
+                    "@uniswap/v3-periphery/=lib/v3-periphery/",
+                ),
+                DependencyConfig::new("aave-v3-core", "aave/aave-v3-core", "@aave/core-v3/=lib/aave-v3-core/"),
+                DependencyConfig::new(
+                    "aave-v3-periphery",
+                    "aave/aave-v3-periphery",
+                    "@aave/periphery-v3/=lib/aave-v3-periphery/",
+                ),
+            ],
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Socket addresses to listen on, e.g. `0.0.0.0:3000` or `[::1]:3000`
+    pub fn bind_addresses(&self) -> Vec<String> {
+        self.hosts
+            .iter()
+            .map(|host| {
+                if host.contains(':') && !host.starts_with('[') {
+                    format!("[{}]:{}", host, self.port)
+                } else {
+                    format!("{}:{}", host, self.port)
+                }
+            })
+            .collect()
+    }
+}
+
+impl Config {
+    /// Load the config from a TOML file, falling back to defaults if it doesn't exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)?;
+        toml::from_str(&content)
+            .map_err(|e| eyre::eyre!("Failed to parse config {}: {}", path.display(), e))
+    }
+
+    /// Keys in the config that must never reach logs or clients
+    pub fn secrets(&self) -> Vec<&str> {
+        [
+            self.portfolio.alchemy_api_key.as_deref(),
+            self.prices.coingecko_api_key.as_deref(),
+            self.risk.etherscan_api_key.as_deref(),
+            self.llm.fallback.as_ref().map(|fallback| fallback.api_key.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.rate_limit.keys.keys().map(String::as_str))
+        .collect()
+    }
+}
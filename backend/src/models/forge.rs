@@ -1,53 +1,254 @@
+use ethers::types::{transaction::eip2718::TypedTransaction, Address};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use serde_json::Value;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use tempfile::TempDir;
 use tokio::sync::Mutex;
 use tokio::sync::Semaphore;
-use crate::processors::LLMImpl;
+use crate::processors::{ExampleStore, LLMImpl};
 use async_openai::types::ChatCompletionRequestUserMessage;
 use crate::ProtocolGuidelinesProcessor;
-use std::path::PathBuf;
+use crate::utils::{AnvilForks, AuditLog, AuthStore, CircuitBreaker, Experiment, FeedbackStore, JobStats, ProjectPool, RateLimiter, ResultCache, RpcPool, SessionEnv, SessionEvents, StorageQuota, TemplateStore, TokenCache, TokenRegistry};
+use crate::models::{ActionPlan, Config};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-#[derive(Serialize, Debug)]
-pub struct ForgeStep {
-    pub title: String,
-    pub output: String,
+/// An event streamed to the client over SSE, serialized as JSON tagged by `type`
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ForgeStep {
+    /// A session directory was created; its path is the `temp_dir` for /forge/fix
+    SessionCreated { session: String },
+    /// Human-readable progress of a pipeline stage
+    Status { stage: Stage, message: String },
+    /// Estimated overall progress of the job, for progress bars
+    Progress {
+        stage: Stage,
+        /// 0-100 across the whole job
+        percent: u8,
+        /// Units of work done and expected within the stage, e.g. generated tokens
+        #[serde(skip_serializing_if = "Option::is_none")]
+        done: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<u64>,
+    },
+    /// The structured action plan parsed from the intent
+    Plan { plan: ActionPlan },
+    /// A piece of the LLM response as it streams in
+    TokenDelta { text: String },
+    /// The script extracted from the LLM response
+    CodeChunk { code: String },
+    /// Unified diff of a fixed script against the previous version
+    ScriptDiff { diff: String },
+    /// Something the user should know before signing that doesn't fail the job
+    Warning { message: String },
+    /// A line of output from forge, npm or another child process
+    CompileOutput { stage: Stage, line: String },
+    /// A compiler error or warning about the script
+    Diagnostic {
+        stage: Stage,
+        #[serde(flatten)]
+        diagnostic: Diagnostic,
+    },
+    /// A console.log line from the script or a test
+    ConsoleLog { stage: Stage, message: String },
+    /// Gas a simulation or test used
+    GasReport { stage: Stage, name: String, gas_used: u64 },
+    /// The calls a simulation made, depth first
+    Trace { stage: Stage, calls: Vec<TraceCall> },
+    /// Transactions produced by a successful simulation
+    Transactions { transactions: Vec<TransactionDetails> },
+    /// Plain-English walkthrough of the transactions, for review before signing
+    Explanation { explanation: String },
+    /// A failure that ended the job
+    Error {
+        code: ErrorCode,
+        /// Stage that failed; `None` when the job was stopped from outside
+        stage: Option<Stage>,
+        /// Whether resending the same request may succeed
+        retriable: bool,
+        message: String,
+    },
+    /// An event from one part of a parallel request
+    Part { part: usize, event: Box<ForgeStep> },
+    /// Final event of a successful job, with everything needed to review and sign it
+    Result {
+        session: String,
+        script: String,
+        transactions: Vec<TransactionDetails>,
+        /// Net token movements for the sender across all transactions
+        token_deltas: Vec<TokenDelta>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        risk: Option<RiskReport>,
+        /// Set when an identical earlier request's result was reused: how old it is
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cached_age_secs: Option<u64>,
+        /// The transactions ran as an impersonated sender, so the user can't sign them as is
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        impersonated: bool,
+        /// The sender was given balances it doesn't hold, so the transactions
+        /// would fail on-chain as is
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        funded: bool,
+        /// The script warped time between phases, so each phase's transactions
+        /// can only be sent once that much time has really passed
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        time_travel: bool,
+    },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ForgeTransaction {
-    pub hash: Option<String>,
-    pub transactionType: String,
-    pub contractName: Option<String>,
-    pub contractAddress: String,
-    pub function: String,
-    pub arguments: Vec<String>,
-    pub transaction: ForgeTransactionDetails,
+impl ForgeStep {
+    pub fn status(stage: Stage, message: impl Into<String>) -> Self {
+        ForgeStep::Status {
+            stage,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(stage: Stage, code: ErrorCode, message: impl Into<String>) -> Self {
+        ForgeStep::Error {
+            code,
+            stage: Some(stage),
+            retriable: code.is_retriable(),
+            message: message.into(),
+        }
+    }
+
+    /// Progress at the start of `stage`
+    pub fn progress(stage: Stage) -> Self {
+        Self::progress_within(stage, 0, 1)
+    }
+
+    /// Progress `done` of an estimated `total` units into `stage`
+    pub fn progress_within(stage: Stage, done: u64, total: u64) -> Self {
+        let (start, end) = stage.progress_band();
+        let fraction = (done as f64 / total.max(1) as f64).min(1.0);
+        let counted = total > 1;
+        ForgeStep::Progress {
+            stage,
+            percent: start + ((end - start) as f64 * fraction) as u8,
+            done: counted.then_some(done),
+            total: counted.then_some(total),
+        }
+    }
+
+    /// The serialized `type`, for metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ForgeStep::SessionCreated { .. } => "session_created",
+            ForgeStep::Status { .. } => "status",
+            ForgeStep::Progress { .. } => "progress",
+            ForgeStep::Plan { .. } => "plan",
+            ForgeStep::TokenDelta { .. } => "token_delta",
+            ForgeStep::CodeChunk { .. } => "code_chunk",
+            ForgeStep::ScriptDiff { .. } => "script_diff",
+            ForgeStep::Warning { .. } => "warning",
+            ForgeStep::CompileOutput { .. } => "compile_output",
+            ForgeStep::Diagnostic { .. } => "diagnostic",
+            ForgeStep::ConsoleLog { .. } => "console_log",
+            ForgeStep::GasReport { .. } => "gas_report",
+            ForgeStep::Trace { .. } => "trace",
+            ForgeStep::Transactions { .. } => "transactions",
+            ForgeStep::Explanation { .. } => "explanation",
+            ForgeStep::Error { .. } => "error",
+            ForgeStep::Part { .. } => "part",
+            ForgeStep::Result { .. } => "result",
+        }
+    }
+
+    /// Events a later one of the same kind makes redundant, which may be
+    /// dropped when the client falls behind
+    pub fn is_droppable(&self) -> bool {
+        match self {
+            ForgeStep::Progress { .. } => true,
+            ForgeStep::Part { event, .. } => event.is_droppable(),
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ForgeTransactionDetails {
-    pub from: String,
-    pub to: String,
-    pub gas: String,
-    pub value: String,
-    pub input: String,
-    pub nonce: String,
-    pub chainId: String,
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Queued,
+    Initializing,
+    Planning,
+    Generating,
+    Writing,
+    InstallingDependencies,
+    Fixing,
+    Simulating,
+    SecurityReview,
+    Parsing,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ForgeOutput {
-    pub transactions: Vec<ForgeTransaction>,
-    pub receipts: Vec<Value>,
-    pub libraries: Vec<Value>,
-    pub pending: Vec<Value>,
-    pub returns: HashMap<String, Value>,
-    pub timestamp: u64,
-    pub chain: u64,
-    pub commit: Option<String>,
+impl Stage {
+    /// The slice of overall progress, in percent, a stage accounts for
+    fn progress_band(self) -> (u8, u8) {
+        match self {
+            Stage::Queued => (0, 0),
+            Stage::Initializing => (0, 5),
+            Stage::Planning => (5, 8),
+            Stage::Generating | Stage::Fixing => (8, 60),
+            Stage::Writing => (60, 65),
+            Stage::InstallingDependencies => (65, 70),
+            Stage::Simulating => (70, 90),
+            Stage::SecurityReview => (90, 95),
+            Stage::Parsing => (95, 100),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    Internal,
+    SessionNotFound,
+    SessionInvalid,
+    Forbidden,
+    LlmFailed,
+    LlmTimeout,
+    /// The LLM gateway's circuit breaker is open and no fallback is configured
+    LlmUnavailable,
+    EnsResolutionFailed,
+    InvalidScript,
+    PolicyViolation,
+    SecurityReviewBlocked,
+    DependencyInstallFailed,
+    CompileFailed,
+    SimulationReverted,
+    /// The script ran but the generated invariant checks failed
+    InvariantFailed,
+    /// The simulated balance changes don't accomplish the planned actions
+    IntentNotSatisfied,
+    SimulationFailed,
+    RpcUnreachable,
+    /// The RPC serves a different chain than the request asked for
+    RpcWrongChain,
+    /// The RPC doesn't keep the historical state the server requires
+    RpcNotArchive,
+    ForgeTimeout,
+    /// The request needs an anvil fork and the RPC isn't one
+    ForkUnsupported,
+    ShuttingDown,
+}
+
+impl ErrorCode {
+    /// Failures caused by the environment rather than the request or the
+    /// generated script, which a plain retry may get past
+    pub fn is_retriable(self) -> bool {
+        matches!(
+            self,
+            ErrorCode::LlmFailed
+                | ErrorCode::LlmTimeout
+                | ErrorCode::LlmUnavailable
+                | ErrorCode::DependencyInstallFailed
+                | ErrorCode::RpcUnreachable
+                | ErrorCode::ForgeTimeout
+                | ErrorCode::ShuttingDown
+        )
+    }
 }
 
 #[derive(Deserialize)]
@@ -55,12 +256,74 @@ pub struct ForgeRequest {
     pub intent: String,
     pub from_address: String,
     pub rpc_url: Option<String>,
+    /// Chain to simulate on through its preset public RPC, when `rpc_url` isn't given
+    pub chain_id: Option<u64>,
     pub session_id: Option<String>,
+    /// Split the intent into independent actions and simulate them concurrently
+    #[serde(default)]
+    pub parallel: bool,
+    /// Slippage tolerance for swaps, in basis points
+    pub slippage_bps: Option<u32>,
+    /// Generate and simulate even if an identical request's result is cached or
+    /// an identical request is already running
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Compiler settings for protocols whose code needs them, on top of the base project's
+    pub optimizer_runs: Option<u32>,
+    pub via_ir: Option<bool>,
+    pub evm_version: Option<String>,
+    /// Send the transactions to an anvil fork as `from_address` without its key,
+    /// to preview what an address the user doesn't control would do
+    #[serde(default)]
+    pub impersonate: bool,
+    /// Give the sender ETH and the tokens the intent mentions on an anvil fork
+    /// first, to preview an intent it couldn't afford
+    #[serde(default)]
+    pub simulate_with_funding: bool,
+    /// Let the script advance the fork's clock and block number between the
+    /// intent's phases, to preview an intent that spans time such as
+    /// "deposit, wait a week, claim rewards"
+    #[serde(default)]
+    pub time_travel: bool,
+    /// The signed-in address making the request, set by the server when SIWE
+    /// is on; differs from `from_address` when impersonating
+    #[serde(skip)]
+    pub requested_by: Option<Address>,
+    /// ID of the HTTP request that started the job, set by the server
+    #[serde(skip)]
+    pub request_id: String,
+}
+
+impl ForgeRequest {
+    pub fn compiler_settings(&self) -> CompilerSettings {
+        CompilerSettings {
+            optimizer: self.optimizer_runs.map(|_| true),
+            optimizer_runs: self.optimizer_runs,
+            via_ir: self.via_ir,
+            evm_version: self.evm_version.clone(),
+        }
+    }
+}
+
+/// Compiler settings a request asks for, kept as a profile in the session's
+/// foundry.toml; unset ones come from the base project
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompilerSettings {
+    /// Set whenever `optimizer_runs` is, which does nothing otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimizer: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimizer_runs: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via_ir: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evm_version: Option<String>,
 }
 
-#[derive(Serialize, Debug)]
-pub struct ForgeResponse {
-    pub transactions: Vec<ForgeTransaction>,
+impl CompilerSettings {
+    pub fn is_default(&self) -> bool {
+        self.optimizer_runs.is_none() && self.via_ir.is_none() && self.evm_version.is_none()
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -70,22 +333,321 @@ pub struct Transaction {
     pub value: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionDetails {
+    pub from: String,
     pub to: String,
     pub function: String,
     pub arguments: Vec<String>,
     pub value: String,
     pub input_data: String,
+    /// Gas forge estimated for the transaction
+    pub gas: String,
+    /// `value` in USD at the fork's ETH price
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_usd: Option<f64>,
+    /// Which independent part of a parallel request produced this transaction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub part: Option<usize>,
+    /// Position within its part's script
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<usize>,
+    /// Which phase of a time-travel simulation sent this transaction, counting
+    /// the `vm.warp`/`vm.roll` calls before it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phase: Option<usize>,
+    /// Set when the transaction deploys a contract. `to` is empty for a plain
+    /// CREATE and the CREATE2 factory otherwise.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<Deployment>,
+}
+
+impl TransactionDetails {
+    /// Whether this is a CREATE, which has no recipient
+    pub fn is_create(&self) -> bool {
+        self.to.is_empty()
+    }
+}
+
+/// A contract a transaction deploys
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deployment {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contract_name: Option<String>,
+    /// Where the contract lands, as simulated
+    pub address: String,
+    /// Creation bytecode with the encoded constructor arguments appended
+    pub init_code: String,
+    pub constructor_args: Vec<String>,
+    /// Set when the contract is a library the script is linked against: its `path:Name`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub library: Option<String>,
 } 
 
 
+/// A compiler error or warning
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    /// error, warning or info
+    pub severity: String,
+    /// solc's error code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// 1-based
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// The message with the offending source quoted, as forge prints it
+    pub formatted: String,
+}
+
+/// A call in a simulation's trace
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceCall {
+    pub depth: usize,
+    /// CALL, STATICCALL, DELEGATECALL, CREATE, ...
+    pub kind: String,
+    pub address: String,
+    /// Contract name or label, when forge knows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Decoded signature of the called function
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    pub success: bool,
+    pub gas_used: u64,
+}
+
+/// How risky signing the final transactions looks, from 0 (nothing flagged) to 100
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskReport {
+    pub score: u32,
+    pub level: RiskLevel,
+    pub reasons: Vec<RiskReason>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskLevel {
+    Low,
+    Medium,
+    High,
+}
+
+/// One finding that added to the risk score
+#[derive(Debug, Clone, Serialize)]
+pub struct RiskReason {
+    pub factor: RiskFactor,
+    pub points: u32,
+    /// Index of the transaction it was found in, when it's about a single one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<usize>,
+    pub detail: String,
+}
+
+impl std::fmt::Display for RiskReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.transaction {
+            Some(index) => write!(f, "- transaction {}: {} (+{})", index, self.detail, self.points),
+            None => write!(f, "- {} (+{})", self.detail, self.points),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskFactor {
+    UnlimitedApproval,
+    UnverifiedTarget,
+    ValueAtRisk,
+    Delegatecall,
+}
+
+/// Net change in one token's balance for the sender
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenDelta {
+    pub token: String,
+    pub symbol: Option<String>,
+    /// Signed and scaled by the token's decimals when they're known
+    pub amount: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usd: Option<f64>,
+}
+
 pub struct AppState {
     pub template_generator: Mutex<LLMImpl>,
     pub process_limiter: Arc<Semaphore>,
+    /// Jobs spawned but still waiting for a `process_limiter` permit
+    pub queued_jobs: Arc<AtomicUsize>,
     pub temp_dirs: Mutex<HashMap<String, TempDir>>,
+    /// Recorded events per session directory, for resuming dropped streams
+    pub session_events: Mutex<HashMap<String, Arc<SessionEvents>>>,
+    /// Recorded events of running forge jobs by request, so identical requests
+    /// arriving together share one job
+    pub running_jobs: Mutex<HashMap<String, Arc<SessionEvents>>>,
     pub protocol_processor: Arc<ProtocolGuidelinesProcessor>,
-    pub base_forge_dir: PathBuf,
+    /// Known-good scripts included in generation prompts
+    pub examples: ExampleStore,
+    pub templates: TemplateStore,
+    pub feedback: FeedbackStore,
+    pub stats: JobStats,
+    pub experiment: Option<Experiment>,
+    /// `None` unless the audit trail is enabled
+    pub audit: Option<AuditLog>,
+    pub storage: StorageQuota,
+    /// Environment variables requests set for their sessions' forge runs
+    pub session_env: SessionEnv,
+    pub anvil_forks: AnvilForks,
+    pub rpc_pool: RpcPool,
+    pub project_pool: Arc<ProjectPool>,
+    /// Cancelled when the server begins shutting down
+    pub shutdown: CancellationToken,
+    /// Tracks in-flight forge jobs so shutdown can wait for them
+    pub tasks: TaskTracker,
+    pub rate_limiter: RateLimiter,
+    pub auth: AuthStore,
+    /// Canonical token addresses for resolving symbols in intents
+    pub token_registry: TokenRegistry,
+    pub token_cache: TokenCache,
+    pub result_cache: ResultCache,
+    /// Shared with the LLM generator; `None` when the breaker is disabled
+    pub llm_breaker: Option<Arc<CircuitBreaker>>,
+    /// Models the LLM gateway reported at startup; empty when it couldn't be asked
+    pub models: Vec<String>,
+    pub config: Config,
+}
+
+/// Models a client may pick from, and the ones the server uses by default
+#[derive(Debug, Serialize)]
+pub struct ModelList {
+    pub models: Vec<String>,
+    pub code_model: String,
+    pub chat_model: String,
+}
+
+/// A protocol the generator has guidelines for, for a client-side protocol picker
+#[derive(Debug, Serialize)]
+pub struct ProtocolInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Chains the guideline's addresses are for; empty when it doesn't say
+    pub chains: Vec<u64>,
+    /// Digest of the guideline and its prompt-optimized version, which changes
+    /// whenever either is edited or compressed again
+    pub version: String,
+    /// Unix time the guideline file was last modified
+    pub updated_at: Option<u64>,
+    /// Generation is given a prompt-optimized version rather than the full one
+    pub compact: bool,
+}
+
+/// Confirmation that a session's data was purged
+#[derive(Debug, Serialize)]
+pub struct SessionDeleted {
+    pub session_id: String,
+    /// Cached results the session had produced, now dropped
+    pub cached_results: usize,
+}
+
+#[derive(Deserialize)]
+pub struct ReplayRequest {
+    /// Playback speed relative to the original run; 0 sends everything at once
+    pub speed: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct GuidelineRequest {
+    /// Version the client expects, as listed by `/protocols`
+    pub version: Option<String>,
+    /// The full guideline written for people, rather than what generation is given
+    #[serde(default)]
+    pub full: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DiffRequest {
+    /// Version to diff from, as `v1` or `1`
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitRequest {
+    pub session_id: String,
+    /// One of the session's transactions, signed by its sender
+    pub raw_transaction: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubmitResponse {
+    pub hash: String,
+    pub chain_id: u64,
+    /// Host of the private RPC the transaction was sent to
+    pub relay: String,
+}
+
+#[derive(Deserialize)]
+pub struct BundleRequest {
+    /// RPC of the chain the bundle is for, to read the sender's nonce and the base fee
+    pub rpc_url: Option<String>,
+    /// Chain the bundle is for when the session's isn't known, read through its
+    /// fastest RPC when `rpc_url` isn't given
+    pub chain_id: Option<u64>,
+    /// Sign the transactions with the key in the session's environment
+    #[serde(default)]
+    pub signed: bool,
+    /// Block the bundle targets; the next one when unset
+    pub block: Option<u64>,
+}
+
+/// A session's transactions as a Flashbots `eth_sendBundle` request
+#[derive(Debug, Serialize)]
+pub struct FlashbotsBundle {
+    /// Whether `payload` holds signed transactions ready to send. Otherwise
+    /// each entry of its `txs` is an unsigned transaction to replace with its
+    /// signed encoding.
+    pub signed: bool,
+    pub target_block: u64,
+    /// JSON-RPC body to send to a relay, with the `X-Flashbots-Signature` header
+    pub payload: serde_json::Value,
+    /// The transactions in the bundle, in order, with nonces and fees filled in
+    pub transactions: Vec<TypedTransaction>,
+}
+
+/// What changed between two script versions of a session
+#[derive(Debug, Serialize)]
+pub struct VersionDiff {
+    pub from: usize,
+    pub to: usize,
+    /// Unified diff of the scripts
+    pub diff: String,
+    /// Simulated transactions paired by position; `None` unless both versions simulated successfully
+    pub transactions: Option<Vec<TransactionChange>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionChange {
+    pub index: usize,
+    pub change: ChangeKind,
+    /// Fields that differ between the two transactions
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<&'static str>,
+    pub before: Option<TransactionDetails>,
+    pub after: Option<TransactionDetails>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+    Unchanged,
 }
 
 #[derive(Deserialize)]
@@ -96,7 +658,116 @@ pub struct FixRequest {
 }
 
 
+/// How a session's job was set up, for attributing on-chain feedback
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobOrigin {
+    /// The intent as submitted, before resolved names were added
+    pub intent: String,
+    pub from_address: String,
+    pub chain_id: Option<u64>,
+    /// Tolerance the request gave explicitly
+    pub slippage_bps: Option<u32>,
+    /// Protocols whose guidelines the script was generated with
+    pub protocols: Vec<String>,
+    /// Scripted from a template rather than generated
+    pub from_template: bool,
+    /// Experiment variant the session was assigned
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// Simulated by impersonating `from_address` on an anvil fork
+    #[serde(default)]
+    pub impersonate: bool,
+    /// Tokens the sender was given on the fork, when simulated with funding
+    #[serde(default)]
+    pub funding: Option<Vec<Address>>,
+    /// The script was allowed to warp time between phases
+    #[serde(default)]
+    pub time_travel: bool,
+    /// Signed-in address that requested the job, when SIWE is on
+    #[serde(default)]
+    pub requested_by: Option<Address>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedbackRequest {
+    pub session_id: String,
+    /// Whether the transactions executed successfully on-chain
+    pub success: bool,
+    #[serde(default)]
+    pub tx_hashes: Vec<String>,
+    pub comment: Option<String>,
+}
+
+/// A feedback report as stored
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeedbackRecord {
+    pub timestamp: u64,
+    pub session_id: String,
+    pub protocols: Vec<String>,
+    pub from_template: bool,
+    #[serde(default)]
+    pub variant: Option<String>,
+    pub success: bool,
+    pub tx_hashes: Vec<String>,
+    pub comment: Option<String>,
+}
+
+/// On-chain outcomes reported for one protocol's or experiment variant's scripts
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProtocolFeedback {
+    pub successes: u64,
+    pub failures: u64,
+}
+
+/// How jobs have gone since startup, for maintainers deciding which guidelines need work
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub timestamp: u64,
+    pub protocols: HashMap<String, OutcomeStats>,
+    /// The running experiment's name and its variants' outcomes
+    pub experiment: Option<String>,
+    pub variants: HashMap<String, OutcomeStats>,
+    /// By call type
+    pub llm: HashMap<String, LlmCallStats>,
+    /// Protocols whose scripts keep failing on-chain
+    pub flagged_protocols: Vec<String>,
+}
+
+/// Outcomes of the jobs for a protocol or experiment variant
+#[derive(Debug, Serialize)]
+pub struct OutcomeStats {
+    pub jobs: u64,
+    /// Jobs that produced a simulated result, first time or after fixes
+    pub succeeded: u64,
+    pub success_rate: Option<f64>,
+    pub avg_fix_iterations: Option<f64>,
+    /// Outcomes clients reported after executing the transactions
+    pub onchain: ProtocolFeedback,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LlmCallStats {
+    pub calls: u64,
+    pub avg_secs: Option<f64>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SessionData {
     pub messages: Vec<ChatCompletionRequestUserMessage>,
+    /// Address the session was generated for
+    #[serde(default)]
+    pub from_address: Option<String>,
+    /// Signed-in address that started the session, when SIWE is on; not the
+    /// sender when the sender was impersonated
+    #[serde(default)]
+    pub owner: Option<Address>,
+}
+
+impl SessionData {
+    /// The address allowed to act on the session: whoever signed in to start
+    /// it, or its sender for sessions started without SIWE
+    pub fn owner(&self) -> Option<Address> {
+        self.owner
+            .or_else(|| self.from_address.as_deref().and_then(|from| from.parse().ok()))
+    }
 }
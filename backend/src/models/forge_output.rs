@@ -0,0 +1,184 @@
+//! What forge writes in its machine-readable modes: the broadcast file and the
+//! `--json` output of `build`, `script` and `test`. Only the fields the server
+//! reads are modeled.
+
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+/// Parse a field that forge versions have serialized differently, falling back
+/// to its default rather than failing the whole output
+fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: for<'a> Deserialize<'a> + Default,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).unwrap_or_default())
+}
+
+/// A script run's broadcast file (`broadcast/<script>/<chain>/dry-run/run-latest.json`)
+#[derive(Debug, Deserialize)]
+pub struct Broadcast {
+    pub transactions: Vec<BroadcastTransaction>,
+    /// Libraries forge deployed and linked the script against, as `path:Name:address`
+    #[serde(default)]
+    pub libraries: Vec<String>,
+}
+
+/// A transaction the script would send, with what forge decoded about it
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastTransaction {
+    /// CALL, CREATE or CREATE2
+    pub transaction_type: String,
+    pub contract_name: Option<String>,
+    /// Callee, or the address a CREATE deploys to
+    pub contract_address: Option<String>,
+    /// Signature of the called function; unset for deployments and plain transfers
+    pub function: Option<String>,
+    /// Call arguments, or a deployment's constructor arguments
+    pub arguments: Option<Vec<String>>,
+    pub transaction: BroadcastRequest,
+}
+
+/// The transaction request itself; quantities are hex
+#[derive(Debug, Deserialize)]
+pub struct BroadcastRequest {
+    pub from: String,
+    pub to: Option<String>,
+    pub gas: Option<String>,
+    pub value: Option<String>,
+    /// `data` in older forge versions
+    #[serde(alias = "data")]
+    pub input: String,
+}
+
+/// What `forge script --json` prints once the script has run, failed or not
+#[derive(Debug, Deserialize)]
+pub struct ScriptOutput {
+    pub success: bool,
+    /// console.log output, decoded
+    #[serde(default)]
+    pub logs: Vec<String>,
+    #[serde(default)]
+    pub gas_used: u64,
+    /// Traces by kind: `Deployment`, `Setup` and `Execution`
+    #[serde(default, deserialize_with = "lenient")]
+    pub traces: Vec<(String, TraceArena)>,
+}
+
+/// The calls of a trace, in the order they were made
+#[derive(Debug, Default, Deserialize)]
+pub struct TraceArena {
+    #[serde(default)]
+    pub arena: Vec<TraceNode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TraceNode {
+    pub trace: CallTrace,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallTrace {
+    #[serde(default)]
+    pub depth: usize,
+    #[serde(default)]
+    pub success: bool,
+    #[serde(default)]
+    pub address: String,
+    /// CALL, STATICCALL, DELEGATECALL, CREATE, ...
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub gas_used: u64,
+    #[serde(default, deserialize_with = "lenient")]
+    pub decoded: Option<DecodedCall>,
+}
+
+/// What forge could tell about a call from the ABIs and labels it knows
+#[derive(Debug, Deserialize)]
+pub struct DecodedCall {
+    pub label: Option<String>,
+    pub call_data: Option<DecodedCallData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DecodedCallData {
+    pub signature: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// A compiled contract in `out/`
+#[derive(Debug, Deserialize)]
+pub struct Artifact {
+    pub bytecode: ArtifactBytecode,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactBytecode {
+    /// Placeholders for library addresses, by source file then library name
+    #[serde(default)]
+    pub link_references: HashMap<String, HashMap<String, serde_json::Value>>,
+}
+
+/// `forge build --json`: solc's standard JSON output, of which only the
+/// diagnostics are read
+#[derive(Debug, Deserialize)]
+pub struct CompilerOutput {
+    #[serde(default)]
+    pub errors: Vec<CompilerError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompilerError {
+    /// error, warning or info
+    pub severity: String,
+    pub error_code: Option<String>,
+    pub message: String,
+    /// The message with the offending source quoted, as forge prints it
+    pub formatted_message: Option<String>,
+    pub source_location: Option<SourceLocation>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SourceLocation {
+    pub file: String,
+    /// Byte offset into the file, -1 when unknown
+    pub start: i64,
+}
+
+/// `forge test --json`: results by suite (`<path>:<contract>`)
+pub type TestResults = HashMap<String, TestSuite>;
+
+#[derive(Debug, Deserialize)]
+pub struct TestSuite {
+    #[serde(default)]
+    pub test_results: HashMap<String, TestResult>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestResult {
+    /// Success, Failure or Skipped
+    pub status: String,
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub decoded_logs: Vec<String>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub kind: TestKind,
+}
+
+/// How a test ran; gas is only reported for unit tests
+#[derive(Debug, Default, Deserialize)]
+pub struct TestKind {
+    #[serde(rename = "Unit")]
+    pub unit: Option<UnitTest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnitTest {
+    pub gas: u64,
+}
@@ -0,0 +1,131 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+/// Most actions a single intent may plan
+const MAX_ACTIONS: usize = 10;
+
+/// A free-text intent broken down into the on-chain actions it asks for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionPlan {
+    pub actions: Vec<PlannedAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedAction {
+    pub action: ActionType,
+    /// Protocol the action goes through, e.g. "uniswap" or "aave"; `None` for plain transfers
+    #[serde(default)]
+    pub protocol: Option<String>,
+    /// Tokens involved as symbols or addresses, input token first
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// Amounts as written or resolved ("100", "all", "50%"), matching `tokens` by position
+    #[serde(default)]
+    pub amounts: Vec<String>,
+    #[serde(default)]
+    pub slippage_bps: Option<u32>,
+    /// Address or ENS name receiving the output, when it isn't the sender
+    #[serde(default)]
+    pub recipient: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionType {
+    Swap,
+    Transfer,
+    Approve,
+    Wrap,
+    Unwrap,
+    Supply,
+    Withdraw,
+    Borrow,
+    Repay,
+    Stake,
+    Unstake,
+    Bridge,
+    Other,
+}
+
+impl ActionType {
+    /// Actions that can't be scripted without knowing which token moves
+    fn needs_token(self) -> bool {
+        !matches!(self, ActionType::Wrap | ActionType::Unwrap | ActionType::Other)
+    }
+
+    /// Actions where a protocol contract pulls the first token from the sender
+    pub fn pulls_tokens(self) -> bool {
+        matches!(
+            self,
+            ActionType::Swap | ActionType::Supply | ActionType::Repay | ActionType::Stake | ActionType::Bridge
+        )
+    }
+}
+
+impl ActionPlan {
+    /// Check the plan is usable beyond what deserialization already enforces
+    pub fn validate(&self) -> Result<()> {
+        if self.actions.is_empty() {
+            return Err(eyre!("Plan has no actions"));
+        }
+        if self.actions.len() > MAX_ACTIONS {
+            return Err(eyre!("Plan has {} actions, at most {} are allowed", self.actions.len(), MAX_ACTIONS));
+        }
+
+        for (index, action) in self.actions.iter().enumerate() {
+            let position = index + 1;
+            if action.action.needs_token() && action.tokens.is_empty() {
+                return Err(eyre!("Action {} ({:?}) names no token", position, action.action));
+            }
+            if action.amounts.len() > action.tokens.len().max(1) {
+                return Err(eyre!("Action {} has more amounts than tokens", position));
+            }
+            if action.amounts.iter().any(|amount| amount.trim().is_empty()) {
+                return Err(eyre!("Action {} has an empty amount", position));
+            }
+            if action.slippage_bps.is_some_and(|bps| bps > 10_000) {
+                return Err(eyre!("Action {} has slippage above 100%", position));
+            }
+            if action.recipient.as_deref().is_some_and(|recipient| recipient.trim().is_empty()) {
+                return Err(eyre!("Action {} has an empty recipient", position));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What an intent would do, worked out without generating code
+#[derive(Debug, Serialize)]
+pub struct IntentPreview {
+    pub plan: ActionPlan,
+    /// Protocols with guidelines the intent was matched to
+    pub protocols: Vec<String>,
+    /// The fork's chain, when it could be read
+    pub chain_id: Option<u64>,
+    /// Sender after ENS resolution
+    pub from_address: String,
+    pub ens_names: Vec<ResolvedName>,
+    pub tokens: Vec<ResolvedName>,
+    pub approvals: Vec<RequiredApproval>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedName {
+    pub name: String,
+    pub address: String,
+}
+
+/// An ERC-20 allowance the plan needs the sender to grant
+#[derive(Debug, Serialize)]
+pub struct RequiredApproval {
+    /// The token as the plan names it
+    pub token: String,
+    /// Resolved contract, when the token is listed or given as an address
+    pub address: Option<String>,
+    pub amount: Option<String>,
+    /// Protocol that pulls the token
+    pub protocol: Option<String>,
+}
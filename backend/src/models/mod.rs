@@ -1,7 +1,12 @@
 mod cli;
 mod forge;
+mod forge_output;
 mod etherscan;
+mod config;
+mod intent;
 
-pub use cli::{Cli, Commands, ForgeArgs, GenerateArgs};
-pub use forge::{ForgeOutput, ForgeRequest, ForgeResponse, ForgeTransaction, ForgeTransactionDetails, Transaction, ForgeStep, AppState, FixRequest, SessionData, TransactionDetails};
-pub use etherscan::{EtherscanResponse, ContractSourceCode}; 
\ No newline at end of file
+pub use cli::{AuditAction, BaseProjectAction, Cli, Commands};
+pub use forge::{BundleRequest, CompilerSettings, Deployment, Diagnostic, ForgeRequest, TraceCall, ForgeStep, Stage, ErrorCode, AppState, ModelList, ChangeKind, DiffRequest, FeedbackRecord, FlashbotsBundle, FeedbackRequest, FixRequest, GuidelineRequest, JobOrigin, LlmCallStats, OutcomeStats, ProtocolFeedback, ProtocolInfo, ReplayRequest, SubmitRequest, SubmitResponse, RiskFactor, RiskLevel, RiskReason, RiskReport, SessionData, SessionDeleted, Stats, TokenDelta, TransactionChange, TransactionDetails, VersionDiff};
+pub use forge_output::{Artifact, Broadcast, BroadcastTransaction, CallTrace, CompilerOutput, ScriptOutput, TestResults};
+pub use intent::{ActionPlan, ActionType, IntentPreview, PlannedAction, RequiredApproval, ResolvedName};
+pub use config::{AnalysisEngine, AuditConfig, AuthConfig, BaseProjectConfig, BundleConfig, CircuitBreakerConfig, DependencyConfig, Config, ExperimentConfig, FeedbackConfig, GuidelinesConfig, FoundryConfig, KeyLimits, LlmConfig, LlmFallbackConfig, PolicyConfig, PortfolioConfig, PricesConfig, RateLimitConfig, RiskConfig, RpcConfig, SandboxConfig, SandboxMode, StatsConfig, StorageConfig, TemplatesConfig, TokenListsConfig, TracingConfig, ValidationConfig};
@@ -1,6 +1,7 @@
 use serde::Deserialize;
 use reqwest::Client;
 use eyre::Result;
+use tracing::warn;
 
 
 #[derive(Debug, Deserialize)]
@@ -11,6 +12,23 @@ pub struct ContractInfo {
     pub contract_name: String,
     #[serde(rename = "ABI")]
     pub abi: String,
+    /// "1" when Etherscan knows the contract for a proxy
+    #[serde(rename = "Proxy", default)]
+    pub proxy: String,
+    /// The implementation behind a proxy
+    #[serde(rename = "Implementation", default)]
+    pub implementation: String,
+}
+
+impl ContractInfo {
+    pub fn is_verified(&self) -> bool {
+        !self.source_code.is_empty()
+    }
+
+    /// The implementation's address when this is a proxy
+    pub fn implementation(&self) -> Option<&str> {
+        (self.proxy == "1" && !self.implementation.is_empty()).then_some(self.implementation.as_str())
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -20,40 +38,192 @@ pub struct EtherscanResponse<T> {
     result: T,
 }
 
-pub async fn get_etherscan_contract(address: &str, api_key: &str) -> Result<ContractInfo> {
+/// A contract's verified source and ABI from an Etherscan v2 (multichain) API
+pub async fn get_etherscan_contract(api_url: &str, chain_id: u64, address: &str, api_key: &str) -> Result<ContractInfo> {
     let client = Client::new();
-    let url = format!(
-        "https://api.etherscan.io/api?module=contract&action=getsourcecode&address={}&apikey={}",
-        address, api_key
-    );
-
-    let response = client.get(&url).send().await?;
-    let data: EtherscanResponse<Vec<ContractInfo>> = response.json().await?;
+    let response = client
+        .get(api_url)
+        .query(&[
+            ("chainid", chain_id.to_string()),
+            ("module", "contract".to_string()),
+            ("action", "getsourcecode".to_string()),
+            ("address", address.to_string()),
+            ("apikey", api_key.to_string()),
+        ])
+        .send()
+        .await?
+        .error_for_status()?;
+    // Errors come back as a string result rather than a list
+    let data: EtherscanResponse<serde_json::Value> = response.json().await?;
+    if data.status != "1" {
+        return Err(eyre::eyre!("Etherscan: {} ({})", data.message, data.result));
+    }
 
-    data.result
+    serde_json::from_value::<Vec<ContractInfo>>(data.result)?
         .into_iter()
         .next()
         .ok_or_else(|| eyre::eyre!("No contract found"))
 }
 
+/// An ABI entry as the explorer returns it. Read raw rather than through
+/// ethers' parser, which drops the struct names and fields of tuples.
+#[derive(Debug, Deserialize)]
+struct AbiItem {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    inputs: Vec<AbiParam>,
+    #[serde(default)]
+    outputs: Vec<AbiParam>,
+    #[serde(rename = "stateMutability", default)]
+    state_mutability: String,
+    /// Before `stateMutability`, ABIs marked view functions `constant`
+    #[serde(default)]
+    constant: bool,
+    #[serde(default)]
+    payable: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AbiParam {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    /// The Solidity type, e.g. `struct PoolKey` for a tuple
+    #[serde(rename = "internalType", default)]
+    internal_type: Option<String>,
+    #[serde(default)]
+    components: Vec<AbiParam>,
+    #[serde(default)]
+    indexed: bool,
+}
+
+/// The structs, functions and events of a contract ABI as Solidity
+/// declarations that compile inside an interface
+pub fn abi_signatures(abi: &str) -> Result<Vec<String>> {
+    let items: Vec<AbiItem> = serde_json::from_str(abi)?;
+    let mut structs = Vec::new();
+    let mut declarations = Vec::new();
+
+    for item in items.iter().filter(|item| item.kind == "function") {
+        let inputs = function_params(&item.inputs, "calldata", &mut structs);
+        let mutability = match item.state_mutability.as_str() {
+            "pure" => " pure",
+            "view" => " view",
+            "payable" => " payable",
+            "" if item.constant => " view",
+            "" if item.payable => " payable",
+            _ => "",
+        };
+        let returns = if item.outputs.is_empty() {
+            String::new()
+        } else {
+            format!(" returns ({})", function_params(&item.outputs, "memory", &mut structs))
+        };
+        declarations.push(format!("function {}({}) external{}{};", item.name, inputs, mutability, returns));
+    }
+    for item in items.iter().filter(|item| item.kind == "event") {
+        let inputs = item
+            .inputs
+            .iter()
+            .map(|input| {
+                let kind = solidity_type(input, &mut structs);
+                let indexed = if input.indexed { " indexed" } else { "" };
+                format!("{}{}{}", kind, indexed, param_name(&input.name))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        declarations.push(format!("event {}({});", item.name, inputs));
+    }
+
+    let mut signatures = structs.into_iter().map(|(_, definition)| definition).collect::<Vec<_>>();
+    signatures.extend(declarations);
+    Ok(signatures)
+}
+
+/// A function's parameters, with `location` on the ones of reference types
+fn function_params(params: &[AbiParam], location: &str, structs: &mut Vec<(String, String)>) -> String {
+    params
+        .iter()
+        .map(|param| {
+            let kind = solidity_type(param, structs);
+            let location = if is_reference_type(&param.kind) { format!(" {}", location) } else { String::new() };
+            format!("{}{}{}", kind, location, param_name(&param.name))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Arrays, `bytes`, `string` and structs, which need a data location
+fn is_reference_type(kind: &str) -> bool {
+    kind.ends_with(']') || kind == "bytes" || kind == "string" || kind.starts_with("tuple")
+}
+
+fn param_name(name: &str) -> String {
+    if name.is_empty() {
+        String::new()
+    } else {
+        format!(" {}", name)
+    }
+}
+
+/// The Solidity type of a parameter. A tuple is named after its struct, whose
+/// definition is added to `structs` unless one of that name already is.
+fn solidity_type(param: &AbiParam, structs: &mut Vec<(String, String)>) -> String {
+    let Some(dimensions) = param.kind.strip_prefix("tuple") else {
+        return param.kind.clone();
+    };
+    // `struct Pool.Key[]` declares `Key`, as the interface has no `Pool` to scope it
+    let name = param
+        .internal_type
+        .as_deref()
+        .and_then(|internal| internal.strip_prefix("struct "))
+        .map(|internal| internal.split('[').next().unwrap_or(internal))
+        .and_then(|internal| internal.rsplit('.').next())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Struct{}", structs.len() + 1));
+
+    if !structs.iter().any(|(defined, _)| *defined == name) {
+        let fields = param
+            .components
+            .iter()
+            .enumerate()
+            .map(|(index, component)| {
+                let field = if component.name.is_empty() { format!("field{}", index) } else { component.name.clone() };
+                format!("    {} {};", solidity_type(component, structs), field)
+            })
+            .collect::<Vec<_>>();
+        structs.push((name.clone(), format!("struct {} {{\n{}\n}}", name, fields.join("\n"))));
+    }
+    format!("{}{}", name, dimensions)
+}
+
 pub fn extract_contract_source(contract_info: &ContractInfo) -> Result<String> {
     let source_code = &contract_info.source_code;
 
-    // Remove the leading/trailing {{ and }} if present
-    let source_code = source_code.trim_start_matches("{{").trim_end_matches("}}");
-
-    // Clean up any whitespace/newlines at start/end
-    let source_code = source_code.trim();
+    // Single-file verifications are the plain source
+    if !source_code.trim_start().starts_with('{') {
+        return Ok(source_code.clone());
+    }
 
-    // Add opening and closing braces to make it valid JSON
-    let source_code = format!("{{{}}}", source_code);
+    // Standard JSON input is wrapped in {{ and }}; multi-file sources are plain JSON
+    let source_code = match source_code.trim().strip_prefix("{{").and_then(|s| s.strip_suffix("}}")) {
+        Some(inner) => format!("{{{}}}", inner.trim()),
+        None => source_code.trim().to_string(),
+    };
 
     // First decode: handle the escaped JSON string
     let decoded = match serde_json::from_str::<serde_json::Value>(&source_code) {
         Ok(d) => d,
         Err(e) => {
-            println!("JSON parse error: {}", e);
-            println!("First few characters: {:?}", &source_code[..50]);
+            warn!(
+                "Failed to parse contract source JSON: {}; it starts with {:?}",
+                e,
+                source_code.chars().take(50).collect::<String>()
+            );
             return Err(eyre::eyre!("Failed to parse JSON: {}", e));
         }
     };
@@ -61,7 +231,7 @@ pub fn extract_contract_source(contract_info: &ContractInfo) -> Result<String> {
     // Get the sources object
     let sources = decoded
         .get("sources")
-        .ok_or_else(|| eyre::eyre!("No sources found"))?
+        .unwrap_or(&decoded)
         .as_object()
         .ok_or_else(|| eyre::eyre!("Sources is not an object"))?;
 
@@ -0,0 +1,118 @@
+use crate::models::ActionType;
+use eyre::Result;
+use std::fs;
+use std::path::Path;
+
+/// Comment on a script's first lines giving the intent it implements
+const INTENT_PREFIX: &str = "// Intent:";
+
+/// A known-good script for one protocol and action
+pub struct Example {
+    pub name: String,
+    pub protocol: String,
+    pub action: Option<ActionType>,
+    pub intent: Option<String>,
+    pub script: String,
+}
+
+/// Curated scripts shown to the model as examples, laid out as
+/// `<dir>/<protocol>/<action>[-variant].s.sol`, where the protocol matches a
+/// guidelines file and the action is one of the action plan's types
+#[derive(Default)]
+pub struct ExampleStore {
+    examples: Vec<Example>,
+}
+
+impl ExampleStore {
+    /// Load every example under `dir`; a missing directory gives an empty store
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut examples = Vec::new();
+        if !dir.is_dir() {
+            return Ok(Self { examples });
+        }
+
+        for protocol_dir in fs::read_dir(dir)? {
+            let protocol_dir = protocol_dir?.path();
+            let Some(protocol) = protocol_dir.file_name().and_then(|name| name.to_str()).map(str::to_string) else {
+                continue;
+            };
+            if !protocol_dir.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&protocol_dir)? {
+                let path = file?.path();
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let Some(stem) = name.strip_suffix(".s.sol") else {
+                    continue;
+                };
+                let action = stem.split('-').next().and_then(|action| {
+                    serde_json::from_value::<ActionType>(serde_json::Value::String(action.to_string())).ok()
+                });
+                let script = fs::read_to_string(&path)?;
+                let intent = script
+                    .lines()
+                    .take(5)
+                    .find_map(|line| line.trim().strip_prefix(INTENT_PREFIX))
+                    .map(|intent| intent.trim().to_string());
+                examples.push(Example {
+                    name: format!("{}/{}", protocol, stem),
+                    protocol: protocol.clone(),
+                    action,
+                    intent,
+                    script,
+                });
+            }
+        }
+        examples.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Self { examples })
+    }
+
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    /// Up to `limit` examples for the classified protocols, those for one of
+    /// the planned actions first
+    pub fn select(&self, protocols: &[String], actions: &[ActionType], limit: usize) -> Vec<&Example> {
+        let mut matches = self
+            .examples
+            .iter()
+            .filter(|example| protocols.contains(&example.protocol))
+            .collect::<Vec<_>>();
+        // Stable, so examples of equal relevance keep their name order
+        matches.sort_by_key(|example| !example.action.is_some_and(|action| actions.contains(&action)));
+        // One example per protocol before a second for any of them
+        let mut selected: Vec<&Example> = Vec::new();
+        for example in &matches {
+            if selected.len() < limit && !selected.iter().any(|chosen| chosen.protocol == example.protocol) {
+                selected.push(example);
+            }
+        }
+        for example in matches {
+            if selected.len() < limit && !selected.iter().any(|chosen| std::ptr::eq(*chosen, example)) {
+                selected.push(example);
+            }
+        }
+        selected
+    }
+}
+
+/// The examples as a prompt section
+pub fn examples_section(examples: &[&Example]) -> String {
+    let mut section = String::from(
+        "Example scripts known to compile and simulate correctly for similar intents. \
+        Follow their structure and addresses where they apply, but implement the user's intent, not the example's:\n",
+    );
+    for example in examples {
+        section.push_str(&format!(
+            "\nExample ({}){}:\n```solidity\n{}\n```\n",
+            example.name,
+            example.intent.as_deref().map(|intent| format!(" for \"{}\"", intent)).unwrap_or_default(),
+            example.script.trim()
+        ));
+    }
+    section
+}
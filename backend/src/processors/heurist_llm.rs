@@ -1,19 +1,196 @@
 use async_openai::{
     config::OpenAIConfig,
-    types::{ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs, ChatCompletionRequestUserMessage},
+    types::{
+        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage, ChatCompletionRequestUserMessageArgs,
+        ChatCompletionResponseStream, CreateChatCompletionRequest,
+        CreateChatCompletionRequestArgs, CreateChatCompletionResponse, ChatCompletionRequestUserMessage, CompletionUsage,
+        FinishReason, ResponseFormat, ResponseFormatJsonSchema,
+    },
     Client as OpenAIClient,
 };
 use ethers::providers::StreamExt;
 use eyre::{Result, eyre};
 use std::fs;
 use tokio::sync::mpsc::Sender;
-use crate::models::ForgeStep;
+use crate::models::{ActionPlan, ForgeStep, LlmFallbackConfig, Stage};
+use crate::utils::{log_prompt, send_droppable, CallGuard, CircuitBreaker, METRICS};
 use super::LLMGenerator;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Typical length of a generated script, for estimating generation progress
+const ESTIMATED_SCRIPT_TOKENS: u64 = 1200;
+/// Streamed tokens between progress events
+const PROGRESS_EVERY_TOKENS: u64 = 25;
+/// Characters of a resumed answer checked for text repeated from before the interruption
+const RESUME_OVERLAP_WINDOW: usize = 200;
+const MIN_RESUME_OVERLAP: usize = 8;
 
 pub struct LLMTemplateGenerator {
     client: OpenAIClient<OpenAIConfig>,
+    /// Writes and fixes Solidity
+    code_model: String,
+    /// Classification, planning and explanations
+    chat_model: String,
+    breaker: Option<Arc<CircuitBreaker>>,
+    /// Takes calls while the breaker is open
+    fallback: Option<Fallback>,
+    /// Times an interrupted streamed answer is resumed before giving up
+    stream_resumes: u32,
+    /// Times a truncated answer is continued
+    max_continuations: u32,
+}
+
+struct Fallback {
+    client: OpenAIClient<OpenAIConfig>,
+    code_model: String,
+    chat_model: String,
+}
+
+impl LLMTemplateGenerator {
+    pub fn with_models(mut self, code_model: &str, chat_model: &str) -> Self {
+        self.code_model = code_model.to_string();
+        self.chat_model = chat_model.to_string();
+        self
+    }
+
+    pub fn with_breaker(mut self, breaker: Option<Arc<CircuitBreaker>>, fallback: Option<&LlmFallbackConfig>) -> Self {
+        self.breaker = breaker;
+        self.fallback = fallback.map(|config| Fallback {
+            client: OpenAIClient::with_config(
+                OpenAIConfig::new()
+                    .with_api_key(&config.api_key)
+                    .with_api_base(&config.api_base),
+            ),
+            code_model: config.code_model.clone(),
+            chat_model: config.chat_model.clone(),
+        });
+        self
+    }
+
+    /// The client to send `request` to: the gateway while its circuit breaker lets
+    /// calls through, otherwise the fallback with the matching model. The guard,
+    /// if any, records how the gateway call went.
+    fn route(&self, request: &mut CreateChatCompletionRequest) -> Result<(&OpenAIClient<OpenAIConfig>, Option<CallGuard>)> {
+        let Some(breaker) = &self.breaker else {
+            return Ok((&self.client, None));
+        };
+        match breaker.try_call() {
+            Ok(guard) => Ok((&self.client, Some(guard))),
+            Err(open) => {
+                let Some(fallback) = &self.fallback else {
+                    return Err(open.into());
+                };
+                request.model = if request.model == self.code_model {
+                    fallback.code_model.clone()
+                } else {
+                    fallback.chat_model.clone()
+                };
+                Ok((&fallback.client, None))
+            }
+        }
+    }
+
+    async fn create(&self, mut request: CreateChatCompletionRequest) -> Result<CreateChatCompletionResponse> {
+        let (client, guard) = self.route(&mut request)?;
+        log_prompt(&request);
+        let response = client.chat().create(request).await;
+        if let Some(guard) = guard {
+            guard.finish(response.is_ok());
+        }
+        Ok(response?)
+    }
+
+    pub fn with_stream_resumes(mut self, stream_resumes: u32) -> Self {
+        self.stream_resumes = stream_resumes;
+        self
+    }
+
+    pub fn with_max_continuations(mut self, max_continuations: u32) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    /// Stream a code-model completion of `messages` onto `response`, forwarding
+    /// the text to the client. When `response` already holds the start of an
+    /// interrupted answer, text the model repeats from it is dropped. Returns
+    /// whether the model stopped at the token limit.
+    async fn stream_response(
+        &self,
+        messages: Vec<ChatCompletionRequestMessage>,
+        response: &mut String,
+        chunks: &mut u64,
+        tx: &Sender<ForgeStep>,
+    ) -> Result<bool> {
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.code_model)
+            .messages(messages)
+            .max_tokens(2048u16)
+            .temperature(0.3)
+            .stream(true)
+            .build()?;
+
+        let mut stream = self.create_stream(request).await?;
+        // A resumed answer is held back until it's clear how much of it is repeated
+        let mut head = (!response.is_empty()).then(String::new);
+        let mut hit_limit = false;
+
+        while let Some(result) = stream.next().await {
+            let chat_response = result.map_err(|e| eyre!("Stream error: {}", e))?;
+            if let Some(usage) = &chat_response.usage {
+                record_usage("chat_stream", usage);
+                *chunks = 0;
+            }
+            if let Some(reason) = chat_response.choices.first().and_then(|c| c.finish_reason) {
+                hit_limit = reason == FinishReason::Length;
+            }
+            let Some(content) = chat_response.choices.first().and_then(|c| c.delta.content.as_ref()) else {
+                continue;
+            };
+            *chunks += 1;
+            if chunks.is_multiple_of(PROGRESS_EVERY_TOKENS) {
+                send_droppable(tx, ForgeStep::progress_within(Stage::Generating, *chunks, ESTIMATED_SCRIPT_TOKENS)).await;
+            }
+
+            let text = match &mut head {
+                Some(held) if held.len() + content.len() < RESUME_OVERLAP_WINDOW => {
+                    held.push_str(content);
+                    continue;
+                }
+                Some(held) => {
+                    held.push_str(content);
+                    let text = skip_repeated(response, held);
+                    head = None;
+                    text
+                }
+                None => content.clone(),
+            };
+            std::io::stdout().flush()?;
+            response.push_str(&text);
+            tx.send(ForgeStep::TokenDelta { text }).await.ok();
+        }
+
+        // The resumed answer ended before filling the window
+        if let Some(held) = head {
+            let text = skip_repeated(response, &held);
+            response.push_str(&text);
+            tx.send(ForgeStep::TokenDelta { text }).await.ok();
+        }
+        Ok(hit_limit)
+    }
+
+    /// Only getting the stream started counts towards the breaker
+    async fn create_stream(&self, mut request: CreateChatCompletionRequest) -> Result<ChatCompletionResponseStream> {
+        let (client, guard) = self.route(&mut request)?;
+        log_prompt(&request);
+        let stream = client.chat().create_stream(request).await;
+        if let Some(guard) = guard {
+            guard.finish(stream.is_ok());
+        }
+        Ok(stream?)
+    }
 }
 
 impl LLMGenerator for LLMTemplateGenerator {
@@ -23,7 +200,13 @@ impl LLMGenerator for LLMTemplateGenerator {
                 OpenAIConfig::new()
                     .with_api_key(api_key)
                     .with_api_base("https://llm-gateway.heurist.xyz")
-            )
+            ),
+            code_model: "qwen/qwen-2.5-coder-32b-instruct".to_string(),
+            chat_model: "mistralai/mixtral-8x7b-instruct".to_string(),
+            breaker: None,
+            fallback: None,
+            stream_resumes: 2,
+            max_continuations: 3,
         })
     }
 
@@ -106,54 +289,76 @@ impl LLMGenerator for LLMTemplateGenerator {
         self.chat_stream(&messages, tx).await
     }
 
+    #[tracing::instrument(name = "llm.chat_stream", skip_all)]
     async fn chat_stream(&self, messages: &[ChatCompletionRequestUserMessage], tx: Sender<ForgeStep>) -> Result<String> {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model("qwen/qwen-2.5-coder-32b-instruct")
-            .messages(messages.iter().map(|m| m.clone().into()).collect::<Vec<_>>())
-            .max_tokens(2048u16)
-            .temperature(0.3)
-            .stream(true)
-            .build()?;
+        let messages = messages.iter().map(|m| m.clone().into()).collect::<Vec<ChatCompletionRequestMessage>>();
 
-        let mut stream = self.client.chat().create_stream(request).await?;
+        let timer = METRICS.llm_duration.with_label_values(&["chat_stream"]).start_timer();
         let mut response = String::new();
-        
-        while let Some(result) = stream.next().await {
-            match result {
-                Ok(chat_response) => {
-                    if let Some(ref content) = chat_response.choices[0].delta.content {
-                        std::io::stdout().flush()?;
-                        response.push_str(content);
-                        tx.send(ForgeStep {
-                            title: "Generating Code".to_string(),
-                            output: content.clone(),
-                        })
-                        .await
-                        .ok();
+        let mut chunks = 0u64;
+        let mut resumes = 0;
+        let mut continuations = 0;
+        // An answer that breaks off or runs out of tokens partway is asked to carry
+        // on from what it had written
+        loop {
+            match self.stream_response(continuation(&messages, &response)?, &mut response, &mut chunks, &tx).await {
+                Ok(hit_limit) => {
+                    if !(hit_limit || looks_truncated(&response)) || continuations >= self.max_continuations {
+                        break;
                     }
+                    continuations += 1;
+                    warn!("LLM answer truncated after {} chars, continuing ({}/{})", response.len(), continuations, self.max_continuations);
+                    tx.send(ForgeStep::Warning {
+                        message: "The generated script was cut off at the length limit; asking for the rest".to_string(),
+                    })
+                    .await
+                    .ok();
+                }
+                Err(e) => {
+                    if response.is_empty() || resumes >= self.stream_resumes {
+                        return Err(e);
+                    }
+                    resumes += 1;
+                    warn!("LLM stream broke off after {} chars, resuming ({}/{}): {}", response.len(), resumes, self.stream_resumes, e);
+                    tx.send(ForgeStep::Warning {
+                        message: "The LLM stream was interrupted; resuming where it stopped".to_string(),
+                    })
+                    .await
+                    .ok();
                 }
-                Err(e) => return Err(eyre!("Stream error: {}", e)),
             }
         }
 
+        timer.observe_duration();
+        // Without a usage report each streamed delta is roughly one token
+        METRICS.llm_tokens.with_label_values(&["chat_stream", "completion"]).inc_by(chunks);
+
         Ok(response)
     }
 
+    #[tracing::instrument(name = "llm.generate", skip_all)]
     async fn generate(&self, messages: &mut Vec<ChatCompletionRequestUserMessage>) -> Result<String> {
         let request = CreateChatCompletionRequestArgs::default()
-            .model("mistralai/mixtral-8x7b-instruct")
+            .model(&self.chat_model)
             .messages(messages.iter().map(|m| m.clone().into()).collect::<Vec<_>>())
             .max_tokens(32u16)
             .temperature(0.1)
             .build()?;
 
-        let mut stream = self.client.chat().create_stream(request).await?;
+        let timer = METRICS.llm_duration.with_label_values(&["generate"]).start_timer();
+        let mut stream = self.create_stream(request).await?;
         let mut response = String::new();
+        let mut chunks = 0u64;
 
         while let Some(result) = stream.next().await {
             match result {
                 Ok(chat_response) => {
-                    if let Some(ref content) = chat_response.choices[0].delta.content {
+                    if let Some(usage) = &chat_response.usage {
+                        record_usage("generate", usage);
+                        chunks = 0;
+                    }
+                    if let Some(content) = chat_response.choices.first().and_then(|c| c.delta.content.as_ref()) {
+                        chunks += 1;
                         println!("{}", content);
                         response.push_str(content);
                     }
@@ -162,6 +367,286 @@ impl LLMGenerator for LLMTemplateGenerator {
             }
         }
 
+        timer.observe_duration();
+        METRICS.llm_tokens.with_label_values(&["generate", "completion"]).inc_by(chunks);
+
         Ok(response)
     }
-}
\ No newline at end of file
+
+    #[tracing::instrument(name = "llm.decompose_intent", skip_all)]
+    async fn decompose_intent(&self, intent: &str) -> Result<Vec<String>> {
+        let prompt = format!(
+            "Split this blockchain transaction intent into independent actions.\n\
+            Actions are independent only if neither uses the result, tokens or approvals of another \
+            (\"swap ETH for USDC and supply the USDC to Aave\" is ONE action; \
+            \"swap ETH for USDC and separately stake 1 ETH on Lido\" is TWO).\n\
+            Respond with ONLY a JSON array of strings, one self-contained intent per action.\n\n\
+            Intent: {}",
+            intent
+        );
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.chat_model)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .max_tokens(512u16)
+            .temperature(0.1)
+            .build()?;
+
+        let timer = METRICS.llm_duration.with_label_values(&["decompose_intent"]).start_timer();
+        let response = self.create(request).await?;
+        timer.observe_duration();
+        if let Some(usage) = &response.usage {
+            record_usage("decompose_intent", usage);
+        }
+
+        let content = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default();
+        let json = content
+            .find('[')
+            .zip(content.rfind(']'))
+            .and_then(|(start, end)| content.get(start..=end))
+            .ok_or_else(|| eyre!("No JSON array in decomposition: {}", content))?;
+
+        let parts = serde_json::from_str::<Vec<String>>(json)?
+            .into_iter()
+            .map(|part| part.trim().to_string())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            return Ok(vec![intent.to_string()]);
+        }
+        Ok(parts)
+    }
+
+    async fn classify_protocols(&self, intent: &str, protocols: &[String]) -> Result<String> {
+        let prompt = format!(
+            "Which of these protocols does this blockchain transaction intent interact with?\n\
+            Protocols: {}\n\
+            Respond with JSON of the form {{\"protocols\": [\"uniswap_v3\"]}}, using only names from the list, \
+            and an empty list if the intent uses none of them.\n\n\
+            Intent: {}",
+            protocols.join(", "),
+            intent
+        );
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "protocols": {
+                    "type": "array",
+                    "items": { "type": "string", "enum": protocols },
+                }
+            },
+            "required": ["protocols"],
+            "additionalProperties": false,
+        });
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.chat_model)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .response_format(ResponseFormat::JsonSchema {
+                json_schema: ResponseFormatJsonSchema {
+                    description: Some("Protocols the intent interacts with".to_string()),
+                    name: "protocol_classification".to_string(),
+                    schema: Some(schema),
+                    strict: Some(true),
+                },
+            })
+            .max_tokens(128u16)
+            .temperature(0.0)
+            .build()?;
+
+        let timer = METRICS.llm_duration.with_label_values(&["classify_protocols"]).start_timer();
+        let response = self.create(request).await?;
+        timer.observe_duration();
+        if let Some(usage) = &response.usage {
+            record_usage("classify_protocols", usage);
+        }
+
+        Ok(response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default())
+    }
+
+    async fn parse_intent(&self, intent: &str) -> Result<ActionPlan> {
+        let prompt = format!(
+            "Convert this blockchain transaction intent into a JSON action plan.\n\
+            Respond with ONLY a JSON object of the form:\n\
+            {{\"actions\": [{{\"action\": \"swap\", \"protocol\": \"uniswap\", \"tokens\": [\"USDC\", \"WETH\"], \
+            \"amounts\": [\"100\"], \"slippage_bps\": 50, \"recipient\": null}}]}}\n\
+            \"action\" is one of: swap, transfer, approve, wrap, unwrap, supply, withdraw, borrow, repay, stake, unstake, bridge, other.\n\
+            List tokens as the intent names them (symbol or address), the token spent first, and amounts in the same order. \
+            Use null for anything the intent doesn't say; never invent values.\n\n\
+            Intent: {}",
+            intent
+        );
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.chat_model)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .max_tokens(512u16)
+            .temperature(0.1)
+            .build()?;
+
+        let timer = METRICS.llm_duration.with_label_values(&["parse_intent"]).start_timer();
+        let response = self.create(request).await?;
+        timer.observe_duration();
+        if let Some(usage) = &response.usage {
+            record_usage("parse_intent", usage);
+        }
+
+        let content = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default();
+        let json = content
+            .find('{')
+            .zip(content.rfind('}'))
+            .and_then(|(start, end)| content.get(start..=end))
+            .ok_or_else(|| eyre!("No JSON object in action plan: {}", content))?;
+
+        let plan = serde_json::from_str::<ActionPlan>(json).map_err(|e| eyre!("Action plan doesn't match the schema: {}", e))?;
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    async fn explain_transactions(&self, transactions: &str, token_deltas: &str) -> Result<String> {
+        let prompt = format!(
+            "Explain these simulated Ethereum transactions to a non-technical user who is about to sign them.\n\
+            For each transaction, in order, say in one or two plain sentences which contract it calls, \
+            what the function does and which tokens or ETH move and where. \
+            Then list any risks worth checking, such as unlimited token approvals, sending funds to an unfamiliar \
+            address or large value transfers. Don't invent details that aren't in the data.\n\n\
+            Transactions:\n{}\n\n\
+            Net token changes for the sender:\n{}",
+            transactions, token_deltas
+        );
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.chat_model)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .max_tokens(1024u16)
+            .temperature(0.2)
+            .build()?;
+
+        let timer = METRICS.llm_duration.with_label_values(&["explain_transactions"]).start_timer();
+        let response = self.create(request).await?;
+        timer.observe_duration();
+        if let Some(usage) = &response.usage {
+            record_usage("explain_transactions", usage);
+        }
+
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .map(|content| content.trim().to_string())
+            .filter(|content| !content.is_empty())
+            .ok_or_else(|| eyre!("Empty explanation"))
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self.client.models().list().await?;
+        Ok(response.data.into_iter().map(|model| model.id).collect())
+    }
+
+    async fn generate_invariant_test(&self, address: &str, intent: &str, script: &str) -> Result<String> {
+        let prompt = format!(
+            "Write a Solidity forge test that checks the script below does what the user intended.\n\
+            The test file lives in test/ next to script/Script.s.sol: import the script contract from \
+            \"../script/Script.s.sol\" and import {{Test}} from \"forge-std/Test.sol\". \
+            Name the contract IntentInvariantsTest. In a single test function named test_invariants: \
+            record the balances of {} that the intent affects (ETH and the ERC-20 tokens the script uses, \
+            at the addresses the script uses), deploy the script contract and call run(), then assert the \
+            changes with assertLe/assertGe, e.g. the input token decreases by at most the amount spent and \
+            the output token increases by at least the minimum output. Allow for gas when checking ETH. \
+            Don't assert exact amounts that depend on market prices. Never use console.\n\n\
+            User intent: {}\n\n\
+            Script:\n```solidity\n{}\n```\n\n\
+            Format the response as a complete Solidity file with SPDX license and pragma in a solidity code block.",
+            address, intent, script
+        );
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.code_model)
+            .messages(vec![ChatCompletionRequestUserMessageArgs::default()
+                .content(prompt)
+                .build()?
+                .into()])
+            .max_tokens(2048u16)
+            .temperature(0.1)
+            .build()?;
+
+        let timer = METRICS.llm_duration.with_label_values(&["generate_invariant_test"]).start_timer();
+        let response = self.create(request).await?;
+        timer.observe_duration();
+        if let Some(usage) = &response.usage {
+            record_usage("generate_invariant_test", usage);
+        }
+
+        response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .ok_or_else(|| eyre!("Empty invariant test"))
+    }
+}
+
+/// `messages`, followed when `partial` holds an interrupted answer by that answer
+/// and an instruction to carry on from where it stopped
+fn continuation(messages: &[ChatCompletionRequestMessage], partial: &str) -> Result<Vec<ChatCompletionRequestMessage>> {
+    let mut messages = messages.to_vec();
+    if !partial.is_empty() {
+        messages.push(ChatCompletionRequestAssistantMessageArgs::default().content(partial).build()?.into());
+        messages.push(
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(
+                    "Your response was cut off. Continue exactly from where it stopped, \
+                    without repeating anything already written and without any commentary.",
+                )
+                .build()?
+                .into(),
+        );
+    }
+    Ok(messages)
+}
+
+/// Whether an answer's code stops partway: a code block that's never closed, or
+/// more opening braces than closing ones
+fn looks_truncated(response: &str) -> bool {
+    response.matches("```").count() % 2 == 1 || response.matches('{').count() > response.matches('}').count()
+}
+
+/// `continuation` without any start of it that repeats the end of `partial`.
+/// Overlaps shorter than `MIN_RESUME_OVERLAP` are taken as coincidence.
+fn skip_repeated(partial: &str, continuation: &str) -> String {
+    let overlap = (MIN_RESUME_OVERLAP..=continuation.len().min(partial.len()))
+        .rev()
+        .filter(|len| continuation.is_char_boundary(*len))
+        .find(|len| partial.ends_with(&continuation[..*len]))
+        .unwrap_or(0);
+    continuation[overlap..].to_string()
+}
+
+fn record_usage(call: &str, usage: &CompletionUsage) {
+    METRICS
+        .llm_tokens
+        .with_label_values(&[call, "prompt"])
+        .inc_by(usage.prompt_tokens as u64);
+    METRICS
+        .llm_tokens
+        .with_label_values(&[call, "completion"])
+        .inc_by(usage.completion_tokens as u64);
+}
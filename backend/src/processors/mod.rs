@@ -4,13 +4,17 @@ use async_openai::types::ChatCompletionRequestUserMessage;
 use eyre::Result;
 use tokio::sync::mpsc::Sender;
 use std::path::PathBuf;
-use crate::models::ForgeStep;
+use crate::models::{ActionPlan, ForgeStep};
 mod protocol_guidelines;
+mod few_shot;
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TemplatePattern {
     pub template: String,
     pub parameter_order: Vec<usize>,
+    /// Protocols the template's script was generated for
+    #[serde(default)]
+    pub protocols: Vec<String>,
     pub frequency: u64,
     pub success_rate: f64,
 }
@@ -39,6 +43,26 @@ pub trait LLMGenerator {
 
     async fn generate(&self, messages: &mut Vec<ChatCompletionRequestUserMessage>) -> Result<String>;
 
+    /// Split an intent into actions that can be scripted and simulated
+    /// independently. Dependent actions stay together as a single entry.
+    async fn decompose_intent(&self, intent: &str) -> Result<Vec<String>>;
+
+    /// Which of `protocols` an intent interacts with, as JSON of the form
+    /// `{"protocols": [...]}`
+    async fn classify_protocols(&self, intent: &str, protocols: &[String]) -> Result<String>;
+
+    /// Turn an intent into a structured, validated action plan
+    async fn parse_intent(&self, intent: &str) -> Result<ActionPlan>;
+
+    /// Explain simulated transactions (JSON) and the sender's token deltas (JSON) for a non-technical reader
+    async fn explain_transactions(&self, transactions: &str, token_deltas: &str) -> Result<String>;
+
+    /// Write a forge test that runs `script` and asserts the balance changes `intent` implies
+    async fn generate_invariant_test(&self, address: &str, intent: &str, script: &str) -> Result<String>;
+
+    /// Models the gateway serves
+    async fn list_models(&self) -> Result<Vec<String>>;
+
 }
 
 pub enum LLMImpl {
@@ -91,11 +115,48 @@ impl LLMGenerator for LLMImpl {
         }
     }
 
+    async fn decompose_intent(&self, intent: &str) -> Result<Vec<String>> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.decompose_intent(intent).await,
+        }
+    }
+
+    async fn classify_protocols(&self, intent: &str, protocols: &[String]) -> Result<String> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.classify_protocols(intent, protocols).await,
+        }
+    }
+
+    async fn parse_intent(&self, intent: &str) -> Result<ActionPlan> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.parse_intent(intent).await,
+        }
+    }
+
+    async fn explain_transactions(&self, transactions: &str, token_deltas: &str) -> Result<String> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.explain_transactions(transactions, token_deltas).await,
+        }
+    }
+
+    async fn generate_invariant_test(&self, address: &str, intent: &str, script: &str) -> Result<String> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.generate_invariant_test(address, intent, script).await,
+        }
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        match self {
+            LLMImpl::Heurist(llm) => llm.list_models().await,
+        }
+    }
+
 }
 
 pub use heurist_llm::LLMTemplateGenerator as HeuristLLM;
 
-pub use protocol_guidelines::ProtocolGuidelinesProcessor;
+pub use protocol_guidelines::{ClassificationError, ProtocolGuidelinesProcessor, VerifiedContract};
+pub use few_shot::{examples_section, ExampleStore};
 
 // pub fn extract_source_code(source_code: &str) -> Result<String> {
 //     // Handle standard JSON format
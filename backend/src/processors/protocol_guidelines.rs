@@ -1,14 +1,453 @@
 use eyre::{eyre, Result};
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use super::LLMGenerator;
+use crate::models::{ActionType, GuidelinesConfig, PlannedAction, ProtocolInfo};
 use reqwest::Client;
+use tracing::{debug, info, warn};
 use async_openai::types::ChatCompletionRequestUserMessageArgs;
 
+/// Delimits the TOML front matter a guideline file may start with
+const FRONT_MATTER: &str = "+++";
+/// Curated guidelines built into the binary, seeded into the guidelines
+/// directory so a fresh deployment has more than what the operator generated
+const BUILTIN_GUIDELINES: &[(&str, &str)] = &[
+    ("balancer_v2", include_str!("../../guideline_packs/balancer_v2.md")),
+    ("compound_v3", include_str!("../../guideline_packs/compound_v3.md")),
+    ("curve", include_str!("../../guideline_packs/curve.md")),
+    ("lido", include_str!("../../guideline_packs/lido.md")),
+    ("morpho_blue", include_str!("../../guideline_packs/morpho_blue.md")),
+];
+/// Contract source given to the model when generating from a verified
+/// contract; flattened sources past this are cut off
+const MAX_CONTRACT_SOURCE_CHARS: usize = 60_000;
+/// Characters per token assumed when budgeting guidelines
+const CHARS_PER_TOKEN: usize = 4;
+/// Intent words shorter than this are too generic to rank sections by
+const MIN_KEYWORD_LEN: usize = 3;
+/// A keyword in a section's heading counts this many times one in its text
+const HEADING_MATCH_WEIGHT: usize = 3;
+const STOPWORDS: &[&str] = &["the", "and", "for", "with", "from", "into", "then", "that", "this", "all", "using", "use", "via"];
+/// What guideline sections are about, by the stems of words in their headings
+const TOPICS: &[(&str, &[&str])] = &[
+    ("swap", &["swap", "exchange", "quot", "router", "trade"]),
+    ("liquidity", &["liquidity", "position", "collect", "join", "exit", "lp"]),
+    ("supply", &["suppl", "deposit", "lend", "collateral"]),
+    ("borrow", &["borrow", "loan", "leverag"]),
+    ("repay", &["repay"]),
+    ("withdraw", &["withdraw", "redeem"]),
+    ("stake", &["stak", "submit"]),
+    ("wrap", &["wrap", "unwrap"]),
+    ("liquidation", &["liquidat"]),
+    ("rewards", &["reward", "claim"]),
+];
+/// Subdirectory of the guidelines directory holding prompt-optimized versions
+const COMPACT_DIR: &str = "compact";
+/// Lists the built-in guidelines already seeded, so one the operator deleted
+/// or replaced isn't written back
+const SEEDED_FILE: &str = ".seeded";
+
 pub struct ProtocolGuidelinesProcessor {
     guidelines_dir: PathBuf,
-    guidelines: HashMap<String, String>,
+    guidelines: HashMap<String, Guideline>,
+}
+
+/// A protocol's guideline file: the full markdown, what its front matter says
+/// about it, and the prompt-optimized version generation is given when there's one
+struct Guideline {
+    content: String,
+    compact: Option<String>,
+    /// Sections of the text generation is given, and what each is about
+    sections: Vec<IndexedSection>,
+    metadata: GuidelineMetadata,
+    /// Unix time the file was last modified
+    updated_at: Option<u64>,
+}
+
+/// Front matter of a guideline file, e.g.
+///
+/// ```toml
+/// +++
+/// description = "Concentrated-liquidity AMM"
+/// chains = [1, 8453]
+/// +++
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GuidelineMetadata {
+    description: Option<String>,
+    /// Chains the guideline's addresses are for
+    chains: Vec<u64>,
+}
+
+/// Front matter of a prompt-optimized guideline
+#[derive(Debug, Deserialize)]
+struct CompactMetadata {
+    /// Version of the full guideline it was made from
+    source_version: String,
+}
+
+impl Guideline {
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let (metadata, content) = match split_front_matter(&text) {
+            Some((front_matter, content)) => (
+                toml::from_str(front_matter).map_err(|e| eyre!("Invalid front matter in {}: {}", path.display(), e))?,
+                content,
+            ),
+            None => (GuidelineMetadata::default(), text.as_str()),
+        };
+        let updated_at = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|age| age.as_secs());
+        let mut guideline = Self { content: content.to_string(), compact: None, sections: Vec::new(), metadata, updated_at };
+        guideline.compact = guideline.load_compact(path);
+        guideline.sections = index_sections(guideline.for_generation());
+        Ok(guideline)
+    }
+
+    /// The prompt-optimized version next to a guideline file, unless it was made
+    /// from an earlier version of the guideline
+    fn load_compact(&self, path: &Path) -> Option<String> {
+        let compact_path = path.parent()?.join(COMPACT_DIR).join(path.file_name()?);
+        let text = fs::read_to_string(&compact_path).ok()?;
+        let (front_matter, content) = split_front_matter(&text)?;
+        let metadata = toml::from_str::<CompactMetadata>(front_matter)
+            .inspect_err(|e| warn!("Ignoring {}: invalid front matter: {}", compact_path.display(), e))
+            .ok()?;
+        if metadata.source_version != self.source_version() {
+            warn!(
+                "Ignoring {}: made from an earlier version of the guideline; run compress-guidelines again",
+                compact_path.display()
+            );
+            return None;
+        }
+        Some(content.to_string())
+    }
+
+    /// Digest of the full content, which changes whenever it's edited
+    fn source_version(&self) -> String {
+        ethers::utils::hex::encode(ethers::utils::keccak256(&self.content))
+    }
+
+    /// Digest of the full content and of the prompt-optimized version when
+    /// there's one, which changes whenever either is edited or compressed again
+    fn version(&self) -> String {
+        match &self.compact {
+            Some(compact) => ethers::utils::hex::encode(ethers::utils::keccak256(format!("{}{}", self.source_version(), compact))),
+            None => self.source_version(),
+        }
+    }
+
+    /// What generation is given: the prompt-optimized version when there's one
+    fn for_generation(&self) -> &str {
+        self.compact.as_deref().unwrap_or(&self.content)
+    }
+
+    /// What generation is given, without the sections about topics none of
+    /// the planned actions involve: a swap gets the router sections but not
+    /// liquidity management. Sections about no topic in particular are kept.
+    fn select(&self, actions: &[ActionType]) -> Cow<'_, str> {
+        let content = self.for_generation();
+        let mut wanted = Vec::new();
+        for action in actions {
+            match action_topics(*action) {
+                Some(topics) => wanted.extend_from_slice(topics),
+                // Nothing to go on for an action of no known kind
+                None => return Cow::Borrowed(content),
+            }
+        }
+        if wanted.is_empty() {
+            return Cow::Borrowed(content);
+        }
+
+        let mut kept = self
+            .sections
+            .iter()
+            .map(|section| section.topics.is_empty() || section.topics.iter().any(|topic| wanted.contains(topic)))
+            .collect::<Vec<_>>();
+        for (index, section) in self.sections.iter().enumerate() {
+            if let Some(parent) = section.parent.filter(|_| kept[index]) {
+                kept[parent] = true;
+            }
+        }
+        if kept.iter().all(|kept| *kept) {
+            return Cow::Borrowed(content);
+        }
+        Cow::Owned(
+            self.sections
+                .iter()
+                .zip(&kept)
+                .filter(|(_, kept)| **kept)
+                .map(|(section, _)| &content[section.range.clone()])
+                .collect(),
+        )
+    }
+}
+
+/// A section of a guideline, located by byte range
+struct IndexedSection {
+    range: Range<usize>,
+    parent: Option<usize>,
+    /// Topics from `TOPICS` its heading names
+    topics: Vec<&'static str>,
+}
+
+fn index_sections(content: &str) -> Vec<IndexedSection> {
+    split_sections(content)
+        .into_iter()
+        .map(|section| {
+            let start = section.text.as_ptr() as usize - content.as_ptr() as usize;
+            IndexedSection {
+                range: start..start + section.text.len(),
+                parent: section.parent,
+                topics: heading_topics(section.heading),
+            }
+        })
+        .collect()
+}
+
+/// Topics a heading names, matching word stems so that "Swaps",
+/// "SwapExactInputSingle" and "Staking" count
+fn heading_topics(heading: &str) -> Vec<&'static str> {
+    let words = heading_words(heading);
+    TOPICS
+        .iter()
+        .filter(|(_, stems)| words.iter().any(|word| stems.iter().any(|stem| word.starts_with(stem))))
+        .map(|(topic, _)| *topic)
+        .collect()
+}
+
+/// Lowercased words of a heading, splitting identifiers at camel-case humps
+fn heading_words(heading: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous_lower = false;
+    for c in heading.chars() {
+        if (!c.is_ascii_alphanumeric() || (c.is_ascii_uppercase() && previous_lower)) && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        if c.is_ascii_alphanumeric() {
+            word.push(c.to_ascii_lowercase());
+        }
+        previous_lower = c.is_ascii_lowercase();
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Guideline topics an action needs; `None` when it could need any of them
+fn action_topics(action: ActionType) -> Option<&'static [&'static str]> {
+    Some(match action {
+        ActionType::Swap => &["swap"],
+        ActionType::Wrap | ActionType::Unwrap => &["wrap"],
+        // Planners call adding liquidity a supply and removing it a withdrawal
+        ActionType::Supply => &["supply", "liquidity"],
+        ActionType::Withdraw => &["withdraw", "liquidity"],
+        // Borrowing usually starts with supplying the collateral
+        ActionType::Borrow => &["borrow", "supply"],
+        ActionType::Repay => &["repay"],
+        ActionType::Stake => &["stake", "wrap"],
+        ActionType::Unstake => &["withdraw", "wrap"],
+        ActionType::Transfer | ActionType::Approve | ActionType::Bridge => &[],
+        ActionType::Other => return None,
+    })
+}
+
+/// Whether a planned action's protocol, as the planner wrote it ("Uniswap"),
+/// names a guideline's protocol ("uniswap_v3"): one reads as the start of the
+/// other once case and punctuation are dropped
+fn same_protocol(planned: &str, protocol: &str) -> bool {
+    let normalize = |name: &str| name.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_ascii_lowercase();
+    let (planned, protocol) = (normalize(planned), normalize(protocol));
+    !planned.is_empty() && (protocol.starts_with(&planned) || planned.starts_with(&protocol))
+}
+
+/// Write the built-in guidelines that were never seeded into `dir`, leaving
+/// any file already there alone
+fn seed_builtin_guidelines(dir: &Path) -> Result<()> {
+    let seeded_path = dir.join(SEEDED_FILE);
+    let mut seeded = fs::read_to_string(&seeded_path)
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    let mut added = Vec::new();
+    for (protocol, content) in BUILTIN_GUIDELINES {
+        if seeded.iter().any(|name| name == protocol) {
+            continue;
+        }
+        let path = dir.join(format!("{}.md", protocol));
+        if !path.exists() {
+            fs::write(&path, content)?;
+            added.push(*protocol);
+        }
+        seeded.push(protocol.to_string());
+    }
+    if !added.is_empty() {
+        info!("Seeded built-in guidelines: {}", added.join(", "));
+    }
+    fs::write(&seeded_path, seeded.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Rough token count of prompt text; close enough for budgeting English and code
+fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Lowercased words of an intent worth matching against guideline sections
+fn intent_keywords(intent: &str) -> Vec<String> {
+    let mut keywords = intent
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| word.len() >= MIN_KEYWORD_LEN)
+        .map(str::to_ascii_lowercase)
+        .filter(|word| !STOPWORDS.contains(&word.as_str()))
+        .collect::<Vec<_>>();
+    keywords.sort();
+    keywords.dedup();
+    keywords
+}
+
+/// A guideline heading and the text up to the next one of level 3 or above
+struct Section<'a> {
+    heading: &'a str,
+    text: &'a str,
+    /// The level-2 section a level-3 one sits under
+    parent: Option<usize>,
+}
+
+/// Split markdown at its `##` and `###` headings, ignoring lines in code fences.
+/// Text before the first such heading is a section with no heading.
+fn split_sections(content: &str) -> Vec<Section<'_>> {
+    let mut sections = Vec::new();
+    let mut start = 0;
+    let mut heading = "";
+    let mut parent = None;
+    let mut current_parent = None;
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        let level = line.chars().take_while(|&c| c == '#').count();
+        if !in_fence && (level == 2 || level == 3) && line[level..].starts_with(' ') {
+            if offset > start {
+                sections.push(Section { heading, text: &content[start..offset], parent });
+            }
+            start = offset;
+            heading = line[level..].trim();
+            if level == 2 {
+                current_parent = Some(sections.len());
+                parent = None;
+            } else {
+                parent = current_parent;
+            }
+        }
+        offset += line.len();
+    }
+    if offset > start {
+        sections.push(Section { heading, text: &content[start..], parent });
+    }
+    sections
+}
+
+/// Keep a guideline's opening text and deployment addresses, then as many of
+/// the other sections as fit in `budget` tokens, most relevant to the intent
+/// first, in their original order
+fn trim_guideline(content: &str, keywords: &[String], budget: usize) -> String {
+    let sections = split_sections(content);
+    let mut kept = vec![false; sections.len()];
+    let mut used = 0;
+
+    let essential = |section: &Section| section.heading.is_empty() || section.heading.to_lowercase().contains("address");
+    for (index, section) in sections.iter().enumerate() {
+        if essential(section) {
+            kept[index] = true;
+            used += estimate_tokens(section.text);
+        }
+    }
+
+    let relevance = |section: &Section| {
+        let heading = section.heading.to_lowercase();
+        let text = section.text.to_lowercase();
+        keywords
+            .iter()
+            .map(|keyword| HEADING_MATCH_WEIGHT * heading.contains(keyword.as_str()) as usize + text.contains(keyword.as_str()) as usize)
+            .sum::<usize>()
+    };
+    let mut ranked = (0..sections.len()).filter(|&index| !kept[index]).collect::<Vec<_>>();
+    // Stable, so equally relevant sections keep their document order
+    ranked.sort_by_key(|&index| std::cmp::Reverse(relevance(&sections[index])));
+
+    for index in ranked {
+        if kept[index] {
+            continue;
+        }
+        // A subsection brings the heading of the section it sits under
+        let parent = sections[index].parent.filter(|&parent| !kept[parent]);
+        let cost = estimate_tokens(sections[index].text) + parent.map_or(0, |parent| estimate_tokens(sections[parent].text));
+        if used + cost > budget {
+            continue;
+        }
+        kept[index] = true;
+        if let Some(parent) = parent {
+            kept[parent] = true;
+        }
+        used += cost;
+    }
+
+    let mut trimmed = sections
+        .iter()
+        .zip(&kept)
+        .filter(|(_, kept)| **kept)
+        .map(|(section, _)| section.text)
+        .collect::<String>();
+    let dropped = sections
+        .iter()
+        .zip(&kept)
+        .filter(|(_, kept)| !**kept)
+        .map(|(section, _)| section.heading)
+        .collect::<Vec<_>>();
+    if !dropped.is_empty() {
+        trimmed.push_str(&format!("\n(Sections left out for length: {})\n", dropped.join("; ")));
+    }
+    trimmed
+}
+
+/// Lowercased hex addresses in a guideline
+fn find_addresses(text: &str) -> Vec<String> {
+    let mut addresses = Vec::new();
+    for (start, _) in text.match_indices("0x") {
+        let hex = text[start + 2..].chars().take_while(char::is_ascii_hexdigit).count();
+        if hex == 40 {
+            let address = text[start..start + 42].to_lowercase();
+            if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+    }
+    addresses
+}
+
+/// A guideline's front matter and the markdown after it
+fn split_front_matter(text: &str) -> Option<(&str, &str)> {
+    let rest = text.strip_prefix(FRONT_MATTER)?.trim_start_matches('\r').strip_prefix('\n')?;
+    let end = rest.find(&format!("\n{}", FRONT_MATTER))?;
+    let content = &rest[end + 1 + FRONT_MATTER.len()..];
+    Some((&rest[..end], content.trim_start_matches(['\r', '\n'])))
 }
 
 impl ProtocolGuidelinesProcessor {
@@ -20,6 +459,8 @@ impl ProtocolGuidelinesProcessor {
             fs::create_dir_all(&dir_path)?;
         }
         
+        seed_builtin_guidelines(&dir_path)?;
+
         let mut guidelines = HashMap::new();
         
         // Load existing guidelines
@@ -30,8 +471,7 @@ impl ProtocolGuidelinesProcessor {
                 
                 if path.is_file() && path.extension().map_or(false, |ext| ext == "md") {
                     if let Some(protocol_name) = path.file_stem().and_then(|s| s.to_str()) {
-                        let content = fs::read_to_string(&path)?;
-                        guidelines.insert(protocol_name.to_string(), content);
+                        guidelines.insert(protocol_name.to_string(), Guideline::load(&path)?);
                     }
                 }
             }
@@ -43,46 +483,163 @@ impl ProtocolGuidelinesProcessor {
         })
     }
     
-    pub async fn get_guideline(&self, llm: &impl LLMGenerator, intent: &str) -> Result<String> {
-        let prompt = format!(
-            "Based on this user input, determine which protocols the user is trying to interact with. \
-            Return a concise list of the protocols in a json array. 
-            Example output: \
-            [\"uniswap_v3\"]
-            The user input is: {}\n\
-            The protocols are: {}",
-            intent,
-            self.guidelines.keys().cloned().collect::<Vec<_>>().join(", ")
-        );
-
-        let mut messages = Vec::new();
-        messages.push(ChatCompletionRequestUserMessageArgs::default()
-        .content(prompt)
-        .build()?);
-
-        let content = llm.generate( &mut messages).await?;
+    /// The guidelines of detected protocols, each narrowed to the sections its
+    /// own planned actions need and concatenated within the config's token
+    /// budget. Each protocol gets an even share, with what a shorter one doesn't
+    /// need going to the others, and a guideline over its share keeps only the
+    /// sections most relevant to the intent.
+    pub fn guidelines_for(&self, protocols: &[String], intent: &str, actions: &[PlannedAction], config: &GuidelinesConfig) -> String {
+        let selected = protocols
+            .iter()
+            .map(|protocol| {
+                let own = actions
+                    .iter()
+                    .filter(|action| action.protocol.as_deref().is_some_and(|name| same_protocol(name, protocol)))
+                    .map(|action| action.action)
+                    .collect::<Vec<_>>();
+                (protocol, self.guidelines[protocol].select(&own))
+            })
+            .collect::<HashMap<_, _>>();
+        let mut by_size = protocols
+            .iter()
+            .map(|protocol| (protocol, estimate_tokens(&selected[protocol])))
+            .collect::<Vec<_>>();
+        by_size.sort_by_key(|(_, tokens)| *tokens);
 
-        // Extract just the JSON array part using a more robust approach
-        let protocols = content
-            .lines()  // Split into lines
-            .find(|line| line.trim().starts_with('['))  // Find the line that starts with [
-            .ok_or_else(|| eyre::eyre!("No JSON array found in response"))?;  // Error if not found
-
-        let protocols: Vec<String> = serde_json::from_str(protocols)?;
+        let mut remaining = config.max_tokens;
+        let mut budgets = HashMap::new();
+        for (index, (protocol, tokens)) in by_size.iter().enumerate() {
+            let share = remaining / (by_size.len() - index);
+            let budget = (*tokens).min(share.max(config.min_tokens_per_protocol));
+            budgets.insert(*protocol, budget);
+            remaining = remaining.saturating_sub(budget);
+        }
 
+        let keywords = intent_keywords(intent);
         let mut guidelines = String::new();
         for protocol in protocols {
-            guidelines.push_str(&self.guidelines[&protocol]);
+            let content = &selected[protocol];
+            let budget = budgets[protocol];
+            if estimate_tokens(content) <= budget {
+                guidelines.push_str(content);
+            } else {
+                debug!(protocol = %protocol, budget, "Trimming guideline to its token budget");
+                guidelines.push_str(&trim_guideline(content, &keywords, budget));
+            }
             guidelines.push_str("\n\n");
         }
+        guidelines
+    }
+
+    /// Protocols with guidelines that the intent interacts with; empty when it
+    /// names none. Fails with a `ClassificationError` when the answer isn't usable.
+    pub async fn detect_protocols(&self, llm: &impl LLMGenerator, intent: &str) -> Result<Vec<String>> {
+        let mut available = self.available_protocols();
+        available.sort();
+        let content = llm.classify_protocols(intent, &available).await?;
+        let protocols = parse_classification(&content)?;
 
-        Ok(guidelines)
+        // The model sometimes names protocols there are no guidelines for
+        let (known, unknown): (Vec<_>, Vec<_>) = protocols
+            .into_iter()
+            .partition(|protocol| self.guidelines.contains_key(protocol));
+        if known.is_empty() && !unknown.is_empty() {
+            return Err(ClassificationError::NoMatchingGuidelines(unknown).into());
+        }
+        Ok(known)
     }
     
     pub fn available_protocols(&self) -> Vec<String> {
         self.guidelines.keys().cloned().collect()
     }
+
+    /// A protocol's guideline as generation is given it, or the full version
+    /// written for people when `full` is set, and its version
+    pub fn guideline(&self, protocol: &str, full: bool) -> Option<(&str, String)> {
+        let guideline = self.guidelines.get(protocol)?;
+        let content = if full { &guideline.content } else { guideline.for_generation() };
+        Some((content, guideline.version()))
+    }
+
+    /// Every protocol with guidelines and what's known about them, by name
+    pub fn protocols(&self) -> Vec<ProtocolInfo> {
+        let mut protocols = self
+            .guidelines
+            .iter()
+            .map(|(name, guideline)| ProtocolInfo {
+                name: name.clone(),
+                description: guideline.metadata.description.clone(),
+                chains: guideline.metadata.chains.clone(),
+                version: guideline.version(),
+                updated_at: guideline.updated_at,
+                compact: guideline.compact.is_some(),
+            })
+            .collect::<Vec<_>>();
+        protocols.sort_by(|a, b| a.name.cmp(&b.name));
+        protocols
+    }
+
+    /// Digest of every loaded guideline as generation is given it, which changes
+    /// whenever one is edited or compressed again
+    pub fn version(&self) -> String {
+        let mut protocols = self.guidelines.iter().map(|(name, guideline)| (name, guideline.for_generation())).collect::<Vec<_>>();
+        protocols.sort();
+        let content = serde_json::to_string(&protocols).unwrap_or_default();
+        ethers::utils::hex::encode(ethers::utils::keccak256(content))
+    }
     
+    /// Write a prompt-optimized version of a protocol's guideline, which
+    /// generation is given instead of the full one until the full one changes.
+    /// Any deployment address the model leaves out is added back from the full
+    /// guideline's address sections.
+    pub async fn compress_guideline(&self, llm: &impl LLMGenerator, protocol: &str) -> Result<String> {
+        let guideline = self
+            .guidelines
+            .get(protocol)
+            .ok_or_else(|| eyre!("No guidelines for protocol {}", protocol))?;
+
+        let prompt = format!(
+            "Rewrite the following guidelines for the {} protocol as a compact reference an AI assistant is given \
+            when generating Foundry scripts. Keep only:\n\
+            1. Every deployment address, with its contract name and chain, copied exactly\n\
+            2. The signatures of the contract functions the guidelines use, as Solidity interface declarations\n\
+            3. The rules that prevent lost funds or reverts (slippage, approvals, decimals, ordering), one line each\n\
+            4. One canonical example script, the most representative one\n\
+            Drop overviews, explanations and repeated examples. Don't add anything the guidelines don't say. \
+            Answer with the markdown document only.\n\n{}",
+            protocol, guideline.content
+        );
+        let mut messages = vec![ChatCompletionRequestUserMessageArgs::default().content(prompt).build()?];
+        let mut content = llm.generate(&mut messages).await?;
+
+        let missing = find_addresses(&guideline.content)
+            .into_iter()
+            .filter(|address| !content.to_lowercase().contains(address))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            warn!(protocol, missing = missing.len(), "Compressed guideline dropped deployment addresses; adding the address sections back");
+            for section in split_sections(&guideline.content) {
+                if section.heading.to_lowercase().contains("address") {
+                    content.push_str("\n\n");
+                    content.push_str(section.text.trim());
+                }
+            }
+            content.push('\n');
+        }
+
+        let compact_dir = self.guidelines_dir.join(COMPACT_DIR);
+        fs::create_dir_all(&compact_dir)?;
+        let file = format!(
+            "{}\nsource_version = \"{}\"\n{}\n\n{}",
+            FRONT_MATTER,
+            guideline.source_version(),
+            FRONT_MATTER,
+            content.trim_start()
+        );
+        fs::write(compact_dir.join(format!("{}.md", protocol)), file)?;
+        Ok(content)
+    }
+
     pub async fn generate_guidelines(
         &self,
         llm: &impl LLMGenerator,
@@ -129,6 +686,139 @@ impl ProtocolGuidelinesProcessor {
         
         Ok(content)
     }
+
+    /// Guidelines for a contract with no documentation, written from its
+    /// verified source (and the NatSpec in it) and ABI
+    pub async fn generate_contract_guidelines(
+        &self,
+        llm: &impl LLMGenerator,
+        protocol: String,
+        contract: &VerifiedContract,
+    ) -> Result<String> {
+        let source = match contract.source.char_indices().nth(MAX_CONTRACT_SOURCE_CHARS) {
+            Some((end, _)) => format!("{}\n// ... source truncated", &contract.source[..end]),
+            None => contract.source.clone(),
+        };
+
+        let prompt = format!(
+            "Generate comprehensive guidelines for the {} protocol that will help an AI assistant generate secure, production-ready, bug-free Solidity code that can be executed. \
+            The protocol has no documentation; all you have is its verified contract {} deployed at {} on chain {}. \
+            The guidelines should include:\n\
+            1. Protocol overview, from the contract's NatSpec and code\n\
+            2. Core functions and example implementation / template \n\
+            3. Security considerations specific to the protocol\n\
+            4. Common pitfalls\n\
+            5. Deployment Addresses\n\
+            The contract's external interface:\n```solidity\n{}\n```\n\n\
+            The contract's verified source:\n```solidity\n{}\n```\n\n\
+            Format the output as a markdown document with appropriate sections, code examples, and security warnings. \
+            The code examples should be production-ready and follow all security best practices.
+            Only use functions from the interface above, with exactly those signatures; read the source for what each one requires (access control, modifiers, require statements) and what it does with the caller's tokens.
+            Include the view functions that should be read before a call, such as quotes, balances or limits, and how their results are used for slippage or validation.
+            Scripts must call the contract at {}{}.
+            Under Deployment Addresses, list {} on chain {} and no other addresses unless the source hardcodes them.",
+            protocol,
+            contract.name,
+            contract.address,
+            contract.chain_id,
+            contract.signatures.join("\n"),
+            source,
+            contract.address,
+            match &contract.implementation {
+                Some(implementation) => format!(", a proxy whose logic is the implementation at {} shown above; never call the implementation directly", implementation),
+                None => String::new(),
+            },
+            contract.address,
+            contract.chain_id,
+        );
+
+        info!("Generating guidelines for {} from {}", protocol, contract.address);
+
+        let mut messages = Vec::new();
+        messages.push(ChatCompletionRequestUserMessageArgs::default()
+        .content(prompt)
+        .build()?);
+
+        let content = llm.generate(&mut messages).await?;
+        let content = format!(
+            "{}\ndescription = {}\nchains = [{}]\n{}\n\n{}",
+            FRONT_MATTER,
+            toml::Value::String(format!("{} ({})", contract.name, contract.address)),
+            contract.chain_id,
+            FRONT_MATTER,
+            content.trim_start(),
+        );
+
+        let file_path = self.guidelines_dir.join(format!("{}.md", protocol));
+        fs::write(&file_path, &content)?;
+
+        Ok(content)
+    }
+}
+
+/// What guidelines are generated from for a contract without documentation
+pub struct VerifiedContract {
+    pub address: String,
+    pub chain_id: u64,
+    pub name: String,
+    /// The address of the logic when `address` is a proxy
+    pub implementation: Option<String>,
+    pub source: String,
+    /// Solidity declarations of the ABI's functions and events
+    pub signatures: Vec<String>,
+}
+
+/// Why protocol classification gave nothing to go on
+#[derive(Debug)]
+pub enum ClassificationError {
+    /// The answer wasn't the requested JSON
+    InvalidResponse(String),
+    /// Every protocol named has no guidelines
+    NoMatchingGuidelines(Vec<String>),
+}
+
+impl fmt::Display for ClassificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassificationError::InvalidResponse(content) => {
+                write!(f, "Protocol classification isn't the expected JSON: {}", content)
+            }
+            ClassificationError::NoMatchingGuidelines(protocols) => {
+                write!(f, "No guidelines for the protocols named: {}", protocols.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClassificationError {}
+
+#[derive(Deserialize)]
+struct Classification {
+    protocols: Vec<String>,
+}
+
+/// The protocols in a classification answer: the schema's object, or, from
+/// providers that ignore the response format, a bare array or either one
+/// surrounded by prose or a code fence
+fn parse_classification(content: &str) -> Result<Vec<String>, ClassificationError> {
+    let embedded = |open: char, close: char| {
+        content
+            .find(open)
+            .zip(content.rfind(close))
+            .and_then(|(start, end)| content.get(start..=end))
+    };
+    let protocols = serde_json::from_str::<Classification>(content.trim())
+        .map(|classification| classification.protocols)
+        .ok()
+        .or_else(|| embedded('{', '}').and_then(|json| serde_json::from_str::<Classification>(json).ok()).map(|classification| classification.protocols))
+        .or_else(|| embedded('[', ']').and_then(|json| serde_json::from_str::<Vec<String>>(json).ok()))
+        .ok_or_else(|| ClassificationError::InvalidResponse(content.to_string()))?;
+
+    Ok(protocols
+        .into_iter()
+        .map(|protocol| protocol.trim().to_lowercase())
+        .filter(|protocol| !protocol.is_empty())
+        .collect())
 }
 
 async fn fetch_doc_links(links: Vec<String>) -> Result<Vec<String>> {
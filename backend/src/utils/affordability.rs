@@ -0,0 +1,47 @@
+use super::rpc_client::rpc_provider;
+use super::trace::{parse_address, parse_quantity};
+use crate::models::TransactionDetails;
+use ethers::providers::Middleware;
+use ethers::types::U256;
+use ethers::utils::{format_ether, format_units};
+use eyre::Result;
+
+/// Compare the sender's native balance on the fork against the ETH the
+/// transactions send plus their estimated gas at the current gas price.
+/// Returns a warning for the client when the balance falls short.
+pub async fn check_affordability(rpc_url: &str, transactions: &[TransactionDetails]) -> Result<Option<String>> {
+    let Some(from) = transactions.first().map(|transaction| transaction.from.as_str()) else {
+        return Ok(None);
+    };
+    let from = parse_address(from)?;
+
+    let provider = rpc_provider(rpc_url)?;
+    let balance = provider.get_balance(from, None).await?;
+    let gas_price = provider.get_gas_price().await?;
+
+    let value = transactions
+        .iter()
+        .fold(U256::zero(), |total, transaction| total.saturating_add(parse_quantity(&transaction.value)));
+    let gas = transactions
+        .iter()
+        .fold(U256::zero(), |total, transaction| total.saturating_add(parse_quantity(&transaction.gas)));
+    let gas_cost = gas.saturating_mul(gas_price);
+    let required = value.saturating_add(gas_cost);
+
+    if balance >= required {
+        return Ok(None);
+    }
+
+    let gwei = format_units(gas_price, "gwei").unwrap_or_else(|_| gas_price.to_string());
+    let what = if balance >= value { "insufficient ETH for gas" } else { "insufficient ETH" };
+    Ok(Some(format!(
+        "Sender has {}: needs {} ETH ({} ETH sent + {} ETH for {} gas at {} gwei) but holds {} ETH",
+        what,
+        format_ether(required),
+        format_ether(value),
+        format_ether(gas_cost),
+        gas,
+        gwei,
+        format_ether(balance),
+    )))
+}
@@ -0,0 +1,126 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::process::Stdio;
+use super::sandbox::{sandboxed_command, NetworkAccess};
+use crate::models::SandboxConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Informational,
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub severity: Severity,
+    pub check: String,
+    pub description: String,
+    pub line: Option<usize>,
+}
+
+/// Substring heuristics for patterns that rarely belong in a user's transaction script
+const BUILTIN_CHECKS: &[(&str, Severity, &str, &str)] = &[
+    ("selfdestruct(", Severity::High, "selfdestruct", "Script destroys a contract"),
+    ("delegatecall(", Severity::High, "delegatecall", "Script performs a delegatecall, running foreign code in the caller's context"),
+    ("tx.origin", Severity::Medium, "tx-origin", "tx.origin is used; it should never be relied on for authorization"),
+    (".call{value:", Severity::Medium, "low-level-call-value", "ETH is sent with a low-level call"),
+    (".call(", Severity::Low, "low-level-call", "Low-level call bypasses ABI checks"),
+    ("assembly", Severity::Informational, "inline-assembly", "Inline assembly is used"),
+];
+
+/// Patterns that make an `approve` unlimited
+const UNLIMITED_AMOUNTS: &[&str] = &["type(uint256).max", "type(uint).max", "2**256 - 1", "2 ** 256 - 1", "uint256(-1)"];
+
+/// Line-based heuristic review of generated script source
+pub fn builtin_analysis(source: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        // Ignore commented-out code
+        let code = line.split("//").next().unwrap_or_default();
+        if code.trim_start().starts_with('*') {
+            continue;
+        }
+
+        for (pattern, severity, check, description) in BUILTIN_CHECKS {
+            if code.contains(pattern) {
+                findings.push(Finding {
+                    severity: *severity,
+                    check: check.to_string(),
+                    description: description.to_string(),
+                    line: Some(index + 1),
+                });
+            }
+        }
+
+        if code.contains("approve(") && UNLIMITED_AMOUNTS.iter().any(|amount| code.contains(amount)) {
+            findings.push(Finding {
+                severity: Severity::Medium,
+                check: "unlimited-approval".to_string(),
+                description: "Token approval is unlimited; approve the exact amount needed instead".to_string(),
+                line: Some(index + 1),
+            });
+        }
+    }
+
+    findings
+}
+
+#[derive(Deserialize)]
+struct SlitherOutput {
+    success: bool,
+    error: Option<String>,
+    results: Option<SlitherResults>,
+}
+
+#[derive(Deserialize)]
+struct SlitherResults {
+    #[serde(default)]
+    detectors: Vec<SlitherDetector>,
+}
+
+#[derive(Deserialize)]
+struct SlitherDetector {
+    check: String,
+    impact: String,
+    description: String,
+}
+
+/// Run slither over the session's script and map its detectors to findings
+pub async fn slither_analysis(project_path: &Path, sandbox: &SandboxConfig) -> Result<Vec<Finding>> {
+    let output = sandboxed_command("slither", project_path, NetworkAccess::None, sandbox)
+        .args(["script/Script.s.sol", "--json", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| eyre!("Failed to run slither: {}", e))?;
+
+    // slither exits non-zero when it finds issues, so rely on its JSON instead
+    let parsed: SlitherOutput = serde_json::from_slice(&output.stdout)
+        .map_err(|e| eyre!("Failed to parse slither output: {}", e))?;
+    if !parsed.success {
+        return Err(eyre!("slither failed: {}", parsed.error.unwrap_or_default()));
+    }
+
+    Ok(parsed
+        .results
+        .map(|results| results.detectors)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|detector| Finding {
+            severity: match detector.impact.as_str() {
+                "High" => Severity::High,
+                "Medium" => Severity::Medium,
+                "Low" => Severity::Low,
+                _ => Severity::Informational,
+            },
+            check: detector.check,
+            description: detector.description.trim().to_string(),
+            line: None,
+        })
+        .collect())
+}
@@ -0,0 +1,174 @@
+use super::rpc_client::{rpc_provider, RpcProvider};
+use super::trace::view_call;
+use crate::models::TransactionDetails;
+use ethers::providers::Middleware;
+use ethers::types::{Address, H256, U256};
+use ethers::utils::keccak256;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+use tracing::warn;
+
+/// `balanceOf(address)`
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+/// `decimals()`
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+/// Storage slots searched for a token's balance mapping
+const MAX_BALANCE_SLOT: u64 = 64;
+
+/// Anvil nodes simulations fork from. Each is used by one simulation at a time
+/// while its snapshot is outstanding, so a revert never lands in the middle of
+/// another job's run.
+#[derive(Default)]
+pub struct AnvilForks {
+    nodes: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+}
+
+impl AnvilForks {
+    /// Snapshot the node's state when `rpc_url` is an anvil node, waiting for
+    /// any other simulation on it to finish first. `None` for other nodes.
+    pub async fn snapshot(&self, rpc_url: &str) -> Result<Option<ForkSnapshot>> {
+        let provider = rpc_provider(rpc_url)?;
+        if !provider.client_version().await?.starts_with("anvil") {
+            return Ok(None);
+        }
+
+        let node = self.nodes.lock().unwrap().entry(rpc_url.to_string()).or_default().clone();
+        let guard = node.lock_owned().await;
+        let id = provider.request::<_, U256>("evm_snapshot", ()).await?;
+        Ok(Some(ForkSnapshot {
+            provider: Arc::new(provider),
+            id,
+            impersonated: None,
+            guard: Some(guard),
+        }))
+    }
+}
+
+/// An anvil node's state before a simulation, held until it's reverted to.
+/// One dropped without being reverted, say when its job is cancelled
+/// mid-simulation, is reverted in the background before the node is let go.
+pub struct ForkSnapshot {
+    provider: Arc<RpcProvider>,
+    id: U256,
+    impersonated: Option<Address>,
+    /// `None` once the node has been reverted
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ForkSnapshot {
+    /// Let transactions from `address` be sent unsigned until the revert
+    pub async fn impersonate(&mut self, address: Address) -> Result<()> {
+        self.provider.request::<_, ()>("anvil_impersonateAccount", [address]).await?;
+        self.impersonated = Some(address);
+        Ok(())
+    }
+
+    /// Mine a transaction from the impersonated account until the revert
+    pub async fn execute(&self, transaction: &TransactionDetails) -> Result<()> {
+        let from = self.impersonated.ok_or_else(|| eyre!("No account is impersonated"))?;
+        let call = serde_json::json!({
+            "from": from,
+            // No recipient makes it a deployment
+            "to": (!transaction.is_create()).then_some(&transaction.to),
+            "value": transaction.value,
+            "data": transaction.input_data,
+        });
+        self.provider.request::<_, H256>("eth_sendTransaction", [call]).await?;
+        Ok(())
+    }
+
+    /// Set an account's ETH balance until the revert
+    pub async fn set_balance(&self, address: Address, wei: U256) -> Result<()> {
+        self.provider.request::<_, ()>("anvil_setBalance", (address, wei)).await?;
+        Ok(())
+    }
+
+    /// Give `holder` `units` whole tokens of an ERC-20 until the revert, the way
+    /// forge-std's `deal` does: candidate slots of the balance mapping are
+    /// written until `balanceOf` reads the amount back. Returns the amount in
+    /// the token's smallest unit.
+    pub async fn deal(&self, token: Address, holder: Address, units: u64) -> Result<U256> {
+        let decimals = view_call(&self.provider, token, DECIMALS.to_vec())
+            .await
+            .ok()
+            .filter(|output| output.len() >= 32)
+            .map_or(18, |output| U256::from_big_endian(&output[..32]).low_u32());
+        let amount = U256::from(units) * U256::exp10(decimals as usize);
+        let mut value = [0u8; 32];
+        amount.to_big_endian(&mut value);
+
+        for slot in 0..MAX_BALANCE_SLOT {
+            // Solidity mappings hash the key before the slot, Vyper's after
+            for key in [mapping_key(holder, slot, false), mapping_key(holder, slot, true)] {
+                let original = self.provider.get_storage_at(token, key, None).await?;
+                self.set_storage(token, key, H256(value)).await?;
+                if self.balance_of(token, holder).await? == amount {
+                    return Ok(amount);
+                }
+                self.set_storage(token, key, original).await?;
+            }
+        }
+        Err(eyre!("No balance mapping found in the first {} storage slots of token {:?}", MAX_BALANCE_SLOT, token))
+    }
+
+    async fn set_storage(&self, address: Address, key: H256, value: H256) -> Result<()> {
+        self.provider.request::<_, bool>("anvil_setStorageAt", (address, key, value)).await?;
+        Ok(())
+    }
+
+    async fn balance_of(&self, token: Address, holder: Address) -> Result<U256> {
+        let mut calldata = BALANCE_OF.to_vec();
+        calldata.extend_from_slice(H256::from(holder).as_bytes());
+        let output = view_call(&self.provider, token, calldata).await?;
+        Ok(output.get(..32).map(U256::from_big_endian).unwrap_or_default())
+    }
+
+    /// Put the node back the way it was and let the next simulation use it
+    pub async fn revert(mut self) -> Result<()> {
+        let reverted = restore(&self.provider, self.id, self.impersonated).await;
+        self.guard = None;
+        reverted
+    }
+}
+
+impl Drop for ForkSnapshot {
+    fn drop(&mut self) {
+        let Some(guard) = self.guard.take() else {
+            return;
+        };
+        let (provider, id, impersonated) = (self.provider.clone(), self.id, self.impersonated);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = restore(&provider, id, impersonated).await {
+                        warn!("Failed to revert the fork to its snapshot: {}", e);
+                    }
+                    drop(guard);
+                });
+            }
+            Err(_) => warn!("Fork snapshot {} dropped outside the runtime; it isn't reverted", id),
+        }
+    }
+}
+
+/// Revert a node to snapshot `id`, ending the impersonation of `impersonated`
+async fn restore(provider: &RpcProvider, id: U256, impersonated: Option<Address>) -> Result<()> {
+    // Impersonation isn't part of the state a snapshot restores
+    if let Some(address) = impersonated {
+        provider.request::<_, ()>("anvil_stopImpersonatingAccount", [address]).await?;
+    }
+    if !provider.request::<_, bool>("evm_revert", [id]).await? {
+        return Err(eyre!("anvil has no snapshot {}", id));
+    }
+    Ok(())
+}
+
+/// Storage key of `holder`'s entry in the mapping at `slot`
+fn mapping_key(holder: Address, slot: u64, vyper: bool) -> H256 {
+    let holder = H256::from(holder);
+    let slot = H256::from_low_u64_be(slot);
+    let (first, second) = if vyper { (slot, holder) } else { (holder, slot) };
+    H256(keccak256([first.as_bytes(), second.as_bytes()].concat()))
+}
@@ -0,0 +1,125 @@
+use super::rpc_client::rpc_provider;
+use super::trace::{parse_address, trace_transaction, view_call, CallFrame};
+use crate::models::TransactionDetails;
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, TransactionRequest, U256};
+use ethers::utils::hex;
+use eyre::{eyre, Result};
+
+const TRANSFER_FROM: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+const APPROVE: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3];
+const ALLOWANCE: [u8; 4] = [0xdd, 0x62, 0xed, 0x3e];
+
+/// Gas assumed for an approval when the fork can't estimate it
+const DEFAULT_APPROVE_GAS: u64 = 60_000;
+
+/// Tokens pulled from the sender by a spender, in first-seen order
+#[derive(Default)]
+struct Pulls(Vec<(Address, Address, U256)>);
+
+impl Pulls {
+    fn add(&mut self, token: Address, spender: Address, amount: U256) {
+        match self.0.iter_mut().find(|(t, s, _)| *t == token && *s == spender) {
+            Some((_, _, total)) => *total = total.saturating_add(amount),
+            None => self.0.push((token, spender, amount)),
+        }
+    }
+
+    /// Walk a call tree for `transferFrom(owner, _, amount)` calls made by someone other than `owner`
+    fn collect(&mut self, frame: &CallFrame, owner: Address) {
+        frame.visit(&mut |call| {
+            if let (Some(token), Some((from, amount))) = (call.to, decode_transfer_from(&call.input)) {
+                if from == owner && call.from != owner {
+                    self.add(token, call.from, amount);
+                }
+            }
+        });
+    }
+}
+
+fn decode_transfer_from(input: &[u8]) -> Option<(Address, U256)> {
+    let args = input.strip_prefix(&TRANSFER_FROM)?;
+    let tokens = abi::decode(&[abi::ParamType::Address, abi::ParamType::Address, abi::ParamType::Uint(256)], args).ok()?;
+    Some((tokens[0].clone().into_address()?, tokens[2].clone().into_uint()?))
+}
+
+fn decode_approve(input: &[u8]) -> Option<Address> {
+    let args = input.strip_prefix(&APPROVE)?;
+    let tokens = abi::decode(&[abi::ParamType::Address, abi::ParamType::Uint(256)], args).ok()?;
+    tokens[0].clone().into_address()
+}
+
+fn parse_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|e| eyre!("Invalid hex {}: {}", value, e))
+}
+
+/// Find token pulls in the simulated transactions that rely on an allowance the
+/// sender hasn't granted on the fork and that the bundle doesn't approve itself,
+/// and build the exact-amount `approve` transactions that cover them.
+pub async fn missing_approvals(rpc_url: &str, transactions: &[TransactionDetails]) -> Result<Vec<TransactionDetails>> {
+    let Some(first) = transactions.first() else {
+        return Ok(Vec::new());
+    };
+    let owner = parse_address(&first.from)?;
+    let provider = rpc_provider(rpc_url)?;
+
+    let mut approved = Vec::new();
+    let mut pulls = Pulls::default();
+    for transaction in transactions {
+        if !transaction.is_create() {
+            let to = parse_address(&transaction.to)?;
+            let input = parse_hex(&transaction.input_data)?;
+            if let Some(spender) = decode_approve(&input) {
+                approved.push((to, spender));
+            }
+        }
+
+        // A call that reverts on a missing allowance still shows the transferFrom it tried
+        let frame = trace_transaction(&provider, owner, transaction).await?;
+        pulls.collect(&frame, owner);
+    }
+
+    let mut approvals = Vec::new();
+    for (token, spender, amount) in pulls.0 {
+        if approved.contains(&(token, spender)) {
+            continue;
+        }
+
+        let mut calldata = ALLOWANCE.to_vec();
+        calldata.extend(abi::encode(&[Token::Address(owner), Token::Address(spender)]));
+        let allowance = U256::from_big_endian(&view_call(&provider, token, calldata).await?);
+        if allowance >= amount {
+            continue;
+        }
+
+        let mut input = APPROVE.to_vec();
+        input.extend(abi::encode(&[Token::Address(spender), Token::Uint(amount)]));
+        let request: TypedTransaction = TransactionRequest::new()
+            .from(owner)
+            .to(token)
+            .data(input.clone())
+            .into();
+        let gas = provider
+            .estimate_gas(&request, None)
+            .await
+            .unwrap_or_else(|_| U256::from(DEFAULT_APPROVE_GAS));
+
+        approvals.push(TransactionDetails {
+            from: format!("{:?}", owner),
+            to: format!("{:?}", token),
+            function: "approve(address,uint256)".to_string(),
+            arguments: vec![format!("{:?}", spender), amount.to_string()],
+            value: "0x0".to_string(),
+            input_data: format!("0x{}", hex::encode(input)),
+            gas: format!("{:#x}", gas),
+            value_usd: None,
+            part: None,
+            order: None,
+            phase: None,
+            deployment: None,
+        });
+    }
+
+    Ok(approvals)
+}
@@ -0,0 +1,154 @@
+use crate::models::{AuditConfig, TransactionDetails};
+use ethers::utils::{hex, keccak256};
+use eyre::{eyre, Result};
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// `prev_hash` of the first record
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Every line ends with its hash: `,"hash":"<64 hex>"}`
+const HASH_SUFFIX_LEN: usize = r#","hash":""}"#.len() + 64;
+
+/// What happened in a session
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent<'a> {
+    /// A script was written, generated, fixed or taken from a template. `version`
+    /// is unset if it couldn't be stored among the session's versions.
+    Script { version: Option<usize>, script: &'a str },
+    /// The latest script was simulated; failed simulations have no transactions
+    Simulation { succeeded: bool, transactions: &'a [TransactionDetails] },
+}
+
+#[derive(Serialize)]
+struct Body<'a> {
+    seq: u64,
+    timestamp: u64,
+    prev_hash: &'a str,
+    session: &'a str,
+    /// Address the job was requested for
+    requested_by: Option<&'a str>,
+    intent: Option<&'a str>,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// A line's body without its hash, and the hash
+fn split_line(line: &str) -> Option<(String, &str)> {
+    let suffix_start = line.len().checked_sub(HASH_SUFFIX_LEN)?;
+    let hash = line
+        .get(suffix_start..)?
+        .strip_prefix(r#","hash":""#)?
+        .strip_suffix("\"}")?;
+    Some((format!("{}}}", &line[..suffix_start]), hash))
+}
+
+fn seal(body: &str) -> String {
+    hex::encode(keccak256(body))
+}
+
+/// Sequence number and `prev_hash` of a line's body
+fn chain_fields(body: &str) -> Option<(u64, String)> {
+    let value = serde_json::from_str::<serde_json::Value>(body).ok()?;
+    Some((value["seq"].as_u64()?, value["prev_hash"].as_str()?.to_string()))
+}
+
+/// Append-only trail of the scripts sessions produced and how their simulations
+/// went. Each record carries the hash of the one before and its own, so an edited,
+/// removed or reordered record breaks the chain.
+pub struct AuditLog {
+    path: PathBuf,
+    /// Sequence number and hash of the last record
+    head: Mutex<(u64, String)>,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let path = PathBuf::from(&config.path);
+        let head = match fs::read_to_string(&path) {
+            Ok(content) => match content.lines().last() {
+                Some(line) => {
+                    let (body, hash) = split_line(line)
+                        .ok_or_else(|| eyre!("Last record of audit log {} is malformed", path.display()))?;
+                    let (seq, _) = chain_fields(&body)
+                        .ok_or_else(|| eyre!("Last record of audit log {} is malformed", path.display()))?;
+                    (seq, hash.to_string())
+                }
+                None => (0, GENESIS_HASH.to_string()),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, GENESIS_HASH.to_string()),
+            Err(e) => return Err(eyre!("Failed to read audit log {}: {}", path.display(), e)),
+        };
+        Ok(Self {
+            path,
+            head: Mutex::new(head),
+        })
+    }
+
+    pub fn append(&self, session: &str, requested_by: Option<&str>, intent: Option<&str>, event: AuditEvent) {
+        let mut head = self.head.lock().unwrap();
+        let body = Body {
+            seq: head.0 + 1,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            prev_hash: &head.1,
+            session,
+            requested_by,
+            intent,
+            event,
+        };
+        let Ok(body) = serde_json::to_string(&body) else {
+            warn!("Failed to serialize audit record");
+            return;
+        };
+        let hash = seal(&body);
+        let line = format!("{},\"hash\":\"{}\"}}", &body[..body.len() - 1], hash);
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        match written {
+            Ok(()) => *head = (head.0 + 1, hash),
+            Err(e) => warn!("Failed to append audit record: {}", e),
+        }
+    }
+}
+
+/// Check the audit log's hash chain end to end, then write its records (those of
+/// `session`, or since `since` if given) to `output`. Returns how many were written.
+pub fn export_audit(path: &Path, session: Option<&str>, since: Option<u64>, output: &mut impl Write) -> Result<usize> {
+    let content = fs::read_to_string(path).map_err(|e| eyre!("Failed to read audit log {}: {}", path.display(), e))?;
+
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        let (body, hash) = split_line(line).ok_or_else(|| eyre!("Record {} is malformed", number))?;
+        let (seq, recorded_prev_hash) = chain_fields(&body).ok_or_else(|| eyre!("Record {} is malformed", number))?;
+        if seq != number as u64 || recorded_prev_hash != prev_hash {
+            return Err(eyre!("Audit chain is broken at record {}: records were removed or reordered", number));
+        }
+        if seal(&body) != hash {
+            return Err(eyre!("Audit chain is broken at record {}: its content was modified", number));
+        }
+        prev_hash = hash.to_string();
+    }
+
+    let mut exported = 0;
+    for line in content.lines() {
+        let record = serde_json::from_str::<serde_json::Value>(line)?;
+        if session.is_some_and(|session| record["session"].as_str() != Some(session))
+            || since.is_some_and(|since| record["timestamp"].as_u64().unwrap_or_default() < since)
+        {
+            continue;
+        }
+        writeln!(output, "{}", line)?;
+        exported += 1;
+    }
+    Ok(exported)
+}
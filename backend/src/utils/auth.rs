@@ -0,0 +1,188 @@
+use crate::models::AuthConfig;
+use chrono::{DateTime, Utc};
+use ethers::types::{Address, Signature};
+use eyre::{eyre, Result};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long an issued nonce can be used to sign in
+const NONCE_TTL: Duration = Duration::from_secs(300);
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// The fields of an EIP-4361 (Sign-In with Ethereum) message that the server checks
+#[derive(Debug)]
+pub struct SiweMessage {
+    pub domain: String,
+    pub address: Address,
+    pub uri: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub expiration_time: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+}
+
+impl FromStr for SiweMessage {
+    type Err = eyre::Report;
+
+    fn from_str(message: &str) -> Result<Self> {
+        let mut lines = message.lines();
+
+        let domain = lines
+            .next()
+            .and_then(|line| line.strip_suffix(HEADER_SUFFIX))
+            .ok_or_else(|| eyre!("Not a Sign-In with Ethereum message"))?
+            .to_string();
+
+        let address = lines
+            .next()
+            .ok_or_else(|| eyre!("Missing address"))?
+            .trim()
+            .parse::<Address>()
+            .map_err(|e| eyre!("Invalid address: {}", e))?;
+
+        let mut fields = HashMap::new();
+        for line in lines {
+            if let Some((key, value)) = line.split_once(": ") {
+                fields.insert(key, value.trim());
+            }
+        }
+
+        let parse_time = |key: &str| -> Result<Option<DateTime<Utc>>> {
+            fields
+                .get(key)
+                .map(|value| {
+                    DateTime::parse_from_rfc3339(value)
+                        .map(|time| time.with_timezone(&Utc))
+                        .map_err(|e| eyre!("Invalid {}: {}", key, e))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            domain,
+            address,
+            uri: fields
+                .get("URI")
+                .ok_or_else(|| eyre!("Missing URI"))?
+                .to_string(),
+            chain_id: fields
+                .get("Chain ID")
+                .ok_or_else(|| eyre!("Missing chain id"))?
+                .parse()
+                .map_err(|e| eyre!("Invalid chain id: {}", e))?,
+            nonce: fields
+                .get("Nonce")
+                .ok_or_else(|| eyre!("Missing nonce"))?
+                .to_string(),
+            expiration_time: parse_time("Expiration Time")?,
+            not_before: parse_time("Not Before")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthSession {
+    pub address: Address,
+    pub expires_at: Instant,
+}
+
+/// Outstanding sign-in nonces and the session tokens issued for verified wallets
+pub struct AuthStore {
+    domain: Option<String>,
+    chain_ids: Vec<u64>,
+    session_ttl: Duration,
+    nonces: Mutex<HashMap<String, Instant>>,
+    sessions: Mutex<HashMap<String, AuthSession>>,
+}
+
+impl AuthStore {
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            domain: config.domain.clone(),
+            chain_ids: config.chain_ids.clone(),
+            session_ttl: Duration::from_secs(config.session_ttl_secs),
+            nonces: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn issue_nonce(&self) -> String {
+        let nonce = Uuid::new_v4().simple().to_string();
+        let mut nonces = self.nonces.lock().unwrap();
+        nonces.retain(|_, issued| issued.elapsed() < NONCE_TTL);
+        nonces.insert(nonce.clone(), Instant::now());
+        nonce
+    }
+
+    /// Verify a signed SIWE message and issue a session token for its address
+    pub fn sign_in(&self, message: &str, signature: &str) -> Result<(String, AuthSession)> {
+        let siwe = SiweMessage::from_str(message)?;
+
+        // Binding the message to this server's domain is what stops a message
+        // signed for another site from being relayed here
+        let domain = self.domain.as_deref().ok_or_else(|| eyre!("No SIWE domain is configured"))?;
+        if siwe.domain != domain {
+            return Err(eyre!("Message was issued for domain {}", siwe.domain));
+        }
+        let uri = Url::parse(&siwe.uri).map_err(|e| eyre!("Invalid URI: {}", e))?;
+        let host = uri.host_str().unwrap_or_default();
+        let authority = match uri.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        if authority != domain {
+            return Err(eyre!("Message URI {} isn't on domain {}", siwe.uri, domain));
+        }
+        if !self.chain_ids.is_empty() && !self.chain_ids.contains(&siwe.chain_id) {
+            return Err(eyre!("Message was signed for unsupported chain {}", siwe.chain_id));
+        }
+
+        let now = Utc::now();
+        if siwe.expiration_time.is_some_and(|expires| expires <= now) {
+            return Err(eyre!("Message has expired"));
+        }
+        if siwe.not_before.is_some_and(|not_before| not_before > now) {
+            return Err(eyre!("Message is not valid yet"));
+        }
+
+        let signature = Signature::from_str(signature.trim_start_matches("0x"))
+            .map_err(|e| eyre!("Invalid signature: {}", e))?;
+        signature
+            .verify(message, siwe.address)
+            .map_err(|_| eyre!("Signature does not match {:?}", siwe.address))?;
+
+        // Nonces are single use, but only a signed message uses one up, so
+        // requests that fail above can't burn another wallet's nonce
+        let issued = self.nonces.lock().unwrap().remove(&siwe.nonce);
+        if issued.filter(|issued| issued.elapsed() < NONCE_TTL).is_none() {
+            return Err(eyre!("Unknown or expired nonce"));
+        }
+
+        let session = AuthSession {
+            address: siwe.address,
+            expires_at: Instant::now() + self.session_ttl,
+        };
+        let token = Uuid::new_v4().to_string();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > Instant::now());
+        sessions.insert(token.clone(), session.clone());
+
+        Ok((token, session))
+    }
+
+    /// The session for a token, if it exists and hasn't expired
+    pub fn session(&self, token: &str) -> Option<AuthSession> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .filter(|session| session.expires_at > Instant::now())
+            .cloned()
+    }
+}
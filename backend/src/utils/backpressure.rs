@@ -0,0 +1,78 @@
+//! What happens to a job's events when they arrive faster than they're consumed.
+//! Streamed text is merged rather than lost, progress updates superseded by a
+//! later one are dropped, and everything else, including the terminal `error`
+//! and `result` events, is always delivered.
+
+use super::metrics::METRICS;
+use crate::models::ForgeStep;
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+/// Send an event that a later one supersedes, dropping it instead of waiting
+/// when the channel is full
+pub async fn send_droppable(tx: &Sender<ForgeStep>, step: ForgeStep) {
+    debug_assert!(step.is_droppable());
+    if let Err(TrySendError::Full(step)) = tx.try_send(step) {
+        METRICS.events_dropped.with_label_values(&[step.kind()]).inc();
+    }
+}
+
+/// Part a step belongs to, and the step itself
+fn unwrap_part(step: &ForgeStep) -> (Option<usize>, &ForgeStep) {
+    match step {
+        ForgeStep::Part { part, event } => (Some(*part), event),
+        step => (None, step),
+    }
+}
+
+/// Append `next` to `previous` when both are streamed text of the same kind
+/// from the same part
+fn merge(previous: &mut ForgeStep, next: &ForgeStep) -> bool {
+    match (previous, next) {
+        (ForgeStep::Part { part, event }, ForgeStep::Part { part: next_part, event: next_event }) if part == next_part => {
+            merge(event, next_event)
+        }
+        (ForgeStep::TokenDelta { text }, ForgeStep::TokenDelta { text: next }) => {
+            text.push_str(next);
+            true
+        }
+        (ForgeStep::CompileOutput { stage, line }, ForgeStep::CompileOutput { stage: next_stage, line: next })
+            if stage == next_stage =>
+        {
+            line.push('\n');
+            line.push_str(next);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Shrink a backlog of events: consecutive streamed text is merged and progress
+/// updates followed by a later one for the same part are dropped
+pub fn coalesce(backlog: Vec<ForgeStep>) -> Vec<ForgeStep> {
+    if backlog.len() < 2 {
+        return backlog;
+    }
+
+    let superseded = |index: usize, step: &ForgeStep| {
+        let part = unwrap_part(step).0;
+        step.is_droppable()
+            && backlog[index + 1..]
+                .iter()
+                .any(|later| later.is_droppable() && unwrap_part(later).0 == part)
+    };
+    let mut kept: Vec<ForgeStep> = Vec::with_capacity(backlog.len());
+    for (index, step) in backlog.iter().enumerate() {
+        if superseded(index, step) {
+            METRICS.events_dropped.with_label_values(&[unwrap_part(step).1.kind()]).inc();
+            continue;
+        }
+        if let Some(previous) = kept.last_mut() {
+            if merge(previous, step) {
+                METRICS.events_coalesced.with_label_values(&[unwrap_part(step).1.kind()]).inc();
+                continue;
+            }
+        }
+        kept.push(step.clone());
+    }
+    kept
+}
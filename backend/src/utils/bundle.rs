@@ -0,0 +1,90 @@
+use super::rpc_client::rpc_provider;
+use super::trace::{parse_address, parse_quantity};
+use crate::models::{BundleConfig, FlashbotsBundle, TransactionDetails};
+use ethers::providers::Middleware;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{transaction::eip2718::TypedTransaction, BlockNumber, Bytes, Eip1559TransactionRequest, U256};
+use ethers::utils::hex;
+use eyre::{eyre, Result};
+
+/// Package transactions as a Flashbots `eth_sendBundle` request for `block`, the
+/// chain's next block when unset. Nonces follow the sender's pending nonce and
+/// fees come from the latest base fee. With a `signer`, which must be the
+/// sender, the bundle is ready to send; without one its `txs` are the unsigned
+/// encodings the sender's wallet signs.
+pub async fn flashbots_bundle(
+    rpc_url: &str,
+    transactions: &[TransactionDetails],
+    signer: Option<LocalWallet>,
+    block: Option<u64>,
+    config: &BundleConfig,
+) -> Result<FlashbotsBundle> {
+    let first = transactions.first().ok_or_else(|| eyre!("The session has no transactions to bundle"))?;
+    let sender = parse_address(&first.from)?;
+    for transaction in transactions {
+        if parse_address(&transaction.from)? != sender {
+            return Err(eyre!("A bundle's transactions must all come from one sender"));
+        }
+        if parse_quantity(&transaction.gas).is_zero() {
+            return Err(eyre!("Transaction to {} has no gas estimate to bundle it with", transaction.to));
+        }
+    }
+    if let Some(signer) = signer.as_ref().filter(|signer| signer.address() != sender) {
+        return Err(eyre!("The session's key is for {:?}, not the sender {:?}", signer.address(), sender));
+    }
+
+    let provider = rpc_provider(rpc_url)?;
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let latest = provider
+        .get_block(BlockNumber::Latest)
+        .await?
+        .ok_or_else(|| eyre!("The RPC returned no latest block"))?;
+    let latest_number = latest.number.ok_or_else(|| eyre!("The latest block has no number"))?.as_u64();
+    let target_block = block.unwrap_or(latest_number + 1);
+    if target_block <= latest_number {
+        return Err(eyre!("Block {} has already been mined; the latest is {}", target_block, latest_number));
+    }
+    let priority_fee = U256::from(config.priority_fee_gwei) * U256::exp10(9);
+    let max_fee = latest.base_fee_per_gas.unwrap_or_default() * config.base_fee_multiplier + priority_fee;
+    let nonce = provider.get_transaction_count(sender, Some(BlockNumber::Pending.into())).await?;
+    let signer = signer.map(|signer| signer.with_chain_id(chain_id));
+
+    let mut bundled = Vec::with_capacity(transactions.len());
+    let mut txs = Vec::with_capacity(transactions.len());
+    for (index, transaction) in transactions.iter().enumerate() {
+        let input = hex::decode(transaction.input_data.trim_start_matches("0x"))
+            .map_err(|e| eyre!("Invalid input data for {}: {}", transaction.to, e))?;
+        let mut request = Eip1559TransactionRequest::new()
+            .from(sender)
+            .value(parse_quantity(&transaction.value))
+            .data(Bytes::from(input))
+            .gas(parse_quantity(&transaction.gas))
+            .nonce(nonce + index)
+            .chain_id(chain_id)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(priority_fee);
+        // A plain CREATE has no recipient
+        if !transaction.is_create() {
+            request = request.to(parse_address(&transaction.to)?);
+        }
+        let request: TypedTransaction = request.into();
+        let encoded = match &signer {
+            Some(signer) => request.rlp_signed(&signer.sign_transaction(&request).await?),
+            None => request.rlp(),
+        };
+        txs.push(encoded.to_string());
+        bundled.push(request);
+    }
+
+    Ok(FlashbotsBundle {
+        signed: signer.is_some(),
+        target_block,
+        payload: serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_sendBundle",
+            "params": [{ "txs": txs, "blockNumber": format!("{:#x}", target_block) }],
+        }),
+        transactions: bundled,
+    })
+}
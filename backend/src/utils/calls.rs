@@ -0,0 +1,171 @@
+//! Text-level helpers for finding calls and arguments in generated Solidity,
+//! for checks that only need to look at a few well-known call shapes
+
+/// The source lowercased with `//` comments removed, keeping line breaks so
+/// byte offsets still map to line numbers
+pub fn normalize(source: &str) -> String {
+    source
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or_default().to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `source` with comments and the contents of string literals blanked out with
+/// spaces, keeping line breaks so lines and byte offsets stay where they were
+pub fn blank_comments_and_strings(source: &str) -> String {
+    enum State {
+        Code,
+        LineComment,
+        BlockComment,
+        Str(char),
+    }
+
+    let mut state = State::Code;
+    let mut blanked = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let kept = match state {
+            State::Code => match (c, next) {
+                ('/', Some('/')) => {
+                    state = State::LineComment;
+                    false
+                }
+                ('/', Some('*')) => {
+                    state = State::BlockComment;
+                    false
+                }
+                ('"' | '\'', _) => {
+                    state = State::Str(c);
+                    true
+                }
+                _ => true,
+            },
+            State::LineComment if c == '\n' => {
+                state = State::Code;
+                true
+            }
+            State::BlockComment if c == '*' && next == Some('/') => {
+                chars.next();
+                blanked.push(' ');
+                state = State::Code;
+                false
+            }
+            State::Str(quote) if c == quote => {
+                state = State::Code;
+                true
+            }
+            State::Str(_) if c == '\\' => {
+                if let Some(escaped) = chars.next() {
+                    blanked.extend(std::iter::repeat_n(' ', escaped.len_utf8()));
+                }
+                false
+            }
+            _ => c == '\n',
+        };
+        if kept {
+            blanked.push(c);
+        } else {
+            blanked.extend(std::iter::repeat_n(' ', c.len_utf8()));
+        }
+    }
+    blanked
+}
+
+/// 1-based line number of a byte offset
+pub fn line_at(code: &str, offset: usize) -> usize {
+    code[..offset].matches('\n').count() + 1
+}
+
+/// Comma-separated arguments up to the closing parenthesis, respecting nesting.
+/// Stops early at a `;` outside any brackets.
+pub fn split_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    for c in text.chars() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth == 0 => break,
+            ')' | ']' | '}' => depth -= 1,
+            ',' | ';' if depth == 0 => {
+                arguments.push(current.trim().to_string());
+                current.clear();
+                if c == ';' {
+                    return arguments;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        arguments.push(current.trim().to_string());
+    }
+    arguments
+}
+
+/// The expression at the start of `text`, up to a separator at nesting depth zero
+pub fn first_expression(text: &str) -> String {
+    split_arguments(text).into_iter().next().unwrap_or_default()
+}
+
+/// Values given to `field` in normalized code, as struct literal members
+/// (`field: value`) or assignments (`field = value`), with their line
+pub fn field_values(code: &str, field: &str) -> Vec<(usize, String)> {
+    let mut values = Vec::new();
+    for (start, _) in code.match_indices(field) {
+        let preceded_by_word = code[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+        let rest = code[start + field.len()..].trim_start();
+        let value = rest
+            .strip_prefix(':')
+            .or_else(|| rest.strip_prefix('=').filter(|rest| !rest.starts_with('=')));
+        if let (false, Some(value)) = (preceded_by_word, value) {
+            values.push((line_at(code, start), first_expression(value)));
+        }
+    }
+    values
+}
+
+/// Calls in normalized code whose function name satisfies `matches`, with the
+/// line the call starts on, the name and its arguments (which may span lines).
+/// Function declarations, e.g. in interfaces, aren't calls.
+pub fn calls(code: &str, matches: impl Fn(&str) -> bool) -> Vec<(usize, String, Vec<String>)> {
+    find_calls(code, false, matches)
+}
+
+/// Like `calls`, but only calls to another contract, `<expr>.name(...)`. Events,
+/// `emit`s and internal functions that happen to share a name aren't included.
+pub fn external_calls(code: &str, matches: impl Fn(&str) -> bool) -> Vec<(usize, String, Vec<String>)> {
+    find_calls(code, true, matches)
+}
+
+fn find_calls(code: &str, external_only: bool, matches: impl Fn(&str) -> bool) -> Vec<(usize, String, Vec<String>)> {
+    let mut found = Vec::new();
+    for (open, _) in code.match_indices('(') {
+        // Skip call options: `swap{value: amount}(...)`
+        let mut name_end = open;
+        if code[..name_end].trim_end().ends_with('}') {
+            match code[..name_end].rfind('{') {
+                Some(brace) => name_end = brace,
+                None => continue,
+            }
+        }
+        let name_start = code[..name_end]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |index| index + 1);
+        let name = &code[name_start..name_end];
+        let before = code[..name_start].trim_end();
+        let declaration = before.ends_with("function") || before.ends_with("event");
+        let external = before.ends_with('.');
+        if !name.is_empty() && !declaration && (external || !external_only) && matches(name) {
+            found.push((line_at(code, name_start), name.to_string(), split_arguments(&code[open + 1..])));
+        }
+    }
+    found
+}
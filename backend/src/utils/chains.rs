@@ -0,0 +1,54 @@
+use serde::Serialize;
+
+/// A chain requests can name by id instead of giving an RPC URL
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ChainPreset {
+    pub chain_id: u64,
+    pub name: &'static str,
+    /// Public RPC for requests that give only the chain id, after any
+    /// endpoints configured for the chain
+    pub rpc_url: &'static str,
+    /// Block explorer, for linking transactions and addresses
+    pub explorer_url: &'static str,
+}
+
+/// Chains with curated public RPCs. Keyless public endpoints are rate limited,
+/// so deployments that simulate a lot should configure their own endpoints.
+pub const CHAIN_PRESETS: &[ChainPreset] = &[
+    ChainPreset {
+        chain_id: 1,
+        name: "mainnet",
+        rpc_url: "https://ethereum-rpc.publicnode.com",
+        explorer_url: "https://etherscan.io",
+    },
+    ChainPreset {
+        chain_id: 10,
+        name: "optimism",
+        rpc_url: "https://mainnet.optimism.io",
+        explorer_url: "https://optimistic.etherscan.io",
+    },
+    ChainPreset {
+        chain_id: 137,
+        name: "polygon",
+        rpc_url: "https://polygon-rpc.com",
+        explorer_url: "https://polygonscan.com",
+    },
+    ChainPreset {
+        chain_id: 8453,
+        name: "base",
+        rpc_url: "https://mainnet.base.org",
+        explorer_url: "https://basescan.org",
+    },
+    ChainPreset {
+        chain_id: 42161,
+        name: "arbitrum",
+        rpc_url: "https://arb1.arbitrum.io/rpc",
+        explorer_url: "https://arbiscan.io",
+    },
+    ChainPreset {
+        chain_id: 11155111,
+        name: "sepolia",
+        rpc_url: "https://ethereum-sepolia-rpc.publicnode.com",
+        explorer_url: "https://sepolia.etherscan.io",
+    },
+];
@@ -0,0 +1,135 @@
+use super::metrics::METRICS;
+use crate::models::CircuitBreakerConfig;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A call refused because the circuit is open
+#[derive(Debug)]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The LLM gateway is failing and has been paused; retry in {}s",
+            self.retry_after.as_secs().max(1)
+        )
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    /// When the circuit last opened; `None` while closed
+    opened_at: Option<Instant>,
+    /// A half-open trial call is in flight
+    probing: bool,
+}
+
+/// Stops calls to a failing upstream. Consecutive failures, with calls slower than
+/// `slow_call_secs` counting as failures, open the circuit; after `open_secs` one
+/// trial call is let through, which closes it again on success.
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+/// An admitted call. Drop it without `finish` (the caller timed out or was
+/// cancelled) and it counts as a failure.
+pub struct CallGuard {
+    config: CircuitBreakerConfig,
+    state: Arc<Mutex<BreakerState>>,
+    started: Instant,
+    finished: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(BreakerState::default())),
+        }
+    }
+
+    fn open_for(&self) -> Duration {
+        Duration::from_secs(self.config.open_secs)
+    }
+
+    /// How long until calls are let through again, `None` while they are
+    pub fn retry_after(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        let opened_at = state.opened_at?;
+        match self.open_for().checked_sub(opened_at.elapsed()) {
+            Some(remaining) => Some(remaining),
+            // Half-open: only one trial at a time
+            None if state.probing => Some(Duration::from_secs(1)),
+            None => None,
+        }
+    }
+
+    /// Admit a call, or refuse it while the circuit is open
+    pub fn try_call(&self) -> Result<CallGuard, CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(opened_at) = state.opened_at {
+            if let Some(retry_after) = self.open_for().checked_sub(opened_at.elapsed()) {
+                return Err(CircuitOpen { retry_after });
+            }
+            if state.probing {
+                return Err(CircuitOpen { retry_after: Duration::from_secs(1) });
+            }
+            state.probing = true;
+        }
+        Ok(CallGuard {
+            config: self.config.clone(),
+            state: self.state.clone(),
+            started: Instant::now(),
+            finished: false,
+        })
+    }
+}
+
+impl CallGuard {
+    /// Record how the call went
+    pub fn finish(mut self, ok: bool) {
+        self.finished = true;
+        let slow = self.started.elapsed() > Duration::from_secs(self.config.slow_call_secs);
+        self.record(ok && !slow);
+    }
+
+    fn record(&self, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        let probe = std::mem::take(&mut state.probing);
+        if ok {
+            *state = BreakerState::default();
+            METRICS.llm_circuit_open.set(0);
+            return;
+        }
+
+        state.consecutive_failures += 1;
+        // A failed trial reopens the circuit straight away
+        if probe || state.consecutive_failures >= self.config.failure_threshold.max(1) {
+            if state.opened_at.is_none() || probe {
+                tracing::warn!(
+                    failures = state.consecutive_failures,
+                    "LLM gateway circuit opened for {}s",
+                    self.config.open_secs
+                );
+            }
+            state.opened_at = Some(Instant::now());
+            METRICS.llm_circuit_open.set(1);
+        }
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.record(false);
+        }
+    }
+}
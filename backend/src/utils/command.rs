@@ -2,63 +2,82 @@ use crate::models::ForgeStep;
 use eyre::Result;
 use tokio::process::Command;
 use tokio::io::AsyncBufReadExt;
-use std::process::Stdio;
+use std::process::{Output, Stdio};
+use std::time::Duration;
 
-pub async fn run_command_with_output(
+pub enum CommandOutcome {
+    Completed(Output),
+    /// The process group was killed after exceeding the timeout
+    TimedOut,
+}
+
+/// SIGKILL a process group, catching children (solc, anvil, node) the leader spawned
+#[cfg(unix)]
+fn kill_process_group(pid: u32) {
+    // SAFETY: killpg has no memory safety preconditions
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_pid: u32) {}
+
+/// Run a command printing machine-readable output (`forge --json`), streaming
+/// each stderr line as a step while collecting stdout for parsing. The whole
+/// process group is killed if it runs longer than `timeout`. Commands that
+/// execute generated code should be built with `sandboxed_command`.
+pub async fn run_json_command(
     command: &mut Command,
     tx: &tokio::sync::mpsc::Sender<ForgeStep>,
-    step_type: impl Fn(String) -> ForgeStep + Send + 'static + Clone,
-) -> Result<()> {
+    step_type: impl Fn(String) -> ForgeStep + Send + 'static,
+    timeout: Duration,
+) -> Result<CommandOutcome> {
     let mut child = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()?;
+    let pid = child.id();
 
     let stdout = tokio::io::BufReader::new(child.stdout.take().unwrap());
     let stderr = tokio::io::BufReader::new(child.stderr.take().unwrap());
-    let tx_clone = tx.clone();
-    let tx_clone2 = tx.clone();
-    let step_type_stdout = step_type.clone(); // Clone for stdout task
-    let step_type_stderr = step_type; // Use original for stderr task
+    let tx = tx.clone();
 
-    tokio::spawn(async move {
+    let stdout_task = tokio::spawn(async move {
+        let mut collected = String::new();
         let mut lines = stdout.lines();
-        let mut current_progress = String::new();
-
         while let Ok(Some(line)) = lines.next_line().await {
-            let line = line.trim();
-
-            // If this is a progress message (contains percentage)
-            if line.contains("Counting objects:")
-                || line.contains("Compressing objects:")
-                || line.contains("Receiving objects:")
-                || line.contains("Resolving deltas:")
-            {
-                current_progress = line.to_string();
-            } else {
-                // For non-progress messages, send as normal
-                tx_clone
-                    .send(step_type_stdout(line.to_string() + "\n"))
-                    .await
-                    .ok();
-
-                // If we had a progress message, send it now that it's complete
-                if !current_progress.is_empty() {
-                    let progress = current_progress.clone(); // Clone before sending
-                    tx_clone.send(step_type_stdout(progress + "\n")).await.ok();
-                    current_progress.clear();
-                }
-            }
+            collected.push_str(&line);
+            collected.push('\n');
         }
+        collected
     });
 
-    tokio::spawn(async move {
+    let stderr_task = tokio::spawn(async move {
         let mut lines = stderr.lines();
+        let mut collected = String::new();
         while let Ok(Some(line)) = lines.next_line().await {
-            tx_clone2.send(step_type_stderr(line + "\n")).await.ok();
+            collected.push_str(&line);
+            collected.push('\n');
+            tx.send(step_type(line + "\n")).await.ok();
         }
+        collected
     });
 
-    child.wait().await?;
-    Ok(())
-}
\ No newline at end of file
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(status) => status?,
+        Err(_) => {
+            if let Some(pid) = pid {
+                kill_process_group(pid);
+            }
+            return Ok(CommandOutcome::TimedOut);
+        }
+    };
+
+    Ok(CommandOutcome::Completed(Output {
+        status,
+        stdout: stdout_task.await.unwrap_or_default().into_bytes(),
+        stderr: stderr_task.await.unwrap_or_default().into_bytes(),
+    }))
+}
@@ -0,0 +1,96 @@
+use super::calls::{external_calls, field_values, first_expression, normalize};
+
+/// Router functions that take a deadline as their last argument, as name
+/// prefixes; only matched on calls to a contract, so events like
+/// `SwapExactInputSingleExecuted` aren't mistaken for them
+const DEADLINE_CALL_PREFIXES: &[&str] = &[
+    "swapexact",
+    "swaptokensforexact",
+    "swapethforexact",
+    "addliquidity",
+    "removeliquidity",
+];
+
+/// Deadlines that never expire
+const UNBOUNDED: &[&str] = &["type(uint256).max", "type(uint).max", "uint256(-1)", "2**256"];
+
+/// A deadline the script sets badly: line, rule and reason
+pub type DeadlineProblem = (usize, &'static str, String);
+
+/// Check that every deadline passed to a protocol is `block.timestamp` plus at
+/// most `max_secs`, rather than a hardcoded timestamp or one that never expires
+pub fn check_deadlines(source: &str, max_secs: u64) -> Vec<DeadlineProblem> {
+    let code = normalize(source);
+
+    let mut sites = field_values(&code, "deadline");
+    let positional = external_calls(&code, |name| DEADLINE_CALL_PREFIXES.iter().any(|prefix| name.starts_with(prefix)));
+    sites.extend(
+        positional
+            .into_iter()
+            .filter_map(|(line, _, mut arguments)| Some((line, arguments.pop()?))),
+    );
+    sites.sort();
+    sites.dedup();
+
+    sites
+        .into_iter()
+        .filter_map(|(line, expression)| {
+            let expression = resolve_variable(&code, &expression)?;
+            let compact = expression.replace(char::is_whitespace, "");
+            if UNBOUNDED.iter().any(|unbounded| compact.contains(unbounded)) {
+                return Some((line, "far-future-deadline", "deadline never expires".to_string()));
+            }
+            if !compact.contains("block.timestamp") {
+                return Some((
+                    line,
+                    "hardcoded-deadline",
+                    format!("deadline `{}` must be relative to block.timestamp", expression),
+                ));
+            }
+            match buffer_secs(&expression) {
+                Some(secs) if secs > max_secs => Some((
+                    line,
+                    "far-future-deadline",
+                    format!("deadline is {}s after block.timestamp, at most {}s is allowed", secs, max_secs),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Follow a bare identifier to the value it's assigned, so `deadline: expiry`
+/// is judged by `uint256 expiry = ...`. `None` when the value can't be found,
+/// e.g. for a function parameter.
+fn resolve_variable(code: &str, expression: &str) -> Option<String> {
+    let is_identifier = !expression.is_empty()
+        && expression.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !expression.starts_with(|c: char| c.is_ascii_digit());
+    if !is_identifier {
+        return Some(expression.to_string());
+    }
+
+    let assignment = format!("{} =", expression);
+    let start = code.find(&assignment)? + assignment.len();
+    let value = first_expression(&code[start..]);
+    (value != expression).then_some(value)
+}
+
+/// Seconds added to `block.timestamp`, for literal buffers like `1200` or `20 minutes`
+fn buffer_secs(expression: &str) -> Option<u64> {
+    let rest = expression.split("block.timestamp").nth(1)?.trim_start().strip_prefix('+')?.trim();
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit() || *c == '_').collect::<String>();
+    let amount: u64 = digits.replace('_', "").parse().ok()?;
+    let unit = rest[digits.len()..].trim_start();
+    let unit = unit.split(|c: char| !c.is_ascii_alphabetic()).next().unwrap_or_default();
+    let scale = match unit {
+        "" | "seconds" => 1,
+        "minutes" => 60,
+        "hours" => 3600,
+        "days" => 86_400,
+        "weeks" => 604_800,
+        // Anything else isn't a literal we can judge
+        _ => return None,
+    };
+    Some(amount.saturating_mul(scale))
+}
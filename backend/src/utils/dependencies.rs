@@ -1,99 +1,61 @@
-use crate::models::ForgeStep;
-use eyre::Result;
+use super::sandbox::{sandboxed_command, NetworkAccess};
+use crate::models::{DependencyConfig, ForgeStep, SandboxConfig, Stage};
+use eyre::{eyre, Result};
 use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use std::process::Stdio;
+use tokio::sync::mpsc::Sender;
 
+/// `forge install` a package into the project at its pinned tag, then
+/// `npm install` its own dependencies if it ships a package.json
 pub async fn install_dependencies(
-    project_path: &std::path::Path,
-    lib_name: &str,
-    tx: tokio::sync::mpsc::Sender<ForgeStep>,
+    project_path: &Path,
+    package: &DependencyConfig,
+    sandbox: &SandboxConfig,
+    tx: Sender<ForgeStep>,
 ) -> Result<()> {
-    let lib_path = project_path.join("lib").join(lib_name);
-
-    if lib_path.join("package.json").exists() {
-        let mut child = Command::new("npm")
-            .arg("install")
-            .current_dir(&lib_path)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        let stdout = child.stdout.take().unwrap();
-        let stderr = child.stderr.take().unwrap();
-        let tx_clone = tx.clone();
-        let tx_clone2 = tx.clone();
-
-        // Handle stdout in a separate task
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                tx_clone
-                    .send(ForgeStep {
-                        title: "Installing Dependencies".to_string(),
-                        output: line + "\n",
-                    })
-                    .await
-                    .ok();
-            }
-        });
-
-        // Handle stderr in a separate task
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stderr).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                tx_clone2
-                    .send(ForgeStep {
-                        title: "Installing Dependencies".to_string(),
-                        output: line + "\n",
-                    })
-                    .await
-                    .ok();
-            }
-        });
-
-        // Wait for the command to complete
-        child.wait().await?;
+    let target = package.install_target();
+    let mut forge = sandboxed_command("forge", project_path, NetworkAccess::Full, sandbox);
+    forge.args(["install", &target, "--no-commit"]);
+    if !run_streaming(&mut forge, &tx).await?.success() {
+        return Err(eyre!("forge install {} failed", package.repo));
     }
 
-    // Similar modification for forge install...
-    if lib_path.join("foundry.toml").exists() || lib_path.join("remappings.txt").exists() {
-        let mut child = Command::new("forge")
-            .arg("install")
-            .arg(&lib_name)
-            .arg("--no-commit")
-            .current_dir(&project_path)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        let stdout = child.stdout.take().unwrap();
-        let stderr = child.stderr.take().unwrap();
-
-        let mut stdout_reader = BufReader::new(stdout).lines();
-        let mut stderr_reader = BufReader::new(stderr).lines();
-
-        while let Ok(Some(line)) = stdout_reader.next_line().await {
-            tx.send(ForgeStep {
-                title: "Installing Dependencies".to_string(),
-                output: line,
-            })
-            .await
-            .ok();
-        }
-
-        while let Ok(Some(line)) = stderr_reader.next_line().await {
-            tx.send(ForgeStep {
-                title: "Installing Dependencies".to_string(),
-                output: line,
-            })
-            .await
-            .ok();
+    let lib_path = project_path.join("lib").join(&package.name);
+    if lib_path.join("package.json").exists() {
+        let mut npm = sandboxed_command("npm", &lib_path, NetworkAccess::Full, sandbox);
+        npm.arg("install");
+        if !run_streaming(&mut npm, &tx).await?.success() {
+            return Err(eyre!("npm install for {} failed", package.name));
         }
-
-        child.wait().await?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Run a command, streaming its stdout and stderr lines to the client
+async fn run_streaming(command: &mut Command, tx: &Sender<ForgeStep>) -> Result<ExitStatus> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    tokio::join!(forward_lines(stdout, tx), forward_lines(stderr, tx));
+
+    Ok(child.wait().await?)
+}
+
+async fn forward_lines(output: impl AsyncRead + Unpin, tx: &Sender<ForgeStep>) {
+    let mut reader = BufReader::new(output).lines();
+    while let Ok(Some(line)) = reader.next_line().await {
+        tx.send(ForgeStep::CompileOutput {
+            stage: Stage::InstallingDependencies,
+            line: line + "\n",
+        })
+        .await
+        .ok();
+    }
+}
@@ -0,0 +1,83 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Line-based unified diff of `old` against `new` with `context` lines around
+/// each change. Empty when the texts have the same lines.
+pub fn unified_diff(path: &str, old: &str, new: &str, context: usize) -> String {
+    let ops = diff_lines(&old.lines().collect::<Vec<_>>(), &new.lines().collect::<Vec<_>>());
+
+    // Group changes into hunks, merging those whose context would overlap
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, _) in ops.iter().enumerate().filter(|(_, (op, _))| *op != Op::Equal) {
+        let start = index.saturating_sub(context);
+        let end = (index + context + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- a/{}\n+++ b/{}\n", path, path);
+    for (start, end) in hunks {
+        let count = |range: &[(Op, &str)], skip: Op| range.iter().filter(|(op, _)| *op != skip).count();
+        let old_len = count(&ops[start..end], Op::Insert);
+        let new_len = count(&ops[start..end], Op::Delete);
+        // Unified diffs number an empty range by the line before it
+        let old_start = count(&ops[..start], Op::Insert) + usize::from(old_len > 0);
+        let new_start = count(&ops[..start], Op::Delete) + usize::from(new_len > 0);
+
+        diff.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len));
+        for (op, line) in &ops[start..end] {
+            let marker = match op {
+                Op::Equal => ' ',
+                Op::Delete => '-',
+                Op::Insert => '+',
+            };
+            diff.push(marker);
+            diff.push_str(line);
+            diff.push('\n');
+        }
+    }
+    diff
+}
+
+/// Edit script turning `old` into `new`, from their longest common subsequence
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Op, &'a str)> {
+    // lcs[i][j] is the LCS length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            ops.push((Op::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push((Op::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((Op::Insert, new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|line| (Op::Delete, *line)));
+    ops.extend(new[j..].iter().map(|line| (Op::Insert, *line)));
+    ops
+}
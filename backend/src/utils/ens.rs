@@ -0,0 +1,45 @@
+use super::rpc_client::rpc_provider;
+use ethers::providers::Middleware;
+use ethers::types::Address;
+use eyre::{eyre, Result};
+
+/// Whether `value` is shaped like an ENS name, e.g. `vitalik.eth` or `pay.alice.eth`
+pub fn is_ens_name(value: &str) -> bool {
+    let Some(labels) = value.strip_suffix(".eth") else {
+        return false;
+    };
+    !labels.is_empty()
+        && labels.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Distinct ENS names mentioned in free text, lowercased
+pub fn find_ens_names(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || matches!(c, ',' | '(' | ')' | '"' | '\'' | '?' | '!')) {
+        // Sentence punctuation after a name isn't part of it
+        let word = word.trim_end_matches(['.', ':', ';']).to_ascii_lowercase();
+        if is_ens_name(&word) && !names.contains(&word) {
+            names.push(word);
+        }
+    }
+    names
+}
+
+/// Resolve ENS names against the registry on the fork
+pub async fn resolve_ens_names(rpc_url: &str, names: &[String]) -> Result<Vec<(String, Address)>> {
+    let provider = rpc_provider(rpc_url)?;
+    let mut resolved = Vec::with_capacity(names.len());
+    for name in names {
+        let address = provider
+            .resolve_name(name)
+            .await
+            .map_err(|e| eyre!("Could not resolve {}: {}", name, e))?;
+        resolved.push((name.clone(), address));
+    }
+    Ok(resolved)
+}
@@ -0,0 +1,237 @@
+use super::backpressure::coalesce;
+use super::redact::redact;
+use crate::models::ForgeStep;
+use ethers::types::Address;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc::Receiver, watch};
+use tracing::warn;
+
+/// Every event of a session, one JSON object per line, kept in its directory
+pub const EVENT_LOG_FILE: &str = "events.jsonl";
+
+/// Most queued events coalesced together
+const MAX_BACKLOG: usize = 256;
+
+/// Most events of a session whose data is kept in memory; older ones are read
+/// back from the on-disk log when a resuming client asks for them
+const MAX_IN_MEMORY: usize = 1024;
+
+/// How every line of the on-disk log starts, ahead of the event as it was sent
+const LOGGED_PREFIX: &str = "{\"timestamp_ms\":";
+
+/// A step as sent to clients, tagged with the request that produced it
+#[derive(Serialize)]
+struct Tagged<'a> {
+    request_id: &'a str,
+    #[serde(flatten)]
+    step: &'a ForgeStep,
+}
+
+/// A line of the on-disk event log
+#[derive(Serialize)]
+struct Logged<'a> {
+    timestamp_ms: u128,
+    #[serde(flatten)]
+    event: &'a Tagged<'a>,
+}
+
+#[derive(Clone)]
+enum Entry {
+    /// An event, and where its line starts in the on-disk log when it was
+    /// written there. Only the latest `MAX_IN_MEMORY` keep their data.
+    Step { data: Option<Arc<str>>, offset: Option<u64> },
+    /// The job feeding a stream finished; its stream should close here
+    End,
+}
+
+struct Entries {
+    all: Vec<Entry>,
+    /// Index of the oldest step that still has its data
+    resident_from: usize,
+}
+
+/// Every event a session has emitted, numbered so a reconnecting client can
+/// pick up after the last event it saw (`Last-Event-ID: <session>:<index>`).
+/// Only the latest events are held in memory; the rest come from the on-disk log.
+pub struct SessionEvents {
+    session: OnceLock<String>,
+    owner: Option<Address>,
+    entries: Mutex<Entries>,
+    len: watch::Sender<usize>,
+    /// When the last job feeding this session ended, and whether it produced a result
+    finished: Mutex<Option<(Instant, bool)>>,
+}
+
+/// What a subscriber gets next
+pub enum NextEvent {
+    Step { id: Option<String>, data: Arc<str> },
+    /// The event was dropped from memory and can't be read back from disk
+    Lost,
+    End,
+}
+
+impl SessionEvents {
+    /// `session` is `None` for jobs that never got a session directory; their
+    /// events are not resumable
+    pub fn new(session: Option<String>, owner: Option<Address>) -> Arc<Self> {
+        Arc::new(Self {
+            session: session.map(OnceLock::from).unwrap_or_default(),
+            owner,
+            entries: Mutex::new(Entries { all: Vec::new(), resident_from: 0 }),
+            len: watch::channel(0).0,
+            finished: Mutex::new(None),
+        })
+    }
+
+    /// Give events created before their job's directory was checked out that
+    /// session, before recording starts
+    pub fn set_session(&self, session: String) {
+        let _ = self.session.set(session);
+    }
+
+    pub fn owner(&self) -> Option<Address> {
+        self.owner
+    }
+
+    /// Whether the job feeding this session has ended
+    pub fn is_finished(&self) -> bool {
+        matches!(self.entries.lock().unwrap().all.last(), Some(Entry::End))
+    }
+
+    /// When the job feeding this session ended, if it has
+    pub fn finished_at(&self) -> Option<Instant> {
+        self.outcome().map(|(finished_at, _)| finished_at)
+    }
+
+    /// When the job feeding this session ended and whether it produced a result,
+    /// if it has ended
+    pub fn outcome(&self) -> Option<(Instant, bool)> {
+        let finished = *self.finished.lock().unwrap();
+        finished.filter(|_| self.is_finished())
+    }
+
+    /// Index the next recorded event will get
+    pub fn next_index(&self) -> usize {
+        self.entries.lock().unwrap().all.len()
+    }
+
+    fn push(&self, entry: Entry) {
+        let len = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.all.push(entry);
+            // Dropping the oldest step's data keeps the memory a long session holds bounded
+            if entries.all.len() - entries.resident_from > MAX_IN_MEMORY {
+                let oldest = entries.resident_from;
+                if let Entry::Step { data, .. } = &mut entries.all[oldest] {
+                    *data = None;
+                }
+                entries.resident_from += 1;
+            }
+            entries.all.len()
+        };
+        self.len.send_replace(len);
+    }
+
+    /// Record everything a job sends until its sender is dropped, appending it
+    /// to the session's on-disk log as well
+    pub async fn record(self: Arc<Self>, mut rx: Receiver<ForgeStep>, request_id: String) {
+        let mut log = self.session.get().and_then(|session| open_log(Path::new(session)));
+        let mut succeeded = false;
+
+        while let Some(first) = rx.recv().await {
+            // Events that piled up while the last ones were written are coalesced
+            let mut backlog = vec![first];
+            while backlog.len() < MAX_BACKLOG {
+                match rx.try_recv() {
+                    Ok(step) => backlog.push(step),
+                    Err(_) => break,
+                }
+            }
+
+            for step in coalesce(backlog) {
+                succeeded |= matches!(step, ForgeStep::Result { .. });
+                let tagged = Tagged { request_id: &request_id, step: &step };
+                let mut offset = None;
+                if let Some(file) = &mut log {
+                    match append(file, &tagged) {
+                        Ok(written_at) => offset = Some(written_at),
+                        Err(e) => {
+                            warn!("Failed to write session event log, disabling it: {}", e);
+                            log = None;
+                        }
+                    }
+                }
+                let data = redact(&serde_json::to_string(&tagged).unwrap()).into();
+                self.push(Entry::Step { data: Some(data), offset });
+            }
+        }
+        *self.finished.lock().unwrap() = Some((Instant::now(), succeeded));
+        self.push(Entry::End);
+    }
+
+    /// Wait for the event at `index`
+    pub async fn next(&self, index: usize) -> NextEvent {
+        let mut len = self.len.subscribe();
+        // The sender lives in `self`, so this only fails if it was dropped mid-wait
+        let _ = len.wait_for(|len| *len > index).await;
+
+        let entry = self.entries.lock().unwrap().all[index].clone();
+        let data = match entry {
+            Entry::Step { data: Some(data), .. } => data,
+            Entry::Step { data: None, offset } => {
+                let logged = offset.zip(self.session.get()).and_then(|(offset, session)| read_logged(Path::new(session), offset));
+                let Some(data) = logged else {
+                    return NextEvent::Lost;
+                };
+                data.into()
+            }
+            Entry::End => return NextEvent::End,
+        };
+        NextEvent::Step {
+            id: self.session.get().map(|session| format!("{}:{}", session, index)),
+            data,
+        }
+    }
+}
+
+fn open_log(session_dir: &Path) -> Option<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(session_dir.join(EVENT_LOG_FILE))
+        .map_err(|e| warn!("Failed to open session event log: {}", e))
+        .ok()
+}
+
+/// Append an event to the log, returning where its line starts
+fn append(file: &mut File, event: &Tagged) -> std::io::Result<u64> {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let line = serde_json::to_string(&Logged { timestamp_ms, event })?;
+    let offset = file.seek(SeekFrom::End(0))?;
+    writeln!(file, "{}", redact(&line))?;
+    Ok(offset)
+}
+
+/// The event logged at `offset`, as it was sent: without the timestamp the log adds
+fn read_logged(session_dir: &Path, offset: u64) -> Option<String> {
+    let mut file = File::open(session_dir.join(EVENT_LOG_FILE)).ok()?;
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line).ok()?;
+    let (_timestamp, event) = line.trim_end().strip_prefix(LOGGED_PREFIX)?.split_once(',')?;
+    Some(format!("{{{}", event))
+}
+
+/// Split a `Last-Event-ID` back into the session and the index to resume after
+pub fn parse_event_id(id: &str) -> Option<(&str, usize)> {
+    let (session, index) = id.rsplit_once(':')?;
+    Some((session, index.parse().ok()?))
+}
@@ -0,0 +1,84 @@
+use crate::models::{ExperimentConfig, LlmConfig};
+use crate::processors::LLMImpl;
+use eyre::{eyre, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use tokio::sync::Mutex;
+
+pub struct Variant {
+    pub name: String,
+    weight: u32,
+    /// Extra instructions for the generation prompt
+    pub prompt: Option<String>,
+    /// Code and chat models the variant generates with
+    pub models: [String; 2],
+    /// Its own generator, when it swaps the models
+    pub generator: Option<Mutex<LLMImpl>>,
+}
+
+/// Splits new sessions between prompt or model variants by weight
+pub struct Experiment {
+    pub name: String,
+    variants: Vec<Variant>,
+    total_weight: u64,
+}
+
+impl Experiment {
+    /// `generator` builds an LLM client for a code and a chat model
+    pub fn new(
+        config: &ExperimentConfig,
+        llm: &LlmConfig,
+        generator: impl Fn(&str, &str) -> Result<LLMImpl>,
+    ) -> Result<Self> {
+        let mut names = HashSet::new();
+        let mut variants = Vec::new();
+        for variant in &config.variants {
+            if !names.insert(variant.name.as_str()) {
+                return Err(eyre!("Experiment {} has two variants named {}", config.name, variant.name));
+            }
+            let code_model = variant.code_model.clone().unwrap_or_else(|| llm.code_model.clone());
+            let chat_model = variant.chat_model.clone().unwrap_or_else(|| llm.chat_model.clone());
+            let generator = if variant.code_model.is_some() || variant.chat_model.is_some() {
+                Some(Mutex::new(generator(&code_model, &chat_model)?))
+            } else {
+                None
+            };
+            variants.push(Variant {
+                name: variant.name.clone(),
+                weight: variant.weight,
+                prompt: variant.prompt.clone(),
+                models: [code_model, chat_model],
+                generator,
+            });
+        }
+
+        let total_weight = variants.iter().map(|variant| variant.weight as u64).sum();
+        if total_weight == 0 {
+            return Err(eyre!("Experiment {} has no variant with a weight", config.name));
+        }
+        Ok(Self {
+            name: config.name.clone(),
+            variants,
+            total_weight,
+        })
+    }
+
+    /// The variant a new session runs in
+    pub fn assign(&self, session: &str) -> &Variant {
+        let mut hasher = DefaultHasher::new();
+        (&self.name, session).hash(&mut hasher);
+        let mut point = hasher.finish() % self.total_weight;
+        for variant in &self.variants {
+            if point < variant.weight as u64 {
+                return variant;
+            }
+            point -= variant.weight as u64;
+        }
+        unreachable!("point is below the total weight")
+    }
+
+    pub fn variant(&self, name: &str) -> Option<&Variant> {
+        self.variants.iter().find(|variant| variant.name == name)
+    }
+}
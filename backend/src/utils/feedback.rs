@@ -0,0 +1,104 @@
+use super::metrics::METRICS;
+use super::stats::keys;
+use crate::models::{FeedbackConfig, FeedbackRecord, ProtocolFeedback};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// On-chain outcomes clients report for generated transactions, appended to a
+/// JSONL file and tallied per protocol so guidelines that keep producing
+/// failing code stand out, and per experiment variant
+pub struct FeedbackStore {
+    config: FeedbackConfig,
+    by_protocol: Mutex<HashMap<String, ProtocolFeedback>>,
+    by_variant: Mutex<HashMap<String, ProtocolFeedback>>,
+}
+
+impl FeedbackStore {
+    /// Tally the reports already on disk
+    pub fn load(config: &FeedbackConfig) -> Self {
+        let mut by_protocol = HashMap::new();
+        let mut by_variant = HashMap::new();
+        if let Ok(content) = fs::read_to_string(&config.path) {
+            for record in content.lines().filter_map(|line| serde_json::from_str::<FeedbackRecord>(line).ok()) {
+                tally(&mut by_protocol, &keys(&record.protocols), record.success);
+                tally(&mut by_variant, record.variant.as_slice(), record.success);
+            }
+        }
+        Self {
+            config: config.clone(),
+            by_protocol: Mutex::new(by_protocol),
+            by_variant: Mutex::new(by_variant),
+        }
+    }
+
+    pub fn record(&self, record: &FeedbackRecord) {
+        let outcome = if record.success { "success" } else { "failure" };
+        let source = if record.from_template { "template" } else { "generated" };
+        METRICS.onchain_feedback.with_label_values(&[outcome, source]).inc();
+
+        let appended = serde_json::to_string(record).map_err(|e| e.to_string()).and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(PathBuf::from(&self.config.path))
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = appended {
+            warn!("Failed to append feedback: {}", e);
+        }
+
+        tally(&mut self.by_variant.lock().unwrap(), record.variant.as_slice(), record.success);
+        let mut by_protocol = self.by_protocol.lock().unwrap();
+        let was_flagged = self.flagged_in(&by_protocol);
+        tally(&mut by_protocol, &keys(&record.protocols), record.success);
+        for protocol in self.flagged_in(&by_protocol) {
+            if !was_flagged.contains(&protocol) {
+                warn!(protocol, "Guidelines are producing transactions that fail on-chain");
+            }
+        }
+    }
+
+    /// Reports so far, per protocol
+    pub fn by_protocol(&self) -> HashMap<String, ProtocolFeedback> {
+        self.by_protocol.lock().unwrap().clone()
+    }
+
+    pub fn by_variant(&self) -> HashMap<String, ProtocolFeedback> {
+        self.by_variant.lock().unwrap().clone()
+    }
+
+    pub fn flagged(&self) -> Vec<String> {
+        self.flagged_in(&self.by_protocol.lock().unwrap())
+    }
+
+    /// Protocols with enough reports whose failure rate is over the limit
+    fn flagged_in(&self, by_protocol: &HashMap<String, ProtocolFeedback>) -> Vec<String> {
+        let mut flagged = by_protocol
+            .iter()
+            .filter(|(_, feedback)| {
+                let reports = feedback.successes + feedback.failures;
+                reports >= self.config.min_reports
+                    && feedback.failures as f64 / reports as f64 > self.config.max_failure_rate
+            })
+            .map(|(protocol, _)| protocol.clone())
+            .collect::<Vec<_>>();
+        flagged.sort();
+        flagged
+    }
+}
+
+fn tally(tallies: &mut HashMap<String, ProtocolFeedback>, names: &[String], success: bool) {
+    for name in names {
+        let feedback = tallies.entry(name.clone()).or_default();
+        if success {
+            feedback.successes += 1;
+        } else {
+            feedback.failures += 1;
+        }
+    }
+}
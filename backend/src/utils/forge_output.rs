@@ -0,0 +1,313 @@
+use crate::models::{
+    Artifact, Broadcast, BroadcastTransaction, CallTrace, CompilerOutput, Deployment, Diagnostic, ScriptOutput, TestResults, TraceCall, TransactionDetails,
+};
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where runs of the session's script leave their broadcast files, one
+/// directory per chain id
+fn broadcast_dir(project_path: &Path) -> PathBuf {
+    project_path.join("broadcast").join("Script.s.sol")
+}
+
+/// Remove the broadcast files of earlier runs, so a run that sends nothing isn't
+/// read as sending what the last one did
+pub fn clear_broadcasts(project_path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(broadcast_dir(project_path)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Where forge writes a chain's broadcast file under `broadcast/Script.s.sol/<chain>`:
+/// in `dry-run` unless the transactions were actually sent
+fn run_file(chain_dir: &Path, sent: bool) -> PathBuf {
+    if sent {
+        chain_dir.join("run-latest.json")
+    } else {
+        chain_dir.join("dry-run").join("run-latest.json")
+    }
+}
+
+/// Chains the script has a broadcast file for
+fn broadcast_chains(project_path: &Path, sent: bool) -> Vec<u64> {
+    let mut chains = fs::read_dir(broadcast_dir(project_path))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| run_file(&entry.path(), sent).is_file())
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .collect::<Vec<_>>();
+    chains.sort_unstable();
+    chains
+}
+
+/// The result object in `--json` stdout. Forge may print other JSON lines around
+/// it, so the last line that is one wins.
+fn last_json_line<T: for<'a> serde::Deserialize<'a>>(stdout: &str) -> Option<T> {
+    stdout
+        .lines()
+        .rev()
+        .filter(|line| line.trim_start().starts_with('{'))
+        .find_map(|line| serde_json::from_str(line).ok())
+}
+
+pub fn parse_script_output(stdout: &str) -> Option<ScriptOutput> {
+    last_json_line(stdout)
+}
+
+pub fn parse_test_results(stdout: &str) -> Option<TestResults> {
+    last_json_line(stdout)
+}
+
+/// Compiler errors and warnings in `forge build --json` stdout, `None` if it
+/// holds no compiler output. Source lines are read from `project_path`.
+pub fn parse_diagnostics(stdout: &str, project_path: &Path) -> Option<Vec<Diagnostic>> {
+    // The output is pretty-printed over many lines
+    let start = stdout.find('{')?;
+    let output = serde_json::Deserializer::from_str(&stdout[start..])
+        .into_iter::<CompilerOutput>()
+        .next()?
+        .ok()?;
+    Some(
+        output
+            .errors
+            .into_iter()
+            .map(|error| {
+                let line = error.source_location.as_ref().and_then(|location| {
+                    let start = usize::try_from(location.start).ok()?;
+                    let source = fs::read(project_path.join(&location.file)).ok()?;
+                    Some(source.get(..start)?.iter().filter(|byte| **byte == b'\n').count() + 1)
+                });
+                Diagnostic {
+                    severity: error.severity.to_ascii_lowercase(),
+                    code: error.error_code,
+                    formatted: error.formatted_message.unwrap_or_else(|| error.message.clone()),
+                    message: error.message,
+                    file: error.source_location.map(|location| location.file),
+                    line,
+                }
+            })
+            .collect(),
+    )
+}
+
+/// The calls the script's `run` made, leaving out its deployment and `setUp`
+pub fn execution_trace(output: &ScriptOutput) -> Vec<TraceCall> {
+    output
+        .traces
+        .iter()
+        .filter(|(kind, _)| kind == "Execution")
+        .flat_map(|(_, arena)| &arena.arena)
+        .map(|node| trace_call(&node.trace))
+        .collect()
+}
+
+fn trace_call(trace: &CallTrace) -> TraceCall {
+    let decoded = trace.decoded.as_ref();
+    let call_data = decoded.and_then(|decoded| decoded.call_data.as_ref());
+    TraceCall {
+        depth: trace.depth,
+        kind: trace.kind.clone(),
+        address: trace.address.clone(),
+        label: decoded.and_then(|decoded| decoded.label.clone()),
+        function: call_data.map(|call_data| call_data.signature.clone()),
+        args: call_data.map(|call_data| call_data.args.clone()).unwrap_or_default(),
+        success: trace.success,
+        gas_used: trace.gas_used,
+    }
+}
+
+/// forge-std's cheatcode address, `address(vm)`
+const VM_ADDRESS: &str = "0x7109709ecfa91a80626ff3989d68f67f5b1dd12d";
+
+/// Split a time-travel simulation's transactions into phases: each `vm.warp` or
+/// `vm.roll` the script made after sending a transaction starts the next one. Transactions are matched in order to the script's own calls
+/// in the trace; when that fails the phases are left unset and `None` is
+/// returned. Otherwise returns when each phase runs, for the client.
+pub fn assign_phases(transactions: &mut [TransactionDetails], trace: &[TraceCall]) -> Option<Vec<String>> {
+    // The warps and rolls before each phase
+    let mut phases: Vec<Vec<String>> = vec![Vec::new()];
+    let mut assigned = Vec::with_capacity(transactions.len());
+    let mut pending = transactions.iter().peekable();
+
+    for call in trace.iter().filter(|call| call.depth == 1) {
+        if call.address.eq_ignore_ascii_case(VM_ADDRESS) {
+            let Some(function) = call.function.as_deref() else {
+                continue;
+            };
+            let name = function.split('(').next().unwrap_or(function);
+            if !matches!(name, "warp" | "roll") {
+                continue;
+            }
+            let step = format!("vm.{}({})", name, call.args.join(", "));
+            // Several warps in a row before a phase's first transaction are one step
+            if assigned.last() == Some(&(phases.len() - 1)) {
+                phases.push(vec![step]);
+            } else {
+                phases.last_mut().unwrap().push(step);
+            }
+            continue;
+        }
+
+        let Some(transaction) = pending.peek() else {
+            break;
+        };
+        let sent = if transaction.is_create() {
+            call.kind.starts_with("CREATE")
+        } else {
+            call.kind != "STATICCALL" && call.address.eq_ignore_ascii_case(&transaction.to)
+        };
+        if sent {
+            pending.next();
+            assigned.push(phases.len() - 1);
+        }
+    }
+
+    if assigned.len() != transactions.len() {
+        return None;
+    }
+    // Warps after the last transaction don't start a phase of their own
+    phases.truncate(assigned.last().map_or(1, |last| last + 1));
+    for (transaction, phase) in transactions.iter_mut().zip(assigned) {
+        transaction.phase = Some(phase);
+    }
+    Some(
+        phases
+            .into_iter()
+            .map(|steps| if steps.is_empty() { "immediately".to_string() } else { format!("after {}", steps.join(", ")) })
+            .collect(),
+    )
+}
+
+/// A trace laid out one call per line, indented by depth, for error messages
+pub fn render_trace(calls: &[TraceCall]) -> String {
+    calls
+        .iter()
+        .map(|call| {
+            let target = call.label.as_deref().unwrap_or(&call.address);
+            let function = call
+                .function
+                .as_deref()
+                .map(|signature| signature.split('(').next().unwrap_or(signature))
+                .unwrap_or("fallback");
+            format!(
+                "{}[{}] {}::{}({}){}\n",
+                "  ".repeat(call.depth),
+                call.gas_used,
+                target,
+                function,
+                call.args.join(", "),
+                if call.success { "" } else { " [reverted]" }
+            )
+        })
+        .collect()
+}
+
+/// The broadcast file of the script's run against the fork's chain, `None` if
+/// the script sent no transactions. `sent` reads the file of a run that sent
+/// them to the fork instead of a dry run. With the chain unknown, the only
+/// broadcast file there is is read.
+pub fn read_broadcast(project_path: &Path, chain_id: Option<u64>, sent: bool) -> Result<Option<Broadcast>> {
+    let chains = broadcast_chains(project_path, sent);
+    let listed = || chains.iter().map(u64::to_string).collect::<Vec<_>>().join(", ");
+    let chain = match (chain_id, chains.as_slice()) {
+        (_, []) => return Ok(None),
+        (Some(chain_id), _) if chains.contains(&chain_id) => chain_id,
+        (Some(chain_id), _) => {
+            return Err(eyre!(
+                "Forge wrote no broadcast file for the fork's chain {}, only for chain {}",
+                chain_id,
+                listed()
+            ))
+        }
+        (None, [chain]) => *chain,
+        (None, _) => {
+            return Err(eyre!(
+                "The fork's chain id couldn't be read, and forge wrote broadcast files for chains {}",
+                listed()
+            ))
+        }
+    };
+
+    let path = run_file(&broadcast_dir(project_path).join(chain.to_string()), sent);
+    let content = fs::read_to_string(&path).map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| eyre!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// The simulated transactions, in the order the script sends them
+pub fn transaction_details(broadcast: Broadcast) -> Vec<TransactionDetails> {
+    // `path:Name:address`, the name itself free of colons
+    let libraries = broadcast
+        .libraries
+        .iter()
+        .filter_map(|library| {
+            let (reference, address) = library.rsplit_once(':')?;
+            Some((address.to_ascii_lowercase(), reference.to_string()))
+        })
+        .collect::<HashMap<_, _>>();
+    broadcast
+        .transactions
+        .into_iter()
+        .map(|transaction| {
+            let mut details = details(transaction);
+            if let Some(deployment) = &mut details.deployment {
+                deployment.library = libraries.get(&deployment.address.to_ascii_lowercase()).cloned();
+            }
+            details
+        })
+        .collect()
+}
+
+/// External libraries the compiled script must be linked against, as `path:Name`
+pub fn script_libraries(project_path: &Path) -> Vec<String> {
+    let mut libraries = fs::read_dir(project_path.join("out").join("Script.s.sol"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| serde_json::from_str::<Artifact>(&fs::read_to_string(entry.path()).ok()?).ok())
+        .flat_map(|artifact| artifact.bytecode.link_references)
+        .flat_map(|(file, names)| names.into_keys().map(move |name| format!("{}:{}", file, name)))
+        .collect::<Vec<_>>();
+    libraries.sort();
+    libraries.dedup();
+    libraries
+}
+
+fn details(transaction: BroadcastTransaction) -> TransactionDetails {
+    let request = transaction.transaction;
+    let arguments = transaction.arguments.unwrap_or_default();
+    let (to, arguments, deployment) = match transaction.transaction_type.as_str() {
+        "CREATE" | "CREATE2" => (
+            request.to.unwrap_or_default(),
+            Vec::new(),
+            Some(Deployment {
+                contract_name: transaction.contract_name,
+                address: transaction.contract_address.unwrap_or_default(),
+                init_code: request.input.clone(),
+                constructor_args: arguments,
+                library: None,
+            }),
+        ),
+        _ => (transaction.contract_address.or(request.to).unwrap_or_default(), arguments, None),
+    };
+    TransactionDetails {
+        from: request.from,
+        to,
+        function: transaction.function.unwrap_or_default(),
+        arguments,
+        value: request.value.unwrap_or_else(|| "0x0".to_string()),
+        input_data: request.input,
+        gas: request.gas.unwrap_or_default(),
+        value_usd: None,
+        part: None,
+        order: None,
+        phase: None,
+        deployment,
+    }
+}
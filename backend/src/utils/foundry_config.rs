@@ -0,0 +1,93 @@
+use crate::models::{CompilerSettings, FoundryConfig};
+use eyre::{eyre, Result};
+use semver::Version;
+use std::fs;
+use std::path::Path;
+
+const FOUNDRY_TOML: &str = "foundry.toml";
+/// Profile in a session's foundry.toml with what its request asked for; foundry
+/// takes everything else from `default`
+const SESSION_PROFILE: &str = "session";
+
+/// Generate a project's foundry.toml from the server's config: the layout
+/// `forge init` sets up, filesystem permissions, RPC and explorer endpoints and
+/// any configured profiles. Sessions copy it from the base project.
+pub fn write_foundry_toml(project_path: &Path, config: &FoundryConfig) -> Result<()> {
+    if config.profiles.contains_key(SESSION_PROFILE) {
+        return Err(eyre!("Profile `{}` is reserved for the settings requests ask for", SESSION_PROFILE));
+    }
+
+    let mut default = toml::Table::new();
+    default.insert("src".to_string(), "src".into());
+    default.insert("out".to_string(), "out".into());
+    default.insert("libs".to_string(), vec!["lib"].into());
+    if !config.fs_permissions.is_empty() {
+        default.insert("fs_permissions".to_string(), toml::Value::try_from(&config.fs_permissions)?);
+    }
+    let mut profiles = toml::Table::new();
+    profiles.insert("default".to_string(), default.into());
+    for (name, settings) in &config.profiles {
+        let profile = profiles.entry(name.clone()).or_insert_with(|| toml::Table::new().into());
+        if let Some(profile) = profile.as_table_mut() {
+            profile.extend(settings.clone());
+        }
+    }
+
+    let mut foundry = toml::Table::new();
+    foundry.insert("profile".to_string(), profiles.into());
+    if !config.rpc_endpoints.is_empty() {
+        foundry.insert("rpc_endpoints".to_string(), toml::Value::try_from(&config.rpc_endpoints)?);
+    }
+    if !config.etherscan.is_empty() {
+        foundry.insert("etherscan".to_string(), toml::Value::try_from(&config.etherscan)?);
+    }
+    fs::write(project_path.join(FOUNDRY_TOML), toml::to_string(&foundry)?)?;
+    Ok(())
+}
+
+/// Write a request's compiler settings into the session's foundry.toml as its
+/// own profile. Nothing is written for a request that sets none.
+pub fn write_session_profile(project_path: &Path, settings: &CompilerSettings) -> Result<()> {
+    if settings.is_default() {
+        return Ok(());
+    }
+    let toml::Value::Table(settings) = toml::Value::try_from(settings)? else {
+        return Err(eyre!("Compiler settings aren't a table"));
+    };
+    update_session_profile(project_path, |profile| profile.extend(settings))
+}
+
+/// Pin the solc version the session's script compiles with, replacing forge's
+/// per-file auto-detection. Returns whether that changed the pinned version.
+pub fn pin_solc_version(project_path: &Path, version: &Version) -> Result<bool> {
+    let mut changed = false;
+    update_session_profile(project_path, |profile| {
+        let previous = profile.insert("solc_version".to_string(), version.to_string().into());
+        changed = previous.and_then(|previous| previous.as_str().map(str::to_string)) != Some(version.to_string());
+    })?;
+    Ok(changed)
+}
+
+/// Edit the session profile in the session's foundry.toml, creating it if needed
+fn update_session_profile(project_path: &Path, update: impl FnOnce(&mut toml::Table)) -> Result<()> {
+    let path = project_path.join(FOUNDRY_TOML);
+    let mut config = fs::read_to_string(&path)?.parse::<toml::Table>()?;
+    let profile = config
+        .entry("profile")
+        .or_insert_with(|| toml::Table::new().into())
+        .as_table_mut()
+        .ok_or_else(|| eyre!("`profile` in {} isn't a table", FOUNDRY_TOML))?
+        .entry(SESSION_PROFILE)
+        .or_insert_with(|| toml::Table::new().into())
+        .as_table_mut()
+        .ok_or_else(|| eyre!("`profile.{}` in {} isn't a table", SESSION_PROFILE, FOUNDRY_TOML))?;
+    update(profile);
+    fs::write(&path, toml::to_string(&config)?)?;
+    Ok(())
+}
+
+/// The profile forge runs with in a session, if it has its own
+pub fn session_profile(project_path: &Path) -> Option<&'static str> {
+    let config = fs::read_to_string(project_path.join(FOUNDRY_TOML)).ok()?.parse::<toml::Table>().ok()?;
+    config.get("profile")?.get(SESSION_PROFILE).map(|_| SESSION_PROFILE)
+}
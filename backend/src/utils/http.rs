@@ -0,0 +1,15 @@
+use reqwest::Client;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// How long a call to a price, token list or explorer API may take
+const HTTP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Client for the third-party HTTP APIs jobs call, shared so connections are
+/// reused, and with a timeout so a hung API can't stall a job
+pub(super) static HTTP_CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    Client::builder()
+        .timeout(HTTP_TIMEOUT)
+        .build()
+        .expect("Failed to build the HTTP client")
+});
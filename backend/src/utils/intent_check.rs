@@ -0,0 +1,128 @@
+use super::trace::parse_quantity;
+use crate::models::{ActionPlan, ActionType, PlannedAction, TokenDelta, TransactionDetails};
+use ethers::utils::format_ether;
+
+/// Relative slack on amounts, for rounding in decimal formatting
+const ROUNDING: f64 = 1e-6;
+
+/// Which way an action should move the sender's balance of a token
+enum Flow {
+    Out,
+    In,
+}
+
+/// What the sender should see for each planned action's tokens: the first token
+/// leaves for swaps, transfers, supplies, repayments, stakes and bridges and
+/// arrives for withdrawals, borrows and unstakes; a swap's second token arrives.
+fn expected_flows(action: &PlannedAction) -> Vec<(&str, Flow, Option<&str>)> {
+    let token = |index: usize| action.tokens.get(index).map(String::as_str);
+    let amount = |index: usize| action.amounts.get(index).map(String::as_str);
+    let first = match action.action {
+        ActionType::Swap
+        | ActionType::Transfer
+        | ActionType::Supply
+        | ActionType::Repay
+        | ActionType::Stake
+        | ActionType::Bridge => Flow::Out,
+        ActionType::Withdraw | ActionType::Borrow | ActionType::Unstake => Flow::In,
+        ActionType::Wrap => return vec![("WETH", Flow::In, amount(0))],
+        ActionType::Unwrap => return vec![("WETH", Flow::Out, amount(0))],
+        ActionType::Approve | ActionType::Other => return Vec::new(),
+    };
+
+    let mut flows = token(0).map(|token| vec![(token, first, amount(0))]).unwrap_or_default();
+    if action.action == ActionType::Swap {
+        flows.extend(token(1).map(|token| (token, Flow::In, amount(1))));
+    }
+    flows
+}
+
+/// A plain decimal amount; "all", "50%" and the like can't be checked
+fn parse_amount(amount: &str) -> Option<f64> {
+    amount.trim().replace(',', "").parse().ok()
+}
+
+fn is_eth(token: &str) -> bool {
+    token.eq_ignore_ascii_case("ETH")
+}
+
+/// The sender's net change in `token`, matched by symbol or address. ETH is only
+/// visible as value sent, so received ETH isn't tracked.
+fn net_change(token: &str, transactions: &[TransactionDetails], deltas: &[TokenDelta]) -> Option<f64> {
+    if is_eth(token) {
+        let sent = transactions
+            .iter()
+            .fold(ethers::types::U256::zero(), |total, transaction| total.saturating_add(parse_quantity(&transaction.value)));
+        return format_ether(sent).parse::<f64>().ok().map(|sent| -sent);
+    }
+
+    deltas
+        .iter()
+        .find(|delta| {
+            delta.token.eq_ignore_ascii_case(token)
+                || delta.symbol.as_deref().is_some_and(|symbol| symbol.eq_ignore_ascii_case(token))
+        })
+        .map_or(Some(0.0), |delta| delta.amount.parse().ok())
+}
+
+/// Compare the sender's simulated balance changes against the plan: each action
+/// must move its tokens the right way, by no more than the amount spent, and a
+/// swap must deliver at least its stated output less the slippage tolerance.
+/// Tokens that one action receives and another spends (e.g. swap then supply)
+/// aren't checked, nor amounts of tokens several actions move. Returns one problem
+/// per unmet expectation.
+pub fn unmet_intent(plan: &ActionPlan, transactions: &[TransactionDetails], deltas: &[TokenDelta]) -> Vec<String> {
+    let flows = plan
+        .actions
+        .iter()
+        .enumerate()
+        .flat_map(|(index, action)| expected_flows(action).into_iter().map(move |flow| (index + 1, action, flow)))
+        .collect::<Vec<_>>();
+    // Balances only show each token's net change across the bundle
+    let shared = |token: &str| flows.iter().filter(|(_, _, (other, _, _))| other.eq_ignore_ascii_case(token)).count() > 1;
+    let both_ways = |token: &str| {
+        let moves = |wanted: fn(&Flow) -> bool| {
+            flows.iter().any(|(_, _, (other, flow, _))| other.eq_ignore_ascii_case(token) && wanted(flow))
+        };
+        moves(|flow| matches!(flow, Flow::In)) && moves(|flow| matches!(flow, Flow::Out))
+    };
+
+    let mut problems = Vec::new();
+    for (position, action, (token, flow, amount)) in &flows {
+        // Received ETH arrives as an internal transfer the deltas don't show
+        if (is_eth(token) && matches!(flow, Flow::In)) || both_ways(token) {
+            continue;
+        }
+        let Some(change) = net_change(token, transactions, deltas) else {
+            continue;
+        };
+        let amount = amount.and_then(parse_amount).filter(|_| !shared(token));
+        let tolerance = 1.0 - action.slippage_bps.unwrap_or(0) as f64 / 10_000.0;
+        match flow {
+            Flow::Out if change >= 0.0 => {
+                problems.push(format!("- action {} ({:?}): the sender's {} balance doesn't decrease", position, action.action, token));
+            }
+            Flow::Out => {
+                if let Some(amount) = amount.filter(|amount| -change > amount * (1.0 + ROUNDING)) {
+                    problems.push(format!(
+                        "- action {} ({:?}): spends {} {}, more than the {} asked for",
+                        position, action.action, -change, token, amount
+                    ));
+                }
+            }
+            Flow::In if change <= 0.0 => {
+                problems.push(format!("- action {} ({:?}): the sender receives no {}", position, action.action, token));
+            }
+            Flow::In => {
+                let minimum = amount.map(|amount| amount * tolerance);
+                if let Some(minimum) = minimum.filter(|minimum| change < minimum * (1.0 - ROUNDING)) {
+                    problems.push(format!(
+                        "- action {} ({:?}): the sender receives {} {}, less than the minimum {}",
+                        position, action.action, change, token, minimum
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
@@ -0,0 +1,159 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub llm_duration: HistogramVec,
+    pub llm_tokens: IntCounterVec,
+    pub forge_duration: HistogramVec,
+    pub fix_iterations: IntCounterVec,
+    pub active_sessions: IntGauge,
+    pub job_slots_total: IntGauge,
+    pub job_slots_available: IntGauge,
+    pub job_queue_depth: IntGauge,
+    pub job_queue_wait: Histogram,
+    pub events_dropped: IntCounterVec,
+    pub events_coalesced: IntCounterVec,
+    pub jobs_joined: IntCounter,
+    pub llm_circuit_open: IntGauge,
+    pub onchain_feedback: IntCounterVec,
+    pub session_storage_bytes: IntGauge,
+    pub sessions_evicted: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("ff".to_string()), None)
+            .expect("valid metrics registry");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by endpoint and status"),
+            &["endpoint", "status"],
+        )
+        .unwrap();
+        let llm_duration = HistogramVec::new(
+            HistogramOpts::new("llm_request_duration_seconds", "LLM call latency")
+                .buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0]),
+            &["call"],
+        )
+        .unwrap();
+        let llm_tokens = IntCounterVec::new(
+            Opts::new("llm_tokens_total", "LLM tokens by call and kind (prompt/completion)"),
+            &["call", "kind"],
+        )
+        .unwrap();
+        let forge_duration = HistogramVec::new(
+            HistogramOpts::new("forge_duration_seconds", "forge subprocess duration by phase")
+                .buckets(vec![1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0]),
+            &["phase"],
+        )
+        .unwrap();
+        let fix_iterations = IntCounterVec::new(
+            Opts::new("fix_iterations_total", "Fix-loop iterations by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let active_sessions = IntGauge::new("active_sessions", "Session directories currently held").unwrap();
+        let job_slots_total = IntGauge::new("job_slots_total", "Configured concurrency permits").unwrap();
+        let job_slots_available = IntGauge::new("job_slots_available", "Unused concurrency permits").unwrap();
+        let job_queue_depth = IntGauge::new("job_queue_depth", "Jobs waiting for a concurrency permit").unwrap();
+        let job_queue_wait = Histogram::with_opts(
+            HistogramOpts::new("job_queue_wait_seconds", "Time queued jobs waited for a permit")
+                .buckets(vec![0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0]),
+        )
+        .unwrap();
+
+        let events_dropped = IntCounterVec::new(
+            Opts::new("stream_events_dropped_total", "Superseded events dropped because a stream fell behind, by type"),
+            &["type"],
+        )
+        .unwrap();
+        let events_coalesced = IntCounterVec::new(
+            Opts::new("stream_events_coalesced_total", "Events merged into the one before them because a stream fell behind, by type"),
+            &["type"],
+        )
+        .unwrap();
+
+        let jobs_joined = IntCounter::new(
+            "forge_jobs_joined_total",
+            "Forge requests served by an identical job that was already running",
+        )
+        .unwrap();
+
+        let llm_circuit_open = IntGauge::new(
+            "llm_circuit_open",
+            "1 while the LLM gateway's circuit breaker is refusing calls",
+        )
+        .unwrap();
+
+        let onchain_feedback = IntCounterVec::new(
+            Opts::new("onchain_feedback_total", "Client reports of on-chain execution by outcome and script source"),
+            &["outcome", "source"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(llm_duration.clone())).unwrap();
+        registry.register(Box::new(llm_tokens.clone())).unwrap();
+        registry.register(Box::new(forge_duration.clone())).unwrap();
+        registry.register(Box::new(fix_iterations.clone())).unwrap();
+        registry.register(Box::new(active_sessions.clone())).unwrap();
+        registry.register(Box::new(job_slots_total.clone())).unwrap();
+        registry.register(Box::new(job_slots_available.clone())).unwrap();
+        registry.register(Box::new(job_queue_depth.clone())).unwrap();
+        registry.register(Box::new(job_queue_wait.clone())).unwrap();
+        registry.register(Box::new(events_dropped.clone())).unwrap();
+        registry.register(Box::new(events_coalesced.clone())).unwrap();
+        let session_storage_bytes = IntGauge::new(
+            "session_storage_bytes",
+            "Disk space session directories take up, as of the last scan",
+        )
+        .unwrap();
+        let sessions_evicted = IntCounterVec::new(
+            Opts::new("sessions_evicted_total", "Sessions removed by the server rather than the client, by reason"),
+            &["reason"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(jobs_joined.clone())).unwrap();
+        registry.register(Box::new(llm_circuit_open.clone())).unwrap();
+        registry.register(Box::new(onchain_feedback.clone())).unwrap();
+        registry.register(Box::new(session_storage_bytes.clone())).unwrap();
+        registry.register(Box::new(sessions_evicted.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            llm_duration,
+            llm_tokens,
+            forge_duration,
+            fix_iterations,
+            active_sessions,
+            job_slots_total,
+            job_slots_available,
+            job_queue_depth,
+            job_queue_wait,
+            events_dropped,
+            events_coalesced,
+            jobs_joined,
+            llm_circuit_open,
+            onchain_feedback,
+            session_storage_bytes,
+            sessions_evicted,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .ok();
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
@@ -1,7 +1,99 @@
 mod command;
 mod tokens;
 mod dependencies;
+mod metrics;
+mod telemetry;
+mod rate_limit;
+mod auth;
+mod validation;
+mod solidity;
+mod analysis;
+mod policy;
+mod sandbox;
+mod project_pool;
+mod packages;
+mod event_log;
+mod diff;
+mod affordability;
+mod approvals;
+mod trace;
+mod prices;
+mod ens;
+mod portfolio;
+mod token_list;
+mod spam;
+mod slippage;
+mod calls;
+mod deadline;
+mod versions;
+mod risk;
+mod intent_check;
+mod backpressure;
+mod result_cache;
+mod circuit_breaker;
+mod templates;
+mod feedback;
+mod stats;
+mod experiments;
+mod prompt_log;
+mod redact;
+mod audit;
+mod storage;
+mod forge_output;
+mod foundry_config;
+mod solc;
+mod session_env;
+mod anvil;
+mod bundle;
+mod chains;
+mod rpc;
+mod rpc_client;
+mod http;
 
 pub use dependencies::install_dependencies;
-pub use command::run_command_with_output; 
-pub use tokens::get_token_balances;
+pub use command::{run_json_command, CommandOutcome};
+pub use tokens::TokenCache;
+pub use metrics::METRICS;
+pub use telemetry::{init_tracing, make_request_span};
+pub use rate_limit::{RateLimiter, RateLimitError};
+pub use auth::AuthStore;
+pub use validation::{validate_bundle_request, validate_fix_request, validate_forge_request, ValidationError};
+pub use solidity::{check_script, script_imports};
+pub use analysis::{builtin_analysis, slither_analysis, Severity};
+pub use policy::{check_policy, find_addresses};
+pub use sandbox::{sandboxed_command, NetworkAccess};
+pub use project_pool::ProjectPool;
+pub use packages::{add_remappings, missing_imports, package_for_import, package_remappings};
+pub use diff::unified_diff;
+pub use versions::{compare_transactions, parse_version, read_version, save_script_version, save_version_transactions};
+pub use affordability::check_affordability;
+pub use approvals::missing_approvals;
+pub use prices::annotate_usd;
+pub use portfolio::{portfolio_summary, portfolio_value_usd};
+pub use risk::score_risk;
+pub use intent_check::unmet_intent;
+pub use backpressure::send_droppable;
+pub use result_cache::{CacheKey, CachedResult, ResultCache};
+pub use circuit_breaker::{CallGuard, CircuitBreaker, CircuitOpen};
+pub use templates::{TemplateKey, TemplateStore};
+pub use feedback::FeedbackStore;
+pub use stats::{export_stats, JobStats};
+pub use experiments::Experiment;
+pub use prompt_log::{log_prompt, with_prompt_log};
+pub use redact::register_secret;
+pub use audit::{export_audit, AuditEvent, AuditLog};
+pub use storage::{session_size, StorageQuota};
+pub use session_env::SessionEnv;
+pub use anvil::{AnvilForks, ForkSnapshot};
+pub use bundle::flashbots_bundle;
+pub use chains::{ChainPreset, CHAIN_PRESETS};
+pub use rpc::{preflight_rpc, PreflightFailure, RpcPool};
+pub use rpc_client::{configure_rpc_client, fork_args, rpc_provider};
+pub use foundry_config::{pin_solc_version, session_profile, write_foundry_toml, write_session_profile};
+pub use solc::select_solc_version;
+pub use forge_output::{assign_phases, clear_broadcasts, execution_trace, parse_diagnostics, parse_script_output, parse_test_results, read_broadcast, render_trace, script_libraries, transaction_details};
+pub use slippage::check_slippage;
+pub use token_list::{ListedToken, TokenRegistry};
+pub use trace::chain_id;
+pub use ens::{find_ens_names, is_ens_name, resolve_ens_names};
+pub use event_log::{parse_event_id, NextEvent, SessionEvents, EVENT_LOG_FILE};
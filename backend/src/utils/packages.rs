@@ -0,0 +1,60 @@
+use crate::models::DependencyConfig;
+use std::path::Path;
+
+/// The dependency providing an import path, if any
+pub fn package_for_import<'a>(packages: &'a [DependencyConfig], import: &str) -> Option<&'a DependencyConfig> {
+    packages.iter().find(|package| import.starts_with(package.prefix()))
+}
+
+/// Import paths forge or solc failed to resolve, from either foundry's
+/// "Unable to resolve imports" list or solc's `Source "..." not found` errors
+pub fn missing_imports(error: &str) -> Vec<String> {
+    let mut imports = Vec::new();
+    let mut in_unresolved_list = false;
+
+    for line in error.lines().map(str::trim) {
+        if line.contains("Unable to resolve imports") {
+            in_unresolved_list = true;
+            continue;
+        }
+
+        let listed = in_unresolved_list && line.starts_with('"');
+        in_unresolved_list = listed;
+        if listed || line.contains("not found") {
+            if let Some(path) = line.split('"').nth(1).filter(|path| !path.is_empty()) {
+                if !imports.iter().any(|import| import == path) {
+                    imports.push(path.to_string());
+                }
+            }
+        }
+    }
+
+    imports
+}
+
+/// Append remappings for `packages` that the project's remappings.txt lacks
+pub fn add_remappings(project_path: &Path, packages: &[&DependencyConfig]) -> std::io::Result<()> {
+    let path = project_path.join("remappings.txt");
+    let mut remappings = std::fs::read_to_string(&path).unwrap_or_default();
+
+    for package in packages {
+        if !remappings.lines().any(|line| line.starts_with(package.prefix())) {
+            if !remappings.is_empty() && !remappings.ends_with('\n') {
+                remappings.push('\n');
+            }
+            remappings.push_str(&package.remapping);
+            remappings.push('\n');
+        }
+    }
+
+    std::fs::write(path, remappings)
+}
+
+/// remappings.txt lines for every package, installed or not, so prompts can
+/// reference them before they're installed
+pub fn package_remappings(packages: &[DependencyConfig]) -> String {
+    packages
+        .iter()
+        .map(|package| format!("{}\n", package.remapping))
+        .collect()
+}
@@ -0,0 +1,134 @@
+use super::calls::blank_comments_and_strings;
+use super::deadline::check_deadlines;
+use crate::models::PolicyConfig;
+use ethers::types::Address;
+use std::collections::HashSet;
+
+/// Calls that move value to an address argument, matched case-insensitively
+/// so `transfer`, `safeTransfer`, `transferFrom` and `safeTransferFrom` all count
+const TRANSFER_CALLS: &[&str] = &["transfer(", "transferfrom(", ".send(", ".call{value:"];
+
+/// Cheatcodes that move the fork's clock or block number
+const TIME_TRAVEL_CALLS: &[&str] = &["vm.warp(", "vm.roll("];
+
+#[derive(Debug, Clone)]
+pub struct PolicyViolation {
+    pub rule: String,
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "- line {}: {} ({})", self.line, self.reason, self.rule)
+    }
+}
+
+/// Every `0x`-prefixed 20-byte hex literal in `text`, with its byte offset
+pub fn find_addresses(text: &str) -> Vec<(usize, Address)> {
+    let bytes = text.as_bytes();
+    let mut addresses = Vec::new();
+
+    for (start, _) in text.match_indices("0x") {
+        let hex_len = bytes[start + 2..]
+            .iter()
+            .take_while(|b| b.is_ascii_hexdigit())
+            .count();
+        let preceded_by_word = start > 0 && bytes[start - 1].is_ascii_alphanumeric();
+        if hex_len == 40 && !preceded_by_word {
+            if let Ok(address) = text[start..start + 42].parse::<Address>() {
+                addresses.push((start, address));
+            }
+        }
+    }
+
+    addresses
+}
+
+/// Check generated source against the configured policy. `known_addresses` are
+/// addresses the user or the protocol guidelines provided, which value may be sent to.
+/// `time_travel` allows the script to warp time, for intents that span it.
+pub fn check_policy(
+    source: &str,
+    known_addresses: &HashSet<Address>,
+    config: &PolicyConfig,
+    time_travel: bool,
+) -> Vec<PolicyViolation> {
+    let mut violations = Vec::new();
+    if !config.enabled {
+        return violations;
+    }
+
+    let allowed: HashSet<Address> = config
+        .allowed_addresses
+        .iter()
+        .filter_map(|address| address.parse().ok())
+        .chain(known_addresses.iter().copied())
+        .collect();
+
+    // Time travel is only flagged where it's called, not mentioned
+    let blanked = blank_comments_and_strings(source);
+    let calls = blanked.lines().collect::<Vec<_>>();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split("//").next().unwrap_or_default();
+        if code.trim_start().starts_with('*') {
+            continue;
+        }
+        let mut violate = |rule: &str, reason: String| {
+            violations.push(PolicyViolation {
+                rule: rule.to_string(),
+                line: index + 1,
+                reason,
+            })
+        };
+
+        if config.forbid_selfdestruct && code.contains("selfdestruct(") {
+            violate("selfdestruct", "selfdestruct is not allowed".to_string());
+        }
+        if config.forbid_delegatecall && code.contains("delegatecall(") {
+            violate("delegatecall", "delegatecall to arbitrary code is not allowed".to_string());
+        }
+        if config.forbid_tx_origin && code.contains("tx.origin") {
+            violate("tx-origin", "tx.origin must not be used; use the provided sender address".to_string());
+        }
+        let line_calls = calls.get(index).copied().unwrap_or_default();
+        if config.forbid_time_travel && !time_travel && TIME_TRAVEL_CALLS.iter().any(|call| line_calls.contains(call)) {
+            violate(
+                "time-travel",
+                "vm.warp and vm.roll are only allowed in time-travel simulations".to_string(),
+            );
+        }
+        if config.forbid_unknown_recipients
+            && TRANSFER_CALLS.iter().any(|call| code.to_lowercase().contains(call))
+        {
+            for (_, address) in find_addresses(code) {
+                if !allowed.contains(&address) {
+                    violate(
+                        "unknown-recipient",
+                        format!(
+                            "value is sent to hardcoded address {:?} which is neither the user nor a known protocol address",
+                            address
+                        ),
+                    );
+                }
+            }
+        }
+        for rule in &config.rules {
+            if code.contains(&rule.pattern) {
+                violate(&rule.name, rule.reason.clone());
+            }
+        }
+    }
+
+    if config.forbid_bad_deadlines {
+        for (line, rule, reason) in check_deadlines(source, config.max_deadline_secs) {
+            violations.push(PolicyViolation {
+                rule: rule.to_string(),
+                line,
+                reason,
+            });
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,112 @@
+use super::prices::token_usd_prices;
+use super::spam::filter_spam;
+use super::token_list::TokenRegistry;
+use super::tokens::{get_nft_holdings, get_token_balances, NftHolding, TokenCache, TokenHolding};
+use crate::models::{PortfolioConfig, PricesConfig};
+use ethers::types::Address;
+use eyre::Result;
+use tracing::warn;
+
+/// A compact listing of `address`'s token balances and NFTs on `chain_id` for the
+/// generation prompt, without likely spam tokens. `None` when portfolio context
+/// is disabled or unconfigured.
+pub async fn portfolio_summary(
+    address: &str,
+    chain_id: u64,
+    config: &PortfolioConfig,
+    prices: &PricesConfig,
+    registry: &TokenRegistry,
+    cache: &TokenCache,
+) -> Result<Option<String>> {
+    let Some(api_key) = config.alchemy_api_key.as_deref().filter(|_| config.enabled) else {
+        return Ok(None);
+    };
+
+    let holdings = get_token_balances(address, chain_id, api_key, cache).await?;
+    let (holdings, spam) = filter_spam(holdings, registry, config, prices).await;
+    let mut summary = if holdings.is_empty() {
+        "The sender holds no ERC-20 tokens.".to_string()
+    } else {
+        let mut lines = holdings
+            .iter()
+            .take(config.max_holdings)
+            .map(describe)
+            .collect::<Vec<_>>();
+        if holdings.len() > config.max_holdings {
+            lines.push(format!("- ...and {} more", holdings.len() - config.max_holdings));
+        }
+        if spam > 0 {
+            lines.push(format!("({} likely spam tokens omitted; never interact with them)", spam));
+        }
+        format!("Sender's token balances:\n{}", lines.join("\n"))
+    };
+
+    // Token IDs ground intents like "list my Pudgy Penguin #1234"; a failed
+    // lookup only costs that, so the ERC-20 balances are still given
+    if config.include_nfts {
+        match get_nft_holdings(address, chain_id, api_key, cache).await {
+            Ok(nfts) if !nfts.is_empty() => {
+                let mut lines = nfts.iter().take(config.max_holdings).map(describe_nft).collect::<Vec<_>>();
+                if nfts.len() > config.max_holdings {
+                    lines.push(format!("- ...and {} more", nfts.len() - config.max_holdings));
+                }
+                summary.push_str(&format!("\n\nSender's NFTs:\n{}", lines.join("\n")));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to fetch the sender's NFTs: {}", e),
+        }
+    }
+    Ok(Some(summary))
+}
+
+fn describe(holding: &TokenHolding) -> String {
+    let symbol = holding.symbol.as_deref().unwrap_or("unknown token");
+    format!("- {} {} ({})", holding.balance, symbol, holding.contract_address)
+}
+
+fn describe_nft(nft: &NftHolding) -> String {
+    let collection = nft
+        .collection
+        .as_deref()
+        .or(nft.symbol.as_deref())
+        .unwrap_or("unknown collection");
+    let standard = nft.token_type.as_deref().unwrap_or("NFT");
+    let copies = match nft.balance.as_str() {
+        "1" => String::new(),
+        balance => format!("{} x ", balance),
+    };
+    format!("- {}{} #{} ({}, {})", copies, collection, nft.token_id, standard, nft.contract_address)
+}
+
+/// USD value of `address`'s priced ERC-20 balances on `chain_id`, without likely
+/// spam tokens. `None` when portfolio data is disabled or unconfigured.
+pub async fn portfolio_value_usd(
+    address: &str,
+    chain_id: u64,
+    config: &PortfolioConfig,
+    prices: &PricesConfig,
+    registry: &TokenRegistry,
+    cache: &TokenCache,
+) -> Result<Option<f64>> {
+    let Some(api_key) = config.alchemy_api_key.as_deref().filter(|_| config.enabled) else {
+        return Ok(None);
+    };
+
+    let holdings = get_token_balances(address, chain_id, api_key, cache).await?;
+    let (holdings, _) = filter_spam(holdings, registry, config, prices).await;
+    let tokens = holdings
+        .iter()
+        .filter_map(|holding| holding.contract_address.parse::<Address>().ok())
+        .collect::<Vec<_>>();
+    let usd = token_usd_prices(prices, chain_id, &tokens).await?;
+
+    Ok(Some(
+        holdings
+            .iter()
+            .filter_map(|holding| {
+                let price = usd.get(&holding.contract_address.parse::<Address>().ok()?)?;
+                Some(holding.balance.parse::<f64>().ok()? * price)
+            })
+            .sum(),
+    ))
+}
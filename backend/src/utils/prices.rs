@@ -0,0 +1,205 @@
+use super::anvil::AnvilForks;
+use super::http::HTTP_CLIENT;
+use super::rpc_client::{rpc_provider, RpcProvider};
+use super::trace::{parse_address, parse_quantity, trace_transaction, view_call, CallFrame};
+use crate::models::{PricesConfig, TokenDelta, TransactionDetails};
+use ethers::abi::{self, ParamType};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::format_units;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use tracing::warn;
+
+const TRANSFER: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb];
+const TRANSFER_FROM: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd];
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const LATEST_ROUND_DATA: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+
+/// Tokens received minus tokens sent by the owner, per token
+#[derive(Default)]
+struct Flows(Vec<(Address, U256, U256)>);
+
+impl Flows {
+    fn entry(&mut self, token: Address) -> &mut (Address, U256, U256) {
+        match self.0.iter().position(|(t, _, _)| *t == token) {
+            Some(index) => &mut self.0[index],
+            None => {
+                self.0.push((token, U256::zero(), U256::zero()));
+                self.0.last_mut().unwrap()
+            }
+        }
+    }
+
+    fn collect(&mut self, frame: &CallFrame, owner: Address) {
+        frame.visit_succeeded(&mut |call| {
+            let Some(token) = call.to else { return };
+            let transfer = if let Some(args) = call.input.strip_prefix(&TRANSFER) {
+                decode(&[ParamType::Address, ParamType::Uint(256)], args)
+                    .map(|tokens| (call.from, tokens[0].clone(), tokens[1].clone()))
+            } else if let Some(args) = call.input.strip_prefix(&TRANSFER_FROM) {
+                decode(&[ParamType::Address, ParamType::Address, ParamType::Uint(256)], args)
+                    .map(|tokens| (tokens[0].clone().into_address().unwrap_or_default(), tokens[1].clone(), tokens[2].clone()))
+            } else {
+                None
+            };
+            let Some((from, to, amount)) = transfer else { return };
+            let (Some(to), Some(amount)) = (to.into_address(), amount.into_uint()) else { return };
+
+            if from == owner {
+                let entry = self.entry(token);
+                entry.2 = entry.2.saturating_add(amount);
+            }
+            if to == owner {
+                let entry = self.entry(token);
+                entry.1 = entry.1.saturating_add(amount);
+            }
+        });
+    }
+}
+
+fn decode(types: &[ParamType], data: &[u8]) -> Option<Vec<abi::Token>> {
+    abi::decode(types, data).ok()
+}
+
+/// USD price of the fork's native token from its chain's Chainlink aggregator
+pub(super) async fn native_usd_price(provider: &RpcProvider, config: &PricesConfig) -> Result<f64> {
+    let chain_id = provider.get_chainid().await?;
+    let feed = config
+        .native_usd_feeds
+        .get(&chain_id.to_string())
+        .ok_or_else(|| eyre!("No native token price feed is configured for chain {}", chain_id))?;
+    let feed = parse_address(feed)?;
+    let round = view_call(provider, feed, LATEST_ROUND_DATA.to_vec()).await?;
+    let answer = decode(
+        &[ParamType::Uint(80), ParamType::Int(256), ParamType::Uint(256), ParamType::Uint(256), ParamType::Uint(80)],
+        &round,
+    )
+    .and_then(|tokens| tokens[1].clone().into_int())
+    .ok_or_else(|| eyre!("Unexpected latestRoundData response"))?;
+    let decimals = token_decimals(provider, feed).await.unwrap_or(8);
+    to_f64(answer, decimals)
+}
+
+async fn token_decimals(provider: &RpcProvider, token: Address) -> Option<u32> {
+    let data = view_call(provider, token, DECIMALS.to_vec()).await.ok()?;
+    let decimals = decode(&[ParamType::Uint(8)], &data)?[0].clone().into_uint()?;
+    Some(decimals.as_u32())
+}
+
+async fn token_symbol(provider: &RpcProvider, token: Address) -> Option<String> {
+    let data = view_call(provider, token, SYMBOL.to_vec()).await.ok()?;
+    decode(&[ParamType::String], &data)?[0].clone().into_string()
+}
+
+/// Per-token USD prices of tokens on `chain_id` from CoinGecko, keyed by contract address
+pub async fn token_usd_prices(config: &PricesConfig, chain_id: u64, tokens: &[Address]) -> Result<HashMap<Address, f64>> {
+    let Some(base_url) = &config.coingecko_url else {
+        return Ok(HashMap::new());
+    };
+    if tokens.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let platform = config
+        .coingecko_platforms
+        .get(&chain_id.to_string())
+        .ok_or_else(|| eyre!("No CoinGecko platform is configured for chain {}", chain_id))?;
+
+    let addresses = tokens.iter().map(|token| format!("{:?}", token)).collect::<Vec<_>>().join(",");
+    let mut request = HTTP_CLIENT
+        .get(format!("{}/simple/token_price/{}", base_url.trim_end_matches('/'), platform))
+        .query(&[("contract_addresses", addresses.as_str()), ("vs_currencies", "usd")]);
+    if let Some(key) = &config.coingecko_api_key {
+        request = request.header("x-cg-demo-api-key", key);
+    }
+
+    let prices: HashMap<String, HashMap<String, f64>> = request.send().await?.error_for_status()?.json().await?;
+    Ok(prices
+        .into_iter()
+        .filter_map(|(address, price)| Some((address.parse().ok()?, *price.get("usd")?)))
+        .collect())
+}
+
+fn to_f64(amount: U256, decimals: u32) -> Result<f64> {
+    Ok(format_units(amount, decimals)?.parse()?)
+}
+
+/// Set `value_usd` on each transaction and work out the sender's net token
+/// movements across the bundle, priced in USD where a price is known. On an
+/// anvil fork each transaction is mined after it's traced, on a snapshot that's
+/// reverted afterwards, so later ones see the effects of earlier ones.
+pub async fn annotate_usd(
+    rpc_url: &str,
+    forks: &AnvilForks,
+    config: &PricesConfig,
+    transactions: &mut [TransactionDetails],
+) -> Result<Vec<TokenDelta>> {
+    let Some(first) = transactions.first() else {
+        return Ok(Vec::new());
+    };
+    let owner = parse_address(&first.from)?;
+    let provider = rpc_provider(rpc_url)?;
+
+    match native_usd_price(&provider, config).await {
+        Ok(native_usd) => {
+            for transaction in transactions.iter_mut() {
+                let value = to_f64(parse_quantity(&transaction.value), 18)?;
+                transaction.value_usd = Some(value * native_usd);
+            }
+        }
+        Err(e) => warn!("No native token price from the fork: {}", e),
+    }
+
+    let mut snapshot = forks.snapshot(rpc_url).await.unwrap_or_else(|e| {
+        warn!("Failed to snapshot the fork, tracing transactions independently: {}", e);
+        None
+    });
+    if let Some(fork) = &mut snapshot {
+        fork.impersonate(owner).await?;
+    }
+    let mut flows = Flows::default();
+    for transaction in transactions.iter() {
+        let frame = trace_transaction(&provider, owner, transaction).await?;
+        flows.collect(&frame, owner);
+        if let Some(fork) = &snapshot {
+            if let Err(e) = fork.execute(transaction).await {
+                warn!("Failed to mine a traced transaction on the fork: {}", e);
+            }
+        }
+    }
+    if let Some(fork) = snapshot {
+        fork.revert().await?;
+    }
+
+    let tokens = flows.0.iter().map(|(token, _, _)| *token).collect::<Vec<_>>();
+    let chain_id = provider.get_chainid().await?.as_u64();
+    let prices = token_usd_prices(config, chain_id, &tokens).await.unwrap_or_else(|e| {
+        warn!("Failed to fetch token prices: {}", e);
+        HashMap::new()
+    });
+
+    let mut deltas = Vec::new();
+    for (token, received, sent) in flows.0 {
+        if received == sent {
+            continue;
+        }
+        let (magnitude, sign) = if received > sent { (received - sent, "") } else { (sent - received, "-") };
+        let decimals = token_decimals(&provider, token).await;
+        let amount = decimals.and_then(|decimals| to_f64(magnitude, decimals).ok());
+
+        deltas.push(TokenDelta {
+            token: format!("{:?}", token),
+            symbol: token_symbol(&provider, token).await,
+            amount: match decimals {
+                Some(decimals) => format!("{}{}", sign, format_units(magnitude, decimals)?),
+                None => format!("{}{}", sign, magnitude),
+            },
+            usd: amount
+                .zip(prices.get(&token))
+                .map(|(amount, price)| if sign.is_empty() { amount * price } else { -amount * price }),
+        });
+    }
+
+    Ok(deltas)
+}
@@ -0,0 +1,250 @@
+use eyre::{eyre, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Base project files a session may rewrite, copied back when it's recycled
+/// (or removed, if the base project has none). `forge install` updates all but
+/// foundry.toml.
+const SESSION_OVERRIDES: &[&str] = &["foundry.toml", "remappings.txt", ".gitmodules", "foundry.lock"];
+
+/// Where a session's forge keeps its build cache, seeded from the shared one.
+/// Forge rewrites the cache as it compiles, so sessions can't share a directory.
+const SESSION_CACHE: &str = "cache";
+
+/// Files and directories a session adds on top of the base project; removing
+/// them returns a session directory to its pristine state
+const SESSION_ARTIFACTS: &[&str] = &[
+    "script/Script.s.sol",
+    "session.json",
+    "plan.json",
+    "origin.json",
+    "feedback.json",
+    "events.jsonl",
+    "prompts.jsonl",
+    "versions",
+    "invariants",
+    "test/Invariants.t.sol",
+    "broadcast",
+    "out/Script.s.sol",
+    "out/Invariants.t.sol",
+];
+
+/// Pre-copied base project directories, so sessions don't wait for a copy
+pub struct ProjectPool {
+    base_dir: PathBuf,
+    /// Build cache of the base project's compile, copied into each session
+    shared_cache: Option<PathBuf>,
+    size: usize,
+    ready: Mutex<Vec<TempDir>>,
+    refill: Notify,
+}
+
+impl ProjectPool {
+    pub fn new(base_dir: PathBuf, shared_cache: Option<PathBuf>, size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            base_dir,
+            shared_cache,
+            size,
+            ready: Mutex::new(Vec::with_capacity(size)),
+            refill: Notify::new(),
+        })
+    }
+
+    /// Take a ready project directory, copying one on the spot if the pool is empty
+    pub async fn checkout(&self) -> Result<TempDir> {
+        let pooled = self.ready.lock().unwrap().pop();
+        self.refill.notify_one();
+
+        match pooled {
+            Some(dir) => Ok(dir),
+            None => {
+                debug!("Project pool empty, copying base project");
+                let (base_dir, shared_cache) = (self.base_dir.clone(), self.shared_cache.clone());
+                tokio::task::spawn_blocking(move || prepare_project(&base_dir, shared_cache.as_deref())).await?
+            }
+        }
+    }
+
+    /// Return a session directory to the pool once its session is finished.
+    /// Dropped instead if the pool is full or the directory can't be reset.
+    pub async fn recycle(&self, dir: TempDir) {
+        if self.ready.lock().unwrap().len() >= self.size {
+            return;
+        }
+
+        let (base_dir, shared_cache) = (self.base_dir.clone(), self.shared_cache.clone());
+        let reset =
+            tokio::task::spawn_blocking(move || reset_project(&base_dir, shared_cache.as_deref(), dir.path()).map(|_| dir)).await;
+        match reset {
+            Ok(Ok(dir)) => {
+                let mut ready = self.ready.lock().unwrap();
+                if ready.len() < self.size {
+                    ready.push(dir);
+                }
+            }
+            Ok(Err(e)) => warn!("Failed to reset session directory: {}", e),
+            Err(e) => warn!("Session directory reset panicked: {}", e),
+        }
+    }
+
+    /// Keep the pool topped up until shutdown
+    pub async fn run_refill(self: Arc<Self>, shutdown: CancellationToken) {
+        loop {
+            while self.ready.lock().unwrap().len() < self.size {
+                let (base_dir, shared_cache) = (self.base_dir.clone(), self.shared_cache.clone());
+                match tokio::task::spawn_blocking(move || prepare_project(&base_dir, shared_cache.as_deref())).await {
+                    Ok(Ok(dir)) => self.ready.lock().unwrap().push(dir),
+                    Ok(Err(e)) => {
+                        warn!("Failed to prepare pooled project: {}", e);
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Pooled project copy panicked: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            tokio::select! {
+                _ = self.refill.notified() => {}
+                _ = shutdown.cancelled() => break,
+            }
+        }
+
+        // Dropping the pooled directories removes them from disk
+        self.ready.lock().unwrap().clear();
+    }
+}
+
+fn prepare_project(base_dir: &Path, shared_cache: Option<&Path>) -> Result<TempDir> {
+    let dir = TempDir::with_prefix("forge_")?;
+    for entry in fs::read_dir(base_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let mode = if name == "lib" {
+            CloneMode::ReflinkOrHardlink
+        } else if name == "out" {
+            CloneMode::Reflink
+        } else {
+            CloneMode::Copy
+        };
+        copy_tree(&entry.path(), &dir.path().join(&name), mode)
+            .map_err(|e| eyre!("Failed to copy base project {:?}: {}", name, e))?;
+    }
+    if let Some(shared_cache) = shared_cache {
+        seed_cache(shared_cache, dir.path()).map_err(|e| eyre!("Failed to copy the shared build cache: {}", e))?;
+    }
+    Ok(dir)
+}
+
+/// Replace a session's build cache with a copy of the shared one
+fn seed_cache(shared_cache: &Path, path: &Path) -> io::Result<()> {
+    let cache = path.join(SESSION_CACHE);
+    if cache.exists() {
+        fs::remove_dir_all(&cache)?;
+    }
+    if shared_cache.is_dir() {
+        copy_tree(shared_cache, &cache, CloneMode::Reflink)?;
+    }
+    Ok(())
+}
+
+/// How files are duplicated into a session directory
+#[derive(Clone, Copy)]
+enum CloneMode {
+    Copy,
+    /// Copy-on-write clone where the filesystem supports it, otherwise a copy.
+    /// Used for `out/`, which forge rewrites in place.
+    Reflink,
+    /// Copy-on-write clone, otherwise a hardlink. Only safe for `lib/`, which
+    /// sessions add to but never modify.
+    ReflinkOrHardlink,
+}
+
+/// Set once a reflink fails, so later copies skip straight to the fallback
+static REFLINK_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+fn copy_tree(src: &Path, dst: &Path, mode: CloneMode) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(src)?.file_type();
+    if file_type.is_dir() {
+        fs::create_dir_all(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()), mode)?;
+        }
+        return Ok(());
+    }
+    if file_type.is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(fs::read_link(src)?, dst);
+    }
+
+    if !matches!(mode, CloneMode::Copy) && !REFLINK_UNSUPPORTED.load(Ordering::Relaxed) {
+        match reflink(src, dst) {
+            Ok(()) => return Ok(()),
+            Err(_) => {
+                let _ = fs::remove_file(dst);
+                REFLINK_UNSUPPORTED.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    if matches!(mode, CloneMode::ReflinkOrHardlink) && fs::hard_link(src, dst).is_ok() {
+        return Ok(());
+    }
+    fs::copy(src, dst).map(|_| ())
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let source = fs::File::open(src)?;
+    let target = fs::OpenOptions::new().write(true).create_new(true).open(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    target.set_permissions(source.metadata()?.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+fn reset_project(base_dir: &Path, shared_cache: Option<&Path>, path: &Path) -> Result<()> {
+    for artifact in SESSION_ARTIFACTS {
+        let target = path.join(artifact);
+        if target.is_dir() {
+            fs::remove_dir_all(&target)?;
+        } else if target.exists() {
+            fs::remove_file(&target)?;
+        }
+    }
+    for file in SESSION_OVERRIDES {
+        let base_file = base_dir.join(file);
+        if base_file.exists() {
+            fs::copy(base_file, path.join(file))?;
+        } else if path.join(file).exists() {
+            fs::remove_file(path.join(file))?;
+        }
+    }
+    // Packages the session installed
+    for entry in fs::read_dir(path.join("lib"))? {
+        let entry = entry?;
+        if !base_dir.join("lib").join(entry.file_name()).exists() {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    if let Some(shared_cache) = shared_cache {
+        seed_cache(shared_cache, path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use super::redact::redact;
+use async_openai::types::CreateChatCompletionRequest;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Every LLM request a session's jobs made, one JSON object per line
+const PROMPT_LOG_FILE: &str = "prompts.jsonl";
+
+tokio::task_local! {
+    /// Session directory of the job running on this task
+    static SESSION_DIR: PathBuf;
+}
+
+#[derive(Serialize)]
+struct LoggedPrompt<'a> {
+    timestamp_ms: u128,
+    /// As sent, after routing picked the model
+    request: &'a CreateChatCompletionRequest,
+}
+
+/// Run a job with the LLM requests it makes logged to its session directory
+pub async fn with_prompt_log<F: Future>(session_dir: PathBuf, job: F) -> F::Output {
+    SESSION_DIR.scope(session_dir, job).await
+}
+
+/// Append a request to the running job's prompt log, if it keeps one
+pub fn log_prompt(request: &CreateChatCompletionRequest) {
+    let Ok(session_dir) = SESSION_DIR.try_with(|dir| dir.clone()) else {
+        return;
+    };
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let appended = serde_json::to_string(&LoggedPrompt { timestamp_ms, request })
+        .map_err(|e| e.to_string())
+        .and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(session_dir.join(PROMPT_LOG_FILE))
+                .and_then(|mut file| writeln!(file, "{}", redact(&line)))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = appended {
+        warn!("Failed to log prompt: {}", e);
+    }
+}
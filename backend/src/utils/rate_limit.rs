@@ -0,0 +1,120 @@
+use crate::models::{KeyLimits, RateLimitConfig};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum RateLimitError {
+    /// The key is not in the configured key list and keys are required
+    UnknownKey,
+    /// Too many requests in the last minute; retry after the given delay
+    TooManyRequests(Duration),
+    /// The key already has its maximum number of jobs running
+    TooManyJobs,
+}
+
+#[derive(Default)]
+struct KeyState {
+    requests: VecDeque<Instant>,
+    running_jobs: u32,
+}
+
+/// Sliding-window request limiter and concurrent job counter, tracked per
+/// configured API key; requests without one share the anonymous limits
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    keys: Arc<Mutex<HashMap<String, KeyState>>>,
+}
+
+/// Held for as long as a job's response is streaming; releases the job slot on drop
+pub struct JobGuard {
+    key: String,
+    keys: Arc<Mutex<HashMap<String, KeyState>>>,
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.keys.lock().unwrap().get_mut(&self.key) {
+            state.running_jobs = state.running_jobs.saturating_sub(1);
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            keys: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The limits for `key` and the key its usage is counted under. Keys that
+    /// aren't configured count as anonymous, so making up a new key doesn't
+    /// buy a fresh allowance.
+    fn limits_for(&self, key: Option<&str>) -> Result<(KeyLimits, &str), RateLimitError> {
+        match key.and_then(|key| self.config.keys.get_key_value(key)) {
+            Some((key, limits)) => Ok((
+                KeyLimits {
+                    requests_per_minute: limits.requests_per_minute.or(self.config.requests_per_minute),
+                    max_concurrent_jobs: limits.max_concurrent_jobs.or(self.config.max_concurrent_jobs),
+                },
+                key,
+            )),
+            None if self.config.require_api_key => Err(RateLimitError::UnknownKey),
+            None => Ok((
+                KeyLimits {
+                    requests_per_minute: self.config.requests_per_minute,
+                    max_concurrent_jobs: self.config.max_concurrent_jobs,
+                },
+                "anonymous",
+            )),
+        }
+    }
+
+    /// Record a request for `key` and reserve a job slot, or explain why it was rejected
+    pub fn acquire(&self, key: Option<&str>) -> Result<JobGuard, RateLimitError> {
+        let (limits, key) = self.limits_for(key)?;
+        let key = key.to_string();
+        let now = Instant::now();
+
+        let mut keys = self.keys.lock().unwrap();
+        // Keys idle for a whole window have nothing left to count
+        keys.retain(|_, state| {
+            state.running_jobs > 0 || state.requests.back().is_some_and(|last| now.duration_since(*last) < WINDOW)
+        });
+        let state = keys.entry(key.clone()).or_default();
+
+        while state
+            .requests
+            .front()
+            .is_some_and(|oldest| now.duration_since(*oldest) >= WINDOW)
+        {
+            state.requests.pop_front();
+        }
+
+        if let Some(limit) = limits.requests_per_minute {
+            if state.requests.len() >= limit as usize {
+                let oldest = state.requests.front().copied().unwrap_or(now);
+                return Err(RateLimitError::TooManyRequests(
+                    WINDOW.saturating_sub(now.duration_since(oldest)),
+                ));
+            }
+        }
+
+        if let Some(limit) = limits.max_concurrent_jobs {
+            if state.running_jobs >= limit {
+                return Err(RateLimitError::TooManyJobs);
+            }
+        }
+
+        state.requests.push_back(now);
+        state.running_jobs += 1;
+
+        Ok(JobGuard {
+            key,
+            keys: self.keys.clone(),
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::RwLock;
+
+const REDACTED: &str = "[REDACTED]";
+/// Shorter values would match unrelated text
+const MIN_SECRET_LEN: usize = 8;
+/// URL query parameters whose values are credentials
+const SENSITIVE_PARAMS: &[&str] = &["key", "apikey", "api_key", "api-key", "token", "access_token", "secret", "auth"];
+/// URL path segments this long made of letters and digits are taken for keys, as in
+/// Alchemy's `/v2/<key>` and Infura's `/v3/<key>`
+const MIN_PATH_KEY_LEN: usize = 20;
+/// A 32-byte hex value is taken for a private key when one of these words precedes it
+const KEY_CONTEXT: &[&str] = &["private", "secret"];
+const KEY_CONTEXT_WINDOW: usize = 32;
+
+/// Values known to be secret, such as the API keys in the config, each with how
+/// many times it's been registered
+static SECRETS: RwLock<Vec<(String, usize)>> = RwLock::new(Vec::new());
+
+pub fn register_secret(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    match secrets.iter_mut().find(|(known, _)| known == secret) {
+        Some((_, count)) => *count += 1,
+        None => secrets.push((secret.to_string(), 1)),
+    }
+}
+
+/// Undo a `register_secret`; the value stops being redacted once nothing else
+/// registered it
+pub fn forget_secret(secret: &str) {
+    let secret = secret.trim();
+    let mut secrets = SECRETS.write().unwrap();
+    if let Some(index) = secrets.iter().position(|(known, _)| known == secret) {
+        secrets[index].1 -= 1;
+        if secrets[index].1 == 0 {
+            secrets.swap_remove(index);
+        }
+    }
+}
+
+/// `text` with known secrets, credentials in URLs and private keys replaced
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let mut redacted = Cow::Borrowed(text);
+    for (secret, _) in SECRETS.read().unwrap().iter() {
+        if redacted.contains(secret.as_str()) {
+            redacted = Cow::Owned(redacted.replace(secret.as_str(), REDACTED));
+        }
+    }
+
+    let mut ranges = Vec::new();
+    for (scheme_end, _) in redacted.match_indices("://") {
+        let start = scheme_end + 3;
+        let end = redacted[start..]
+            .find(|c: char| !is_url_char(c))
+            .map_or(redacted.len(), |len| start + len);
+        ranges.extend(url_secrets(&redacted[start..end], true).into_iter().map(|range| start + range.start..start + range.end));
+    }
+    ranges.extend(private_keys(&redacted));
+    replace_ranges(redacted, ranges)
+}
+
+/// A URL, or a path with a query string, with its credentials replaced
+pub fn redact_url(url: &str) -> Cow<'_, str> {
+    let (start, has_authority) = match url.find("://") {
+        Some(scheme_end) => (scheme_end + 3, true),
+        None => (0, !url.starts_with('/')),
+    };
+    let ranges = url_secrets(&url[start..], has_authority)
+        .into_iter()
+        .map(|range| start + range.start..start + range.end)
+        .collect();
+    replace_ranges(Cow::Borrowed(url), ranges)
+}
+
+fn is_url_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-._~:/?#@!$&+=%[]".contains(c)
+}
+
+/// Ranges of `url` (without its scheme) holding a password, a key in the path or
+/// a sensitive query parameter
+fn url_secrets(url: &str, has_authority: bool) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let authority_len = if has_authority { url.find(['/', '?', '#']).unwrap_or(url.len()) } else { 0 };
+    if let Some(at) = url[..authority_len].rfind('@') {
+        if let Some(colon) = url[..at].find(':') {
+            ranges.push(colon + 1..at);
+        }
+    }
+
+    let query_start = url.find('?');
+    let path_end = query_start.or_else(|| url.find('#')).unwrap_or(url.len());
+    let mut offset = authority_len;
+    for segment in url[authority_len..path_end.max(authority_len)].split('/') {
+        if looks_like_key(segment) {
+            ranges.push(offset..offset + segment.len());
+        }
+        offset += segment.len() + 1;
+    }
+
+    if let Some(query_start) = query_start {
+        let query_end = url[query_start..].find('#').map_or(url.len(), |len| query_start + len);
+        let mut offset = query_start + 1;
+        for pair in url[query_start + 1..query_end].split('&') {
+            if let Some((name, value)) = pair.split_once('=') {
+                if !value.is_empty() && SENSITIVE_PARAMS.contains(&name.to_ascii_lowercase().as_str()) {
+                    let value_start = offset + name.len() + 1;
+                    ranges.push(value_start..value_start + value.len());
+                }
+            }
+            offset += pair.len() + 1;
+        }
+    }
+    ranges
+}
+
+fn looks_like_key(segment: &str) -> bool {
+    segment.len() >= MIN_PATH_KEY_LEN
+        && !segment.starts_with("0x")
+        && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        && segment.chars().any(|c| c.is_ascii_digit())
+        && segment.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+/// 32-byte hex values, with or without `0x`, that follow a word like "private"
+fn private_keys(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut ranges = Vec::new();
+    let mut index = 0;
+    while index < bytes.len() {
+        let starts_word = index == 0 || !(bytes[index - 1].is_ascii_alphanumeric() || bytes[index - 1] == b'_');
+        if !starts_word || !bytes[index].is_ascii_hexdigit() {
+            index += 1;
+            continue;
+        }
+        let digits_start = if text[index..].starts_with("0x") { index + 2 } else { index };
+        let digits = bytes[digits_start..].iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        let ends_word = bytes
+            .get(digits_start + digits)
+            .is_none_or(|b| !(b.is_ascii_alphanumeric() || *b == b'_'));
+        if digits == 64 && ends_word {
+            let context_start = (index.saturating_sub(KEY_CONTEXT_WINDOW)..=index)
+                .find(|start| text.is_char_boundary(*start))
+                .unwrap_or(index);
+            let context = text[context_start..index].to_ascii_lowercase();
+            if KEY_CONTEXT.iter().any(|word| context.contains(word)) {
+                ranges.push(index..digits_start + digits);
+            }
+        }
+        index = (digits_start + digits).max(index + 1);
+    }
+    ranges
+}
+
+fn replace_ranges(text: Cow<'_, str>, mut ranges: Vec<Range<usize>>) -> Cow<'_, str> {
+    if ranges.is_empty() {
+        return text;
+    }
+    ranges.sort_by_key(|range| range.start);
+    let mut redacted = String::with_capacity(text.len());
+    let mut copied = 0;
+    for range in ranges {
+        if range.end <= copied {
+            continue;
+        }
+        redacted.push_str(&text[copied..range.start.max(copied)]);
+        redacted.push_str(REDACTED);
+        copied = range.end;
+    }
+    redacted.push_str(&text[copied..]);
+    Cow::Owned(redacted)
+}
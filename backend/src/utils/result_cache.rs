@@ -0,0 +1,112 @@
+use crate::models::{CompilerSettings, RiskReport, TokenDelta, TransactionDetails};
+use ethers::utils::{hex, keccak256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A successful job's output, enough to answer an identical request and leave
+/// behind a session that can still be fixed
+#[derive(Clone)]
+pub struct CachedResult {
+    pub script: String,
+    /// The session's `session.json`, with the generation conversation
+    pub session_file: String,
+    pub transactions: Vec<TransactionDetails>,
+    pub token_deltas: Vec<TokenDelta>,
+    pub risk: Option<RiskReport>,
+}
+
+/// Everything that decides a job's output
+pub struct CacheKey<'a> {
+    pub intent: &'a str,
+    pub from_address: &'a str,
+    pub chain_id: u64,
+    pub slippage_bps: Option<u32>,
+    pub guidelines_version: &'a str,
+    pub models: [&'a str; 2],
+    /// Experiment variant, whose prompt changes the output
+    pub variant: Option<&'a str>,
+    pub compiler: &'a CompilerSettings,
+}
+
+impl CacheKey<'_> {
+    fn digest(&self) -> String {
+        let key = serde_json::json!([
+            self.intent.trim(),
+            self.from_address.to_ascii_lowercase(),
+            self.chain_id,
+            self.slippage_bps,
+            self.guidelines_version,
+            self.models,
+            self.variant,
+            self.compiler,
+        ]);
+        hex::encode(keccak256(key.to_string()))
+    }
+}
+
+/// Results of recent jobs by request, so an identical request within the TTL is
+/// answered without generating or simulating again
+pub struct ResultCache {
+    ttl: Duration,
+    max_entries: usize,
+    /// With the session that produced each result
+    entries: Mutex<HashMap<String, (Instant, PathBuf, CachedResult)>>,
+    /// Key of the request each running session is answering
+    pending: Mutex<HashMap<PathBuf, String>>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A fresh result for `key` and how long ago it was produced
+    pub fn get(&self, key: &CacheKey) -> Option<(Duration, CachedResult)> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored, _, _)| stored.elapsed() < self.ttl);
+        entries
+            .get(&key.digest())
+            .map(|(stored, _, result)| (stored.elapsed(), result.clone()))
+    }
+
+    /// Remember that `session` is answering `key`, for `complete`
+    pub fn expect(&self, session: &Path, key: &CacheKey) {
+        self.pending.lock().unwrap().insert(session.to_path_buf(), key.digest());
+    }
+
+    /// Store the result `session` produced, if it was answering a cacheable request
+    pub fn complete(&self, session: &Path, result: CachedResult) {
+        let Some(key) = self.pending.lock().unwrap().remove(session) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (stored, _, _)| stored.elapsed() < self.ttl);
+        if entries.len() >= self.max_entries {
+            if let Some(oldest) = entries.iter().min_by_key(|(_, (stored, _, _))| *stored).map(|(key, _)| key.clone()) {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key, (Instant::now(), session.to_path_buf(), result));
+    }
+
+    /// Drop a session that ended without a result
+    pub fn forget(&self, session: &Path) {
+        self.pending.lock().unwrap().remove(session);
+    }
+
+    /// Drop everything `session` left in the cache, returning how many results it had stored
+    pub fn purge(&self, session: &Path) -> usize {
+        self.forget(session);
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, (_, producer, _)| producer != session);
+        before - entries.len()
+    }
+}
@@ -0,0 +1,207 @@
+use super::http::HTTP_CLIENT;
+use super::prices::native_usd_price;
+use super::rpc_client::rpc_provider;
+use super::trace::{parse_address, trace_transaction};
+use crate::models::{PricesConfig, RiskConfig, RiskFactor, RiskLevel, RiskReason, RiskReport, TokenDelta, TransactionDetails};
+use ethers::abi::{self, ParamType};
+use ethers::providers::Middleware;
+use ethers::types::{Address, U256};
+use ethers::utils::{format_ether, hex};
+use eyre::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+
+const APPROVE: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3];
+
+const UNLIMITED_APPROVAL_POINTS: u32 = 30;
+const UNVERIFIED_TARGET_POINTS: u32 = 25;
+const DELEGATECALL_POINTS: u32 = 20;
+/// Share of the sender's holdings moved out by the bundle, and the points it adds
+const VALUE_AT_RISK_TIERS: &[(f64, u32)] = &[(0.5, 30), (0.2, 15)];
+
+#[derive(Deserialize)]
+struct SourceResponse {
+    result: Vec<SourceEntry>,
+}
+
+#[derive(Deserialize)]
+struct SourceEntry {
+    #[serde(rename = "SourceCode")]
+    source_code: String,
+}
+
+/// Whether contracts have verified source, asking Etherscan once per address
+struct Verifier<'a> {
+    config: &'a RiskConfig,
+    chain_id: u64,
+    known: &'a (dyn Fn(Address) -> bool + Sync),
+    checked: HashMap<Address, bool>,
+}
+
+impl Verifier<'_> {
+    async fn is_verified(&mut self, address: Address) -> bool {
+        if (self.known)(address) {
+            return true;
+        }
+        if let Some(verified) = self.checked.get(&address) {
+            return *verified;
+        }
+        let verified = match self.etherscan_verified(address).await {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("Failed to check source verification for {:?}: {}", address, e);
+                false
+            }
+        };
+        self.checked.insert(address, verified);
+        verified
+    }
+
+    async fn etherscan_verified(&self, address: Address) -> Result<bool> {
+        let Some(api_key) = &self.config.etherscan_api_key else {
+            return Ok(false);
+        };
+        let response: SourceResponse = HTTP_CLIENT
+            .get(&self.config.etherscan_url)
+            .query(&[
+                ("chainid", self.chain_id.to_string()),
+                ("module", "contract".to_string()),
+                ("action", "getsourcecode".to_string()),
+                ("address", format!("{:?}", address)),
+                ("apikey", api_key.clone()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(response.result.iter().any(|entry| !entry.source_code.is_empty()))
+    }
+}
+
+/// An `approve` whose amount is effectively unlimited, with the spender
+fn unlimited_approval(input_data: &str) -> Option<Address> {
+    let input = hex::decode(input_data.trim_start_matches("0x")).ok()?;
+    let args = input.strip_prefix(&APPROVE)?;
+    let tokens = abi::decode(&[ParamType::Address, ParamType::Uint(256)], args).ok()?;
+    let amount = tokens[1].clone().into_uint()?;
+    (amount >= U256::one() << 255).then(|| tokens[0].clone().into_address()).flatten()
+}
+
+fn level(score: u32) -> RiskLevel {
+    match score {
+        0..=24 => RiskLevel::Low,
+        25..=49 => RiskLevel::Medium,
+        _ => RiskLevel::High,
+    }
+}
+
+/// Score the final transactions for unlimited approvals, calls into contracts
+/// without verified source, delegatecalls made by unverified contracts and the
+/// share of the sender's holdings they move out. `known` addresses (listed tokens,
+/// allowed addresses) count as verified; `portfolio_usd` is the sender's ERC-20
+/// holdings, to which their ETH on the fork is added.
+pub async fn score_risk(
+    rpc_url: &str,
+    transactions: &[TransactionDetails],
+    token_deltas: &[TokenDelta],
+    portfolio_usd: Option<f64>,
+    known: &(dyn Fn(Address) -> bool + Sync),
+    config: &RiskConfig,
+    prices: &PricesConfig,
+) -> Result<RiskReport> {
+    let mut reasons = Vec::new();
+    let Some(first) = transactions.first() else {
+        return Ok(RiskReport { score: 0, level: RiskLevel::Low, reasons });
+    };
+    let owner = parse_address(&first.from)?;
+    let provider = rpc_provider(rpc_url)?;
+    let mut verifier = Verifier {
+        config,
+        chain_id: provider.get_chainid().await?.as_u64(),
+        known,
+        checked: HashMap::new(),
+    };
+
+    let mut seen_targets = Vec::new();
+    let mut seen_delegations = Vec::new();
+    for (index, transaction) in transactions.iter().enumerate() {
+        // A deployment has no target to check; what its constructor calls is traced below
+        if !transaction.is_create() {
+            let to = parse_address(&transaction.to)?;
+            if let Some(spender) = unlimited_approval(&transaction.input_data) {
+                reasons.push(RiskReason {
+                    factor: RiskFactor::UnlimitedApproval,
+                    points: UNLIMITED_APPROVAL_POINTS,
+                    transaction: Some(index),
+                    detail: format!("unlimited approval of {:?} to {:?}", to, spender),
+                });
+            }
+
+            // Plain ETH transfers to accounts without code have no contract to verify
+            let is_contract = !provider.get_code(to, None).await?.is_empty();
+            if is_contract && !seen_targets.contains(&to) {
+                seen_targets.push(to);
+                if !verifier.is_verified(to).await {
+                    reasons.push(RiskReason {
+                        factor: RiskFactor::UnverifiedTarget,
+                        points: UNVERIFIED_TARGET_POINTS,
+                        transaction: Some(index),
+                        detail: format!("calls {:?}, which has no verified source", to),
+                    });
+                }
+            }
+        }
+
+        // Proxies of verified contracts delegating to their implementation are expected
+        let frame = trace_transaction(&provider, owner, transaction).await?;
+        let mut delegations = Vec::new();
+        frame.visit(&mut |call| {
+            if call.call_type == "DELEGATECALL" {
+                delegations.push((call.from, call.to));
+            }
+        });
+        for (from, implementation) in delegations {
+            if seen_delegations.contains(&(from, implementation)) {
+                continue;
+            }
+            seen_delegations.push((from, implementation));
+            if from != owner && verifier.is_verified(from).await {
+                continue;
+            }
+            let implementation = implementation.map_or_else(|| "unknown code".to_string(), |to| format!("{:?}", to));
+            reasons.push(RiskReason {
+                factor: RiskFactor::Delegatecall,
+                points: DELEGATECALL_POINTS,
+                transaction: Some(index),
+                detail: format!("{:?} delegatecalls into {}", from, implementation),
+            });
+        }
+    }
+
+    let native_usd = native_usd_price(&provider, prices).await;
+    if let (Some(tokens_usd), Ok(native_usd)) = (portfolio_usd, native_usd) {
+        let balance: f64 = format_ether(provider.get_balance(owner, None).await?).parse()?;
+        let holdings = tokens_usd + balance * native_usd;
+        let sent = transactions.iter().filter_map(|transaction| transaction.value_usd).sum::<f64>()
+            + token_deltas.iter().filter_map(|delta| delta.usd).filter(|usd| *usd < 0.0).map(f64::abs).sum::<f64>();
+        let share = if holdings > 0.0 { sent / holdings } else { 0.0 };
+        if let Some((_, points)) = VALUE_AT_RISK_TIERS.iter().find(|(threshold, _)| share >= *threshold) {
+            reasons.push(RiskReason {
+                factor: RiskFactor::ValueAtRisk,
+                points: *points,
+                transaction: None,
+                detail: format!(
+                    "moves out ~${:.2}, {:.0}% of the sender's ~${:.2} holdings",
+                    sent,
+                    share * 100.0,
+                    holdings
+                ),
+            });
+        }
+    }
+
+    let score = reasons.iter().map(|reason| reason.points).sum::<u32>().min(100);
+    Ok(RiskReport { score, level: level(score), reasons })
+}
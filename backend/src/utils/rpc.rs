@@ -0,0 +1,207 @@
+use super::chains::CHAIN_PRESETS;
+use super::rpc_client::rpc_provider;
+use crate::models::RpcConfig;
+use ethers::providers::Middleware;
+use ethers::types::{Address, BlockId, BlockNumber};
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Blocks back an archive check reads state at; full nodes keep only the last 128
+const ARCHIVE_DEPTH: u64 = 10_000;
+
+/// What a preflight learned about an RPC
+#[derive(Debug, Clone, Copy)]
+pub struct RpcHealth {
+    pub chain_id: u64,
+    pub block_number: u64,
+}
+
+/// Why an RPC can't be simulated against
+#[derive(Debug)]
+pub enum PreflightFailure {
+    Unreachable(String),
+    WrongChain { expected: u64, actual: u64 },
+    NotArchive,
+}
+
+impl fmt::Display for PreflightFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightFailure::Unreachable(reason) => write!(f, "The RPC is unreachable: {}", reason),
+            PreflightFailure::WrongChain { expected, actual } => {
+                write!(f, "The RPC serves chain {}, not chain {}", actual, expected)
+            }
+            PreflightFailure::NotArchive => write!(
+                f,
+                "The RPC can't read state from {} blocks ago; an archive node is required",
+                ARCHIVE_DEPTH
+            ),
+        }
+    }
+}
+
+/// Check an RPC answers, serves `expected_chain` when one is given, and keeps
+/// historical state when `require_archive` is set, all within `timeout`
+pub async fn preflight_rpc(
+    rpc_url: &str,
+    expected_chain: Option<u64>,
+    require_archive: bool,
+    timeout: Duration,
+) -> Result<RpcHealth, PreflightFailure> {
+    let unreachable = |e: &dyn fmt::Display| PreflightFailure::Unreachable(e.to_string());
+    let provider = rpc_provider(rpc_url).map_err(|e| unreachable(&e))?;
+    let probe = async {
+        let chain_id = provider.get_chainid().await.map_err(|e| unreachable(&e))?.as_u64();
+        if let Some(expected) = expected_chain.filter(|&expected| expected != chain_id) {
+            return Err(PreflightFailure::WrongChain { expected, actual: chain_id });
+        }
+        let block_number = provider.get_block_number().await.map_err(|e| unreachable(&e))?.as_u64();
+        if require_archive {
+            let past = BlockId::Number(BlockNumber::Number(block_number.saturating_sub(ARCHIVE_DEPTH).into()));
+            if provider.get_balance(Address::zero(), Some(past)).await.is_err() {
+                return Err(PreflightFailure::NotArchive);
+            }
+        }
+        Ok(RpcHealth { chain_id, block_number })
+    };
+    tokio::time::timeout(timeout, probe)
+        .await
+        .unwrap_or_else(|_| Err(PreflightFailure::Unreachable(format!("no answer within {}s", timeout.as_secs()))))
+}
+
+/// Weight of the newest probe in an endpoint's smoothed latency
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+#[derive(Default)]
+struct EndpointStats {
+    /// Smoothed response time of successful probes
+    latency: Option<Duration>,
+    checked_at: Option<Instant>,
+    /// Skipped until then after failing
+    failed_until: Option<Instant>,
+}
+
+/// The RPC endpoints of each chain, from the config and the chain presets, for
+/// requests that give a chain id rather than an RPC URL. Picks the fastest
+/// endpoint that answers and fails over to the next when one goes down.
+pub struct RpcPool {
+    endpoints: HashMap<u64, Vec<String>>,
+    stats: Mutex<HashMap<String, EndpointStats>>,
+    probe_interval: Duration,
+    failure_cooldown: Duration,
+    probe_timeout: Duration,
+    require_archive: bool,
+}
+
+impl RpcPool {
+    pub fn new(config: &RpcConfig) -> Self {
+        let mut endpoints = HashMap::<u64, Vec<String>>::new();
+        for (chain, urls) in &config.endpoints {
+            match chain.parse::<u64>() {
+                Ok(chain_id) => endpoints.entry(chain_id).or_default().extend(urls.iter().cloned()),
+                Err(_) => warn!("Ignoring RPC endpoints for chain {:?}: not a chain id", chain),
+            }
+        }
+        // The curated public RPC is the last resort for chains with a preset
+        for preset in CHAIN_PRESETS {
+            let urls = endpoints.entry(preset.chain_id).or_default();
+            if !urls.iter().any(|url| url == preset.rpc_url) {
+                urls.push(preset.rpc_url.to_string());
+            }
+        }
+
+        Self {
+            endpoints,
+            stats: Mutex::new(HashMap::new()),
+            probe_interval: Duration::from_secs(config.probe_interval_secs),
+            failure_cooldown: Duration::from_secs(config.failure_cooldown_secs),
+            probe_timeout: Duration::from_secs(config.preflight_timeout_secs),
+            require_archive: config.require_archive,
+        }
+    }
+
+    /// Chains the pool has endpoints for
+    pub fn chains(&self) -> Vec<u64> {
+        let mut chains = self.endpoints.keys().copied().collect::<Vec<_>>();
+        chains.sort_unstable();
+        chains
+    }
+
+    /// The fastest healthy endpoint of a chain. Endpoints not probed recently
+    /// are probed first, all at once; `None` when the chain has no endpoints or
+    /// none of them answers.
+    pub async fn select(&self, chain_id: u64) -> Option<String> {
+        let candidates = self.endpoints.get(&chain_id)?;
+        let now = Instant::now();
+        let stale = {
+            let stats = self.stats.lock().unwrap();
+            candidates
+                .iter()
+                .filter(|url| {
+                    let Some(stats) = stats.get(*url) else {
+                        return true;
+                    };
+                    let cooling_down = stats.failed_until.is_some_and(|until| until > now);
+                    let fresh = stats.checked_at.is_some_and(|at| now.duration_since(at) < self.probe_interval);
+                    !cooling_down && !fresh
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let probes = stale.iter().map(|url| async move {
+            let started = Instant::now();
+            let result = preflight_rpc(url, Some(chain_id), self.require_archive, self.probe_timeout).await;
+            (url, result.map(|_| started.elapsed()))
+        });
+        for (url, result) in join_all(probes).await {
+            match result {
+                Ok(latency) => self.record_success(url, latency),
+                Err(failure) => {
+                    debug!(url = %url, "RPC probe failed: {}", failure);
+                    self.report_failure(url);
+                }
+            }
+        }
+
+        let stats = self.stats.lock().unwrap();
+        candidates
+            .iter()
+            .filter_map(|url| {
+                let stats = stats.get(url)?;
+                if stats.failed_until.is_some_and(|until| until > Instant::now()) {
+                    return None;
+                }
+                Some((url, stats.latency?))
+            })
+            .min_by_key(|(_, latency)| *latency)
+            .map(|(url, _)| url.clone())
+    }
+
+    /// Take an endpoint out of rotation for a while after it failed a job.
+    /// URLs requests gave themselves aren't tracked.
+    pub fn report_failure(&self, url: &str) {
+        if !self.endpoints.values().flatten().any(|endpoint| endpoint == url) {
+            return;
+        }
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(url.to_string()).or_default();
+        entry.checked_at = Some(Instant::now());
+        entry.failed_until = Some(Instant::now() + self.failure_cooldown);
+    }
+
+    fn record_success(&self, url: &str, latency: Duration) {
+        let mut stats = self.stats.lock().unwrap();
+        let entry = stats.entry(url.to_string()).or_default();
+        entry.latency = Some(match entry.latency {
+            Some(previous) => previous.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING),
+            None => latency,
+        });
+        entry.checked_at = Some(Instant::now());
+        entry.failed_until = None;
+    }
+}
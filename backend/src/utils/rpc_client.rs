@@ -0,0 +1,190 @@
+use crate::models::RpcConfig;
+use async_trait::async_trait;
+use eyre::Result;
+use ethers::core::rand::{thread_rng, Rng};
+use ethers::providers::{
+    Http, HttpClientError, HttpRateLimitRetryPolicy, JsonRpcClient, JsonRpcError, Provider, RetryPolicy,
+};
+use reqwest::Url;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Compute units forge assumes an average request costs when pacing a fork
+/// (Alchemy weighs `eth_getStorageAt` at 17)
+const FORK_UNITS_PER_REQUEST: u64 = 17;
+/// Throttling messages from providers that don't use a status or error code for it
+const THROTTLE_MESSAGES: &[&str] = &[
+    "rate limit",
+    "too many requests",
+    "request limit reached",
+    "compute units per second capacity",
+    "exceeded its throughput",
+];
+
+static SETTINGS: OnceLock<RpcConfig> = OnceLock::new();
+/// When each provider host may next be sent a request
+static NEXT_SLOT: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+/// Use the config's retry and budget settings for every RPC request; until
+/// then the defaults apply
+pub fn configure_rpc_client(config: &RpcConfig) {
+    if SETTINGS.set(config.clone()).is_err() {
+        warn!("The RPC client was already configured");
+    }
+}
+
+fn settings() -> &'static RpcConfig {
+    SETTINGS.get_or_init(RpcConfig::default)
+}
+
+/// A provider whose requests are paced per provider and retried when throttled
+pub type RpcProvider = Provider<ThrottledHttp>;
+
+/// Make a provider for an RPC URL. Every outbound RPC call goes through one of
+/// these, so a throttled provider slows all jobs down rather than failing them.
+pub fn rpc_provider(rpc_url: &str) -> Result<RpcProvider> {
+    Ok(Provider::new(ThrottledHttp::new(Url::parse(rpc_url)?)))
+}
+
+/// Arguments forking `forge` from `rpc_url` with the same retries and
+/// per-provider budget
+pub fn fork_args(rpc_url: &str) -> Vec<String> {
+    let settings = settings();
+    let requests_per_second = Url::parse(rpc_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| budget(settings, host)))
+        .unwrap_or(settings.requests_per_second);
+    vec![
+        "--fork-url".to_string(),
+        rpc_url.to_string(),
+        "--fork-retries".to_string(),
+        settings.max_retries.to_string(),
+        "--fork-retry-backoff".to_string(),
+        settings.initial_backoff_ms.to_string(),
+        "--compute-units-per-second".to_string(),
+        (requests_per_second as u64 * FORK_UNITS_PER_REQUEST).to_string(),
+    ]
+}
+
+/// Requests per second allowed to a provider host; 0 is unlimited. A local
+/// node, such as the default anvil fork, isn't paced unless it has a budget.
+fn budget(settings: &RpcConfig, host: &str) -> u32 {
+    let local = matches!(host, "localhost" | "127.0.0.1" | "[::1]");
+    match settings.provider_budgets.get(host) {
+        Some(budget) => *budget,
+        None if local => 0,
+        None => settings.requests_per_second,
+    }
+}
+
+/// HTTP transport that keeps to each provider's request budget and retries
+/// requests the provider throttled, with jittered exponential backoff
+#[derive(Debug)]
+pub struct ThrottledHttp {
+    inner: Http,
+    host: String,
+}
+
+impl ThrottledHttp {
+    pub fn new(url: Url) -> Self {
+        let host = url.host_str().unwrap_or_default().to_string();
+        Self { inner: Http::new(url), host }
+    }
+
+    /// Wait for this provider's next free slot in its budget
+    async fn acquire(&self) {
+        let requests_per_second = budget(settings(), &self.host);
+        if requests_per_second == 0 {
+            return;
+        }
+        let interval = Duration::from_secs(1) / requests_per_second;
+        let slot = {
+            let mut next_slot = NEXT_SLOT.lock().unwrap();
+            let next = next_slot.get_or_insert_with(HashMap::new).entry(self.host.clone()).or_insert_with(Instant::now);
+            let slot = (*next).max(Instant::now());
+            *next = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+
+    /// Hold back every request to this provider after it throttled one
+    fn back_off(&self, delay: Duration) {
+        let until = Instant::now() + delay;
+        let mut next_slot = NEXT_SLOT.lock().unwrap();
+        let next = next_slot.get_or_insert_with(HashMap::new).entry(self.host.clone()).or_insert(until);
+        *next = (*next).max(until);
+    }
+}
+
+/// Whether the provider refused a request for going over its rate or budget
+fn is_throttled(error: &HttpClientError) -> bool {
+    if HttpRateLimitRetryPolicy.should_retry(error) {
+        return true;
+    }
+    let message = match error {
+        HttpClientError::JsonRpcError(JsonRpcError { message, .. }) => message,
+        HttpClientError::SerdeJson { text, .. } => text,
+        HttpClientError::ReqwestError(_) => return false,
+    };
+    let message = message.to_ascii_lowercase();
+    THROTTLE_MESSAGES.iter().any(|throttle| message.contains(throttle))
+}
+
+/// Exponential backoff for the given retry, or the provider's own hint, with
+/// the upper half jittered so throttled jobs don't all retry at once
+fn backoff(settings: &RpcConfig, error: &HttpClientError, retry: u32) -> Duration {
+    let delay = HttpRateLimitRetryPolicy.backoff_hint(error).unwrap_or_else(|| {
+        let exponential = settings.initial_backoff_ms.saturating_mul(1 << retry.min(16));
+        Duration::from_millis(exponential.min(settings.max_backoff_ms))
+    });
+    let half = delay / 2;
+    half + half.mul_f64(thread_rng().gen::<f64>())
+}
+
+#[async_trait]
+impl JsonRpcClient for ThrottledHttp {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> std::result::Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        // Requests without params must still be sent without them, as `Http` does
+        // for zero-sized params, so only others are kept as JSON for retries
+        let params = match std::mem::size_of::<T>() {
+            0 => None,
+            _ => Some(serde_json::to_value(params).map_err(|err| HttpClientError::SerdeJson {
+                err,
+                text: String::new(),
+            })?),
+        };
+
+        let settings = settings();
+        let mut retry = 0;
+        loop {
+            self.acquire().await;
+            let result = match &params {
+                Some(params) => self.inner.request(method, params).await,
+                None => self.inner.request(method, ()).await,
+            };
+            let error = match result {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+            if retry >= settings.max_retries || !is_throttled(&error) {
+                return Err(error);
+            }
+
+            let delay = backoff(settings, &error, retry);
+            debug!(host = %self.host, method, retry, delay_ms = delay.as_millis() as u64, "RPC throttled, backing off");
+            self.back_off(delay);
+            retry += 1;
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use crate::models::{SandboxConfig, SandboxMode};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Network access a sandboxed process is granted
+#[derive(Debug, Clone)]
+pub enum NetworkAccess {
+    None,
+    /// Outbound TCP to these ports only. Only landlock enforces this; bwrap and
+    /// nsjail share the whole network, which `allow_unrestricted_ports` has to accept.
+    Ports(Vec<u16>),
+    Full,
+}
+
+impl NetworkAccess {
+    /// Access needed to reach a fork RPC URL
+    pub fn for_rpc(rpc_url: &str) -> Self {
+        match reqwest::Url::parse(rpc_url)
+            .ok()
+            .and_then(|url| url.port_or_known_default())
+        {
+            Some(port) => NetworkAccess::Ports(vec![port]),
+            None => NetworkAccess::Full,
+        }
+    }
+
+    fn is_none(&self) -> bool {
+        matches!(self, NetworkAccess::None)
+    }
+}
+
+/// Build a command for `program` running in `project_path`, wrapped in the
+/// configured sandbox with a restricted environment
+pub fn sandboxed_command(
+    program: &str,
+    project_path: &Path,
+    network: NetworkAccess,
+    config: &SandboxConfig,
+) -> Command {
+    let writable = writable_paths(project_path, config);
+
+    let mut command = match config.mode {
+        SandboxMode::None | SandboxMode::Landlock => Command::new(program),
+        SandboxMode::Bwrap => {
+            let mut command = Command::new("bwrap");
+            command.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]);
+            for path in &writable {
+                command.arg("--bind").arg(path).arg(path);
+            }
+            command.args(["--unshare-pid", "--unshare-ipc", "--unshare-uts", "--die-with-parent"]);
+            if network.is_none() {
+                command.arg("--unshare-net");
+            }
+            command.arg("--chdir").arg(project_path).arg("--").arg(program);
+            command
+        }
+        SandboxMode::Nsjail => {
+            let mut command = Command::new("nsjail");
+            command.args(["--mode", "o", "--quiet", "--bindmount_ro", "/", "--tmpfsmount", "/tmp"]);
+            for path in &writable {
+                command.arg("--bindmount").arg(path);
+            }
+            if !network.is_none() {
+                command.arg("--disable_clone_newnet");
+            }
+            // nsjail clears the environment and applies its own small rlimits
+            // (1 MB files, 32 fds) unless told otherwise; the environment is
+            // already filtered below, and limits come from set_resource_limits
+            command
+                .args(["--keep_env", "--disable_rlimits"])
+                .arg("--cwd")
+                .arg(project_path)
+                .arg("--")
+                .arg(resolve_program(program));
+            command
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    if config.mode == SandboxMode::Landlock {
+        warn_if_landlock_partial();
+        // The ruleset and its path fds are built here: the child of a
+        // multithreaded process mustn't allocate between fork and exec
+        let mut ruleset = landlock_ruleset(&writable, &network)
+            .inspect_err(|e| tracing::warn!("Failed to build the Landlock ruleset: {}", e))
+            .ok();
+        // SAFETY: the closure only makes the prctl and landlock_restrict_self
+        // syscalls and closes the ruleset fd, none of which allocate
+        unsafe {
+            command.pre_exec(move || match ruleset.take() {
+                Some(ruleset) => ruleset
+                    .restrict_self()
+                    .map(drop)
+                    .map_err(|_| std::io::Error::last_os_error()),
+                None => Err(std::io::Error::from_raw_os_error(libc::EPERM)),
+            });
+        }
+    }
+
+    // Own process group so timeouts can kill everything the process spawned
+    #[cfg(unix)]
+    {
+        command.process_group(0);
+        let memory_limit = config.memory_limit_mb.map(|mb| mb * 1024 * 1024);
+        let cpu_limit = config.cpu_time_limit_secs;
+        if memory_limit.is_some() || cpu_limit.is_some() {
+            // SAFETY: setrlimit is async-signal-safe
+            unsafe {
+                command.pre_exec(move || set_resource_limits(memory_limit, cpu_limit));
+            }
+        }
+    }
+
+    if config.restrict_env {
+        command.env_clear();
+        for (key, value) in std::env::vars() {
+            if config.env_allowlist.iter().any(|allowed| env_matches(allowed, &key)) {
+                command.env(key, value);
+            }
+        }
+    }
+
+    command.current_dir(project_path).kill_on_drop(true);
+    command
+}
+
+#[cfg(unix)]
+fn set_resource_limits(memory_bytes: Option<u64>, cpu_secs: Option<u64>) -> std::io::Result<()> {
+    let limit = |resource, value: u64| {
+        let rlimit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        // SAFETY: rlimit is a valid, initialized struct
+        if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    };
+
+    if let Some(bytes) = memory_bytes {
+        limit(libc::RLIMIT_AS, bytes)?;
+    }
+    if let Some(secs) = cpu_secs {
+        limit(libc::RLIMIT_CPU, secs)?;
+    }
+    Ok(())
+}
+
+/// Allowlist entries ending in `*` match by prefix
+fn env_matches(allowed: &str, key: &str) -> bool {
+    match allowed.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => allowed == key,
+    }
+}
+
+/// The session directory, forge/npm caches and any extra configured paths
+fn writable_paths(project_path: &Path, config: &SandboxConfig) -> Vec<PathBuf> {
+    let mut paths = vec![project_path.to_path_buf()];
+    if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+        paths.extend([home.join(".foundry"), home.join(".svm"), home.join(".npm")]);
+    }
+    paths.extend(config.writable_paths.iter().map(PathBuf::from));
+    paths.retain(|path| path.exists());
+    paths
+}
+
+/// nsjail execs its target directly, so it needs an absolute path
+fn resolve_program(program: &str) -> PathBuf {
+    std::env::var_os("PATH")
+        .and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(program))
+                .find(|candidate| candidate.is_file())
+        })
+        .unwrap_or_else(|| PathBuf::from(program))
+}
+
+/// Landlock's rules are applied best effort, so on an older kernel commands
+/// quietly run with fewer restrictions; that's reported once, from the parent
+/// since the restricted child can't log
+#[cfg(target_os = "linux")]
+fn warn_if_landlock_partial() {
+    static CHECKED: std::sync::Once = std::sync::Once::new();
+    CHECKED.call_once(|| {
+        // LANDLOCK_CREATE_RULESET_VERSION asks for the kernel's ABI version
+        // instead of creating a ruleset; it fails without Landlock
+        // SAFETY: with a null attribute pointer and zero size the kernel reads nothing
+        let abi = unsafe { libc::syscall(libc::SYS_landlock_create_ruleset, std::ptr::null::<libc::c_void>(), 0usize, 1u32) };
+        if abi < 1 {
+            tracing::warn!("Landlock isn't available on this kernel; sandboxed commands run without filesystem or network restrictions");
+        } else if abi < 4 {
+            tracing::warn!(
+                "The kernel's Landlock ABI is {}, older than the 4 the sandbox needs; filesystem rules are only partly enforced and network ports aren't restricted",
+                abi
+            );
+        }
+    });
+}
+
+/// The Landlock ruleset for a command, ready for the child to restrict itself with
+#[cfg(target_os = "linux")]
+fn landlock_ruleset(writable: &[PathBuf], network: &NetworkAccess) -> std::io::Result<landlock::RulesetCreated> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, AccessNet, NetPort, Ruleset, RulesetAttr,
+        RulesetCreatedAttr, ABI,
+    };
+
+    let abi = ABI::V4;
+    let to_io = std::io::Error::other;
+
+    let mut ruleset = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .map_err(to_io)?;
+    if !matches!(network, NetworkAccess::Full) {
+        ruleset = ruleset.handle_access(AccessNet::ConnectTcp).map_err(to_io)?;
+    }
+
+    let mut created = ruleset
+        .create()
+        .map_err(to_io)?
+        .add_rules(path_beneath_rules(["/"], AccessFs::from_read(abi)))
+        .map_err(to_io)?
+        .add_rules(path_beneath_rules(["/tmp", "/dev"], AccessFs::from_all(abi)))
+        .map_err(to_io)?
+        .add_rules(path_beneath_rules(writable, AccessFs::from_all(abi)))
+        .map_err(to_io)?;
+
+    if let NetworkAccess::Ports(ports) = network {
+        for port in ports {
+            created = created
+                .add_rule(NetPort::new(*port, AccessNet::ConnectTcp))
+                .map_err(to_io)?;
+        }
+    }
+
+    Ok(created)
+}
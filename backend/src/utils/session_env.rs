@@ -0,0 +1,49 @@
+use super::redact::{forget_secret, register_secret};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variables requests set for their session's forge runs, such as
+/// a private key to broadcast with. They're only ever held in memory, never
+/// written into the session directory, and their values are redacted from
+/// logs and recorded events.
+#[derive(Default)]
+pub struct SessionEnv {
+    vars: Mutex<HashMap<PathBuf, Vec<(String, String)>>>,
+}
+
+impl SessionEnv {
+    /// Replace a session's variables
+    pub fn set(&self, session: &Path, vars: Vec<(String, String)>) {
+        for (_, value) in &vars {
+            register_secret(value);
+        }
+        let previous = self.vars.lock().unwrap().insert(session.to_path_buf(), vars);
+        forget_all(previous);
+    }
+
+    /// The variables a session's forge runs get, empty when it has none
+    pub fn get(&self, session: &Path) -> Vec<(String, String)> {
+        self.vars.lock().unwrap().get(session).cloned().unwrap_or_default()
+    }
+
+    /// Give `to` the same variables as `from`, for the parts of a parallel job
+    pub fn share(&self, from: &Path, to: &Path) {
+        let vars = self.get(from);
+        if !vars.is_empty() {
+            self.set(to, vars);
+        }
+    }
+
+    /// Drop a session's variables once it's gone
+    pub fn remove(&self, session: &Path) {
+        let previous = self.vars.lock().unwrap().remove(session);
+        forget_all(previous);
+    }
+}
+
+fn forget_all(vars: Option<Vec<(String, String)>>) {
+    for (_, value) in vars.into_iter().flatten() {
+        forget_secret(&value);
+    }
+}
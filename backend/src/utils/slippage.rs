@@ -0,0 +1,68 @@
+use super::calls::{calls, field_values, normalize};
+
+/// Struct fields and named arguments that carry a swap's minimum output, lowercased
+const MIN_OUT_FIELDS: &[&str] = &["amountoutminimum", "amountoutmin", "minamountout", "minreturnamount", "minout"];
+
+/// Router calls that take the minimum output positionally, with its argument index
+const MIN_OUT_ARGUMENTS: &[(&str, usize)] = &[
+    ("swapexacttokensfortokens", 1),
+    ("swapexacttokensforeth", 1),
+    ("swapexacttokensfortokenssupportingfeeontransfertokens", 1),
+    ("swapexacttokensforethsupportingfeeontransfertokens", 1),
+    ("swapexactethfortokens", 0),
+    ("swapexactethfortokenssupportingfeeontransfertokens", 0),
+];
+
+/// Where a script sets a swap's minimum output: line number and the expression used
+fn min_out_sites(code: &str) -> Vec<(usize, String)> {
+    let mut sites = MIN_OUT_FIELDS
+        .iter()
+        .flat_map(|field| field_values(code, field))
+        .collect::<Vec<_>>();
+
+    let positional = calls(code, |name| MIN_OUT_ARGUMENTS.iter().any(|(call, _)| *call == name));
+    for (line, name, arguments) in positional {
+        let position = MIN_OUT_ARGUMENTS.iter().find(|(call, _)| *call == name).map(|(_, position)| *position);
+        if let Some(argument) = position.and_then(|position| arguments.into_iter().nth(position)) {
+            sites.push((line, argument));
+        }
+    }
+
+    sites.sort();
+    sites
+}
+
+fn numbers(source: &str) -> Vec<u64> {
+    source
+        .split(|c: char| !c.is_ascii_digit() && c != '_')
+        .filter_map(|word| word.replace('_', "").parse().ok())
+        .collect()
+}
+
+/// Check that every swap in the script bounds its output: no literal zero minimum
+/// and, when the request gave a tolerance, the script applies that tolerance
+/// (as `bps` or `10000 - bps`). Returns one problem per line.
+pub fn check_slippage(source: &str, slippage_bps: Option<u32>) -> Vec<String> {
+    let code = normalize(source);
+    let sites = min_out_sites(&code);
+    let mut problems = sites
+        .iter()
+        .filter(|(_, value)| value == "0" || value == "uint256(0)")
+        .map(|(line, _)| format!("- line {}: swap has no minimum output (zero slippage protection)", line))
+        .collect::<Vec<_>>();
+
+    if let Some(bps) = slippage_bps.filter(|bps| *bps > 0 && !sites.is_empty()) {
+        let bps = bps as u64;
+        let used = numbers(&code);
+        if !used.contains(&bps) && !used.contains(&(10_000 - bps)) {
+            problems.push(format!(
+                "- minimum outputs don't apply the requested {} bps slippage tolerance (expected `{}` or `{}` out of 10000)",
+                bps,
+                bps,
+                10_000 - bps
+            ));
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,252 @@
+use super::solidity::{script_imports, solidity_pragma};
+use semver::{Version, VersionReq};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+const SCRIPT: &str = "script/Script.s.sol";
+
+/// Directories of the project forge compiles sources from
+const SOURCE_DIRS: [&str; 3] = ["src", "script", "test"];
+
+/// How long to wait for svm's list of solc releases
+const RELEASE_LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// svm's list of solc releases, newest first, fetched once
+static RELEASES: OnceCell<Vec<Version>> = OnceCell::const_new();
+
+/// A file's `pragma solidity` and the versions it accepts: any one of
+/// `||`-separated alternatives
+struct Pragma {
+    file: String,
+    pragma: String,
+    alternatives: Vec<VersionReq>,
+}
+
+impl Pragma {
+    fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|req| req.matches(version))
+    }
+}
+
+/// Pick the solc version to compile the project with: the newest one accepted by
+/// the pragmas of every source forge compiles and everything they import,
+/// preferring compilers that are already installed. `Ok(None)` when nothing
+/// declares a pragma, or when no installed compiler fits and svm's release list
+/// can't be fetched, leaving forge to choose. When no version satisfies all of
+/// them the error names each conflicting pragma, ready for the fix loop.
+pub async fn select_solc_version(project_path: &Path) -> Result<Option<Version>, String> {
+    let pragmas = collect_pragmas(project_path);
+    if pragmas.is_empty() {
+        return Ok(None);
+    }
+
+    let accepts = |version: &Version| pragmas.iter().all(|pragma| pragma.matches(version));
+    let mut installed = installed_versions();
+    installed.sort_unstable_by(|a, b| b.cmp(a));
+    if let Some(version) = installed.into_iter().find(|version| accepts(version)) {
+        return Ok(Some(version));
+    }
+    let releases = releases().await;
+    if releases.is_empty() {
+        return Ok(None);
+    }
+    if let Some(version) = releases.iter().find(|version| accepts(version)) {
+        return Ok(Some(version.clone()));
+    }
+
+    // Only the pragmas at odds with the script's (the first) are worth naming;
+    // when the script's own can't be met, name everything
+    let script = &pragmas[0];
+    let script_versions = releases
+        .iter()
+        .filter(|version| script.matches(version))
+        .collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let conflicts = pragmas
+        .iter()
+        .skip(1)
+        .filter(|pragma| !script_versions.iter().any(|version| pragma.matches(version)))
+        .filter(|pragma| seen.insert(pragma.pragma.clone()))
+        .map(|pragma| format!("  {}: pragma solidity {};", pragma.file, pragma.pragma))
+        .collect::<Vec<_>>();
+    let conflicts = if conflicts.is_empty() {
+        pragmas
+            .iter()
+            .filter(|pragma| seen.insert(pragma.pragma.clone()))
+            .map(|pragma| format!("  {}: pragma solidity {};", pragma.file, pragma.pragma))
+            .collect()
+    } else {
+        conflicts
+    };
+    Err(format!(
+        "Incompatible Solidity pragmas: no solc version satisfies `pragma solidity {};` in {} together with:\n{}\nChange the script's pragma, or import versions of these files that accept the same compiler.",
+        script.pragma,
+        script.file,
+        conflicts.join("\n")
+    ))
+}
+
+/// Every solc release svm lists for this platform, newest first. Empty while
+/// the list can't be fetched; a later call tries again.
+async fn releases() -> Vec<Version> {
+    let fetched = RELEASES
+        .get_or_try_init(|| async {
+            let mut releases = tokio::time::timeout(RELEASE_LIST_TIMEOUT, svm::all_versions())
+                .await
+                .map_err(|_| "timed out".to_string())?
+                .map_err(|e| e.to_string())?;
+            releases.sort_unstable_by(|a, b| b.cmp(a));
+            Ok::<_, String>(releases)
+        })
+        .await;
+    match fetched {
+        Ok(releases) => releases.clone(),
+        Err(e) => {
+            warn!("Failed to fetch the solc release list: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Pragmas of the script, first, then of every other source forge compiles and
+/// every file they transitively import. Files that can't be found or parsed are
+/// skipped; forge reports those itself.
+fn collect_pragmas(project_path: &Path) -> Vec<Pragma> {
+    let remappings = read_remappings(project_path);
+    let mut pending = SOURCE_DIRS
+        .iter()
+        .flat_map(|dir| solidity_files(&project_path.join(dir)))
+        .collect::<Vec<_>>();
+    // Popped first, so the script's pragma leads
+    pending.push(project_path.join(SCRIPT));
+    let mut visited = HashSet::new();
+    let mut pragmas = Vec::new();
+
+    while let Some(path) = pending.pop() {
+        let Ok(canonical) = path.canonicalize() else {
+            continue;
+        };
+        if !visited.insert(canonical) {
+            continue;
+        }
+        let Ok(source) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let Ok((unit, _comments)) = solang_parser::parse(&source, 0) else {
+            continue;
+        };
+
+        if let Some(pragma) = solidity_pragma(&unit) {
+            let file = path.strip_prefix(project_path).unwrap_or(&path).display().to_string();
+            let alternatives = parse_pragma(&pragma);
+            if !alternatives.is_empty() {
+                pragmas.push(Pragma { file, pragma, alternatives });
+            }
+        }
+        for import in script_imports(&unit) {
+            pending.push(resolve_import(project_path, &path, &import, &remappings));
+        }
+    }
+
+    pragmas
+}
+
+/// `.sol` files under `dir`, recursively
+fn solidity_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                solidity_files(&path)
+            } else if path.extension().is_some_and(|extension| extension == "sol") {
+                vec![path]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Convert a Solidity version pragma into semver requirements. Solidity
+/// separates comparators with spaces rather than commas and reads a bare
+/// version as exact where semver reads it as a caret.
+fn parse_pragma(pragma: &str) -> Vec<VersionReq> {
+    pragma
+        .split("||")
+        .filter_map(|alternative| {
+            let tokens = alternative.split_whitespace().collect::<Vec<_>>();
+            let mut comparators = Vec::new();
+            let mut i = 0;
+            while i < tokens.len() {
+                let token = tokens[i];
+                if tokens.get(i + 1) == Some(&"-") {
+                    // Hyphen range: `0.8.0 - 0.8.10`
+                    let upper = tokens.get(i + 2)?;
+                    comparators.push(format!(">={}", token));
+                    comparators.push(format!("<={}", upper));
+                    i += 3;
+                } else if token.chars().all(|c| "<>=^~".contains(c)) {
+                    // Operator written apart from its version: `>= 0.8.0`
+                    comparators.push(format!("{}{}", token, tokens.get(i + 1)?));
+                    i += 2;
+                } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+                    comparators.push(format!("={}", token));
+                    i += 1;
+                } else {
+                    comparators.push(token.to_string());
+                    i += 1;
+                }
+            }
+            VersionReq::parse(&comparators.join(", ")).ok()
+        })
+        .collect()
+}
+
+/// Where forge finds an import: relative to the importing file, through the
+/// project's remappings (longest prefix wins) or from the project root
+fn resolve_import(project_path: &Path, importer: &Path, import: &str, remappings: &[(String, String)]) -> PathBuf {
+    if import.starts_with("./") || import.starts_with("../") {
+        return importer.parent().unwrap_or(project_path).join(import);
+    }
+    remappings
+        .iter()
+        .filter(|(prefix, _)| import.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, target)| project_path.join(format!("{}{}", target, &import[prefix.len()..])))
+        .unwrap_or_else(|| project_path.join(import))
+}
+
+/// `prefix=target` lines of the project's remappings.txt, without any context
+fn read_remappings(project_path: &Path) -> Vec<(String, String)> {
+    fs::read_to_string(project_path.join("remappings.txt"))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (prefix, target) = line.trim().split_once('=')?;
+            let prefix = prefix.rsplit_once(':').map_or(prefix, |(_, prefix)| prefix);
+            Some((prefix.to_string(), target.to_string()))
+        })
+        .collect()
+}
+
+/// solc versions svm has installed, which forge uses without downloading
+fn installed_versions() -> Vec<Version> {
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local/share"));
+    [home.join(".svm"), data_dir.join("svm")]
+        .iter()
+        .flat_map(|dir| fs::read_dir(dir).into_iter().flatten())
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Version::parse(entry.file_name().to_str()?).ok())
+        .collect()
+}
@@ -0,0 +1,95 @@
+use solang_parser::pt::{ContractPart, ContractTy, FunctionTy, Loc, SourceUnit, SourceUnitPart};
+
+/// Parse generated script source and check it has the shape forge expects: a
+/// contract inheriting `Script` with a `run()` function. Returns a diagnostic
+/// suitable for feeding straight into the fix loop.
+pub fn check_script(source: &str) -> Result<SourceUnit, String> {
+    let (unit, _comments) = solang_parser::parse(source, 0).map_err(|diagnostics| {
+        let errors = diagnostics
+            .iter()
+            .map(|diagnostic| format!("{}: {}", position(source, &diagnostic.loc), diagnostic.message))
+            .collect::<Vec<_>>();
+        format!("Solidity parse error:\n{}", errors.join("\n"))
+    })?;
+
+    let scripts = unit
+        .0
+        .iter()
+        .filter_map(|part| match part {
+            SourceUnitPart::ContractDefinition(contract)
+                if matches!(contract.ty, ContractTy::Contract(_)) =>
+            {
+                Some(contract)
+            }
+            _ => None,
+        })
+        .filter(|contract| {
+            contract.base.iter().any(|base| {
+                base.name
+                    .identifiers
+                    .last()
+                    .is_some_and(|identifier| identifier.name == "Script")
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let Some(script) = scripts.first() else {
+        return Err("Structure error: no contract inherits from `Script` (forge-std/Script.sol)".to_string());
+    };
+
+    let has_run = script.parts.iter().any(|part| match part {
+        ContractPart::FunctionDefinition(function) => {
+            function.ty == FunctionTy::Function
+                && function.name.as_ref().is_some_and(|name| name.name == "run")
+        }
+        _ => false,
+    });
+
+    if !has_run {
+        let name = script
+            .name
+            .as_ref()
+            .map(|name| name.name.as_str())
+            .unwrap_or("Script");
+        return Err(format!(
+            "Structure error: contract `{}` at {} has no `run()` function",
+            name,
+            position(source, &script.loc)
+        ));
+    }
+
+    Ok(unit)
+}
+
+/// Paths of every file the script imports
+pub fn script_imports(unit: &SourceUnit) -> Vec<String> {
+    unit.0
+        .iter()
+        .filter_map(|part| match part {
+            SourceUnitPart::ImportDirective(import) => import.literal().map(|path| path.string.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Version requirement of the file's `pragma solidity`, as written
+pub fn solidity_pragma(unit: &SourceUnit) -> Option<String> {
+    unit.0.iter().find_map(|part| match part {
+        SourceUnitPart::PragmaDirective(_, Some(name), Some(value)) if name.name == "solidity" => {
+            Some(value.string.trim().to_string())
+        }
+        _ => None,
+    })
+}
+
+/// `line:column` (1-based) of a parser location
+fn position(source: &str, loc: &Loc) -> String {
+    let Loc::File(_, offset, _) = loc else {
+        return "unknown position".to_string();
+    };
+
+    let before = source.get(..*offset).unwrap_or(source);
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    format!("Script.s.sol:{}:{}", line, column)
+}
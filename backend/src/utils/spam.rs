@@ -0,0 +1,73 @@
+use super::prices::token_usd_prices;
+use super::tokens::TokenHolding;
+use super::token_list::TokenRegistry;
+use crate::models::{PortfolioConfig, PricesConfig};
+use ethers::types::Address;
+use tracing::{debug, warn};
+
+/// Phrases airdropped scam tokens put in their name or symbol to lure holders to a site
+const LURE_MARKERS: &[&str] = &["http", "www.", ".com", ".io", ".org", ".xyz", "claim", "visit", "reward", "airdrop", "voucher"];
+
+/// Why a token's metadata marks it as spam, if it does
+fn metadata_red_flag(holding: &TokenHolding) -> Option<&'static str> {
+    let (Some(name), Some(symbol)) = (holding.name.as_deref(), holding.symbol.as_deref()) else {
+        return Some("missing name or symbol");
+    };
+    if holding.decimals.is_none() {
+        return Some("missing decimals");
+    }
+
+    let text = format!("{} {}", name, symbol).to_ascii_lowercase();
+    if LURE_MARKERS.iter().any(|marker| text.contains(marker)) {
+        return Some("links or claim bait in metadata");
+    }
+    if !symbol.is_ascii() || symbol.chars().any(|c| c.is_whitespace()) || symbol.len() > 12 {
+        return Some("unusual symbol");
+    }
+    None
+}
+
+/// Drop tokens that are likely spam or honeypots: blocked addresses, tokens whose
+/// metadata looks like an airdrop lure and, when prices are available, tokens no
+/// market prices (no liquidity). Tokens on the token lists are always kept.
+/// Returns the kept holdings and how many were dropped.
+pub async fn filter_spam(
+    holdings: Vec<TokenHolding>,
+    registry: &TokenRegistry,
+    config: &PortfolioConfig,
+    prices: &PricesConfig,
+) -> (Vec<TokenHolding>, usize) {
+    let blocked: Vec<Address> = config.blocked_tokens.iter().filter_map(|token| token.parse().ok()).collect();
+    let total = holdings.len();
+
+    let mut listed = Vec::new();
+    let mut unlisted = Vec::new();
+    for holding in holdings {
+        let Ok(address) = holding.contract_address.parse::<Address>() else {
+            continue;
+        };
+        if blocked.contains(&address) {
+            debug!(token = holding.contract_address, "Dropping blocked token");
+        } else if registry.contains(holding.chain_id, address) {
+            listed.push(holding);
+        } else if let Some(reason) = metadata_red_flag(&holding) {
+            debug!(token = holding.contract_address, reason, "Dropping likely spam token");
+        } else {
+            unlisted.push((address, holding));
+        }
+    }
+
+    if config.require_price && prices.coingecko_url.is_some() && !unlisted.is_empty() {
+        let addresses = unlisted.iter().map(|(address, _)| *address).collect::<Vec<_>>();
+        match token_usd_prices(prices, unlisted[0].1.chain_id, &addresses).await {
+            Ok(priced) => unlisted.retain(|(address, _)| priced.contains_key(address)),
+            Err(e) => warn!("Failed to price tokens for spam filtering: {}", e),
+        }
+    }
+
+    // Listed tokens first so they survive the prompt's holding cap
+    let mut kept = listed;
+    kept.extend(unlisted.into_iter().map(|(_, holding)| holding));
+    let dropped = total - kept.len();
+    (kept, dropped)
+}
@@ -0,0 +1,157 @@
+use super::feedback::FeedbackStore;
+use super::metrics::METRICS;
+use crate::models::{JobOrigin, LlmCallStats, OutcomeStats, ProtocolFeedback, Stats, StatsConfig};
+use prometheus::core::Collector;
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// What a job that used no guidelines is counted under
+const NO_PROTOCOL: &str = "none";
+
+#[derive(Default)]
+struct Counts {
+    jobs: u64,
+    succeeded: u64,
+    fix_iterations: u64,
+}
+
+/// Per-protocol and per-variant job outcomes since startup, combined on request
+/// with LLM latency from the metrics registry and on-chain feedback
+#[derive(Default)]
+pub struct JobStats {
+    by_protocol: Mutex<HashMap<String, Counts>>,
+    by_variant: Mutex<HashMap<String, Counts>>,
+    /// Sessions already counted as succeeded, so later fixes don't count them again
+    succeeded: Mutex<HashSet<String>>,
+}
+
+/// The protocols a job is counted under
+pub(super) fn keys(protocols: &[String]) -> Vec<String> {
+    if protocols.is_empty() {
+        vec![NO_PROTOCOL.to_string()]
+    } else {
+        protocols.to_vec()
+    }
+}
+
+impl JobStats {
+    fn update(&self, origin: &JobOrigin, update: impl Fn(&mut Counts)) {
+        let mut by_protocol = self.by_protocol.lock().unwrap();
+        for protocol in keys(&origin.protocols) {
+            update(by_protocol.entry(protocol).or_default());
+        }
+        if let Some(variant) = &origin.variant {
+            update(self.by_variant.lock().unwrap().entry(variant.clone()).or_default());
+        }
+    }
+
+    pub fn job_started(&self, origin: &JobOrigin) {
+        self.update(origin, |counts| counts.jobs += 1);
+    }
+
+    pub fn fix_iteration(&self, origin: &JobOrigin) {
+        self.update(origin, |counts| counts.fix_iterations += 1);
+    }
+
+    /// A session's job produced a result, first time or after fixes
+    pub fn job_succeeded(&self, session: &str, origin: &JobOrigin) {
+        if self.succeeded.lock().unwrap().insert(session.to_string()) {
+            self.update(origin, |counts| counts.succeeded += 1);
+        }
+    }
+
+    /// Stop tracking a deleted session; its counts stay in the totals
+    pub fn forget_session(&self, session: &str) {
+        self.succeeded.lock().unwrap().remove(session);
+    }
+
+    pub fn snapshot(&self, feedback: &FeedbackStore, experiment: Option<&str>) -> Stats {
+        Stats {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            protocols: outcomes(&self.by_protocol.lock().unwrap(), feedback.by_protocol()),
+            experiment: experiment.map(str::to_string),
+            variants: outcomes(&self.by_variant.lock().unwrap(), feedback.by_variant()),
+            llm: llm_latency(),
+            flagged_protocols: feedback.flagged(),
+        }
+    }
+}
+
+fn outcomes(counts: &HashMap<String, Counts>, feedback: HashMap<String, ProtocolFeedback>) -> HashMap<String, OutcomeStats> {
+    let names = counts.keys().chain(feedback.keys()).cloned().collect::<HashSet<_>>();
+    names
+        .into_iter()
+        .map(|name| {
+            let (jobs, succeeded, fix_iterations) = counts
+                .get(&name)
+                .map_or((0, 0, 0), |counts| (counts.jobs, counts.succeeded, counts.fix_iterations));
+            let ratio = |part: u64| (jobs > 0).then(|| part as f64 / jobs as f64);
+            let stats = OutcomeStats {
+                jobs,
+                succeeded,
+                success_rate: ratio(succeeded),
+                avg_fix_iterations: ratio(fix_iterations),
+                onchain: feedback.get(&name).cloned().unwrap_or_default(),
+            };
+            (name, stats)
+        })
+        .collect()
+}
+
+/// Call counts and mean latency per LLM call type, from the `llm_duration` histogram
+fn llm_latency() -> HashMap<String, LlmCallStats> {
+    let mut calls = HashMap::new();
+    for family in METRICS.llm_duration.collect() {
+        for metric in family.get_metric() {
+            let Some(call) = metric.get_label().iter().find(|label| label.get_name() == "call") else {
+                continue;
+            };
+            let histogram = metric.get_histogram();
+            let count = histogram.get_sample_count();
+            calls.insert(
+                call.get_value().to_string(),
+                LlmCallStats {
+                    calls: count,
+                    avg_secs: (count > 0).then(|| histogram.get_sample_sum() / count as f64),
+                },
+            );
+        }
+    }
+    calls
+}
+
+/// Append a snapshot to the export file every interval until shutdown
+pub async fn export_stats(
+    config: StatsConfig,
+    snapshot: impl Fn() -> Stats,
+    shutdown: CancellationToken,
+) {
+    let Some(path) = config.export_path else {
+        return;
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(config.export_interval_secs.max(1)));
+    // The first tick is immediate; there's nothing to export yet
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+        let appended = serde_json::to_string(&snapshot()).map_err(|e| e.to_string()).and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+        if let Err(e) = appended {
+            warn!("Failed to export stats: {}", e);
+        }
+    }
+}
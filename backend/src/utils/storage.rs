@@ -0,0 +1,74 @@
+use super::metrics::METRICS;
+use crate::models::StorageConfig;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const BYTES_PER_MB: u64 = 1024 * 1024;
+
+/// Bytes a session directory takes up on its own. Files hardlinked with the base
+/// project (most of `lib/`) are shared, so removing the session wouldn't free them.
+pub fn session_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if metadata.is_dir() {
+        return fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| session_size(&entry.path()))
+            .sum();
+    }
+    if !metadata.is_file() || is_shared(&metadata) {
+        return 0;
+    }
+    metadata.len()
+}
+
+#[cfg(unix)]
+fn is_shared(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn is_shared(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Disk usage of session directories as of the last scan, against the quota
+pub struct StorageQuota {
+    quota_bytes: Option<u64>,
+    used: AtomicU64,
+}
+
+impl StorageQuota {
+    pub fn new(config: &StorageConfig) -> Self {
+        Self {
+            quota_bytes: config.quota_mb.map(|mb| mb * BYTES_PER_MB),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_used(&self, bytes: u64) {
+        self.used.store(bytes, Ordering::Relaxed);
+        METRICS.session_storage_bytes.set(bytes as i64);
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Whether `bytes` of usage is over the quota
+    pub fn exceeds(&self, bytes: u64) -> bool {
+        self.quota_bytes.is_some_and(|quota| bytes > quota)
+    }
+
+    /// Usage and quota in MB, while usage is over the quota
+    pub fn exceeded(&self) -> Option<(u64, u64)> {
+        let quota = self.quota_bytes?;
+        let used = self.used();
+        (used > quota).then_some((used / BYTES_PER_MB, quota / BYTES_PER_MB))
+    }
+}
@@ -0,0 +1,108 @@
+use super::redact::{redact, redact_url};
+use crate::models::TracingConfig;
+use axum::http::{HeaderMap, Request};
+use eyre::Result;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::global;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use std::io::{self, Write};
+use tracing::{Level, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Install the global tracing subscriber, exporting spans over OTLP when an
+/// endpoint is configured. The returned provider must be shut down on exit to
+/// flush pending spans.
+pub fn init_tracing(config: &TracingConfig) -> Result<Option<SdkTracerProvider>> {
+    let filter = tracing_subscriber::EnvFilter::new(
+        std::env::var("RUST_LOG")
+            .unwrap_or_else(|_| "backend=debug,tower_http=debug".into()),
+    );
+
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?;
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        Resource::builder()
+                            .with_service_name(config.service_name.clone())
+                            .build(),
+                    )
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    let otel_layer = provider.as_ref().map(|provider| {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("ff-backend"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(|| RedactedStdout))
+        .with(otel_layer)
+        .init();
+
+    Ok(provider)
+}
+
+/// Stdout for log lines, with secrets redacted. The formatter writes each event
+/// in one call, so a secret is never split across writes.
+struct RedactedStdout;
+
+impl Write for RedactedStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Request span that continues the caller's trace if a `traceparent` header is present
+pub fn make_request_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::span!(
+        Level::INFO,
+        "request",
+        method = %request.method(),
+        uri = %redact_url(&request.uri().to_string()),
+        request_id = request
+            .headers()
+            .get("x-request-id")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default(),
+        version = ?request.version(),
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent);
+
+    span
+}
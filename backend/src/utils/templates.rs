@@ -0,0 +1,241 @@
+use crate::models::TemplatesConfig;
+use crate::processors::TemplatePattern;
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Intents that differ only in amounts and addresses share a shape
+const PLACEHOLDER: &str = "{}";
+
+fn marker(index: usize) -> String {
+    format!("__TEMPLATE_PARAM_{}__", index)
+}
+
+fn is_address(token: &str) -> bool {
+    token.len() == 42 && token.starts_with("0x") && token[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_amount(token: &str) -> bool {
+    token.parse::<f64>().is_ok_and(f64::is_finite) && token.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+/// An intent's shape and the amounts and addresses it fills in, after the sender
+fn parameterize(intent: &str, sender: &str) -> (String, Vec<String>) {
+    let mut params = vec![sender.to_string()];
+    let shape = intent
+        .split_whitespace()
+        .map(|token| {
+            let value = token.trim_end_matches([',', '.', ';', ':', '!', '?']);
+            if is_address(value) || is_amount(value) {
+                params.push(value.to_string());
+                token.replacen(value, PLACEHOLDER, 1)
+            } else {
+                token.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    (shape, params)
+}
+
+/// Byte offsets where `value` appears in `script` as a whole literal, ignoring
+/// case for addresses
+fn literal_positions(script: &str, value: &str) -> Vec<usize> {
+    let (haystack, needle) = if is_address(value) {
+        (script.to_ascii_lowercase(), value.to_ascii_lowercase())
+    } else {
+        (script.to_string(), value.to_string())
+    };
+    let part_of_literal = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.';
+    haystack
+        .match_indices(&needle)
+        .map(|(start, _)| start)
+        .filter(|start| {
+            let before = haystack[..*start].chars().next_back();
+            let after = haystack[start + needle.len()..].chars().next();
+            !before.is_some_and(part_of_literal) && !after.is_some_and(part_of_literal)
+        })
+        .collect()
+}
+
+/// `script` with the parameters' literals replaced by markers, by position: a
+/// value the intent gives once takes all its occurrences, and one it repeats must
+/// occur as often in the script, matched in order. `None` when an amount or
+/// address of the intent can't be placed that way; the sender may be absent.
+fn templatize(script: &str, params: &[String]) -> Option<(String, Vec<usize>)> {
+    let mut replacements = Vec::new();
+    let mut parameter_order = Vec::new();
+    let mut placed = vec![false; params.len()];
+    for (index, value) in params.iter().enumerate() {
+        if placed[index] {
+            continue;
+        }
+        let mut group = (index..params.len())
+            .filter(|other| params[*other].eq_ignore_ascii_case(value))
+            .collect::<Vec<_>>();
+        for other in &group {
+            placed[*other] = true;
+        }
+        // An intent naming the sender's own address takes its occurrences
+        if index == 0 && group.len() > 1 {
+            group.remove(0);
+        }
+
+        let positions = literal_positions(script, value);
+        if positions.is_empty() && group == [0] {
+            continue;
+        }
+        let slots = group
+            .iter()
+            .map(|param| {
+                parameter_order.push(*param);
+                parameter_order.len() - 1
+            })
+            .collect::<Vec<_>>();
+        if positions.is_empty() || (slots.len() > 1 && slots.len() != positions.len()) {
+            return None;
+        }
+        for (occurrence, start) in positions.into_iter().enumerate() {
+            let slot = slots[occurrence.min(slots.len() - 1)];
+            replacements.push((start, value.len(), slot));
+        }
+    }
+
+    let mut template = script.to_string();
+    replacements.sort_unstable_by_key(|(start, _, _)| std::cmp::Reverse(*start));
+    for (start, len, slot) in replacements {
+        template.replace_range(start..start + len, &marker(slot));
+    }
+    Some((template, parameter_order))
+}
+
+/// Solidity for a parameter; addresses must be checksummed to compile
+fn render(value: &str) -> String {
+    match value.parse::<Address>() {
+        Ok(address) if is_address(value) => to_checksum(&address, None),
+        _ => value.to_string(),
+    }
+}
+
+/// What a template is looked up and recorded by
+pub struct TemplateKey<'a> {
+    pub intent: &'a str,
+    pub sender: &'a str,
+    /// Who asked for the job; templates aren't shared between users
+    pub requested_by: Option<Address>,
+    pub chain_id: u64,
+    pub slippage_bps: Option<u32>,
+}
+
+impl TemplateKey<'_> {
+    /// The stored pattern's key, and the intent's parameters after the sender
+    fn parameterize(&self) -> (String, Vec<String>) {
+        let (shape, params) = parameterize(self.intent, self.sender);
+        let owner = match self.requested_by {
+            Some(address) => format!("{:?}", address),
+            None => self.sender.to_ascii_lowercase(),
+        };
+        let slippage = self.slippage_bps.map(|bps| bps.to_string()).unwrap_or_default();
+        (format!("{}:{}:{}:{}", owner, self.chain_id, slippage, shape), params)
+    }
+}
+
+/// Successful scripts kept as templates per owner, intent shape, chain and
+/// slippage, so an intent matching one that keeps working is scripted without
+/// the LLM
+pub struct TemplateStore {
+    path: PathBuf,
+    min_frequency: u64,
+    min_success_rate: f64,
+    patterns: Mutex<HashMap<String, TemplatePattern>>,
+}
+
+impl TemplateStore {
+    pub fn load(config: &TemplatesConfig) -> Self {
+        let path = PathBuf::from(&config.path);
+        let patterns = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable template store {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path,
+            min_frequency: config.min_frequency,
+            min_success_rate: config.min_success_rate,
+            patterns: Mutex::new(patterns),
+        }
+    }
+
+    fn save(&self, patterns: &HashMap<String, TemplatePattern>) {
+        let written = serde_json::to_string_pretty(patterns)
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&self.path, json).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            warn!("Failed to save template store: {}", e);
+        }
+    }
+
+    /// A script for the key's intent from a template that has run often enough
+    /// and rarely enough failed, and the protocols it was generated for
+    pub fn instantiate(&self, key: &TemplateKey<'_>) -> Option<(String, Vec<String>)> {
+        let (key, params) = key.parameterize();
+        let patterns = self.patterns.lock().unwrap();
+        let pattern = patterns.get(&key)?;
+        if pattern.frequency < self.min_frequency || pattern.success_rate < self.min_success_rate {
+            return None;
+        }
+
+        let mut script = pattern.template.clone();
+        for (placeholder, index) in pattern.parameter_order.iter().enumerate() {
+            script = script.replace(&marker(placeholder), &render(params.get(*index)?));
+        }
+        Some((script, pattern.protocols.clone()))
+    }
+
+    /// Record how a run of the key's intent went. A successful generated `script`
+    /// becomes the shape's template, with the protocols it was generated for;
+    /// runs of an instantiated template pass `None`.
+    pub fn record(&self, key: &TemplateKey<'_>, script: Option<(&str, &[String])>, succeeded: bool) {
+        let (key, params) = key.parameterize();
+        let template = script
+            .filter(|_| succeeded)
+            .and_then(|(script, protocols)| Some((templatize(script, &params)?, protocols.to_vec())));
+
+        let mut patterns = self.patterns.lock().unwrap();
+        match patterns.get_mut(&key) {
+            Some(pattern) => {
+                let successes = pattern.success_rate * pattern.frequency as f64 + if succeeded { 1.0 } else { 0.0 };
+                pattern.frequency += 1;
+                pattern.success_rate = successes / pattern.frequency as f64;
+                if let Some(((template, parameter_order), protocols)) = template {
+                    pattern.template = template;
+                    pattern.parameter_order = parameter_order;
+                    pattern.protocols = protocols;
+                }
+            }
+            None => {
+                let Some(((template, parameter_order), protocols)) = template else {
+                    return;
+                };
+                patterns.insert(
+                    key,
+                    TemplatePattern {
+                        template,
+                        parameter_order,
+                        protocols,
+                        frequency: 1,
+                        success_rate: 1.0,
+                    },
+                );
+            }
+        }
+        self.save(&patterns);
+    }
+}
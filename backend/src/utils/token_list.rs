@@ -0,0 +1,107 @@
+use super::http::HTTP_CLIENT;
+use crate::models::TokenListsConfig;
+use ethers::types::Address;
+use eyre::Result;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+/// Common tokens on the chains the server is usually pointed at, so symbols
+/// resolve even when no list can be fetched
+const BUNDLED_LIST: &str = include_str!("../../token_lists/default.tokenlist.json");
+
+/// A list in the Uniswap token list format
+#[derive(Debug, Deserialize)]
+struct TokenList {
+    tokens: Vec<ListedToken>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListedToken {
+    pub chain_id: u64,
+    pub address: Address,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Canonical token addresses by chain, from the bundled list followed by the
+/// configured lists. The first list to name a symbol on a chain wins.
+#[derive(Debug, Default)]
+pub struct TokenRegistry {
+    tokens: Vec<ListedToken>,
+}
+
+impl TokenRegistry {
+    pub async fn load(config: &TokenListsConfig) -> Self {
+        let mut registry = Self::default();
+        match serde_json::from_str::<TokenList>(BUNDLED_LIST) {
+            Ok(list) => registry.extend(list.tokens),
+            Err(e) => warn!("Bundled token list is invalid: {}", e),
+        }
+
+        for url in &config.urls {
+            match fetch_list(url).await {
+                Ok(list) => {
+                    info!(url, tokens = list.tokens.len(), "Loaded token list");
+                    registry.extend(list.tokens);
+                }
+                Err(e) => warn!("Failed to fetch token list {}: {}", url, e),
+            }
+        }
+
+        registry
+    }
+
+    fn extend(&mut self, tokens: Vec<ListedToken>) {
+        for token in tokens {
+            if self.get(token.chain_id, &token.symbol).is_none() {
+                self.tokens.push(token);
+            }
+        }
+    }
+
+    pub fn get(&self, chain_id: u64, symbol: &str) -> Option<&ListedToken> {
+        self.tokens
+            .iter()
+            .find(|token| token.chain_id == chain_id && token.symbol == symbol)
+    }
+
+    pub fn contains(&self, chain_id: u64, address: Address) -> bool {
+        self.tokens
+            .iter()
+            .any(|token| token.chain_id == chain_id && token.address == address)
+    }
+
+    /// Tokens mentioned by symbol in `text`, in first-mention order. Symbols
+    /// match exactly or written in capitals ("STETH"), so everyday words that
+    /// happen to be tickers don't resolve.
+    pub fn resolve(&self, text: &str, chain_id: u64) -> Vec<&ListedToken> {
+        let mut found: Vec<&ListedToken> = Vec::new();
+        for word in text.split(|c: char| !c.is_ascii_alphanumeric() && c != '.') {
+            let word = word.trim_matches('.');
+            if word.is_empty() {
+                continue;
+            }
+            let token = self.get(chain_id, word).or_else(|| {
+                let capitals = word.chars().all(|c| !c.is_ascii_lowercase());
+                capitals
+                    .then(|| {
+                        self.tokens.iter().find(|token| {
+                            token.chain_id == chain_id && token.symbol.eq_ignore_ascii_case(word)
+                        })
+                    })
+                    .flatten()
+            });
+            if let Some(token) = token {
+                if !found.iter().any(|t| t.address == token.address) {
+                    found.push(token);
+                }
+            }
+        }
+        found
+    }
+}
+
+async fn fetch_list(url: &str) -> Result<TokenList> {
+    Ok(HTTP_CLIENT.get(url).send().await?.error_for_status()?.json().await?)
+}
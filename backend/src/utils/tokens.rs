@@ -1,42 +1,339 @@
-use eyre::Result;
-use serde::Deserialize;
+use super::http::HTTP_CLIENT;
+use ethers::types::U256;
+use ethers::utils::format_units;
+use eyre::{eyre, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token metadata lookups in flight at once for one address's balances
+const METADATA_CONCURRENCY: usize = 8;
 
 #[derive(Debug, Deserialize)]
-struct AlchemyResponse {
-    jsonrpc: String,
-    id: i64,
-    result: TokenBalancesResult,
+struct AlchemyResponse<T> {
+    result: Option<T>,
+    error: Option<AlchemyError>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct TokenBalancesResult {
-    address: String,
-    tokenBalances: Vec<TokenBalance>,
-    pageKey: Option<String>,
+struct AlchemyError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenBalancesResult {
+    token_balances: Vec<TokenBalance>,
+    page_key: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct TokenBalance {
-    contractAddress: String,
-    tokenBalance: String,
+    contract_address: String,
+    token_balance: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct TokenMetadata {
+    name: Option<String>,
+    symbol: Option<String>,
+    decimals: Option<u32>,
+}
+
+/// An ERC-20 balance with the token's metadata and a human-readable amount
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenHolding {
+    pub chain_id: u64,
+    pub contract_address: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<u32>,
+    /// Balance in the token's smallest unit, as a decimal string
+    pub raw_balance: String,
+    /// Balance scaled by `decimals`; the raw balance when decimals are unknown
+    pub balance: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NftsResult {
+    owned_nfts: Vec<OwnedNft>,
+    page_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OwnedNft {
+    contract: NftContract,
+    id: NftId,
+    balance: Option<String>,
+    title: Option<String>,
+    contract_metadata: Option<NftContractMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NftContract {
+    address: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NftId {
+    token_id: String,
+    token_metadata: Option<NftTokenMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NftTokenMetadata {
+    token_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NftContractMetadata {
+    name: Option<String>,
+    symbol: Option<String>,
+}
+
+/// An ERC-721 or ERC-1155 token owned by an address
+#[derive(Debug, Clone, Serialize)]
+pub struct NftHolding {
+    pub chain_id: u64,
+    pub contract_address: String,
+    /// Collection name from the contract, e.g. "PudgyPenguins"
+    pub collection: Option<String>,
+    pub symbol: Option<String>,
+    /// Token ID as a decimal string, the way users refer to it ("#1234")
+    pub token_id: String,
+    /// "ERC721" or "ERC1155"
+    pub token_type: Option<String>,
+    pub title: Option<String>,
+    /// Copies held; always 1 for ERC-721
+    pub balance: String,
 }
 
-pub async fn get_token_balances(address: &str, api_key: &str) -> Result<TokenBalancesResult> {
-    let client = reqwest::Client::new();
-    let url = format!("https://eth-mainnet.g.alchemy.com/v2/{}", api_key);
+/// Cached values by lowercased address and chain id, with when they were fetched
+type CacheEntries<T> = Mutex<HashMap<(String, u64), (Instant, T)>>;
 
+/// Recent Alchemy balance and metadata responses, so repeated iterations on an
+/// intent don't refetch the same portfolio
+pub struct TokenCache {
+    ttl: Duration,
+    balances: CacheEntries<Vec<TokenHolding>>,
+    metadata: CacheEntries<TokenMetadata>,
+    nfts: CacheEntries<Vec<NftHolding>>,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            balances: Mutex::new(HashMap::new()),
+            metadata: Mutex::new(HashMap::new()),
+            nfts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached<T: Clone>(&self, entries: &CacheEntries<T>, key: &(String, u64)) -> Option<T> {
+        let mut entries = entries.lock().unwrap();
+        entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+        entries.get(key).map(|(_, value)| value.clone())
+    }
+
+    fn store<T>(entries: &CacheEntries<T>, key: (String, u64), value: T) {
+        entries.lock().unwrap().insert(key, (Instant::now(), value));
+    }
+}
+
+/// Alchemy's RPC host for a chain
+fn alchemy_network(chain_id: u64) -> Result<&'static str> {
+    Ok(match chain_id {
+        1 => "eth-mainnet",
+        11155111 => "eth-sepolia",
+        10 => "opt-mainnet",
+        137 => "polygon-mainnet",
+        8453 => "base-mainnet",
+        42161 => "arb-mainnet",
+        _ => return Err(eyre!("Alchemy is not supported on chain {}", chain_id)),
+    })
+}
+
+async fn alchemy_call<T: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<T> {
     let response = client
-        .post(&url)
+        .post(url)
         .json(&serde_json::json!({
             "jsonrpc": "2.0",
-            "method": "alchemy_getTokenBalances",
-            "params": [address, "erc20"],
+            "method": method,
+            "params": params,
             "id": 42
         }))
         .send()
         .await?
-        .json::<AlchemyResponse>()
+        .json::<AlchemyResponse<T>>()
         .await?;
 
-    Ok(response.result)
-}
\ No newline at end of file
+    match (response.result, response.error) {
+        (Some(result), _) => Ok(result),
+        (None, Some(error)) => Err(eyre!("{} failed: {}", method, error.message)),
+        (None, None) => Err(eyre!("{} returned no result", method)),
+    }
+}
+
+/// Non-zero ERC-20 balances of `address` on `chain_id`, with token metadata
+pub async fn get_token_balances(
+    address: &str,
+    chain_id: u64,
+    api_key: &str,
+    cache: &TokenCache,
+) -> Result<Vec<TokenHolding>> {
+    let key = (address.to_ascii_lowercase(), chain_id);
+    if let Some(holdings) = cache.cached(&cache.balances, &key) {
+        return Ok(holdings);
+    }
+
+    let client = &HTTP_CLIENT;
+    let url = format!("https://{}.g.alchemy.com/v2/{}", alchemy_network(chain_id)?, api_key);
+
+    let mut balances = Vec::new();
+    let mut page_key: Option<String> = None;
+    loop {
+        let mut options = serde_json::json!({});
+        if let Some(key) = &page_key {
+            options["pageKey"] = key.clone().into();
+        }
+        let page: TokenBalancesResult = alchemy_call(
+            client,
+            &url,
+            "alchemy_getTokenBalances",
+            serde_json::json!([address, "erc20", options]),
+        )
+        .await?;
+
+        balances.extend(page.token_balances.into_iter().filter_map(|token| {
+            let raw = U256::from_str_radix(token.token_balance?.trim_start_matches("0x"), 16).ok()?;
+            (!raw.is_zero()).then_some((token.contract_address, raw))
+        }));
+
+        match page.page_key {
+            Some(key) => page_key = Some(key),
+            None => break,
+        }
+    }
+
+    let holdings = balances.into_iter().map(|(contract_address, raw)| {
+        let url = &url;
+        async move {
+            let metadata_key = (contract_address.to_ascii_lowercase(), chain_id);
+            let metadata = match cache.cached(&cache.metadata, &metadata_key) {
+                Some(metadata) => metadata,
+                None => {
+                    let metadata = alchemy_call::<TokenMetadata>(
+                        client,
+                        url,
+                        "alchemy_getTokenMetadata",
+                        serde_json::json!([contract_address]),
+                    )
+                    .await;
+                    match metadata {
+                        Ok(metadata) => {
+                            TokenCache::store(&cache.metadata, metadata_key, metadata.clone());
+                            metadata
+                        }
+                        // A token without metadata is still worth listing by address
+                        Err(_) => TokenMetadata::default(),
+                    }
+                }
+            };
+
+            let balance = metadata
+                .decimals
+                .and_then(|decimals| format_units(raw, decimals).ok())
+                .map(|balance| trim_decimal_zeros(&balance))
+                .unwrap_or_else(|| raw.to_string());
+
+            TokenHolding {
+                chain_id,
+                contract_address,
+                name: metadata.name,
+                symbol: metadata.symbol,
+                decimals: metadata.decimals,
+                raw_balance: raw.to_string(),
+                balance,
+            }
+        }
+    });
+
+    // In the order Alchemy listed the balances, so the summary reads the same each time
+    let holdings = futures::stream::iter(holdings).buffered(METADATA_CONCURRENCY).collect::<Vec<_>>().await;
+    TokenCache::store(&cache.balances, key, holdings.clone());
+    Ok(holdings)
+}
+
+/// NFTs owned by `address` on `chain_id`, via Alchemy's `getNFTs`
+pub async fn get_nft_holdings(address: &str, chain_id: u64, api_key: &str, cache: &TokenCache) -> Result<Vec<NftHolding>> {
+    let key = (address.to_ascii_lowercase(), chain_id);
+    if let Some(holdings) = cache.cached(&cache.nfts, &key) {
+        return Ok(holdings);
+    }
+
+    let client = &HTTP_CLIENT;
+    let url = format!("https://{}.g.alchemy.com/nft/v2/{}/getNFTs", alchemy_network(chain_id)?, api_key);
+
+    let mut holdings = Vec::new();
+    let mut page_key: Option<String> = None;
+    loop {
+        let mut request = client
+            .get(&url)
+            .query(&[("owner", address), ("withMetadata", "true")]);
+        if let Some(key) = &page_key {
+            request = request.query(&[("pageKey", key)]);
+        }
+        let page: NftsResult = request.send().await?.error_for_status()?.json().await?;
+
+        holdings.extend(page.owned_nfts.into_iter().map(|nft| {
+            // Alchemy returns IDs as hex, but collections number their tokens in decimal
+            let token_id = U256::from_str_radix(nft.id.token_id.trim_start_matches("0x"), 16)
+                .map(|id| id.to_string())
+                .unwrap_or(nft.id.token_id);
+            let (collection, symbol) = nft
+                .contract_metadata
+                .map(|metadata| (metadata.name, metadata.symbol))
+                .unwrap_or_default();
+
+            NftHolding {
+                chain_id,
+                contract_address: nft.contract.address,
+                collection,
+                symbol,
+                token_id,
+                token_type: nft.id.token_metadata.and_then(|metadata| metadata.token_type),
+                title: nft.title.filter(|title| !title.is_empty()),
+                balance: nft.balance.unwrap_or_else(|| "1".to_string()),
+            }
+        }));
+
+        match page.page_key {
+            Some(key) => page_key = Some(key),
+            None => break,
+        }
+    }
+
+    TokenCache::store(&cache.nfts, key, holdings.clone());
+    Ok(holdings)
+}
+
+/// `format_units` pads to the full precision; "1.500000" reads better as "1.5"
+fn trim_decimal_zeros(amount: &str) -> String {
+    if !amount.contains('.') {
+        return amount.to_string();
+    }
+    amount.trim_end_matches('0').trim_end_matches('.').to_string()
+}
@@ -0,0 +1,88 @@
+use super::rpc_client::{rpc_provider, RpcProvider};
+use crate::models::TransactionDetails;
+use ethers::providers::Middleware;
+use ethers::types::{transaction::eip2718::TypedTransaction, Address, Bytes, TransactionRequest, U256};
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+
+/// A frame of geth's `callTracer` output
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CallFrame {
+    /// CALL, STATICCALL, DELEGATECALL, CREATE, ...
+    #[serde(default, rename = "type")]
+    pub call_type: String,
+    pub from: Address,
+    pub to: Option<Address>,
+    #[serde(default)]
+    pub input: Bytes,
+    #[serde(default)]
+    pub calls: Vec<CallFrame>,
+    /// Why the call reverted, if it did
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl CallFrame {
+    /// Visit this frame and every nested call, depth first
+    pub fn visit(&self, f: &mut impl FnMut(&CallFrame)) {
+        f(self);
+        for call in &self.calls {
+            call.visit(f);
+        }
+    }
+
+    /// Visit the calls whose effects stuck: a reverted call and everything it
+    /// made are skipped
+    pub fn visit_succeeded(&self, f: &mut impl FnMut(&CallFrame)) {
+        if self.error.is_some() {
+            return;
+        }
+        f(self);
+        for call in &self.calls {
+            call.visit_succeeded(f);
+        }
+    }
+}
+
+/// Trace a simulated transaction against the fork head, without the effects of
+/// the transactions before it unless they were mined first. A call that reverts
+/// still shows the calls it made up to the revert.
+pub async fn trace_transaction(
+    provider: &RpcProvider,
+    owner: Address,
+    transaction: &TransactionDetails,
+) -> Result<CallFrame> {
+    let call = serde_json::json!({
+        "from": owner,
+        // No recipient makes it a deployment
+        "to": (!transaction.is_create()).then_some(&transaction.to),
+        "value": transaction.value,
+        "data": transaction.input_data,
+    });
+    Ok(provider
+        .request("debug_traceCall", (call, "latest", serde_json::json!({ "tracer": "callTracer" })))
+        .await?)
+}
+
+/// `eth_call` a view function on the fork head
+pub async fn view_call(provider: &RpcProvider, to: Address, calldata: Vec<u8>) -> Result<Bytes> {
+    let request: TypedTransaction = TransactionRequest::new().to(to).data(calldata).into();
+    Ok(provider.call(&request, None).await?)
+}
+
+/// Chain id reported by the fork, which keeps the id of the chain it forked
+pub async fn chain_id(rpc_url: &str) -> Result<u64> {
+    let provider = rpc_provider(rpc_url)?;
+    Ok(provider.get_chainid().await?.as_u64())
+}
+
+pub fn parse_address(value: &str) -> Result<Address> {
+    value.parse().map_err(|e| eyre!("Invalid address {}: {}", value, e))
+}
+
+pub fn parse_quantity(value: &str) -> U256 {
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).unwrap_or_default(),
+        None => U256::from_dec_str(value).unwrap_or_default(),
+    }
+}
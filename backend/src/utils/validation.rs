@@ -0,0 +1,242 @@
+use super::ens::is_ens_name;
+use crate::models::{BundleRequest, FixRequest, ForgeRequest, ValidationConfig};
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use serde::Serialize;
+
+/// EVM versions solc accepts for `evm_version`
+const EVM_VERSIONS: &[&str] = &[
+    "homestead",
+    "tangerineWhistle",
+    "spuriousDragon",
+    "byzantium",
+    "constantinople",
+    "petersburg",
+    "istanbul",
+    "berlin",
+    "london",
+    "paris",
+    "shanghai",
+    "cancun",
+    "prague",
+    "osaka",
+];
+
+/// Chat-template tokens and role markers that let user text masquerade as
+/// instructions once it's templated into a prompt
+const INJECTION_MARKERS: &[&str] = &[
+    "```",
+    "<|im_start|>",
+    "<|im_end|>",
+    "<|endoftext|>",
+    "<|system|>",
+    "<|user|>",
+    "<|assistant|>",
+    "<|begin_of_text|>",
+    "<|eot_id|>",
+    "[INST]",
+    "[/INST]",
+    "<<SYS>>",
+    "<</SYS>>",
+];
+
+/// A rejected request field, returned to the client as a 400 JSON body
+#[derive(Debug, Serialize)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub(crate) fn new(field: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ValidationError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": self })),
+        )
+            .into_response()
+    }
+}
+
+/// Validate a forge request in place, sanitizing the intent
+pub fn validate_forge_request(
+    request: &mut ForgeRequest,
+    config: &ValidationConfig,
+) -> Result<(), ValidationError> {
+    // ENS names are resolved against the fork once the RPC URL is known
+    if !is_ens_name(&request.from_address) {
+        validate_address("from_address", &request.from_address)?;
+    }
+
+    if let Some(rpc_url) = &request.rpc_url {
+        validate_rpc_url(rpc_url, config)?;
+    }
+
+    if request.slippage_bps.is_some_and(|bps| bps > 10_000) {
+        return Err(ValidationError::new(
+            "slippage_bps",
+            "OUT_OF_RANGE",
+            "Slippage must be at most 10000 basis points",
+        ));
+    }
+
+    if request.impersonate && !config.allow_impersonation {
+        return Err(ValidationError::new(
+            "impersonate",
+            "DISABLED",
+            "Impersonated simulations are disabled on this server",
+        ));
+    }
+
+    // Phases warped apart can't be split into parts run side by side, and an
+    // impersonated run sends each transaction to the node at its real time
+    if request.time_travel && (request.parallel || request.impersonate) {
+        return Err(ValidationError::new(
+            "time_travel",
+            "CONFLICT",
+            "Time-travel simulations can't be parallel or impersonated",
+        ));
+    }
+
+    if let Some(evm_version) = &request.evm_version {
+        if !EVM_VERSIONS.contains(&evm_version.as_str()) {
+            return Err(ValidationError::new(
+                "evm_version",
+                "UNKNOWN",
+                format!("EVM version must be one of {}", EVM_VERSIONS.join(", ")),
+            ));
+        }
+    }
+
+    request.intent = sanitize_prompt_text(&request.intent);
+    if request.intent.is_empty() {
+        return Err(ValidationError::new("intent", "EMPTY", "Intent must not be empty"));
+    }
+    if request.intent.chars().count() > config.max_intent_chars {
+        return Err(ValidationError::new(
+            "intent",
+            "TOO_LONG",
+            format!("Intent must be at most {} characters", config.max_intent_chars),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Validate a fix request in place, sanitizing the reported error
+pub fn validate_fix_request(
+    request: &mut FixRequest,
+    config: &ValidationConfig,
+) -> Result<(), ValidationError> {
+    if let Some(rpc_url) = &request.rpc_url {
+        validate_rpc_url(rpc_url, config)?;
+    }
+
+    request.error = sanitize_prompt_text(&request.error);
+    if request.error.chars().count() > config.max_error_chars {
+        return Err(ValidationError::new(
+            "error",
+            "TOO_LONG",
+            format!("Error must be at most {} characters", config.max_error_chars),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Validate a bundle export request
+pub fn validate_bundle_request(
+    request: &BundleRequest,
+    config: &ValidationConfig,
+) -> Result<(), ValidationError> {
+    if let Some(rpc_url) = &request.rpc_url {
+        validate_rpc_url(rpc_url, config)?;
+    }
+
+    Ok(())
+}
+
+/// Parse an address, enforcing the EIP-55 checksum when the input is mixed case
+fn validate_address(field: &'static str, value: &str) -> Result<Address, ValidationError> {
+    let address = value
+        .parse::<Address>()
+        .map_err(|_| ValidationError::new(field, "INVALID_ADDRESS", "Not a valid hex address"))?;
+
+    let hex = value.trim_start_matches("0x");
+    let mixed_case = hex.chars().any(|c| c.is_ascii_lowercase())
+        && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case && to_checksum(&address, None) != value {
+        return Err(ValidationError::new(
+            field,
+            "BAD_CHECKSUM",
+            "Address checksum does not match (EIP-55)",
+        ));
+    }
+
+    Ok(address)
+}
+
+fn validate_rpc_url(value: &str, config: &ValidationConfig) -> Result<(), ValidationError> {
+    let url = reqwest::Url::parse(value)
+        .map_err(|e| ValidationError::new("rpc_url", "INVALID_URL", e.to_string()))?;
+
+    if !config
+        .allowed_rpc_schemes
+        .iter()
+        .any(|scheme| scheme == url.scheme())
+    {
+        return Err(ValidationError::new(
+            "rpc_url",
+            "SCHEME_NOT_ALLOWED",
+            format!(
+                "RPC URL scheme must be one of: {}",
+                config.allowed_rpc_schemes.join(", ")
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Strip control characters and prompt-injection markers from user text
+pub fn sanitize_prompt_text(text: &str) -> String {
+    let mut sanitized: String = text
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+
+    for marker in INJECTION_MARKERS {
+        sanitized = remove_case_insensitive(&sanitized, marker);
+    }
+
+    sanitized.trim().to_string()
+}
+
+fn remove_case_insensitive(text: &str, pattern: &str) -> String {
+    let lower = text.to_ascii_lowercase();
+    let pattern = pattern.to_ascii_lowercase();
+
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, _) in lower.match_indices(&pattern) {
+        result.push_str(&text[last..start]);
+        last = start + pattern.len();
+    }
+    result.push_str(&text[last..]);
+    result
+}
@@ -0,0 +1,93 @@
+use crate::models::{ChangeKind, TransactionChange, TransactionDetails};
+use eyre::{eyre, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Session subdirectory holding every script version and its simulated transactions
+pub const VERSIONS_DIR: &str = "versions";
+
+fn script_path(project_path: &Path, version: usize) -> PathBuf {
+    project_path.join(VERSIONS_DIR).join(format!("v{}.s.sol", version))
+}
+
+fn transactions_path(project_path: &Path, version: usize) -> PathBuf {
+    project_path.join(VERSIONS_DIR).join(format!("v{}.transactions.json", version))
+}
+
+/// Number of the latest stored version, 0 when none is stored yet
+pub fn latest_version(project_path: &Path) -> usize {
+    (1..).take_while(|version| script_path(project_path, *version).exists()).count()
+}
+
+/// Store a new script version, returning its number (starting at 1)
+pub fn save_script_version(project_path: &Path, code: &str) -> Result<usize> {
+    fs::create_dir_all(project_path.join(VERSIONS_DIR))?;
+    let version = latest_version(project_path) + 1;
+    fs::write(script_path(project_path, version), code)?;
+    Ok(version)
+}
+
+/// Record the transactions the latest version simulated to
+pub fn save_version_transactions(project_path: &Path, transactions: &[TransactionDetails]) -> Result<()> {
+    let version = latest_version(project_path);
+    if version == 0 {
+        return Err(eyre!("No script version to attach transactions to"));
+    }
+    fs::write(transactions_path(project_path, version), serde_json::to_string(transactions)?)?;
+    Ok(())
+}
+
+/// A stored version's script and, if it simulated successfully, its transactions
+pub fn read_version(project_path: &Path, version: usize) -> Result<(String, Option<Vec<TransactionDetails>>)> {
+    let script = fs::read_to_string(script_path(project_path, version))
+        .map_err(|_| eyre!("Version {} does not exist", version))?;
+    let transactions = fs::read_to_string(transactions_path(project_path, version))
+        .ok()
+        .map(|json| serde_json::from_str(&json))
+        .transpose()?;
+    Ok((script, transactions))
+}
+
+/// Parse a version given as `v2` or `2`
+pub fn parse_version(value: &str) -> Option<usize> {
+    value.strip_prefix('v').unwrap_or(value).parse().ok().filter(|version| *version > 0)
+}
+
+/// Pair two versions' transactions by position and note what differs
+pub fn compare_transactions(before: &[TransactionDetails], after: &[TransactionDetails]) -> Vec<TransactionChange> {
+    (0..before.len().max(after.len()))
+        .map(|index| {
+            let (old, new) = (before.get(index), after.get(index));
+            let (change, fields) = match (old, new) {
+                (Some(old), Some(new)) => {
+                    let fields = changed_fields(old, new);
+                    let change = if fields.is_empty() { ChangeKind::Unchanged } else { ChangeKind::Changed };
+                    (change, fields)
+                }
+                (None, _) => (ChangeKind::Added, Vec::new()),
+                (_, None) => (ChangeKind::Removed, Vec::new()),
+            };
+            TransactionChange {
+                index,
+                change,
+                fields,
+                before: old.cloned(),
+                after: new.cloned(),
+            }
+        })
+        .collect()
+}
+
+fn changed_fields(old: &TransactionDetails, new: &TransactionDetails) -> Vec<&'static str> {
+    [
+        ("to", old.to != new.to),
+        ("function", old.function != new.function),
+        ("arguments", old.arguments != new.arguments),
+        ("value", old.value != new.value),
+        ("input_data", old.input_data != new.input_data),
+        ("gas", old.gas != new.gas),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
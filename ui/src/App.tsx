@@ -34,11 +34,24 @@ type ForgeStep = {
 };
 
 interface TransactionDetails {
+  from: string;
   to: string;
   function: string;
   arguments: string[];
   value: string;
   input_data: string;
+  gas: string;
+  value_usd?: number;
+  part?: number;
+  order?: number;
+  phase?: number;
+  deployment?: {
+    contract_name?: string;
+    address: string;
+    init_code: string;
+    constructor_args: string[];
+    library?: string;
+  };
 }
 
 interface FixResponse {
@@ -46,11 +59,163 @@ interface FixResponse {
   message: string;
 }
 
-interface ForgeResponse {
-  title: string;
-  output: string;
+interface TokenDelta {
+  token: string;
+  symbol: string | null;
+  amount: string;
+  usd?: number;
+}
+
+interface Diagnostic {
+  severity: string;
+  code?: string;
+  message: string;
+  file?: string;
+  line?: number;
+  formatted: string;
+}
+
+interface TraceCall {
+  depth: number;
+  kind: string;
+  address: string;
+  label?: string;
+  function?: string;
+  args?: string[];
+  success: boolean;
+  gas_used: number;
+}
+
+interface RiskReport {
+  score: number;
+  level: "low" | "medium" | "high";
+  reasons: { factor: string; points: number; transaction?: number; detail: string }[];
 }
 
+type Stage =
+  | "queued"
+  | "initializing"
+  | "planning"
+  | "generating"
+  | "writing"
+  | "installing_dependencies"
+  | "fixing"
+  | "simulating"
+  | "security_review"
+  | "parsing";
+
+// Top-level events also carry the id of the request that produced them
+type ForgeEvent = { request_id?: string } & (
+  | { type: "session_created"; session: string }
+  | { type: "status"; stage: Stage; message: string }
+  | { type: "progress"; stage: Stage; percent: number; done?: number; total?: number }
+  | { type: "plan"; plan: { actions: Record<string, unknown>[] } }
+  | { type: "token_delta"; text: string }
+  | { type: "code_chunk"; code: string }
+  | { type: "script_diff"; diff: string }
+  | { type: "warning"; message: string }
+  | { type: "compile_output"; stage: Stage; line: string }
+  | ({ type: "diagnostic"; stage: Stage } & Diagnostic)
+  | { type: "console_log"; stage: Stage; message: string }
+  | { type: "gas_report"; stage: Stage; name: string; gas_used: number }
+  | { type: "trace"; stage: Stage; calls: TraceCall[] }
+  | { type: "transactions"; transactions: TransactionDetails[] }
+  | { type: "explanation"; explanation: string }
+  | { type: "error"; code: string; stage: Stage | null; retriable: boolean; message: string }
+  | { type: "part"; part: number; event: ForgeEvent }
+  | { type: "result"; session: string; script: string; transactions: TransactionDetails[]; token_deltas: TokenDelta[]; risk?: RiskReport; cached_age_secs?: number; impersonated?: boolean; funded?: boolean; time_travel?: boolean }
+);
+
+const STAGE_TITLES: Record<Stage, string> = {
+  queued: "Queued",
+  initializing: "Initializing",
+  planning: "Planning Actions",
+  generating: "Generating Code",
+  writing: "Writing Code",
+  installing_dependencies: "Installing Dependencies",
+  fixing: "Fixing Script",
+  simulating: "Simulating Transactions",
+  security_review: "Security Review",
+  parsing: "Reading Results",
+};
+
+// Map an event to the step it appends to; null for events that aren't displayed
+const toStep = (event: ForgeEvent): ForgeStep | null => {
+  switch (event.type) {
+    case "status":
+      return { title: STAGE_TITLES[event.stage], output: event.message };
+    case "plan":
+      return { title: STAGE_TITLES.planning, output: JSON.stringify(event.plan.actions, null, 2) };
+    case "token_delta":
+      return { title: STAGE_TITLES.generating, output: event.text };
+    case "code_chunk":
+      return { title: "Script", output: event.code };
+    case "result": {
+      const lines = event.token_deltas.map((delta) =>
+        `${delta.amount} ${delta.symbol ?? delta.token}`
+          + (delta.usd !== undefined ? ` (~$${delta.usd.toFixed(2)})` : ""));
+      if (event.risk) {
+        if (lines.length > 0) lines.push("");
+        lines.push(`Risk: ${event.risk.score}/100 (${event.risk.level})`);
+        lines.push(...event.risk.reasons.map((reason) =>
+          `- ${reason.transaction !== undefined ? `transaction ${reason.transaction}: ` : ""}${reason.detail}`));
+      }
+      if (event.impersonated) {
+        if (lines.length > 0) lines.push("");
+        lines.push("Preview only: these transactions ran as an impersonated sender and can't be signed as is.");
+      }
+      if (event.funded) {
+        if (lines.length > 0) lines.push("");
+        lines.push("Synthetic: the sender was given balances it doesn't hold, so these transactions won't go through on-chain as is.");
+      }
+      if (event.time_travel) {
+        const phases = new Set(event.transactions.map((transaction) => transaction.phase ?? 0)).size;
+        if (lines.length > 0) lines.push("");
+        lines.push(`Time travel: the script warped time, splitting these transactions into ${phases} phase${phases === 1 ? "" : "s"} to send as that time actually passes.`);
+      }
+      if (lines.length === 0) return null;
+      return { title: event.risk ? "Balance Changes & Risk" : "Balance Changes", output: lines.join("\n") };
+    }
+    case "warning":
+      return { title: "Warning", output: event.message };
+    case "script_diff":
+      return { title: "Script Changes", output: event.diff || "No changes" };
+    case "compile_output":
+      return { title: STAGE_TITLES[event.stage], output: event.line + "\n" };
+    case "diagnostic":
+      return { title: STAGE_TITLES[event.stage], output: event.formatted + "\n" };
+    case "console_log":
+      return { title: STAGE_TITLES[event.stage], output: event.message + "\n" };
+    case "gas_report":
+      return { title: STAGE_TITLES[event.stage], output: `Gas used by ${event.name}: ${event.gas_used}\n` };
+    case "trace":
+      return {
+        title: STAGE_TITLES[event.stage],
+        output: event.calls.map((call) =>
+          `${"  ".repeat(call.depth)}[${call.gas_used}] ${call.label ?? call.address}::`
+            + `${call.function?.split("(")[0] ?? "fallback"}(${(call.args ?? []).join(", ")})`
+            + (call.success ? "" : " [reverted]")).join("\n") + "\n",
+      };
+    case "transactions":
+      return { title: STAGE_TITLES.simulating, output: JSON.stringify(event.transactions, null, 2) };
+    case "explanation":
+      return { title: "Explanation", output: event.explanation };
+    case "error":
+      return {
+        title: "Error",
+        output: `[${event.code}${event.stage ? ` during ${STAGE_TITLES[event.stage]}` : ""}] ${event.message}`
+          + (event.retriable ? "\n\nThis may be temporary; trying again could succeed." : "")
+          + (event.request_id ? `\n\nRequest ID: ${event.request_id}` : ""),
+      };
+    case "part": {
+      const step = toStep(event.event);
+      return step && { title: step.title, output: `[part ${event.part + 1}] ${step.output}` };
+    }
+    default:
+      return null;
+  }
+};
+
 const useEventSourceWithRetry = (
   url: string,
   options: {
@@ -98,6 +263,7 @@ function App() {
   const [prompt, setPrompt] = useState("");
 
   const [tempDir, setTempDir] = useState<string | null>(null);
+  const [progress, setProgress] = useState<number | null>(null);
 
   const { ready, authenticated, user, login, logout } = usePrivy();
 
@@ -155,20 +321,37 @@ function App() {
 
     eventSource.addEventListener('message', (event) => {
       console.log('Received message:', event.data); // Debug log
-      const data = JSON.parse(event.data) as ForgeResponse;
-      
-      if (data.title === "Debug") {
-        console.log(data.output);
+      const forgeEvent = JSON.parse(event.data) as ForgeEvent;
+
+      // Store session ID when received
+      if (forgeEvent.type === "session_created") {
+        setTempDir(forgeEvent.session);
+        console.log(forgeEvent.session);
         return;
       }
 
-      // Store session ID when received
-      if (data.title === "Session") {
-        setTempDir(data.output);
-        console.log(data.output);
+      if (forgeEvent.type === "transactions") {
+        setTransactions(forgeEvent.transactions);
+      }
+
+      if (forgeEvent.type === "error") {
+        setProgress(null);
+      }
+
+      // The job finished; the server ends the stream after this
+      if (forgeEvent.type === "result") {
+        setProgress(null);
+        eventSource.close();
+      }
+
+      if (forgeEvent.type === "progress") {
+        setProgress(forgeEvent.percent);
         return;
       }
 
+      const data = toStep(forgeEvent);
+      if (!data) return;
+
       setMessages((prev) => {
         const messages = [...prev];
         const lastMessage = messages[messages.length - 1];
@@ -231,20 +414,30 @@ function App() {
     );
 
     eventSource.onmessage = (event) => {
-      const data = JSON.parse(event.data) as ForgeResponse;
+      const forgeEvent = JSON.parse(event.data) as ForgeEvent;
 
-      if (data.title === "Debug") {
-        console.log(data.output);
-        return;
+      if (forgeEvent.type === "transactions") {
+        setTransactions(forgeEvent.transactions);
       }
 
-      if (data.title === "Simulating Transactions") {
-        if (data.output.includes("[{")) {
-          const transactions = JSON.parse(data.output);
-          setTransactions(transactions);
-        }
+      if (forgeEvent.type === "error") {
+        setProgress(null);
+      }
+
+      // The job finished; the server ends the stream after this
+      if (forgeEvent.type === "result") {
+        setProgress(null);
+        eventSource.close();
+      }
+
+      if (forgeEvent.type === "progress") {
+        setProgress(forgeEvent.percent);
+        return;
       }
-      
+
+      const data = toStep(forgeEvent);
+      if (!data) return;
+
       setMessages((prev) => {
         const messages = [...prev];
         const lastMessage = messages[messages.length - 1];
@@ -293,12 +486,43 @@ function App() {
 
 
   useEffect(() => {
+    // Sign without sending and relay through the chain's private RPC, keeping
+    // swaps out of the public mempool. Null when the wallet can only sign and
+    // send in one go. A chain without a private RPC gets the signed transaction
+    // through the wallet instead, but only once the user agrees to the public
+    // mempool.
+    const sendPrivately = async (provider: any, params: object): Promise<string | null> => {
+      let raw: string;
+      try {
+        raw = await provider.request({ method: "eth_signTransaction", params: [params] });
+      } catch {
+        return null;
+      }
+      const response = await fetch("http://127.0.0.1:3000/forge/submit", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ session_id: tempDir?.split("/").pop() ?? "", raw_transaction: raw }),
+      });
+      if (response.status === 422) {
+        const reason = await response.text();
+        if (!window.confirm(`${reason}.\n\nSend this transaction through the public mempool instead, where it can be front-run?`)) {
+          throw new Error("Not sent: no private RPC for this chain");
+        }
+        return await provider.request({ method: "eth_sendRawTransaction", params: [raw] });
+      }
+      if (!response.ok) throw new Error(await response.text());
+      const { hash, relay } = await response.json();
+      return `${hash} (sent privately via ${relay})`;
+    };
+
     const handleTransaction = async (transaction: TransactionDetails) => {
       // Add transaction message
       setMessages(prev => [...prev, {
         role: "ai",
-        title: transaction.function + " " + txCount +  "/" + transactions.length,
-        content: `Please sign this transaction:\n\nTo: ${transaction.to}\nFunction: ${transaction.function}\nArguments: ${transaction.arguments.join(", ")}\nValue: ${transaction.value} ETH`,
+        title: (transaction.deployment ? "deploy" : transaction.function) + " " + txCount +  "/" + transactions.length,
+        content: transaction.deployment
+          ? `Please sign this deployment:\n\nContract: ${transaction.deployment.contract_name ?? "unknown"}\nAddress: ${transaction.deployment.address}\nConstructor arguments: ${transaction.deployment.constructor_args.join(", ")}${transaction.deployment.library ? `\nLinked library: ${transaction.deployment.library}` : ""}\nValue: ${transaction.value} ETH`
+          : `Please sign this transaction:\n\nTo: ${transaction.to}\nFunction: ${transaction.function}\nArguments: ${transaction.arguments.join(", ")}\nValue: ${transaction.value} ETH`,
         timestamp: new Date(),
       }]);
 
@@ -307,23 +531,21 @@ function App() {
 
         const provider = await wallet.getEthereumProvider();
   
-        const result = await provider.request({
-          method: "eth_sendTransaction",
-          params: [
-            {
-              from: user?.wallet?.address,
-              to: transaction.to,
-              data: transaction.input_data,
-              value: transaction.value,
-            },
-          ],
-        });
+        const params = {
+          from: user?.wallet?.address,
+          // A plain CREATE has no recipient
+          to: transaction.to || undefined,
+          data: transaction.input_data,
+          value: transaction.value,
+        };
+        const hash = await sendPrivately(provider, params)
+          ?? (await provider.request({ method: "eth_sendTransaction", params: [params] })).hash;
         
         // Add success message
         setMessages(prev => [...prev, {
           role: "ai",
           title: "Transaction",
-          content: `Transaction sent! Hash: ${result.hash}`,
+          content: `Transaction sent! Hash: ${hash}`,
           timestamp: new Date(),
         }]);
 
@@ -448,6 +670,14 @@ function App() {
       {/* Fixed Search Bar */}
       <div className="fixed bottom-0 left-0 right-0 bg-[#0a0a0a] z-10">
         <div className="max-w-3xl mx-auto">
+          {progress !== null && progress < 100 && (
+            <div className="h-1 w-full bg-[#252525] rounded-full mb-2">
+              <div
+                className="h-1 bg-blue-400 rounded-full transition-all"
+                style={{ width: `${progress}%` }}
+              />
+            </div>
+          )}
           <form
             onSubmit={(e) => {
               e.preventDefault();